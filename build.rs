@@ -1,17 +1,237 @@
 use std::env;
-use std::fs::File;
+use std::ffi::OsStr;
+use std::fmt::Write as _;
+use std::fs::{self, File};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 fn main() {
+    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+
+    let blocks = smart_eeprom_blocks();
+
     if env::var_os("CARGO_FEATURE_RT").is_some() {
-        let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+        // Each SmartEEPROM block takes two 8K blocks from the end of flash.
+        let memory = include_str!("memory.x");
+        assert!(
+            memory.contains(FLASH_LENGTH),
+            "memory.x: missing FLASH length"
+        );
+        let memory = memory.replace(FLASH_LENGTH, &format!("512K - 16K - {}K", blocks * 16));
+
         File::create(out.join("memory.x"))
             .unwrap()
-            .write_all(include_bytes!("memory.x"))
+            .write_all(memory.as_bytes())
             .unwrap();
         println!("cargo:rustc-link-search={}", out.display());
         println!("cargo:rerun-if-changed=memory.x");
     }
+
+    fs::write(
+        out.join("nvm.rs"),
+        format!(
+            "/// The number of SmartEEPROM blocks reserved at the end of flash, set by\n\
+             /// `{}` when building.\n\
+             pub const SMART_EEPROM_BLOCKS: u32 = {};\n",
+            SMART_EEPROM_BLOCKS, blocks
+        ),
+    )
+    .unwrap();
+
+    fonts(Path::new("assets/fonts"), &out.join("fonts.rs"));
     println!("cargo:rerun-if-changed=build.rs");
 }
+
+// The environment variable giving the number of SmartEEPROM blocks to reserve,
+// and the length of the FLASH region in memory.x which it shortens.
+const SMART_EEPROM_BLOCKS: &str = "WIO_TERMINAL_SMART_EEPROM_BLOCKS";
+const FLASH_LENGTH: &str = "512K - 16K - 0K";
+
+fn smart_eeprom_blocks() -> u32 {
+    println!("cargo:rerun-if-env-changed={}", SMART_EEPROM_BLOCKS);
+
+    match env::var(SMART_EEPROM_BLOCKS) {
+        Ok(blocks) => match blocks.parse() {
+            Ok(blocks) if blocks <= 10 => blocks,
+            _ => panic!("{} must be a number from 0 to 10", SMART_EEPROM_BLOCKS),
+        },
+        Err(_) => 0,
+    }
+}
+
+// Convert each BDF font in `dir` into a `BitmapFont` static, named after the
+// file, and write them all to `dest`.
+fn fonts(dir: &Path, dest: &Path) {
+    println!("cargo:rerun-if-changed={}", dir.display());
+
+    let mut paths = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some(OsStr::new("bdf")))
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let mut code = String::new();
+    for path in paths {
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        let name = path
+            .file_stem()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_uppercase()
+            .replace('-', "_");
+        let source = fs::read_to_string(&path).unwrap();
+        let font = Bdf::parse(&source).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        font.generate(&name, &mut code);
+    }
+
+    fs::write(dest, code).unwrap();
+}
+
+// A font parsed from BDF, with every glyph rendered into a full cell.
+struct Bdf {
+    width: usize,
+    height: usize,
+    default_char: Option<u32>,
+    // Sorted by code point; each bitmap holds one `bool` per pixel, row-major.
+    glyphs: Vec<(u32, Vec<bool>)>,
+}
+
+impl Bdf {
+    fn parse(source: &str) -> Result<Bdf, String> {
+        let mut cell = None;
+        let mut default_char = None;
+        let mut glyphs = Vec::new();
+        let mut lines = source.lines().map(str::trim);
+
+        while let Some(line) = lines.next() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("FONTBOUNDINGBOX") => cell = Some(numbers(words, 4)?),
+                Some("DEFAULT_CHAR") => default_char = Some(numbers(words, 1)?[0] as u32),
+                Some("STARTCHAR") => {
+                    let cell = cell.as_ref().ok_or("STARTCHAR before FONTBOUNDINGBOX")?;
+                    if let Some(glyph) = Self::glyph(&mut lines, cell)? {
+                        glyphs.push(glyph);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let cell = cell.ok_or("missing FONTBOUNDINGBOX")?;
+        glyphs.sort_by_key(|&(code, _)| code);
+        glyphs.dedup_by_key(|&mut (code, _)| code);
+
+        Ok(Bdf {
+            width: cell[0] as usize,
+            height: cell[1] as usize,
+            default_char,
+            glyphs,
+        })
+    }
+
+    // Parse one glyph, up to and including ENDCHAR, placing its bitmap in the
+    // font's cell. Glyphs without an encoding are skipped.
+    fn glyph<'a>(
+        lines: &mut impl Iterator<Item = &'a str>,
+        cell: &[i32],
+    ) -> Result<Option<(u32, Vec<bool>)>, String> {
+        let (fw, fh, fx, fy) = (cell[0], cell[1], cell[2], cell[3]);
+        let mut encoding = None;
+        let mut bbx = None;
+
+        for line in lines.by_ref() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("ENCODING") => {
+                    let code = numbers(words, 1)?[0];
+                    encoding = if code < 0 { None } else { Some(code as u32) };
+                }
+                Some("BBX") => bbx = Some(numbers(words, 4)?),
+                Some("BITMAP") => break,
+                Some("ENDCHAR") => return Err("ENDCHAR without BITMAP".into()),
+                _ => {}
+            }
+        }
+
+        let bbx = bbx.ok_or("glyph without BBX")?;
+        let (gw, gh, gx, gy) = (bbx[0], bbx[1], bbx[2], bbx[3]);
+        let mut bitmap = vec![false; (fw * fh) as usize];
+
+        for i in 0.. {
+            let line = lines.next().ok_or("unterminated BITMAP")?;
+            if line == "ENDCHAR" {
+                break;
+            }
+            if i >= gh {
+                return Err("too many BITMAP rows".into());
+            }
+
+            let row = u64::from_str_radix(line, 16).map_err(|e| e.to_string())?;
+            let bits = line.len() as i32 * 4;
+            let y = fy + fh - gy - gh + i;
+            for j in 0..gw.min(bits) {
+                let x = gx - fx + j;
+                if x >= 0 && x < fw && y >= 0 && y < fh {
+                    bitmap[(y * fw + x) as usize] = row >> (bits - 1 - j) & 1 != 0;
+                }
+            }
+        }
+
+        Ok(encoding.map(|code| (code, bitmap)))
+    }
+
+    fn generate(&self, name: &str, code: &mut String) {
+        let mut ranges = Vec::<(u32, usize, usize)>::new();
+        for (index, &(c, _)) in self.glyphs.iter().enumerate() {
+            match ranges.last_mut() {
+                Some((start, len, _)) if *start + *len as u32 == c => *len += 1,
+                _ => ranges.push((c, 1, index)),
+            }
+        }
+
+        let default_glyph = self
+            .default_char
+            .and_then(|d| self.glyphs.iter().position(|&(c, _)| c == d))
+            .or_else(|| self.glyphs.iter().position(|&(c, _)| c == '?' as u32))
+            .unwrap_or(0);
+
+        writeln!(code, "pub static {}: BitmapFont = BitmapFont::new(", name).unwrap();
+        writeln!(code, "    {},\n    {},\n    &[", self.width, self.height).unwrap();
+        for (start, len, index) in ranges {
+            writeln!(
+                code,
+                "        GlyphRange {{ start: {:#x}, len: {}, index: {} }},",
+                start, len, index
+            )
+            .unwrap();
+        }
+        writeln!(code, "    ],\n    &[").unwrap();
+        for (c, bitmap) in &self.glyphs {
+            let bytes = bitmap.chunks(8).map(|bits| {
+                bits.iter()
+                    .enumerate()
+                    .fold(0u8, |byte, (i, &bit)| byte | (bit as u8) << (7 - i))
+            });
+            write!(code, "        ").unwrap();
+            for byte in bytes {
+                write!(code, "{:#04x}, ", byte).unwrap();
+            }
+            writeln!(code, "// U+{:04X}", c).unwrap();
+        }
+        writeln!(code, "    ],\n    {},\n);", default_glyph).unwrap();
+    }
+}
+
+fn numbers<'a>(words: impl Iterator<Item = &'a str>, count: usize) -> Result<Vec<i32>, String> {
+    let numbers = words
+        .map(|word| word.parse::<i32>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if numbers.len() < count {
+        return Err(format!("expected {} numbers", count));
+    }
+    Ok(numbers)
+}
@@ -14,12 +14,10 @@ use wio::hal::clock::GenericClockController;
 use wio::hal::delay::Delay;
 use wio::pac::{interrupt, CorePeripherals, Peripherals};
 use wio::{button_interrupt, entry, Button, ButtonController, ButtonEvent, Pins, Sets};
+use wio::{ButtonEventProducer, ButtonEventQueue};
 
 use cortex_m::interrupt::{free as disable_interrupts, CriticalSection};
 
-use heapless::consts::U8;
-use heapless::spsc::Queue;
-
 #[entry]
 fn main() -> ! {
     let mut peripherals = Peripherals::take().unwrap();
@@ -58,19 +56,21 @@ fn main() -> ! {
     let backdrop = Rectangle::new(Point::new(0, 0), Point::new(320, 320)).into_styled(style);
     backdrop.draw(&mut display).unwrap();
 
-    let button_ctrlr = sets.buttons.init(
+    let button_ctrlr = sets.buttons.init_with_top_right(
         peripherals.EIC,
         &mut clocks,
         &mut peripherals.MCLK,
         &mut sets.port,
     );
     let nvic = &mut core.NVIC;
-    disable_interrupts(|_| unsafe {
+    let mut consumer = disable_interrupts(|_| unsafe {
+        let (producer, consumer) = QUEUE.split();
+        PRODUCER = Some(producer);
         button_ctrlr.enable(nvic);
         BUTTON_CTRLR = Some(button_ctrlr);
+        consumer
     });
 
-    let mut consumer = unsafe { Q.split().1 };
     loop {
         if let Some(press) = consumer.dequeue() {
             let color = match press.down {
@@ -113,6 +113,16 @@ fn draw_button_marker<D: DrawTarget<Rgb565>>(
                 .draw(display)
                 .ok();
         }
+        Button::TopRight => {
+            Rectangle::new(Point::new(155, 5), Point::new(155, 35))
+                .into_styled(style)
+                .draw(display)
+                .ok();
+            Triangle::new(Point::new(150, 5), Point::new(155, 0), Point::new(160, 5))
+                .into_styled(style)
+                .draw(display)
+                .ok();
+        }
         Button::Left => {
             Rectangle::new(Point::new(90, 120), Point::new(120, 120))
                 .into_styled(style)
@@ -179,12 +189,14 @@ fn draw_button_marker<D: DrawTarget<Rgb565>>(
 }
 
 static mut BUTTON_CTRLR: Option<ButtonController> = None;
-static mut Q: Queue<ButtonEvent, U8> = Queue(heapless::i::Queue::new());
+static mut QUEUE: ButtonEventQueue = ButtonEventQueue::new();
+static mut PRODUCER: Option<ButtonEventProducer<'static>> = None;
 
 button_interrupt!(
     BUTTON_CTRLR,
     unsafe fn on_button_event(_cs: &CriticalSection, event: ButtonEvent) {
-        let mut q = Q.split().0;
-        q.enqueue(event).ok();
+        if let Some(producer) = PRODUCER.as_mut() {
+            producer.enqueue(event).ok();
+        }
     }
 );
@@ -3,24 +3,19 @@
 
 /// Makes the wio_terminal appear as a USB serial port. The screen can
 /// be written to by sending messages down the serial port.
-use embedded_graphics as eg;
+use core::fmt::Write;
+
 use panic_halt as _;
 use wio_terminal as wio;
 
-use eg::fonts::{Font6x12, Text};
-use eg::pixelcolor::Rgb565;
-use eg::prelude::*;
-use eg::primitives::rectangle::Rectangle;
-use eg::style::{PrimitiveStyleBuilder, TextStyle};
-
 use cortex_m::peripheral::NVIC;
 
+use wio::console::{Console, ConsoleConfig};
 use wio::hal::clock::GenericClockController;
 use wio::hal::delay::Delay;
 use wio::pac::{interrupt, CorePeripherals, Peripherals};
 use wio::prelude::*;
-use wio::{entry, Pins, Sets};
-use wio::{Scroller, LCD};
+use wio::{entry, DisplayConfig, Pins, Sets};
 
 use usb_device::bus::UsbBusAllocator;
 use usb_device::prelude::*;
@@ -50,11 +45,16 @@ fn main() -> ! {
     let mut user_led = sets.user_led.into_open_drain_output(&mut sets.port);
     user_led.set_low().unwrap();
 
-    // Initialize the ILI9341-based LCD display. Create a black backdrop the size of
-    // the screen.
+    // Initialize the ILI9341-based LCD display in portrait, so that the console
+    // can use hardware scrolling.
+    let config = DisplayConfig {
+        orientation: ili9341::Orientation::Portrait,
+        ..DisplayConfig::default()
+    };
     let (display, _backlight) = sets
         .display
-        .init(
+        .init_with_config(
+            config,
             &mut clocks,
             peripherals.SERCOM7,
             &mut peripherals.MCLK,
@@ -93,17 +93,21 @@ fn main() -> ! {
         NVIC::unmask(interrupt::USB_TRCPT1);
     }
 
-    let mut t = Terminal::new(display);
-    t.write_str("Hello! Send text to me over the USB serial port, and I'll display it!");
-    t.write_str("\n");
-    t.write_str("On linux:\n");
-    t.write_str("  sudo stty -F /dev/ttyACM0 115200 raw -echo\n");
-    t.write_str("  sudo bash -c \"echo 'Hi' > /dev/ttyACM0\"\n");
+    let mut t = Console::new(display, ConsoleConfig::default()).unwrap();
+    writeln!(
+        t,
+        "Hello! Send text to me over the USB serial port, and I'll display it!"
+    )
+    .unwrap();
+    writeln!(t, "On linux:").unwrap();
+    writeln!(t, "  sudo stty -F /dev/ttyACM0 115200 raw -echo").unwrap();
+    writeln!(t, "  sudo bash -c \"echo 'Hi' > /dev/ttyACM0\"").unwrap();
 
     let mut consumer = unsafe { Q.split().1 };
     loop {
         if let Some(segment) = consumer.dequeue() {
-            t.write(segment);
+            let (buf, count) = segment;
+            t.write_bytes(&buf[..count]).unwrap();
             user_led.toggle();
         }
     }
@@ -111,95 +115,6 @@ fn main() -> ! {
 
 type TextSegment = ([u8; 32], usize);
 
-struct Terminal {
-    text_style: TextStyle<Rgb565, Font6x12>,
-    cursor: Point,
-    display: LCD,
-    scroller: Scroller,
-}
-
-impl Terminal {
-    pub fn new(mut display: LCD) -> Self {
-        // Clear the screen.
-        let style = PrimitiveStyleBuilder::new()
-            .fill_color(Rgb565::BLACK)
-            .build();
-        let backdrop = Rectangle::new(Point::new(0, 0), Point::new(320, 320)).into_styled(style);
-        backdrop.draw(&mut display).ok().unwrap();
-
-        let scroller = display.configure_vertical_scroll(0, 0).unwrap();
-
-        Self {
-            text_style: TextStyle::new(Font6x12, Rgb565::WHITE),
-            cursor: Point::new(0, 0),
-            display,
-            scroller,
-        }
-    }
-
-    pub fn write_str(&mut self, str: &str) {
-        for character in str.chars() {
-            self.write_character(character);
-        }
-    }
-
-    pub fn write_character(&mut self, c: char) {
-        if self.cursor.x >= 320 || c == '\n' {
-            self.cursor = Point::new(0, self.cursor.y + Font6x12::CHARACTER_SIZE.height as i32);
-        }
-        if self.cursor.y >= 240 {
-            self.animate_clear();
-            self.cursor = Point::new(0, 0);
-        }
-
-        if c != '\n' {
-            let mut buf = [0u8; 8];
-            Text::new(c.encode_utf8(&mut buf), self.cursor)
-                .into_styled(self.text_style)
-                .draw(&mut self.display)
-                .ok()
-                .unwrap();
-
-            self.cursor.x += (Font6x12::CHARACTER_SIZE.width + Font6x12::CHARACTER_SPACING) as i32;
-        }
-    }
-
-    pub fn write(&mut self, segment: TextSegment) {
-        let (buf, count) = segment;
-        for (i, character) in buf.iter().enumerate() {
-            if i >= count {
-                break;
-            }
-            self.write_character(*character as char);
-        }
-    }
-
-    fn animate_clear(&mut self) {
-        for x in (0..320).step_by(Font6x12::CHARACTER_SIZE.width as usize) {
-            self.display
-                .scroll_vertically(&mut self.scroller, Font6x12::CHARACTER_SIZE.width as u16)
-                .ok()
-                .unwrap();
-            Rectangle::new(
-                Point::new(x + 0, 0),
-                Point::new(x + Font6x12::CHARACTER_SIZE.width as i32, 240),
-            )
-            .into_styled(
-                PrimitiveStyleBuilder::new()
-                    .fill_color(Rgb565::BLACK)
-                    .build(),
-            )
-            .draw(&mut self.display)
-            .ok()
-            .unwrap();
-
-            for _ in 0..1000 {
-                cortex_m::asm::nop();
-            }
-        }
-    }
-}
-
 static mut USB_ALLOCATOR: Option<UsbBusAllocator<UsbBus>> = None;
 static mut USB_BUS: Option<UsbDevice<UsbBus>> = None;
 static mut USB_SERIAL: Option<SerialPort<UsbBus>> = None;
@@ -1,7 +1,8 @@
 MEMORY
 {
-  /* Leave 16k for the default bootloader on the Wio Terminal */
-  FLASH (rx)  : ORIGIN = 0x00000000 + 16K, LENGTH = 512K - 16K
+  /* Leave 16k for the default bootloader on the Wio Terminal, and the end of
+     the flash for any SmartEEPROM blocks reserved by build.rs */
+  FLASH (rx)  : ORIGIN = 0x00000000 + 16K, LENGTH = 512K - 16K - 0K
   RAM   (rxw) : ORIGIN = 0x20000000, LENGTH = 192K
 }
 _stack_start = ORIGIN(RAM) + LENGTH(RAM);
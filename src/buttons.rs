@@ -6,6 +6,27 @@ use atsamd_hal::target_device::{interrupt, EIC, MCLK};
 
 use cortex_m::peripheral::NVIC;
 
+use heapless::consts::U8;
+use heapless::Vec;
+
+mod gesture;
+mod joystick;
+#[cfg(feature = "unproven")]
+mod poller;
+mod queue;
+mod state;
+
+pub use gesture::*;
+pub use joystick::*;
+#[cfg(feature = "unproven")]
+pub use poller::*;
+pub use queue::*;
+pub use state::*;
+
+/// The number of buttons: three along the top, plus the five joystick
+/// switches.
+pub const BUTTON_COUNT: usize = 8;
+
 /// pushbuttons and joystick
 pub struct ButtonPins {
     /// button1 pin
@@ -28,12 +49,43 @@ pub struct ButtonPins {
 }
 
 impl ButtonPins {
+    /// Configure the buttons and joystick as external interrupt sources.
+    ///
+    /// `button1` shares EXTINT line 10 with joystick up, so it is not
+    /// configured here; use [`ButtonPins::init_with_top_right`] to support
+    /// both.
     pub fn init(
         self,
         eic: EIC,
         clocks: &mut GenericClockController,
         mclk: &mut MCLK,
         port: &mut Port,
+    ) -> ButtonController {
+        self.init_inner(eic, clocks, mclk, port, false)
+    }
+
+    /// Configure the buttons and joystick as external interrupt sources,
+    /// including `button1`.
+    ///
+    /// `button1` and joystick up share EXTINT line 10, so when the line fires
+    /// the level of both pins is read to determine which of them changed.
+    pub fn init_with_top_right(
+        self,
+        eic: EIC,
+        clocks: &mut GenericClockController,
+        mclk: &mut MCLK,
+        port: &mut Port,
+    ) -> ButtonController {
+        self.init_inner(eic, clocks, mclk, port, true)
+    }
+
+    fn init_inner(
+        self,
+        eic: EIC,
+        clocks: &mut GenericClockController,
+        mclk: &mut MCLK,
+        port: &mut Port,
+        top_right: bool,
     ) -> ButtonController {
         let clk = clocks.gclk1();
         let mut eic = eic::init_with_ulp32k(mclk, clocks.eic(&clk).unwrap(), eic);
@@ -49,10 +101,18 @@ impl ButtonPins {
         ]);
 
         // Unfortunately, the pin assigned to B1 shares the same
-        // ExtInt line as up on the joystick. As such, we don't
-        // support B1.
+        // ExtInt line as up on the joystick. As such, B1 is only
+        // supported when explicitly requested, in which case the
+        // two are told apart by their pin levels.
+        let b1 = if top_right {
+            let mut b1 = self.button1.into_ei(port);
+            b1.sense(&mut eic, Sense::BOTH);
+            b1.enable_interrupt(&mut eic);
+            Some(b1)
+        } else {
+            None
+        };
 
-        // let mut b1 = self.button1.into_ei(port);
         let mut b2 = self.button2.into_ei(port);
         let mut b3 = self.button3.into_ei(port);
         let mut x = self.switch_x.into_ei(port);
@@ -61,7 +121,6 @@ impl ButtonPins {
         let mut u = self.switch_u.into_ei(port);
         let mut b = self.switch_b.into_ei(port);
 
-        // b1.sense(&mut eic, Sense::BOTH);
         b2.sense(&mut eic, Sense::BOTH);
         b3.sense(&mut eic, Sense::BOTH);
         x.sense(&mut eic, Sense::BOTH);
@@ -70,7 +129,6 @@ impl ButtonPins {
         u.sense(&mut eic, Sense::BOTH);
         b.sense(&mut eic, Sense::BOTH);
 
-        // b1.enable_interrupt(&mut eic);
         b2.enable_interrupt(&mut eic);
         b3.enable_interrupt(&mut eic);
         x.enable_interrupt(&mut eic);
@@ -81,7 +139,7 @@ impl ButtonPins {
 
         ButtonController {
             _eic: eic.finalize(),
-            // b1,
+            b1,
             b2,
             b3,
             x,
@@ -89,14 +147,17 @@ impl ButtonPins {
             z,
             u,
             b,
+            line10: SharedLine::new(),
+            state: ButtonState::new(),
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Button {
     TopLeft,
     TopMiddle,
-    // TopRight,
+    TopRight,
     Down,
     Up,
     Left,
@@ -104,14 +165,121 @@ pub enum Button {
     Click,
 }
 
+impl Button {
+    // Buttons are numbered in the order their pins appear in `ButtonPins`, for
+    // use as an index into per-button state.
+    pub(crate) fn index(self) -> usize {
+        match self {
+            Button::TopRight => 0,
+            Button::TopMiddle => 1,
+            Button::TopLeft => 2,
+            Button::Down => 3,
+            Button::Right => 4,
+            Button::Click => 5,
+            Button::Up => 6,
+            Button::Left => 7,
+        }
+    }
+
+    pub(crate) fn from_index(index: usize) -> Button {
+        match index {
+            0 => Button::TopRight,
+            1 => Button::TopMiddle,
+            2 => Button::TopLeft,
+            3 => Button::Down,
+            4 => Button::Right,
+            5 => Button::Click,
+            6 => Button::Up,
+            _ => Button::Left,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct ButtonEvent {
     pub button: Button,
     pub down: bool,
 }
 
+/// A batch of button events; at most one per button.
+pub type ButtonEvents = Vec<ButtonEvent, U8>;
+
+/// A handler for one of the EIC interrupts used by the buttons, as returned by
+/// [`ButtonController::handler`].
+pub type ButtonHandler = fn(&mut ButtonController) -> Option<ButtonEvent>;
+
+/// Tracks the two buttons sharing EXTINT line 10 (`button1` and joystick up),
+/// and determines which of them changed each time the line fires.
+///
+/// This holds no hardware, so it can be driven with any sequence of pin
+/// levels.
+#[derive(Clone, Copy, Debug)]
+pub struct SharedLine {
+    up: bool,
+    top_right: bool,
+    pending: Option<ButtonEvent>,
+}
+
+impl SharedLine {
+    /// Create a new tracker with both buttons released.
+    pub const fn new() -> Self {
+        Self {
+            up: false,
+            top_right: false,
+            pending: None,
+        }
+    }
+
+    /// Update the tracker with the current level of each button, `true`
+    /// meaning pressed. Return the event for whichever button changed, or
+    /// `None` if neither did (eg. due to contact bounce).
+    ///
+    /// If both buttons changed, the event for joystick up is returned and
+    /// the event for `button1` is held until [`SharedLine::take_pending`] is
+    /// called.
+    pub fn update(&mut self, up: bool, top_right: bool) -> Option<ButtonEvent> {
+        let up_changed = up != self.up;
+        let top_right_changed = top_right != self.top_right;
+        self.up = up;
+        self.top_right = top_right;
+
+        let top_right_event = if top_right_changed {
+            Some(ButtonEvent {
+                button: Button::TopRight,
+                down: top_right,
+            })
+        } else {
+            None
+        };
+
+        if up_changed {
+            self.pending = top_right_event;
+            Some(ButtonEvent {
+                button: Button::Up,
+                down: up,
+            })
+        } else {
+            self.pending = None;
+            top_right_event
+        }
+    }
+
+    /// Return the event held back by the last call to
+    /// [`SharedLine::update`], if any.
+    pub fn take_pending(&mut self) -> Option<ButtonEvent> {
+        self.pending.take()
+    }
+}
+
+impl Default for SharedLine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct ButtonController {
     _eic: eic::EIC,
-    // b1: ExtInt10<Pc26<PfA>>,
+    b1: Option<ExtInt10<Pc26<PfA>>>,
     b2: ExtInt11<Pc27<PfA>>,
     b3: ExtInt12<Pc28<PfA>>,
 
@@ -120,21 +288,23 @@ pub struct ButtonController {
     z: ExtInt5<Pd10<PfA>>,
     u: ExtInt10<Pd20<PfA>>,
     b: ExtInt7<Pd12<PfA>>,
+
+    line10: SharedLine,
+    state: ButtonState,
 }
 
 macro_rules! isr {
     ($Handler:ident, $($Event:expr, $Button:ident),+) => {
         pub fn $Handler(&mut self) -> Option<ButtonEvent> {
             $(
-                {
-                    let b = &mut self.$Button;
-                    if b.is_interrupt() {
-                        b.clear_interrupt();
-                        return Some(ButtonEvent {
-                            button: $Event,
-                            down: !b.state(),
-                        })
-                    }
+                if self.$Button.is_interrupt() {
+                    self.$Button.clear_interrupt();
+                    let event = ButtonEvent {
+                        button: $Event,
+                        down: !self.$Button.state(),
+                    };
+                    self.state.set(event.button, event.down);
+                    return Some(event);
                 }
             )+
 
@@ -143,23 +313,150 @@ macro_rules! isr {
     };
 }
 
+/// Configuration for [`ButtonController::enable_with`]: which buttons have
+/// their interrupts enabled, and at what priority.
+#[derive(Clone, Copy, Debug)]
+pub struct ButtonInterruptConfig {
+    priority: u8,
+    buttons: ButtonState,
+}
+
+impl ButtonInterruptConfig {
+    /// Create a configuration enabling every button at priority 1.
+    pub const fn new() -> Self {
+        Self {
+            priority: 1,
+            buttons: ButtonState::ALL,
+        }
+    }
+
+    /// Set the priority of the interrupts, as passed to
+    /// `NVIC::set_priority`.
+    pub fn priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Set which buttons have their interrupts enabled: a button in the set
+    /// has its interrupt unmasked, and the rest are left as they are. Up and
+    /// `TopRight` share a line, so either enables both.
+    pub fn buttons(mut self, buttons: ButtonState) -> Self {
+        self.buttons = buttons;
+        self
+    }
+}
+
+impl Default for ButtonInterruptConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Return the EIC interrupt triggered by the given button.
+fn line(button: Button) -> interrupt {
+    match button {
+        Button::Down => interrupt::EIC_EXTINT_3,
+        Button::Right => interrupt::EIC_EXTINT_4,
+        Button::Click => interrupt::EIC_EXTINT_5,
+        Button::Left => interrupt::EIC_EXTINT_7,
+        Button::Up | Button::TopRight => interrupt::EIC_EXTINT_10,
+        Button::TopMiddle => interrupt::EIC_EXTINT_11,
+        Button::TopLeft => interrupt::EIC_EXTINT_12,
+    }
+}
+
 impl ButtonController {
+    /// Return a snapshot of which buttons are pressed, as of the most recently
+    /// handled interrupt.
+    pub fn state(&self) -> ButtonState {
+        self.state
+    }
+
+    /// Enable the interrupts for every button at priority 1.
     pub fn enable(&self, nvic: &mut NVIC) {
-        unsafe {
-            nvic.set_priority(interrupt::EIC_EXTINT_10, 1);
-            NVIC::unmask(interrupt::EIC_EXTINT_10);
-            nvic.set_priority(interrupt::EIC_EXTINT_11, 1);
-            NVIC::unmask(interrupt::EIC_EXTINT_11);
-            nvic.set_priority(interrupt::EIC_EXTINT_12, 1);
-            NVIC::unmask(interrupt::EIC_EXTINT_12);
-            nvic.set_priority(interrupt::EIC_EXTINT_3, 1);
-            NVIC::unmask(interrupt::EIC_EXTINT_3);
-            nvic.set_priority(interrupt::EIC_EXTINT_4, 1);
-            NVIC::unmask(interrupt::EIC_EXTINT_4);
-            nvic.set_priority(interrupt::EIC_EXTINT_5, 1);
-            NVIC::unmask(interrupt::EIC_EXTINT_5);
-            nvic.set_priority(interrupt::EIC_EXTINT_7, 1);
-            NVIC::unmask(interrupt::EIC_EXTINT_7);
+        self.enable_with(nvic, &ButtonInterruptConfig::new());
+    }
+
+    /// Set the priority of, and enable, the interrupts for the buttons
+    /// selected by `config`.
+    pub fn enable_with(&self, nvic: &mut NVIC, config: &ButtonInterruptConfig) {
+        for button in config.buttons.iter() {
+            unsafe {
+                nvic.set_priority(line(button), config.priority);
+                NVIC::unmask(line(button));
+            }
+        }
+    }
+
+    /// Disable the interrupts for the given buttons, eg. before entering a
+    /// low-power mode. Up and `button1` share an interrupt, so disabling
+    /// either disables both.
+    pub fn disable_buttons(&self, buttons: ButtonState) {
+        for button in buttons.iter() {
+            NVIC::mask(line(button));
+        }
+    }
+
+    /// Re-enable the interrupts for the given buttons, at the priority they
+    /// were previously configured with. Up and `button1` share an interrupt,
+    /// so enabling either enables both.
+    pub fn enable_buttons(&self, buttons: ButtonState) {
+        for button in buttons.iter() {
+            unsafe { NVIC::unmask(line(button)) };
+        }
+    }
+
+    /// Return the handler for the given interrupt, or `None` if it is not
+    /// used by the buttons.
+    pub fn handler(irq: interrupt) -> Option<ButtonHandler> {
+        let handler: ButtonHandler = match irq {
+            interrupt::EIC_EXTINT_3 => Self::interrupt_extint3,
+            interrupt::EIC_EXTINT_4 => Self::interrupt_extint4,
+            interrupt::EIC_EXTINT_5 => Self::interrupt_extint5,
+            interrupt::EIC_EXTINT_7 => Self::interrupt_extint7,
+            interrupt::EIC_EXTINT_10 => Self::interrupt_extint10,
+            interrupt::EIC_EXTINT_11 => Self::interrupt_extint11,
+            interrupt::EIC_EXTINT_12 => Self::interrupt_extint12,
+            _ => return None,
+        };
+
+        Some(handler)
+    }
+
+    /// Handle the given interrupt, and return all of its pending events.
+    /// Interrupts not used by the buttons produce no events.
+    pub fn handle(&mut self, irq: interrupt) -> ButtonEvents {
+        let mut events = ButtonEvents::new();
+        if let Some(handler) = Self::handler(irq) {
+            self.drain(handler, &mut events);
+        }
+
+        events
+    }
+
+    /// Check every interrupt used by the buttons, and return all pending
+    /// events.
+    ///
+    /// Unlike the `button_interrupt!` macro, this needs no `static mut`, so it
+    /// can be called with the controller held in a RTIC resource or a
+    /// `cortex_m::interrupt::Mutex`, eg. from a single handler shared by all
+    /// of the EIC interrupts.
+    pub fn handle_any(&mut self) -> ButtonEvents {
+        let mut events = ButtonEvents::new();
+        self.drain(Self::interrupt_extint3, &mut events);
+        self.drain(Self::interrupt_extint4, &mut events);
+        self.drain(Self::interrupt_extint5, &mut events);
+        self.drain(Self::interrupt_extint7, &mut events);
+        self.drain(Self::interrupt_extint10, &mut events);
+        self.drain(Self::interrupt_extint11, &mut events);
+        self.drain(Self::interrupt_extint12, &mut events);
+
+        events
+    }
+
+    fn drain(&mut self, handler: ButtonHandler, events: &mut ButtonEvents) {
+        while let Some(event) = handler(self) {
+            events.push(event).ok();
         }
     }
 
@@ -167,10 +464,44 @@ impl ButtonController {
     isr!(interrupt_extint4, Button::Right, y);
     isr!(interrupt_extint5, Button::Click, z);
     isr!(interrupt_extint7, Button::Left, b);
-    isr!(interrupt_extint10, Button::Up, u);
-    // isr!(interrupt_extint10, Button::TopRight, b1);
     isr!(interrupt_extint11, Button::TopMiddle, b2);
     isr!(interrupt_extint12, Button::TopLeft, b3);
+
+    /// Handle EXTINT line 10, which is shared by joystick up and (if
+    /// enabled) `button1`.
+    ///
+    /// Should both buttons change at once, the second event is returned by
+    /// the following call, so this should be called until it returns `None`.
+    pub fn interrupt_extint10(&mut self) -> Option<ButtonEvent> {
+        let event = self.extint10_event();
+        if let Some(event) = event {
+            self.state.set(event.button, event.down);
+        }
+        event
+    }
+
+    fn extint10_event(&mut self) -> Option<ButtonEvent> {
+        if let Some(event) = self.line10.take_pending() {
+            return Some(event);
+        }
+
+        if !self.u.is_interrupt() {
+            return None;
+        }
+        self.u.clear_interrupt();
+
+        let up = !self.u.state();
+        match self.b1.as_mut() {
+            Some(b1) => {
+                let top_right = !b1.state();
+                self.line10.update(up, top_right)
+            }
+            None => Some(ButtonEvent {
+                button: Button::Up,
+                down: up,
+            }),
+        }
+    }
 }
 
 #[macro_export]
@@ -186,7 +517,7 @@ macro_rules! button_interrupt {
                 fn $Interrupt() {
                     disable_interrupts(|cs| unsafe {
                         $controller.as_mut().map(|ctrlr| {
-                            if let Some(event) = ctrlr.$Handler() {
+                            while let Some(event) = ctrlr.$Handler() {
                                 $func_name(cs, event);
                             }
                         });
@@ -204,3 +535,65 @@ macro_rules! button_interrupt {
         _button_interrupt_handler!(EIC_EXTINT_12, interrupt_extint12);
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(button: Button, down: bool) -> Option<ButtonEvent> {
+        Some(ButtonEvent { button, down })
+    }
+
+    #[test]
+    fn up_alone() {
+        let mut line = SharedLine::new();
+        assert_eq!(line.update(true, false), event(Button::Up, true));
+        assert_eq!(line.take_pending(), None);
+        assert_eq!(line.update(false, false), event(Button::Up, false));
+        assert_eq!(line.take_pending(), None);
+    }
+
+    #[test]
+    fn top_right_alone() {
+        let mut line = SharedLine::new();
+        assert_eq!(line.update(false, true), event(Button::TopRight, true));
+        assert_eq!(line.take_pending(), None);
+        assert_eq!(line.update(false, false), event(Button::TopRight, false));
+        assert_eq!(line.take_pending(), None);
+    }
+
+    #[test]
+    fn both_together() {
+        let mut line = SharedLine::new();
+        assert_eq!(line.update(true, true), event(Button::Up, true));
+        assert_eq!(line.take_pending(), event(Button::TopRight, true));
+        assert_eq!(line.take_pending(), None);
+
+        // Releasing one leaves the other held.
+        assert_eq!(line.update(true, false), event(Button::TopRight, false));
+        assert_eq!(line.take_pending(), None);
+        assert_eq!(line.update(false, false), event(Button::Up, false));
+    }
+
+    #[test]
+    fn bounce_without_change() {
+        let mut line = SharedLine::new();
+        assert_eq!(line.update(false, false), None);
+        assert_eq!(line.take_pending(), None);
+
+        assert_eq!(line.update(true, false), event(Button::Up, true));
+        assert_eq!(line.update(true, false), None);
+        assert_eq!(line.update(true, false), None);
+        assert_eq!(line.take_pending(), None);
+    }
+
+    #[test]
+    fn pending_is_replaced() {
+        let mut line = SharedLine::default();
+        assert_eq!(line.update(true, true), event(Button::Up, true));
+        // The held event is dropped by the next update if not taken, since
+        // the state it reported is then superseded.
+        assert_eq!(line.update(true, true), None);
+        assert_eq!(line.take_pending(), None);
+    }
+}
@@ -0,0 +1,348 @@
+use heapless::consts::U16;
+use heapless::Vec;
+
+use super::{Button, ButtonEvent, BUTTON_COUNT};
+
+/// The kinds of gesture recognized by [`GestureRecognizer`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GestureKind {
+    /// The button was pressed.
+    Pressed,
+    /// The button was released.
+    Released,
+    /// The button was pressed and released before a long press or repeat was
+    /// recognized.
+    Click,
+    /// The button was clicked a second time within the double click window.
+    /// The first click is reported as a [`GestureKind::Click`].
+    DoubleClick,
+    /// The button has been held for the long press period. Reported once per
+    /// press; the subsequent release does not produce a click.
+    LongPress,
+    /// The button is still held after the repeat delay, and another repeat
+    /// interval has elapsed.
+    Repeat,
+}
+
+/// A gesture performed with a single button.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Gesture {
+    pub button: Button,
+    pub kind: GestureKind,
+}
+
+/// The gestures produced by a single call to [`GestureRecognizer::handle`] or
+/// [`GestureRecognizer::tick`].
+pub type Gestures = Vec<Gesture, U16>;
+
+/// Timing used to recognize gestures, in milliseconds.
+pub struct GestureConfig {
+    /// The maximum time between the release of one click and the press of
+    /// the next for the pair to count as a double click.
+    pub double_click_ms: u32,
+    /// How long a button must be held to count as a long press.
+    pub long_press_ms: u32,
+    /// How long a button must be held before it starts repeating.
+    pub repeat_delay_ms: u32,
+    /// The time between repeats once repeating; `0` disables repeating.
+    pub repeat_interval_ms: u32,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            double_click_ms: 300,
+            long_press_ms: 800,
+            repeat_delay_ms: 500,
+            repeat_interval_ms: 100,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Tracker {
+    down: bool,
+    pressed_at: u32,
+    released_at: Option<u32>,
+    double_pending: bool,
+    long_pressed: bool,
+    repeats: u32,
+}
+
+impl Tracker {
+    const fn new() -> Self {
+        Self {
+            down: false,
+            pressed_at: 0,
+            released_at: None,
+            double_pending: false,
+            long_pressed: false,
+            repeats: 0,
+        }
+    }
+}
+
+/// Recognizes clicks, double clicks, long presses and auto-repeat from a
+/// stream of [`ButtonEvent`]s.
+///
+/// Time is supplied by the caller in milliseconds, and is allowed to wrap
+/// around. Events are passed to [`GestureRecognizer::handle`] as they arrive,
+/// and [`GestureRecognizer::tick`] should be called regularly (eg. on every
+/// iteration of the main loop) to recognize long presses and repeats.
+pub struct GestureRecognizer {
+    config: GestureConfig,
+    trackers: [Tracker; BUTTON_COUNT],
+}
+
+impl GestureRecognizer {
+    /// Create a new recognizer with all buttons released.
+    pub fn new(config: GestureConfig) -> Self {
+        Self {
+            config,
+            trackers: [Tracker::new(); BUTTON_COUNT],
+        }
+    }
+
+    /// Handle a button event which occurred at `now_ms`, and return the
+    /// resulting gestures.
+    pub fn handle(&mut self, event: &ButtonEvent, now_ms: u32) -> Gestures {
+        let mut gestures = Gestures::new();
+        let index = event.button.index();
+        let t = &mut self.trackers[index];
+
+        // Ignore repeated reports of the same level.
+        if event.down == t.down {
+            return gestures;
+        }
+        t.down = event.down;
+
+        if event.down {
+            t.double_pending = match t.released_at {
+                Some(at) => now_ms.wrapping_sub(at) <= self.config.double_click_ms,
+                None => false,
+            };
+            t.pressed_at = now_ms;
+            t.long_pressed = false;
+            t.repeats = 0;
+            push(&mut gestures, index, GestureKind::Pressed);
+        } else {
+            push(&mut gestures, index, GestureKind::Released);
+
+            if t.long_pressed || t.repeats > 0 {
+                t.released_at = None;
+            } else if t.double_pending {
+                // A third click starts over, rather than being another double
+                // click.
+                t.released_at = None;
+                push(&mut gestures, index, GestureKind::DoubleClick);
+            } else {
+                t.released_at = Some(now_ms);
+                push(&mut gestures, index, GestureKind::Click);
+            }
+            t.double_pending = false;
+        }
+
+        gestures
+    }
+
+    /// Check the held buttons at `now_ms`, and return any long presses or
+    /// repeats which are due.
+    pub fn tick(&mut self, now_ms: u32) -> Gestures {
+        let mut gestures = Gestures::new();
+        let config = &self.config;
+
+        for (index, t) in self.trackers.iter_mut().enumerate() {
+            if !t.down {
+                continue;
+            }
+            let held = now_ms.wrapping_sub(t.pressed_at);
+
+            if !t.long_pressed && held >= config.long_press_ms {
+                t.long_pressed = true;
+                push(&mut gestures, index, GestureKind::LongPress);
+            }
+
+            if config.repeat_interval_ms > 0 && held >= config.repeat_delay_ms {
+                // Report at most one repeat per tick, but skip any which were
+                // missed so repeats don't bunch up after a slow tick.
+                let due = (held - config.repeat_delay_ms) / config.repeat_interval_ms + 1;
+                if due > t.repeats {
+                    t.repeats = due;
+                    push(&mut gestures, index, GestureKind::Repeat);
+                }
+            }
+        }
+
+        gestures
+    }
+
+    /// Return whether the given button is currently held.
+    pub fn is_held(&self, button: Button) -> bool {
+        self.trackers[button.index()].down
+    }
+}
+
+fn push(gestures: &mut Gestures, index: usize, kind: GestureKind) {
+    gestures
+        .push(Gesture {
+            button: Button::from_index(index),
+            kind,
+        })
+        .ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use GestureKind::*;
+
+    fn press(button: Button, down: bool) -> ButtonEvent {
+        ButtonEvent { button, down }
+    }
+
+    fn kinds(gestures: Gestures) -> Vec<GestureKind, U16> {
+        gestures.iter().map(|g| g.kind).collect()
+    }
+
+    // Click and release `button` at the given times.
+    fn click(r: &mut GestureRecognizer, button: Button, down: u32, up: u32) -> Gestures {
+        r.handle(&press(button, true), down);
+        r.handle(&press(button, false), up)
+    }
+
+    #[test]
+    fn single_click() {
+        let mut r = GestureRecognizer::new(GestureConfig::default());
+        assert_eq!(kinds(r.handle(&press(Button::Click, true), 0)), [Pressed]);
+        assert!(r.is_held(Button::Click));
+        assert!(r.tick(100).is_empty());
+        assert_eq!(
+            kinds(r.handle(&press(Button::Click, false), 120)),
+            [Released, Click]
+        );
+        assert!(!r.is_held(Button::Click));
+    }
+
+    #[test]
+    fn double_click() {
+        let mut r = GestureRecognizer::new(GestureConfig::default());
+        assert_eq!(
+            kinds(click(&mut r, Button::TopLeft, 0, 50)),
+            [Released, Click]
+        );
+        assert_eq!(
+            kinds(click(&mut r, Button::TopLeft, 350, 400)),
+            [Released, DoubleClick]
+        );
+
+        // A third click starts over.
+        assert_eq!(
+            kinds(click(&mut r, Button::TopLeft, 450, 500)),
+            [Released, Click]
+        );
+    }
+
+    #[test]
+    fn slow_second_click() {
+        let mut r = GestureRecognizer::new(GestureConfig::default());
+        click(&mut r, Button::TopLeft, 0, 50);
+        assert_eq!(
+            kinds(click(&mut r, Button::TopLeft, 351, 400)),
+            [Released, Click]
+        );
+    }
+
+    #[test]
+    fn long_press() {
+        let config = GestureConfig {
+            repeat_interval_ms: 0,
+            ..GestureConfig::default()
+        };
+        let mut r = GestureRecognizer::new(config);
+        r.handle(&press(Button::Down, true), 1000);
+        assert!(r.tick(1799).is_empty());
+        assert_eq!(kinds(r.tick(1800)), [LongPress]);
+        assert!(r.tick(2500).is_empty());
+
+        // The release after a long press is not a click.
+        assert_eq!(
+            kinds(r.handle(&press(Button::Down, false), 2600)),
+            [Released]
+        );
+    }
+
+    #[test]
+    fn repeat() {
+        let config = GestureConfig {
+            long_press_ms: 10_000,
+            ..GestureConfig::default()
+        };
+        let mut r = GestureRecognizer::new(config);
+        r.handle(&press(Button::Right, true), 0);
+        assert!(r.tick(499).is_empty());
+        assert_eq!(kinds(r.tick(500)), [Repeat]);
+        assert!(r.tick(599).is_empty());
+        assert_eq!(kinds(r.tick(600)), [Repeat]);
+
+        // Missed repeats are skipped rather than bunched up.
+        assert_eq!(kinds(r.tick(950)), [Repeat]);
+        assert!(r.tick(999).is_empty());
+        assert_eq!(kinds(r.tick(1000)), [Repeat]);
+
+        assert_eq!(
+            kinds(r.handle(&press(Button::Right, false), 1050)),
+            [Released]
+        );
+    }
+
+    #[test]
+    fn interleaved_buttons() {
+        let mut r = GestureRecognizer::new(GestureConfig::default());
+        r.handle(&press(Button::Up, true), 0);
+        r.handle(&press(Button::Left, true), 10);
+        assert_eq!(
+            &r.handle(&press(Button::Up, false), 20)[..],
+            [
+                Gesture {
+                    button: Button::Up,
+                    kind: Released
+                },
+                Gesture {
+                    button: Button::Up,
+                    kind: Click
+                },
+            ]
+        );
+
+        // Left is held throughout, while Up is clicked twice.
+        assert_eq!(
+            kinds(click(&mut r, Button::Up, 100, 150)),
+            [Released, DoubleClick]
+        );
+        let held = r.tick(810);
+        assert_eq!(
+            &held[..],
+            [
+                Gesture {
+                    button: Button::Left,
+                    kind: LongPress
+                },
+                Gesture {
+                    button: Button::Left,
+                    kind: Repeat
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn time_wraps_around() {
+        let mut r = GestureRecognizer::new(GestureConfig::default());
+        click(&mut r, Button::Click, u32::MAX - 100, u32::MAX - 50);
+        assert_eq!(
+            kinds(click(&mut r, Button::Click, 100, 150)),
+            [Released, DoubleClick]
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use super::{Button, ButtonEvent};
+
+/// The direction the joystick is pushed in, including diagonals.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Center,
+    Up,
+    Down,
+    Left,
+    Right,
+    UpLeft,
+    UpRight,
+    DownLeft,
+    DownRight,
+}
+
+impl Direction {
+    /// Combine the state of the four direction switches. Opposing switches
+    /// cancel each other out.
+    fn from_switches(up: bool, down: bool, left: bool, right: bool) -> Self {
+        let vertical = up as i8 - down as i8;
+        let horizontal = right as i8 - left as i8;
+
+        match (vertical, horizontal) {
+            (1, -1) => Direction::UpLeft,
+            (1, 0) => Direction::Up,
+            (1, 1) => Direction::UpRight,
+            (0, -1) => Direction::Left,
+            (0, 1) => Direction::Right,
+            (-1, -1) => Direction::DownLeft,
+            (-1, 0) => Direction::Down,
+            (-1, 1) => Direction::DownRight,
+            _ => Direction::Center,
+        }
+    }
+}
+
+/// A change in the combined state of the joystick.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JoystickEvent {
+    /// The direction after the change.
+    pub direction: Direction,
+    /// Whether the joystick is clicked after the change.
+    pub clicked: bool,
+}
+
+// The joystick's switches, in the order used to index `Joystick::held`.
+static SWITCHES: [Button; 5] = [
+    Button::Up,
+    Button::Down,
+    Button::Left,
+    Button::Right,
+    Button::Click,
+];
+
+/// Tracks the five joystick switches as a single D-pad.
+///
+/// Feed it every [`ButtonEvent`]; events for the buttons along the top of the
+/// device are ignored.
+#[derive(Clone, Copy, Debug)]
+pub struct Joystick {
+    held: [bool; 5],
+}
+
+impl Joystick {
+    /// Create a new joystick in the center, not clicked.
+    pub const fn new() -> Self {
+        Self { held: [false; 5] }
+    }
+
+    /// Update the joystick with a button event. Return the new combined state
+    /// if the event changed it, or `None` otherwise.
+    pub fn update(&mut self, event: &ButtonEvent) -> Option<JoystickEvent> {
+        let index = switch_index(event.button)?;
+        if self.held[index] == event.down {
+            return None;
+        }
+        self.held[index] = event.down;
+
+        Some(JoystickEvent {
+            direction: self.direction(),
+            clicked: self.is_clicked(),
+        })
+    }
+
+    /// Return the direction the joystick is currently pushed in.
+    pub fn direction(&self) -> Direction {
+        let [up, down, left, right, _] = self.held;
+        Direction::from_switches(up, down, left, right)
+    }
+
+    /// Return whether the joystick is currently clicked.
+    pub fn is_clicked(&self) -> bool {
+        self.held[4]
+    }
+
+    /// Return the direction of a chord, ie. the joystick being clicked while
+    /// pushed in some direction, or `None` if no chord is held.
+    pub fn chord(&self) -> Option<Direction> {
+        match self.direction() {
+            Direction::Center => None,
+            _ if !self.is_clicked() => None,
+            direction => Some(direction),
+        }
+    }
+
+    /// Return whether the given switch is held. Always `false` for the
+    /// buttons along the top of the device.
+    pub fn is_held(&self, button: Button) -> bool {
+        match switch_index(button) {
+            Some(index) => self.held[index],
+            None => false,
+        }
+    }
+
+    /// Return an iterator over the switches which are currently held.
+    pub fn held(&self) -> impl Iterator<Item = Button> + '_ {
+        self.held
+            .iter()
+            .zip(SWITCHES.iter())
+            .filter(|(&held, _)| held)
+            .map(|(_, &button)| button)
+    }
+}
+
+impl Default for Joystick {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn switch_index(button: Button) -> Option<usize> {
+    match button {
+        Button::Up => Some(0),
+        Button::Down => Some(1),
+        Button::Left => Some(2),
+        Button::Right => Some(3),
+        Button::Click => Some(4),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(button: Button, down: bool) -> ButtonEvent {
+        ButtonEvent { button, down }
+    }
+
+    #[test]
+    fn diagonals() {
+        let cases = [
+            ((true, false, true, false), Direction::UpLeft),
+            ((true, false, false, true), Direction::UpRight),
+            ((false, true, true, false), Direction::DownLeft),
+            ((false, true, false, true), Direction::DownRight),
+        ];
+        for &((up, down, left, right), direction) in cases.iter() {
+            assert_eq!(Direction::from_switches(up, down, left, right), direction);
+        }
+    }
+
+    #[test]
+    fn opposites_cancel() {
+        assert_eq!(
+            Direction::from_switches(true, true, false, false),
+            Direction::Center
+        );
+        assert_eq!(
+            Direction::from_switches(false, false, true, true),
+            Direction::Center
+        );
+        assert_eq!(
+            Direction::from_switches(true, true, false, true),
+            Direction::Right
+        );
+        assert_eq!(
+            Direction::from_switches(true, true, true, true),
+            Direction::Center
+        );
+    }
+
+    #[test]
+    fn events_follow_switches() {
+        let mut joystick = Joystick::default();
+        assert_eq!(
+            joystick.update(&press(Button::Up, true)),
+            Some(JoystickEvent {
+                direction: Direction::Up,
+                clicked: false,
+            })
+        );
+        assert_eq!(
+            joystick
+                .update(&press(Button::Left, true))
+                .map(|e| e.direction),
+            Some(Direction::UpLeft)
+        );
+
+        // Repeated levels and the top buttons change nothing.
+        assert_eq!(joystick.update(&press(Button::Left, true)), None);
+        assert_eq!(joystick.update(&press(Button::TopLeft, true)), None);
+        assert!(!joystick.is_held(Button::TopLeft));
+
+        assert!(joystick
+            .held()
+            .eq([Button::Up, Button::Left].iter().copied()));
+    }
+
+    #[test]
+    fn chord() {
+        let mut joystick = Joystick::new();
+        joystick.update(&press(Button::Click, true));
+        assert_eq!(joystick.chord(), None);
+
+        assert_eq!(
+            joystick.update(&press(Button::Up, true)),
+            Some(JoystickEvent {
+                direction: Direction::Up,
+                clicked: true,
+            })
+        );
+        assert_eq!(joystick.chord(), Some(Direction::Up));
+
+        joystick.update(&press(Button::Click, false));
+        assert_eq!(joystick.chord(), None);
+        assert_eq!(joystick.direction(), Direction::Up);
+    }
+}
@@ -0,0 +1,190 @@
+use atsamd_hal::hal::digital::v2::InputPin;
+
+use super::{Button, ButtonEvent, ButtonEvents, ButtonPins, ButtonState, BUTTON_COUNT};
+
+/// Software debouncing and edge detection for the buttons and joystick.
+///
+/// A change in level is only reported once the input has held its new level
+/// for the configured debounce period. Time is supplied by the caller in
+/// milliseconds, and is allowed to wrap around.
+pub struct Debouncer {
+    debounce_ms: u32,
+    stable: ButtonState,
+    candidate: [bool; BUTTON_COUNT],
+    since: [u32; BUTTON_COUNT],
+}
+
+impl Debouncer {
+    /// Create a new debouncer with all inputs released.
+    pub const fn new(debounce_ms: u32) -> Self {
+        Self {
+            debounce_ms,
+            stable: ButtonState::new(),
+            candidate: [false; BUTTON_COUNT],
+            since: [0; BUTTON_COUNT],
+        }
+    }
+
+    /// Feed the debouncer the current level of every input, `true` meaning
+    /// pressed and ordered as the pins in [`ButtonPins`], and return an event
+    /// for each input whose debounced state changed.
+    pub fn update(&mut self, now_ms: u32, levels: [bool; BUTTON_COUNT]) -> ButtonEvents {
+        let mut events = ButtonEvents::new();
+
+        for (i, &level) in levels.iter().enumerate() {
+            let button = Button::from_index(i);
+            if level == self.stable.is_pressed(button) {
+                self.candidate[i] = level;
+                continue;
+            }
+
+            if level != self.candidate[i] {
+                self.candidate[i] = level;
+                self.since[i] = now_ms;
+            }
+
+            if now_ms.wrapping_sub(self.since[i]) >= self.debounce_ms {
+                self.stable.set(button, level);
+                events
+                    .push(ButtonEvent {
+                        button,
+                        down: level,
+                    })
+                    .ok();
+            }
+        }
+
+        events
+    }
+
+    /// Return a snapshot of which buttons are pressed, once debounced.
+    pub fn state(&self) -> ButtonState {
+        self.stable
+    }
+}
+
+/// Reads the buttons and joystick as plain GPIO inputs, without using the
+/// EIC or any interrupts.
+pub struct ButtonPoller {
+    pins: ButtonPins,
+    debouncer: Debouncer,
+}
+
+impl ButtonPins {
+    /// Use the buttons and joystick as polled inputs, debounced in software
+    /// for the given number of milliseconds.
+    pub fn into_poller(self, debounce_ms: u32) -> ButtonPoller {
+        ButtonPoller {
+            pins: self,
+            debouncer: Debouncer::new(debounce_ms),
+        }
+    }
+}
+
+impl ButtonPoller {
+    /// Sample every input and return the debounced events since the previous
+    /// call. `now_ms` is the current time in milliseconds.
+    pub fn poll(&mut self, now_ms: u32) -> ButtonEvents {
+        let levels = self.sample();
+        self.debouncer.update(now_ms, levels)
+    }
+
+    /// Return a snapshot of which buttons are pressed, as of the most recent
+    /// call to [`ButtonPoller::poll`].
+    pub fn state(&self) -> ButtonState {
+        self.debouncer.state()
+    }
+
+    /// Release the pins, so they can be configured for interrupts instead.
+    pub fn release(self) -> ButtonPins {
+        self.pins
+    }
+
+    // The buttons and switches are active low; read each in the order
+    // expected by `Button::from_index`.
+    fn sample(&self) -> [bool; BUTTON_COUNT] {
+        let p = &self.pins;
+        [
+            p.button1.is_low().unwrap_or(false),
+            p.button2.is_low().unwrap_or(false),
+            p.button3.is_low().unwrap_or(false),
+            p.switch_x.is_low().unwrap_or(false),
+            p.switch_y.is_low().unwrap_or(false),
+            p.switch_z.is_low().unwrap_or(false),
+            p.switch_u.is_low().unwrap_or(false),
+            p.switch_b.is_low().unwrap_or(false),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEBOUNCE_MS: u32 = 20;
+
+    fn levels(button: Button, down: bool) -> [bool; BUTTON_COUNT] {
+        let mut levels = [false; BUTTON_COUNT];
+        levels[button.index()] = down;
+        levels
+    }
+
+    fn event(button: Button, down: bool) -> ButtonEvent {
+        ButtonEvent { button, down }
+    }
+
+    #[test]
+    fn stable_press_and_release() {
+        let mut debouncer = Debouncer::new(DEBOUNCE_MS);
+        let down = levels(Button::Click, true);
+
+        assert!(debouncer.update(100, down).is_empty());
+        assert!(debouncer.update(119, down).is_empty());
+        assert_eq!(debouncer.update(120, down), [event(Button::Click, true)]);
+        assert!(debouncer.update(121, down).is_empty());
+        assert!(debouncer.state().is_pressed(Button::Click));
+
+        let up = [false; BUTTON_COUNT];
+        assert!(debouncer.update(200, up).is_empty());
+        assert_eq!(debouncer.update(220, up), [event(Button::Click, false)]);
+        assert!(!debouncer.state().is_pressed(Button::Click));
+    }
+
+    #[test]
+    fn bounce_is_rejected() {
+        let mut debouncer = Debouncer::new(DEBOUNCE_MS);
+        let down = levels(Button::TopLeft, true);
+        let up = [false; BUTTON_COUNT];
+
+        // Each bounce back to the stable level restarts the period.
+        for t in (0..100).step_by(10) {
+            let sample = if t % 20 == 0 { down } else { up };
+            assert!(debouncer.update(t, sample).is_empty());
+        }
+        assert!(debouncer.update(110, down).is_empty());
+        assert!(debouncer.update(129, down).is_empty());
+        assert_eq!(debouncer.update(130, down), [event(Button::TopLeft, true)]);
+    }
+
+    #[test]
+    fn time_wraps_around() {
+        let mut debouncer = Debouncer::new(DEBOUNCE_MS);
+        let down = levels(Button::Up, true);
+
+        assert!(debouncer.update(u32::MAX - 5, down).is_empty());
+        assert!(debouncer.update(13, down).is_empty());
+        assert_eq!(debouncer.update(14, down), [event(Button::Up, true)]);
+    }
+
+    #[test]
+    fn buttons_are_independent() {
+        let mut debouncer = Debouncer::new(DEBOUNCE_MS);
+        let mut both = levels(Button::Left, true);
+        both[Button::Right.index()] = true;
+
+        assert!(debouncer.update(0, levels(Button::Left, true)).is_empty());
+        assert!(debouncer.update(10, both).is_empty());
+        assert_eq!(debouncer.update(20, both), [event(Button::Left, true)]);
+        assert_eq!(debouncer.update(30, both), [event(Button::Right, true)]);
+    }
+}
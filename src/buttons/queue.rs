@@ -0,0 +1,176 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use heapless::consts::U16;
+use heapless::spsc::{Consumer, Producer, Queue};
+
+use super::ButtonEvent;
+
+/// A lock-free queue for passing [`ButtonEvent`]s from the button interrupt
+/// handlers to the main loop.
+///
+/// The queue holds 16 events. Events which arrive while it is full are
+/// dropped and counted, rather than overwriting events which have not yet
+/// been read.
+///
+/// The queue is usually placed in a `static`, and split once into a producer
+/// half for the interrupt handlers and a consumer half for the main loop.
+pub struct ButtonEventQueue {
+    queue: Queue<ButtonEvent, U16>,
+    dropped: AtomicUsize,
+}
+
+impl ButtonEventQueue {
+    /// Create a new, empty queue.
+    pub const fn new() -> Self {
+        Self {
+            queue: Queue(heapless::i::Queue::new()),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Split the queue into its producer and consumer halves.
+    pub fn split(&mut self) -> (ButtonEventProducer<'_>, ButtonEventConsumer<'_>) {
+        let Self { queue, dropped } = self;
+        let (producer, consumer) = queue.split();
+
+        (
+            ButtonEventProducer { producer, dropped },
+            ButtonEventConsumer { consumer, dropped },
+        )
+    }
+}
+
+impl Default for ButtonEventQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The producer half of a [`ButtonEventQueue`], used from the interrupt
+/// handlers.
+pub struct ButtonEventProducer<'a> {
+    producer: Producer<'a, ButtonEvent, U16>,
+    dropped: &'a AtomicUsize,
+}
+
+impl<'a> ButtonEventProducer<'a> {
+    /// Add an event to the queue. If the queue is full the event is dropped,
+    /// counted, and returned.
+    pub fn enqueue(&mut self, event: ButtonEvent) -> Result<(), ButtonEvent> {
+        if let Err(event) = self.producer.enqueue(event) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return Err(event);
+        }
+
+        Ok(())
+    }
+
+    /// Add each of the given events to the queue, eg. those returned by
+    /// [`ButtonController::handle_any`](super::ButtonController::handle_any).
+    /// Return the number of events which were dropped.
+    pub fn enqueue_all<I>(&mut self, events: I) -> usize
+    where
+        I: IntoIterator<Item = ButtonEvent>,
+    {
+        let mut dropped = 0;
+        for event in events {
+            if self.enqueue(event).is_err() {
+                dropped += 1;
+            }
+        }
+
+        dropped
+    }
+
+    /// Return whether there is room for at least one more event.
+    pub fn ready(&self) -> bool {
+        self.producer.ready()
+    }
+}
+
+/// The consumer half of a [`ButtonEventQueue`], used from the main loop.
+pub struct ButtonEventConsumer<'a> {
+    consumer: Consumer<'a, ButtonEvent, U16>,
+    dropped: &'a AtomicUsize,
+}
+
+impl<'a> ButtonEventConsumer<'a> {
+    /// Remove the oldest event from the queue, if any.
+    pub fn dequeue(&mut self) -> Option<ButtonEvent> {
+        self.consumer.dequeue()
+    }
+
+    /// Return whether there is at least one event in the queue.
+    pub fn ready(&self) -> bool {
+        self.consumer.ready()
+    }
+
+    /// Return the number of events dropped because the queue was full.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Return the number of events dropped because the queue was full, and
+    /// reset the count to zero.
+    pub fn take_dropped(&mut self) -> usize {
+        self.dropped.swap(0, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Button;
+
+    fn event(i: usize) -> ButtonEvent {
+        ButtonEvent {
+            button: Button::from_index(i % 8),
+            down: i % 16 < 8,
+        }
+    }
+
+    #[test]
+    fn fifo_order() {
+        let mut queue = ButtonEventQueue::new();
+        let (mut producer, mut consumer) = queue.split();
+        assert!(!consumer.ready());
+
+        for i in 0..10 {
+            producer.enqueue(event(i)).unwrap();
+        }
+        for i in 0..10 {
+            assert_eq!(consumer.dequeue(), Some(event(i)));
+        }
+        assert_eq!(consumer.dequeue(), None);
+        assert_eq!(consumer.dropped(), 0);
+    }
+
+    #[test]
+    fn overflow_is_counted() {
+        let mut queue = ButtonEventQueue::default();
+        let (mut producer, mut consumer) = queue.split();
+
+        for i in 0..16 {
+            producer.enqueue(event(i)).unwrap();
+        }
+        assert!(!producer.ready());
+        assert_eq!(producer.enqueue(event(16)), Err(event(16)));
+        assert_eq!(producer.enqueue_all((17..20).map(event)), 3);
+        assert_eq!(consumer.dropped(), 4);
+
+        // The events already queued are kept, in order.
+        for i in 0..16 {
+            assert_eq!(consumer.dequeue(), Some(event(i)));
+        }
+        assert_eq!(consumer.dequeue(), None);
+
+        assert_eq!(consumer.take_dropped(), 4);
+        assert_eq!(consumer.dropped(), 0);
+
+        // Once there is room again, events are accepted.
+        assert_eq!(producer.enqueue_all((20..22).map(event)), 0);
+        assert_eq!(consumer.dequeue(), Some(event(20)));
+        assert_eq!(consumer.dequeue(), Some(event(21)));
+    }
+}
@@ -0,0 +1,171 @@
+use super::{Button, BUTTON_COUNT};
+
+/// A set of buttons stored as a bitset, most often a snapshot of which buttons
+/// are pressed.
+///
+/// Comparing two snapshots gives the buttons which were pressed or released
+/// in between.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ButtonState(u8);
+
+impl ButtonState {
+    /// Every button.
+    pub const ALL: ButtonState = ButtonState(0xff);
+
+    /// The three buttons along the top of the device.
+    pub const TOP: ButtonState = ButtonState(0b0000_0111);
+
+    /// The five joystick switches.
+    pub const JOYSTICK: ButtonState = ButtonState(0b1111_1000);
+
+    /// Create a snapshot with no buttons pressed.
+    pub const fn new() -> Self {
+        Self(0)
+    }
+
+    /// Create a snapshot from its raw bits, one per button, with bit `n`
+    /// corresponding to the `n`th pin of [`ButtonPins`](super::ButtonPins).
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits)
+    }
+
+    /// Return the raw bits of the snapshot.
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Return whether the given button is pressed.
+    pub fn is_pressed(self, button: Button) -> bool {
+        self.0 & mask(button) != 0
+    }
+
+    /// Mark the given button as pressed or released.
+    pub fn set(&mut self, button: Button, pressed: bool) {
+        if pressed {
+            self.0 |= mask(button);
+        } else {
+            self.0 &= !mask(button);
+        }
+    }
+
+    /// Return whether no buttons are pressed.
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Return the buttons which are pressed in this snapshot, but were not in
+    /// `previous`.
+    pub fn just_pressed(self, previous: ButtonState) -> ButtonState {
+        Self(self.0 & !previous.0)
+    }
+
+    /// Return the buttons which were pressed in `previous`, but are not in
+    /// this snapshot.
+    pub fn just_released(self, previous: ButtonState) -> ButtonState {
+        Self(previous.0 & !self.0)
+    }
+
+    /// Return an iterator over the pressed buttons.
+    pub fn iter(self) -> impl Iterator<Item = Button> {
+        (0..BUTTON_COUNT)
+            .map(Button::from_index)
+            .filter(move |&button| self.is_pressed(button))
+    }
+}
+
+fn mask(button: Button) -> u8 {
+    1 << button.index()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use heapless::consts::U8;
+    use heapless::Vec;
+
+    fn buttons(state: ButtonState) -> Vec<Button, U8> {
+        state.iter().collect()
+    }
+
+    #[test]
+    fn set_and_clear() {
+        let mut state = ButtonState::new();
+        assert!(state.is_empty());
+
+        state.set(Button::Click, true);
+        state.set(Button::TopLeft, true);
+        assert!(state.is_pressed(Button::Click));
+        assert!(state.is_pressed(Button::TopLeft));
+        assert!(!state.is_pressed(Button::Up));
+        assert_eq!(state.bits(), 0b0010_0100);
+
+        // Setting a button twice, or clearing one which is not set, changes
+        // nothing.
+        state.set(Button::Click, true);
+        state.set(Button::Up, false);
+        assert_eq!(state.bits(), 0b0010_0100);
+
+        state.set(Button::Click, false);
+        assert!(!state.is_pressed(Button::Click));
+        state.set(Button::TopLeft, false);
+        assert!(state.is_empty());
+        assert_eq!(state, ButtonState::default());
+    }
+
+    #[test]
+    fn constants() {
+        assert_eq!(
+            buttons(ButtonState::TOP),
+            [Button::TopRight, Button::TopMiddle, Button::TopLeft]
+        );
+        assert_eq!(
+            buttons(ButtonState::JOYSTICK),
+            [
+                Button::Down,
+                Button::Right,
+                Button::Click,
+                Button::Up,
+                Button::Left
+            ]
+        );
+        assert_eq!(
+            ButtonState::TOP.bits() | ButtonState::JOYSTICK.bits(),
+            ButtonState::ALL.bits()
+        );
+        assert_eq!(ButtonState::TOP.bits() & ButtonState::JOYSTICK.bits(), 0);
+        assert_eq!(buttons(ButtonState::ALL).len(), BUTTON_COUNT);
+    }
+
+    #[test]
+    fn bits_follow_the_pin_order() {
+        for index in 0..BUTTON_COUNT {
+            let button = Button::from_index(index);
+            let state = ButtonState::from_bits(1 << index);
+            assert_eq!(buttons(state), [button]);
+        }
+    }
+
+    #[test]
+    fn iteration_is_in_pin_order() {
+        let mut state = ButtonState::new();
+        state.set(Button::Left, true);
+        state.set(Button::TopRight, true);
+        state.set(Button::Down, true);
+        assert_eq!(
+            buttons(state),
+            [Button::TopRight, Button::Down, Button::Left]
+        );
+        assert!(buttons(ButtonState::new()).is_empty());
+    }
+
+    #[test]
+    fn changes_between_snapshots() {
+        let previous = ButtonState::from_bits(0b0000_0011);
+        let current = ButtonState::from_bits(0b0000_0110);
+        assert_eq!(current.just_pressed(previous).bits(), 0b0000_0100);
+        assert_eq!(current.just_released(previous).bits(), 0b0000_0001);
+        assert!(current.just_pressed(current).is_empty());
+        assert!(current.just_released(current).is_empty());
+    }
+}
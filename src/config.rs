@@ -0,0 +1,1096 @@
+//! A wear-levelled key-value store for settings, on a [`Flash`] such as the
+//! `QSPIFlash`.
+//!
+//! The [`ConfigStore`] keeps a log of records in a ring of flash sectors.
+//! Setting a key appends a record holding its new value, and removing one
+//! appends a record marking it deleted; the newest record for a key wins.
+//! When the sector being written fills, the next sector in the ring is
+//! started, and the live records of the oldest sector are copied into it so
+//! that the oldest sector can be erased. Every sector is erased in turn, so
+//! wear is spread over the whole region.
+//!
+//! Each record carries a CRC, so a record torn by a power cut is ignored, and
+//! the store resumes writing in a fresh sector. Sectors record when their
+//! copying is complete, so an interrupted collection is either finished or
+//! undone when the store is next opened, and no value set before the power
+//! cut is lost.
+//!
+//! The store holds no index in memory, so each lookup reads the log from the
+//! newest sector back. Live data should stay well below the size of one
+//! sector fewer than the region, or the store becomes [`ConfigError::Full`].
+
+use crate::{Flash, FLASH_CAPACITY, FLASH_SECTOR_SIZE};
+
+mod value;
+
+pub use value::*;
+
+/// The longest key, in bytes.
+pub const MAX_KEY_LEN: usize = 32;
+
+/// The longest value, in bytes.
+pub const MAX_VALUE_LEN: usize = 256;
+
+/// The number of sectors used by the store by default.
+pub const DEFAULT_CONFIG_SECTORS: u32 = 16;
+
+/// The address of the store by default: the end of the QSPI flash.
+pub const DEFAULT_CONFIG_START: u32 = FLASH_CAPACITY - DEFAULT_CONFIG_SECTORS * FLASH_SECTOR_SIZE;
+
+// Each sector starts with a header: a magic number, the sequence number of
+// the sector and its complement, and a word which is cleared once the sector
+// is complete, after any records from the oldest sector are copied into it.
+const SECTOR_MAGIC: u32 = 0x5743_4647;
+const HEADER_SIZE: u32 = 16;
+const COMPLETE_OFFSET: u32 = 12;
+
+// Each record starts with the length of its key, its kind and the length of
+// its value, then a CRC-32 of those and the key and value which follow.
+const RECORD_HEADER_SIZE: usize = 8;
+const MAX_RECORD_LEN: usize = RECORD_HEADER_SIZE + MAX_KEY_LEN + MAX_VALUE_LEN;
+const KIND_DELETED: u8 = 0x00;
+const KIND_VALUE: u8 = 0x01;
+
+/// Errors which can occur while using the store.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigError<E> {
+    /// The flash could not be read, programmed or erased.
+    Flash(E),
+    /// The region is not aligned to sectors, or has fewer than two.
+    InvalidRegion,
+    /// The key is empty or longer than [`MAX_KEY_LEN`].
+    InvalidKey,
+    /// The value is longer than [`MAX_VALUE_LEN`].
+    ValueTooLong,
+    /// The buffer is too small to hold the value.
+    BufferTooSmall,
+    /// The stored value cannot be decoded as the requested type.
+    TypeMismatch,
+    /// There is no room for the record, even after collecting garbage.
+    Full,
+}
+
+/// Return the CRC-32 (as used by Ethernet and zlib) of `data`, continuing
+/// from `crc`, which is 0 for the first block of data.
+pub fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+// The result of reading the record at an offset in a sector.
+enum Scan {
+    Record(Record),
+    // The rest of the sector is free.
+    End,
+    // The record is torn or corrupt, so the rest of the sector is unusable.
+    Invalid,
+}
+
+// A valid record, read into a buffer.
+#[derive(Clone, Copy)]
+struct Record {
+    kind: u8,
+    key_len: usize,
+    value_len: usize,
+}
+
+impl Record {
+    fn len(&self) -> usize {
+        RECORD_HEADER_SIZE + self.key_len + self.value_len
+    }
+
+    fn key<'b>(&self, buf: &'b [u8]) -> &'b [u8] {
+        &buf[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + self.key_len]
+    }
+
+    fn value<'b>(&self, buf: &'b [u8]) -> &'b [u8] {
+        &buf[RECORD_HEADER_SIZE + self.key_len..self.len()]
+    }
+}
+
+/// A key-value store in a region of flash.
+pub struct ConfigStore<F> {
+    flash: F,
+    start: u32,
+    sectors: u32,
+    // The sector being written, its sequence number, and the offset of the
+    // next record.
+    current: u32,
+    sequence: u32,
+    offset: u32,
+}
+
+impl<F> ConfigStore<F>
+where
+    F: Flash,
+{
+    /// Open the store in the `sectors` sectors of `flash` starting at
+    /// `start`, recovering from any interrupted write, or format the region
+    /// if it holds no store.
+    pub fn new(flash: F, start: u32, sectors: u32) -> Result<Self, ConfigError<F::Error>> {
+        if start % F::SECTOR_SIZE != 0 || sectors < 2 {
+            return Err(ConfigError::InvalidRegion);
+        }
+
+        let mut store = Self {
+            flash,
+            start,
+            sectors,
+            current: 0,
+            sequence: 0,
+            offset: HEADER_SIZE,
+        };
+        store.open()?;
+
+        Ok(store)
+    }
+
+    /// Erase every value in the store.
+    pub fn format(&mut self) -> Result<(), ConfigError<F::Error>> {
+        for sector in 0..self.sectors {
+            if !self.is_blank(sector)? {
+                self.erase(sector)?;
+            }
+        }
+
+        self.current = 0;
+        self.sequence = 1;
+        self.write_header(0, self.sequence)?;
+        self.program(0, COMPLETE_OFFSET, &0u32.to_le_bytes())?;
+        self.offset = HEADER_SIZE;
+
+        Ok(())
+    }
+
+    /// Return the value of `key`, or `None` if it is not set.
+    pub fn get<V: Value>(&mut self, key: &str) -> Result<Option<V>, ConfigError<F::Error>> {
+        let mut buf = [0; MAX_VALUE_LEN];
+        match self.get_bytes(key, &mut buf)? {
+            Some(bytes) => V::decode(bytes).map(Some).ok_or(ConfigError::TypeMismatch),
+            None => Ok(None),
+        }
+    }
+
+    /// Set `key` to `value`.
+    pub fn set<V: Value>(&mut self, key: &str, value: &V) -> Result<(), ConfigError<F::Error>> {
+        let mut buf = [0; MAX_VALUE_LEN];
+        let len = value.encode(&mut buf);
+        self.set_bytes(key, &buf[..len])
+    }
+
+    /// Read the value of `key` into `buf`, returning the part of `buf` it
+    /// fills, or `None` if it is not set.
+    pub fn get_bytes<'b>(
+        &mut self,
+        key: &str,
+        buf: &'b mut [u8],
+    ) -> Result<Option<&'b [u8]>, ConfigError<F::Error>> {
+        check_key(key)?;
+
+        let mut record = [0; MAX_RECORD_LEN];
+        match self.find(key.as_bytes(), &mut record)? {
+            Some(found) if found.kind == KIND_VALUE => {
+                let value = found.value(&record);
+                let buf = buf
+                    .get_mut(..value.len())
+                    .ok_or(ConfigError::BufferTooSmall)?;
+                buf.copy_from_slice(value);
+                Ok(Some(buf))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Set `key` to the bytes `value`. Nothing is written if the key already
+    /// has that value.
+    pub fn set_bytes(&mut self, key: &str, value: &[u8]) -> Result<(), ConfigError<F::Error>> {
+        check_key(key)?;
+        if value.len() > MAX_VALUE_LEN {
+            return Err(ConfigError::ValueTooLong);
+        }
+
+        let mut record = [0; MAX_RECORD_LEN];
+        if let Some(found) = self.find(key.as_bytes(), &mut record)? {
+            if found.kind == KIND_VALUE && found.value(&record) == value {
+                return Ok(());
+            }
+        }
+
+        self.append(KIND_VALUE, key.as_bytes(), value)
+    }
+
+    /// Read the string value of `key` into `buf`, returning the part of `buf`
+    /// it fills, or `None` if it is not set.
+    pub fn get_str<'b>(
+        &mut self,
+        key: &str,
+        buf: &'b mut [u8],
+    ) -> Result<Option<&'b str>, ConfigError<F::Error>> {
+        match self.get_bytes(key, buf)? {
+            Some(bytes) => core::str::from_utf8(bytes)
+                .map(Some)
+                .map_err(|_| ConfigError::TypeMismatch),
+            None => Ok(None),
+        }
+    }
+
+    /// Set `key` to the string `value`.
+    pub fn set_str(&mut self, key: &str, value: &str) -> Result<(), ConfigError<F::Error>> {
+        self.set_bytes(key, value.as_bytes())
+    }
+
+    /// Remove `key` from the store, returning whether it was set.
+    pub fn remove(&mut self, key: &str) -> Result<bool, ConfigError<F::Error>> {
+        check_key(key)?;
+
+        let mut record = [0; MAX_RECORD_LEN];
+        match self.find(key.as_bytes(), &mut record)? {
+            Some(found) if found.kind == KIND_VALUE => {
+                self.append(KIND_DELETED, key.as_bytes(), &[])?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Release the flash.
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    // Find the newest sector, finish or undo any interrupted collection, and
+    // find where the next record will be written.
+    fn open(&mut self) -> Result<(), ConfigError<F::Error>> {
+        loop {
+            let mut newest = None;
+            for sector in 0..self.sectors {
+                match (self.header(sector)?, newest) {
+                    (Some((sequence, _)), Some((_, newest, _))) if sequence <= newest => {}
+                    (Some((sequence, complete)), _) => newest = Some((sector, sequence, complete)),
+                    (None, _) => {}
+                }
+            }
+
+            match newest {
+                None => return self.format(),
+                // The copying into this sector was interrupted, so it holds
+                // nothing which is not still in the oldest sector.
+                Some((sector, _, false)) => self.erase(sector)?,
+                Some((sector, sequence, true)) => {
+                    self.current = sector;
+                    self.sequence = sequence;
+                    break;
+                }
+            }
+        }
+
+        // Only an interrupted collection leaves the sector after the newest
+        // holding records; they have all been copied, so finish erasing it.
+        let next = self.next(self.current);
+        if self.header(next)?.is_some() {
+            self.erase(next)?;
+        }
+
+        // Records after a torn one cannot be trusted, and neither can free
+        // space which is not blank, so start a new sector for the next write.
+        let mut record = [0; MAX_RECORD_LEN];
+        let mut offset = HEADER_SIZE;
+        self.offset = loop {
+            match self.record(self.current, offset, &mut record)? {
+                Scan::Record(found) => offset += found.len() as u32,
+                Scan::End if self.is_blank_from(self.current, offset)? => break offset,
+                _ => break F::SECTOR_SIZE,
+            }
+        };
+
+        Ok(())
+    }
+
+    // Find the newest record for `key`, reading it into `buf`.
+    fn find(
+        &mut self,
+        key: &[u8],
+        buf: &mut [u8; MAX_RECORD_LEN],
+    ) -> Result<Option<Record>, ConfigError<F::Error>> {
+        let mut sector = self.current;
+
+        for _ in 0..self.sectors {
+            if self.header(sector)?.is_some() {
+                let mut found = None;
+                let mut offset = HEADER_SIZE;
+                while let Scan::Record(record) = self.record(sector, offset, buf)? {
+                    if record.key(buf) == key {
+                        found = Some((offset, record));
+                    }
+                    offset += record.len() as u32;
+                }
+
+                // Read the newest match again, as the buffer now holds the
+                // last record of the sector.
+                if let Some((offset, record)) = found {
+                    self.record(sector, offset, buf)?;
+                    return Ok(Some(record));
+                }
+            }
+
+            sector = (sector + self.sectors - 1) % self.sectors;
+        }
+
+        Ok(None)
+    }
+
+    // Return whether a record for `key` is newer than the record ending at
+    // `offset` in `sector`.
+    fn has_newer(
+        &mut self,
+        key: &[u8],
+        sector: u32,
+        offset: u32,
+    ) -> Result<bool, ConfigError<F::Error>> {
+        let mut buf = [0; MAX_RECORD_LEN];
+        let mut sector = sector;
+        let mut offset = offset;
+
+        loop {
+            if self.header(sector)?.is_some() {
+                while let Scan::Record(record) = self.record(sector, offset, &mut buf)? {
+                    if record.key(&buf) == key {
+                        return Ok(true);
+                    }
+                    offset += record.len() as u32;
+                }
+            }
+
+            if sector == self.current {
+                return Ok(false);
+            }
+            sector = self.next(sector);
+            offset = HEADER_SIZE;
+        }
+    }
+
+    // Append a record to the current sector, starting a new one if it does
+    // not fit.
+    fn append(&mut self, kind: u8, key: &[u8], value: &[u8]) -> Result<(), ConfigError<F::Error>> {
+        let mut record = [0; MAX_RECORD_LEN];
+        record[0] = key.len() as u8;
+        record[1] = kind;
+        record[2..4].copy_from_slice(&(value.len() as u16).to_le_bytes());
+        record[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + key.len()].copy_from_slice(key);
+        let len = RECORD_HEADER_SIZE + key.len() + value.len();
+        record[RECORD_HEADER_SIZE + key.len()..len].copy_from_slice(value);
+        let crc = crc32(crc32(0, &record[..4]), &record[RECORD_HEADER_SIZE..len]);
+        record[4..8].copy_from_slice(&crc.to_le_bytes());
+
+        if self.offset + len as u32 > F::SECTOR_SIZE {
+            self.advance(len as u32)?;
+        }
+        self.write_record(&record[..len])
+    }
+
+    fn write_record(&mut self, record: &[u8]) -> Result<(), ConfigError<F::Error>> {
+        let offset = self.offset;
+        // If programming fails, the record may be torn, so nothing more can
+        // be written to this sector.
+        self.offset = F::SECTOR_SIZE;
+        self.program(self.current, offset, record)?;
+        self.offset = offset + record.len() as u32;
+
+        Ok(())
+    }
+
+    // Start writing the next sector, until there is room for `needed` bytes.
+    fn advance(&mut self, needed: u32) -> Result<(), ConfigError<F::Error>> {
+        for _ in 1..self.sectors {
+            // The sector after the current one never holds records, but may
+            // not be blank if it was being erased.
+            let next = self.next(self.current);
+            if !self.is_blank(next)? {
+                self.erase(next)?;
+            }
+
+            self.sequence += 1;
+            self.write_header(next, self.sequence)?;
+            self.current = next;
+            self.offset = HEADER_SIZE;
+
+            // Copy the live records of the oldest sector, so it can be erased.
+            let oldest = self.next(next);
+            let collect = self.header(oldest)?.is_some();
+            if collect {
+                self.collect(oldest)?;
+            }
+            self.program(next, COMPLETE_OFFSET, &0u32.to_le_bytes())?;
+            if collect {
+                self.erase(oldest)?;
+            }
+
+            if self.offset + needed <= F::SECTOR_SIZE {
+                return Ok(());
+            }
+        }
+
+        Err(ConfigError::Full)
+    }
+
+    // Copy each value in `sector` which has no newer record into the current
+    // sector. Deleted records are dropped, as there is nothing older for them
+    // to hide.
+    fn collect(&mut self, sector: u32) -> Result<(), ConfigError<F::Error>> {
+        let mut buf = [0; MAX_RECORD_LEN];
+        let mut offset = HEADER_SIZE;
+
+        while let Scan::Record(record) = self.record(sector, offset, &mut buf)? {
+            offset += record.len() as u32;
+            if record.kind == KIND_VALUE && !self.has_newer(record.key(&buf), sector, offset)? {
+                self.write_record(&buf[..record.len()])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Read the record at `offset` in `sector` into `buf`.
+    fn record(
+        &mut self,
+        sector: u32,
+        offset: u32,
+        buf: &mut [u8; MAX_RECORD_LEN],
+    ) -> Result<Scan, ConfigError<F::Error>> {
+        if offset + RECORD_HEADER_SIZE as u32 > F::SECTOR_SIZE {
+            return Ok(Scan::End);
+        }
+
+        self.read(sector, offset, &mut buf[..RECORD_HEADER_SIZE])?;
+        if buf[..RECORD_HEADER_SIZE].iter().all(|&b| b == 0xff) {
+            return Ok(Scan::End);
+        }
+
+        let record = Record {
+            kind: buf[1],
+            key_len: buf[0] as usize,
+            value_len: u16::from_le_bytes([buf[2], buf[3]]) as usize,
+        };
+        if record.key_len == 0
+            || record.key_len > MAX_KEY_LEN
+            || record.value_len > MAX_VALUE_LEN
+            || (record.kind != KIND_VALUE && record.kind != KIND_DELETED)
+            || offset + record.len() as u32 > F::SECTOR_SIZE
+        {
+            return Ok(Scan::Invalid);
+        }
+
+        self.read(
+            sector,
+            offset + RECORD_HEADER_SIZE as u32,
+            &mut buf[RECORD_HEADER_SIZE..record.len()],
+        )?;
+        let crc = crc32(crc32(0, &buf[..4]), &buf[RECORD_HEADER_SIZE..record.len()]);
+        if crc.to_le_bytes() != buf[4..8] {
+            return Ok(Scan::Invalid);
+        }
+
+        Ok(Scan::Record(record))
+    }
+
+    // Return the sequence number of a sector and whether it is complete, or
+    // `None` if it has no valid header.
+    fn header(&mut self, sector: u32) -> Result<Option<(u32, bool)>, ConfigError<F::Error>> {
+        let mut header = [0; HEADER_SIZE as usize];
+        self.read(sector, 0, &mut header)?;
+
+        let word =
+            |i: usize| u32::from_le_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]]);
+        if word(0) != SECTOR_MAGIC || word(4) != !word(8) {
+            return Ok(None);
+        }
+
+        Ok(Some((word(4), word(12) == 0)))
+    }
+
+    // Write the header of a new sector, which is not yet complete.
+    fn write_header(&mut self, sector: u32, sequence: u32) -> Result<(), ConfigError<F::Error>> {
+        let mut header = [0xff; COMPLETE_OFFSET as usize];
+        header[0..4].copy_from_slice(&SECTOR_MAGIC.to_le_bytes());
+        header[4..8].copy_from_slice(&sequence.to_le_bytes());
+        header[8..12].copy_from_slice(&(!sequence).to_le_bytes());
+        self.program(sector, 0, &header)
+    }
+
+    fn is_blank(&mut self, sector: u32) -> Result<bool, ConfigError<F::Error>> {
+        self.is_blank_from(sector, 0)
+    }
+
+    fn is_blank_from(&mut self, sector: u32, offset: u32) -> Result<bool, ConfigError<F::Error>> {
+        let mut buf = [0; 64];
+        let mut offset = offset;
+
+        while offset < F::SECTOR_SIZE {
+            let len = buf.len().min((F::SECTOR_SIZE - offset) as usize);
+            self.read(sector, offset, &mut buf[..len])?;
+            if buf[..len].iter().any(|&b| b != 0xff) {
+                return Ok(false);
+            }
+            offset += len as u32;
+        }
+
+        Ok(true)
+    }
+
+    fn next(&self, sector: u32) -> u32 {
+        (sector + 1) % self.sectors
+    }
+
+    fn address(&self, sector: u32, offset: u32) -> u32 {
+        self.start + sector * F::SECTOR_SIZE + offset
+    }
+
+    fn read(
+        &mut self,
+        sector: u32,
+        offset: u32,
+        buf: &mut [u8],
+    ) -> Result<(), ConfigError<F::Error>> {
+        let address = self.address(sector, offset);
+        self.flash.read(address, buf).map_err(ConfigError::Flash)
+    }
+
+    fn program(
+        &mut self,
+        sector: u32,
+        offset: u32,
+        data: &[u8],
+    ) -> Result<(), ConfigError<F::Error>> {
+        let address = self.address(sector, offset);
+        self.flash
+            .program(address, data)
+            .map_err(ConfigError::Flash)
+    }
+
+    fn erase(&mut self, sector: u32) -> Result<(), ConfigError<F::Error>> {
+        let address = self.address(sector, 0);
+        self.flash.erase_sector(address).map_err(ConfigError::Flash)
+    }
+}
+
+fn check_key<E>(key: &str) -> Result<(), ConfigError<E>> {
+    if key.is_empty() || key.len() > MAX_KEY_LEN {
+        Err(ConfigError::InvalidKey)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use heapless::consts::{U32, U64};
+    use heapless::Vec;
+
+    const SECTOR_SIZE: u32 = 512;
+    const SECTORS: u32 = 4;
+    // Random operations only use the first keys; the others are set before
+    // them and never again, so their records must survive every collection.
+    const KEYS: [&str; 8] = [
+        "volume",
+        "brightness",
+        "ssid",
+        "password",
+        "rotation",
+        "theme",
+        "serial",
+        "calibration",
+    ];
+    const RANDOM_KEYS: usize = 6;
+
+    // A key, and the value it is set to or `None` if it is removed.
+    type Operation = (usize, Option<Vec<u8, U64>>);
+    type Model = [Option<Vec<u8, U64>>; 8];
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    struct PowerCut;
+
+    // A model of NOR flash which loses power after programming a given number
+    // of bytes, or while starting a given erase. The byte being programmed is
+    // then left half-programmed, and only the bytes of the sector being
+    // erased chosen by `torn_erase` are erased. Nothing can be read or written
+    // until power is restored.
+    #[derive(Clone)]
+    struct FakeFlash {
+        memory: [u8; (SECTOR_SIZE * SECTORS) as usize],
+        powered: bool,
+        bytes_left: Option<usize>,
+        erases_left: Option<usize>,
+        // Whether the byte at an offset in a sector is erased by a torn erase.
+        torn_erase: fn(usize) -> bool,
+        erases: [u32; SECTORS as usize],
+    }
+
+    impl FakeFlash {
+        fn new() -> Self {
+            Self {
+                // The region starts out holding garbage, not erased.
+                memory: [0xa5; (SECTOR_SIZE * SECTORS) as usize],
+                powered: true,
+                bytes_left: None,
+                erases_left: None,
+                torn_erase: |i| i / 8 % 2 == 1,
+                erases: [0; SECTORS as usize],
+            }
+        }
+
+        fn restore_power(&mut self) {
+            self.powered = true;
+            self.bytes_left = None;
+            self.erases_left = None;
+        }
+    }
+
+    impl Flash for &mut FakeFlash {
+        type Error = PowerCut;
+
+        const SECTOR_SIZE: u32 = SECTOR_SIZE;
+
+        fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), PowerCut> {
+            if !self.powered {
+                return Err(PowerCut);
+            }
+            let address = address as usize;
+            buf.copy_from_slice(&self.memory[address..address + buf.len()]);
+            Ok(())
+        }
+
+        fn program(&mut self, address: u32, data: &[u8]) -> Result<(), PowerCut> {
+            if !self.powered {
+                return Err(PowerCut);
+            }
+            for (i, &b) in data.iter().enumerate() {
+                let byte = &mut self.memory[address as usize + i];
+                if self.bytes_left == Some(0) {
+                    *byte &= b | 0xf0;
+                    self.powered = false;
+                    return Err(PowerCut);
+                }
+                *byte &= b;
+                self.bytes_left = self.bytes_left.map(|n| n - 1);
+            }
+            Ok(())
+        }
+
+        fn erase_sector(&mut self, address: u32) -> Result<(), PowerCut> {
+            assert_eq!(address % SECTOR_SIZE, 0);
+            if !self.powered {
+                return Err(PowerCut);
+            }
+
+            let sector = (address / SECTOR_SIZE) as usize;
+            let bytes = &mut self.memory[address as usize..(address + SECTOR_SIZE) as usize];
+            if self.erases_left == Some(0) {
+                for (i, b) in bytes.iter_mut().enumerate() {
+                    if (self.torn_erase)(i) {
+                        *b = 0xff;
+                    }
+                }
+                self.powered = false;
+                return Err(PowerCut);
+            }
+            for b in bytes {
+                *b = 0xff;
+            }
+            self.erases[sector] += 1;
+            self.erases_left = self.erases_left.map(|n| n - 1);
+            Ok(())
+        }
+    }
+
+    struct Random(u32);
+
+    impl Random {
+        // Xorshift, which is plenty for this.
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+
+        fn operation(&mut self) -> Operation {
+            let key = self.next() as usize % RANDOM_KEYS;
+            // Remove the key one time in five.
+            if self.next() < u32::MAX / 5 {
+                return (key, None);
+            }
+            let len = self.next() as usize % 40;
+            let value = (0..len).map(|_| self.next() as u8).collect();
+            (key, Some(value))
+        }
+    }
+
+    fn open(flash: &mut FakeFlash) -> ConfigStore<&mut FakeFlash> {
+        ConfigStore::new(flash, 0, SECTORS).unwrap()
+    }
+
+    fn apply(
+        store: &mut ConfigStore<&mut FakeFlash>,
+        (key, value): &Operation,
+    ) -> Result<(), ConfigError<PowerCut>> {
+        match value {
+            Some(value) => store.set_bytes(KEYS[*key], value),
+            None => store.remove(KEYS[*key]).map(|_| ()),
+        }
+    }
+
+    fn update(model: &mut Model, (key, value): &Operation) {
+        model[*key] = value.clone();
+    }
+
+    fn check(store: &mut ConfigStore<&mut FakeFlash>, model: &Model) {
+        let mut buf = [0; MAX_VALUE_LEN];
+        for (key, value) in KEYS.iter().zip(model) {
+            let stored = store.get_bytes(key, &mut buf).unwrap();
+            assert_eq!(stored, value.as_ref().map(|v| &v[..]), "{}", key);
+        }
+    }
+
+    // Return a flash holding a store after setting the keys which are not
+    // used by random operations, then `count` random operations, and the
+    // values it holds.
+    fn history(seed: u32, count: usize) -> (FakeFlash, Model) {
+        let mut random = Random(seed);
+        let mut model = Model::default();
+        let mut flash = FakeFlash::new();
+        let mut store = open(&mut flash);
+        for (key, name) in KEYS.iter().enumerate().skip(RANDOM_KEYS) {
+            let operation = (key, Some(name.bytes().collect()));
+            apply(&mut store, &operation).unwrap();
+            update(&mut model, &operation);
+        }
+        for _ in 0..count {
+            let operation = random.operation();
+            apply(&mut store, &operation).unwrap();
+            update(&mut model, &operation);
+        }
+
+        (flash, model)
+    }
+
+    // Run `operations` on copies of `base` which lose power at every point
+    // chosen by `cut` in turn, from 0 until the operations finish without
+    // losing power, checking that the store recovers each time. Return the
+    // number of power cuts.
+    fn cut_everywhere(
+        base: &FakeFlash,
+        model: &Model,
+        operations: &[Operation],
+        cut: fn(&mut FakeFlash, usize),
+    ) -> usize {
+        for point in 0.. {
+            let mut flash = base.clone();
+            let mut model = model.clone();
+            let mut interrupted = None;
+            {
+                let mut store = open(&mut flash);
+                cut(store.flash, point);
+                for (i, operation) in operations.iter().enumerate() {
+                    match apply(&mut store, operation) {
+                        Ok(()) => update(&mut model, operation),
+                        Err(ConfigError::Flash(PowerCut)) => {
+                            interrupted = Some(i);
+                            break;
+                        }
+                        Err(e) => panic!("{:?} at cut {}", e, point),
+                    }
+                }
+            }
+
+            match interrupted {
+                Some(i) => {
+                    flash.restore_power();
+                    recover(&mut flash, model, operations, i, true);
+                }
+                None => return point,
+            }
+        }
+
+        unreachable!()
+    }
+
+    // Check that a store which lost power during `operations[interrupted]`
+    // holds every value set before it, and either the old or new value of its
+    // key, and can carry on. If `cut_again`, first check the same of every
+    // point at which power can be lost while opening the store.
+    fn recover(
+        flash: &mut FakeFlash,
+        mut model: Model,
+        operations: &[Operation],
+        interrupted: usize,
+        cut_again: bool,
+    ) {
+        if cut_again {
+            for erases in 0.. {
+                let mut copy = flash.clone();
+                copy.erases_left = Some(erases);
+                match ConfigStore::new(&mut copy, 0, SECTORS) {
+                    Ok(_) => break,
+                    Err(ConfigError::Flash(PowerCut)) => {
+                        copy.restore_power();
+                        recover(&mut copy, model.clone(), operations, interrupted, false);
+                    }
+                    Err(e) => panic!("{:?}", e),
+                }
+            }
+        }
+
+        let mut store = open(flash);
+        let operation = &operations[interrupted];
+        let mut buf = [0; MAX_VALUE_LEN];
+        let stored = store.get_bytes(KEYS[operation.0], &mut buf).unwrap();
+        if stored != model[operation.0].as_ref().map(|v| &v[..]) {
+            assert_eq!(stored, operation.1.as_ref().map(|v| &v[..]));
+            update(&mut model, operation);
+        }
+        check(&mut store, &model);
+
+        for operation in &operations[interrupted + 1..] {
+            apply(&mut store, operation).unwrap();
+            update(&mut model, operation);
+        }
+        check(&mut store, &model);
+        let flash = store.release();
+        check(&mut open(flash), &model);
+    }
+
+    fn operations(seed: u32) -> Vec<Operation, U32> {
+        let mut random = Random(seed);
+        (0..24).map(|_| random.operation()).collect()
+    }
+
+    fn cut_program(flash: &mut FakeFlash, bytes: usize) {
+        flash.bytes_left = Some(bytes);
+    }
+
+    fn cut_erase(flash: &mut FakeFlash, erases: usize) {
+        flash.erases_left = Some(erases);
+    }
+
+    #[test]
+    fn typed_values() {
+        let mut flash = FakeFlash::new();
+        let mut store = open(&mut flash);
+        store.set("a", &-5i32).unwrap();
+        store.set("b", &1.5f32).unwrap();
+        store.set("c", &true).unwrap();
+        store.set_str("d", "hello").unwrap();
+
+        let mut store = open(store.release());
+        assert_eq!(store.get::<i32>("a"), Ok(Some(-5)));
+        assert_eq!(store.get::<f32>("b"), Ok(Some(1.5)));
+        assert_eq!(store.get::<bool>("c"), Ok(Some(true)));
+        assert_eq!(store.get::<u64>("a"), Err(ConfigError::TypeMismatch));
+        assert_eq!(store.get::<u8>("e"), Ok(None));
+        let mut buf = [0; 8];
+        assert_eq!(store.get_str("d", &mut buf), Ok(Some("hello")));
+        assert_eq!(
+            store.get_str("d", &mut buf[..4]),
+            Err(ConfigError::BufferTooSmall)
+        );
+
+        assert_eq!(store.remove("a"), Ok(true));
+        assert_eq!(store.remove("a"), Ok(false));
+        assert_eq!(open(store.release()).get::<i32>("a"), Ok(None));
+    }
+
+    #[test]
+    fn arguments_are_checked() {
+        let mut flash = FakeFlash::new();
+        assert_eq!(
+            ConfigStore::new(&mut flash, SECTOR_SIZE / 2, SECTORS).err(),
+            Some(ConfigError::InvalidRegion)
+        );
+        assert_eq!(
+            ConfigStore::new(&mut flash, 0, 1).err(),
+            Some(ConfigError::InvalidRegion)
+        );
+
+        let mut store = open(&mut flash);
+        assert_eq!(store.set_str("", "x"), Err(ConfigError::InvalidKey));
+        let long_key = core::str::from_utf8(&[b'k'; MAX_KEY_LEN + 1]).unwrap();
+        assert_eq!(store.set_str(long_key, "x"), Err(ConfigError::InvalidKey));
+        assert_eq!(
+            store.set_bytes("k", &[0; MAX_VALUE_LEN + 1]),
+            Err(ConfigError::ValueTooLong)
+        );
+        store
+            .set_bytes(&long_key[1..], &[0; MAX_VALUE_LEN])
+            .unwrap();
+    }
+
+    #[test]
+    fn unchanged_values_are_not_rewritten() {
+        let mut flash = FakeFlash::new();
+        open(&mut flash).set("a", &1u32).unwrap();
+        let before = flash.memory;
+
+        let mut store = open(&mut flash);
+        store.set("a", &1u32).unwrap();
+        assert_eq!(store.remove("b"), Ok(false));
+        assert!(flash.memory[..] == before[..]);
+    }
+
+    #[test]
+    fn random_operations_level_wear() {
+        let mut random = Random(0x1234_5678);
+        let mut model = Model::default();
+        let mut flash = FakeFlash::new();
+        let mut store = open(&mut flash);
+
+        for i in 0..5000 {
+            let operation = random.operation();
+            apply(&mut store, &operation).unwrap();
+            update(&mut model, &operation);
+            if i % 97 == 0 {
+                store = open(store.release());
+            }
+            if i % 13 == 0 {
+                check(&mut store, &model);
+            }
+        }
+        check(&mut store, &model);
+
+        let erases = flash.erases;
+        let least = erases.iter().min().unwrap();
+        let most = erases.iter().max().unwrap();
+        assert!(*least > 10 && most - least <= 2, "{:?}", erases);
+    }
+
+    #[test]
+    fn torn_record_is_ignored() {
+        let mut flash = FakeFlash::new();
+        let mut store = open(&mut flash);
+        store.set_str("kept", "old").unwrap();
+        store.set_str("torn", "old").unwrap();
+        let sequence = store.sequence;
+
+        // Lose power halfway through the new record's value.
+        store.flash.bytes_left = Some(RECORD_HEADER_SIZE + 4 + 2);
+        assert_eq!(
+            store.set_str("torn", "new"),
+            Err(ConfigError::Flash(PowerCut))
+        );
+        flash.restore_power();
+
+        // The torn record is skipped, and nothing more is written after it.
+        let mut store = open(&mut flash);
+        let mut buf = [0; 8];
+        assert_eq!(store.get_str("kept", &mut buf), Ok(Some("old")));
+        assert_eq!(store.get_str("torn", &mut buf), Ok(Some("old")));
+        assert_eq!(store.offset, SECTOR_SIZE);
+
+        store.set_str("torn", "new").unwrap();
+        assert_eq!(store.sequence, sequence + 1);
+        let mut store = open(store.release());
+        assert_eq!(store.get_str("kept", &mut buf), Ok(Some("old")));
+        assert_eq!(store.get_str("torn", &mut buf), Ok(Some("new")));
+    }
+
+    #[test]
+    fn interrupted_writes_lose_nothing() {
+        for &(seed, count) in &[(1, 0), (2, 20)] {
+            let (base, model) = history(seed, count);
+            let cuts = cut_everywhere(&base, &model, &operations(seed), cut_program);
+            assert!(cuts > 100);
+        }
+    }
+
+    #[test]
+    fn interrupted_collect_loses_nothing() {
+        for &(seed, count) in &[(3, 300), (4, 333)] {
+            let (mut base, model) = history(seed, count);
+
+            // The oldest sector holds records, so starting a new sector
+            // collects it, and the operations fill at least one sector.
+            let mut store = open(&mut base);
+            let sequence = store.sequence;
+            let oldest = store.next(store.next(store.current));
+            assert!(store.header(oldest).unwrap().is_some());
+
+            let operations = operations(seed);
+            let cuts = cut_everywhere(&base, &model, &operations, cut_program);
+            assert!(cuts > 100);
+
+            let mut flash = base.clone();
+            let mut store = open(&mut flash);
+            for operation in &operations {
+                apply(&mut store, operation).unwrap();
+            }
+            assert!(store.sequence > sequence);
+        }
+    }
+
+    #[test]
+    fn interrupted_erase_loses_nothing() {
+        // Power is lost before the erase starts, after it erases the header,
+        // before it reaches the header, and partway through every stripe.
+        let torn_erases: [fn(usize) -> bool; 4] = [
+            |_| false,
+            |i| i < HEADER_SIZE as usize,
+            |i| i >= HEADER_SIZE as usize,
+            |i| i / 8 % 2 == 0,
+        ];
+        for &torn_erase in &torn_erases {
+            for &(seed, count) in &[(5, 300), (6, 301)] {
+                let (mut base, model) = history(seed, count);
+                base.torn_erase = torn_erase;
+                let cuts = cut_everywhere(&base, &model, &operations(seed), cut_erase);
+                assert!(cuts > 0);
+            }
+        }
+    }
+
+    #[test]
+    fn full_store() {
+        let mut flash = FakeFlash::new();
+        let mut store = open(&mut flash);
+        let value = [7; 200];
+        let mut key = *b"k0";
+
+        let mut stored = 0;
+        loop {
+            key[1] = b'0' + stored;
+            match store.set_bytes(core::str::from_utf8(&key).unwrap(), &value) {
+                Ok(()) => stored += 1,
+                Err(ConfigError::Full) => break,
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+        assert!(stored >= 4);
+
+        // Everything stored is still there, and removing values makes room.
+        let mut store = open(store.release());
+        let mut buf = [0; MAX_VALUE_LEN];
+        for i in 0..stored {
+            key[1] = b'0' + i;
+            let key = core::str::from_utf8(&key).unwrap();
+            assert_eq!(store.get_bytes(key, &mut buf), Ok(Some(&value[..])));
+            store.remove(key).unwrap();
+        }
+        for i in 0..stored {
+            key[0] = b'j';
+            key[1] = b'0' + i;
+            store
+                .set_bytes(core::str::from_utf8(&key).unwrap(), &value)
+                .unwrap();
+        }
+    }
+}
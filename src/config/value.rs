@@ -0,0 +1,68 @@
+use core::convert::TryInto;
+
+/// A type which can be stored in a [`ConfigStore`](super::ConfigStore).
+pub trait Value: Sized {
+    /// Encode the value into `buf`, which is [`MAX_VALUE_LEN`] bytes long,
+    /// returning the number of bytes used.
+    ///
+    /// [`MAX_VALUE_LEN`]: super::MAX_VALUE_LEN
+    fn encode(&self, buf: &mut [u8]) -> usize;
+
+    /// Decode a value, or return `None` if `bytes` does not hold one.
+    fn decode(bytes: &[u8]) -> Option<Self>;
+}
+
+// Numbers are stored little-endian, and must be read back as the same type.
+macro_rules! number {
+    ($($type:ty),*) => {
+        $(
+            impl Value for $type {
+                fn encode(&self, buf: &mut [u8]) -> usize {
+                    let bytes = self.to_le_bytes();
+                    buf[..bytes.len()].copy_from_slice(&bytes);
+                    bytes.len()
+                }
+
+                fn decode(bytes: &[u8]) -> Option<Self> {
+                    bytes.try_into().ok().map(Self::from_le_bytes)
+                }
+            }
+        )*
+    };
+}
+
+number!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl Value for f32 {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        self.to_bits().encode(buf)
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        u32::decode(bytes).map(Self::from_bits)
+    }
+}
+
+impl Value for f64 {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        self.to_bits().encode(buf)
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        u64::decode(bytes).map(Self::from_bits)
+    }
+}
+
+impl Value for bool {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        (*self as u8).encode(buf)
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        match u8::decode(bytes)? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,834 @@
+//! A text console for the LCD display, implementing `core::fmt::Write`.
+//!
+//! The console is split into a [`TextGrid`], which models the characters on
+//! screen and needs no display, a [`Vt100`] terminal which interprets escape
+//! sequences against the grid, and the [`Console`] which draws the grid to the
+//! `LCD`.
+//!
+//! Bytes written to the console are decoded as UTF-8 by a [`Utf8Decoder`], and
+//! drawn with a [`BitmapFont`], which by default covers Latin-1, arrows, box
+//! drawing and block elements as well as ASCII.
+
+use core::fmt;
+
+use embedded_graphics::pixelcolor::raw::RawU16;
+use embedded_graphics::pixelcolor::{Rgb565, RgbColor};
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::style::PrimitiveStyleBuilder;
+
+use crate::{DisplayError, Scroller, LCD};
+
+mod ansi;
+mod font;
+mod utf8;
+
+pub use ansi::*;
+pub use font::*;
+pub use utf8::*;
+
+/// The largest number of columns a [`TextGrid`] can have; enough to fill the
+/// display in landscape with a 6 pixel wide font.
+pub const MAX_COLUMNS: usize = 320 / 6;
+
+/// The largest number of rows a [`TextGrid`] can have; enough to fill the
+/// display in portrait with a 12 pixel high font.
+pub const MAX_ROWS: usize = 320 / 12;
+
+/// The default number of columns between tab stops.
+pub const DEFAULT_TAB_WIDTH: u16 = 4;
+
+/// A character on screen, and its colours.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: char,
+    pub fg: Rgb565,
+    pub bg: Rgb565,
+}
+
+impl Cell {
+    /// Create an empty cell with the given colours.
+    pub const fn blank(fg: Rgb565, bg: Rgb565) -> Self {
+        Self { ch: ' ', fg, bg }
+    }
+}
+
+/// The cells `start..end` of a row which were changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CellSpan {
+    pub row: u16,
+    pub start: u16,
+    pub end: u16,
+}
+
+/// The rows `start..end` which were changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RowSpan {
+    pub start: u16,
+    pub end: u16,
+}
+
+/// What changed on screen after writing a character to a [`TextGrid`].
+///
+/// Scrolling happens before the cells are changed, so a renderer should
+/// scroll first, then redraw the rows, then the cells.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Damage {
+    /// The number of rows the whole grid scrolled up by.
+    pub scrolled: u16,
+    /// The rows which were changed, if any.
+    pub rows: Option<RowSpan>,
+    /// The cells which were changed, if any.
+    pub cells: Option<CellSpan>,
+}
+
+/// Which part of a line or of the screen to erase.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Erase {
+    /// From the cursor to the end, inclusive.
+    ToEnd,
+    /// From the start to the cursor, inclusive.
+    ToStart,
+    /// All of it.
+    All,
+}
+
+/// A grid of character cells, with a cursor.
+///
+/// Printable characters are written at the cursor, wrapping onto the next
+/// line once the current one is full. Writing past the bottom of the
+/// scrolling region, which is initially the whole grid, scrolls the region up
+/// by a row. The grid also handles `'\n'`, `'\r'`, `'\t'` and backspace
+/// (`'\x08'`); other control characters are ignored.
+pub struct TextGrid {
+    cells: [[Cell; MAX_COLUMNS]; MAX_ROWS],
+    columns: u16,
+    rows: u16,
+    top: u16,
+    scroll_top: u16,
+    scroll_bottom: u16,
+    column: u16,
+    row: u16,
+    fg: Rgb565,
+    bg: Rgb565,
+    tab_width: u16,
+}
+
+impl TextGrid {
+    /// Create an empty grid of the given size, which is limited to
+    /// [`MAX_COLUMNS`] by [`MAX_ROWS`].
+    pub fn new(columns: u16, rows: u16, fg: Rgb565, bg: Rgb565) -> Self {
+        let rows = rows.max(1).min(MAX_ROWS as u16);
+
+        Self {
+            cells: [[Cell::blank(fg, bg); MAX_COLUMNS]; MAX_ROWS],
+            columns: columns.max(1).min(MAX_COLUMNS as u16),
+            rows,
+            top: 0,
+            scroll_top: 0,
+            scroll_bottom: rows - 1,
+            column: 0,
+            row: 0,
+            fg,
+            bg,
+            tab_width: DEFAULT_TAB_WIDTH,
+        }
+    }
+
+    /// Return the number of columns in the grid.
+    pub fn columns(&self) -> u16 {
+        self.columns
+    }
+
+    /// Return the number of rows in the grid.
+    pub fn rows(&self) -> u16 {
+        self.rows
+    }
+
+    /// Return the cursor position, as `(column, row)`.
+    pub fn cursor(&self) -> (u16, u16) {
+        (self.column, self.row)
+    }
+
+    /// Move the cursor, clamping it to the grid.
+    pub fn set_cursor(&mut self, column: u16, row: u16) {
+        self.column = column.min(self.columns - 1);
+        self.row = row.min(self.rows - 1);
+    }
+
+    /// Return the colours used for newly written characters, as
+    /// `(foreground, background)`.
+    pub fn colors(&self) -> (Rgb565, Rgb565) {
+        (self.fg, self.bg)
+    }
+
+    /// Set the colours used for newly written characters.
+    pub fn set_colors(&mut self, fg: Rgb565, bg: Rgb565) {
+        self.fg = fg;
+        self.bg = bg;
+    }
+
+    /// Set the number of columns between tab stops. A width of zero is treated
+    /// as one.
+    pub fn set_tab_width(&mut self, tab_width: u16) {
+        self.tab_width = tab_width.max(1);
+    }
+
+    /// Return the cell at the given column and row, counted from the top of
+    /// the screen.
+    pub fn cell(&self, column: u16, row: u16) -> Option<&Cell> {
+        if column < self.columns && row < self.rows {
+            Some(&self.cells[self.index(row)][column as usize])
+        } else {
+            None
+        }
+    }
+
+    /// Return the scrolling region, as the `(top, bottom)` rows inclusive.
+    pub fn scroll_region(&self) -> (u16, u16) {
+        (self.scroll_top, self.scroll_bottom)
+    }
+
+    /// Set the scrolling region to the rows `top..=bottom`, and move the
+    /// cursor to the top left. A region of less than two rows is ignored.
+    pub fn set_scroll_region(&mut self, top: u16, bottom: u16) {
+        let bottom = bottom.min(self.rows - 1);
+        if top < bottom {
+            self.scroll_top = top;
+            self.scroll_bottom = bottom;
+        }
+        self.column = 0;
+        self.row = 0;
+    }
+
+    /// Clear every cell to the current background colour, reset the
+    /// scrolling region, and move the cursor to the top left.
+    pub fn clear(&mut self) {
+        let blank = Cell::blank(self.fg, self.bg);
+        self.cells
+            .iter_mut()
+            .for_each(|row| row.iter_mut().for_each(|cell| *cell = blank));
+        self.top = 0;
+        self.scroll_top = 0;
+        self.scroll_bottom = self.rows - 1;
+        self.column = 0;
+        self.row = 0;
+    }
+
+    /// Erase part of the screen to the current background colour, without
+    /// moving the cursor, and return what changed.
+    pub fn erase_display(&mut self, erase: Erase) -> Damage {
+        let row = self.row;
+        let (start, end) = match erase {
+            Erase::ToEnd => (row + 1, self.rows),
+            Erase::ToStart => (0, row),
+            Erase::All => (0, self.rows),
+        };
+
+        for row in start..end {
+            self.blank(row, 0, self.columns);
+        }
+        if erase != Erase::All {
+            self.erase_line(erase);
+        }
+
+        let (start, end) = match erase {
+            Erase::ToEnd => (row, self.rows),
+            Erase::ToStart => (0, row + 1),
+            Erase::All => (0, self.rows),
+        };
+        Damage {
+            rows: Some(RowSpan { start, end }),
+            ..Damage::default()
+        }
+    }
+
+    /// Erase part of the cursor's line to the current background colour,
+    /// without moving the cursor, and return what changed.
+    pub fn erase_line(&mut self, erase: Erase) -> Damage {
+        let column = self.column.min(self.columns - 1);
+        let (start, end) = match erase {
+            Erase::ToEnd => (column, self.columns),
+            Erase::ToStart => (0, column + 1),
+            Erase::All => (0, self.columns),
+        };
+        self.blank(self.row, start, end);
+
+        Damage {
+            cells: Some(self.span(start, end)),
+            ..Damage::default()
+        }
+    }
+
+    /// Write a character at the cursor, and return what changed.
+    pub fn write_char(&mut self, c: char) -> Damage {
+        let mut damage = Damage::default();
+
+        match c {
+            '\n' => self.newline(&mut damage),
+            '\r' => self.column = 0,
+            '\x08' => self.column = self.column.saturating_sub(1),
+            '\t' => {
+                if self.column >= self.columns {
+                    self.newline(&mut damage);
+                }
+                let start = self.column;
+                let stop = (start / self.tab_width + 1) * self.tab_width;
+                let end = stop.min(self.columns);
+                for column in start..end {
+                    self.put(column, ' ');
+                }
+                self.column = end;
+                damage.cells = Some(self.span(start, end));
+            }
+            c if c.is_control() => {}
+            c => {
+                if self.column >= self.columns {
+                    self.newline(&mut damage);
+                }
+                let column = self.column;
+                self.put(column, c);
+                self.column += 1;
+                damage.cells = Some(self.span(column, column + 1));
+            }
+        }
+
+        damage
+    }
+
+    fn newline(&mut self, damage: &mut Damage) {
+        self.column = 0;
+        if self.row == self.scroll_bottom {
+            self.scroll(damage);
+        } else if self.row + 1 < self.rows {
+            self.row += 1;
+        }
+    }
+
+    // Scroll the scrolling region up by a row, leaving its bottom row blank.
+    fn scroll(&mut self, damage: &mut Damage) {
+        let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+
+        if top == 0 && bottom == self.rows - 1 {
+            // The whole grid scrolls, so just move the top of the ring.
+            self.top = (self.top + 1) % self.rows;
+            damage.scrolled += 1;
+        } else {
+            for row in top..bottom {
+                let (to, from) = (self.index(row), self.index(row + 1));
+                self.cells[to] = self.cells[from];
+            }
+            damage.rows = Some(RowSpan {
+                start: top,
+                end: bottom + 1,
+            });
+        }
+
+        self.blank(bottom, 0, self.columns);
+    }
+
+    fn blank(&mut self, row: u16, start: u16, end: u16) {
+        let blank = Cell::blank(self.fg, self.bg);
+        let index = self.index(row);
+        self.cells[index][start as usize..end as usize]
+            .iter_mut()
+            .for_each(|cell| *cell = blank);
+    }
+
+    fn put(&mut self, column: u16, ch: char) {
+        let index = self.index(self.row);
+        self.cells[index][column as usize] = Cell {
+            ch,
+            fg: self.fg,
+            bg: self.bg,
+        };
+    }
+
+    fn span(&self, start: u16, end: u16) -> CellSpan {
+        CellSpan {
+            row: self.row,
+            start,
+            end,
+        }
+    }
+
+    // The index into `cells` of the given row, counted from the top of the
+    // screen.
+    fn index(&self, row: u16) -> usize {
+        ((self.top + row) % self.rows) as usize
+    }
+}
+
+/// Configuration for a [`Console`].
+pub struct ConsoleConfig {
+    /// The initial text colour.
+    pub foreground: Rgb565,
+    /// The initial background colour.
+    pub background: Rgb565,
+    /// The number of columns between tab stops.
+    pub tab_width: u16,
+    /// The font to draw characters with.
+    pub font: &'static BitmapFont,
+}
+
+impl Default for ConsoleConfig {
+    fn default() -> Self {
+        Self {
+            foreground: Rgb565::WHITE,
+            background: Rgb565::BLACK,
+            tab_width: DEFAULT_TAB_WIDTH,
+            font: &WIO_6X12,
+        }
+    }
+}
+
+/// A text console drawn to the LCD display with a [`BitmapFont`].
+///
+/// Text written to the console may contain VT100 escape sequences, for
+/// colours, cursor movement, erasing and scrolling regions; see [`Parser`].
+///
+/// The ILI9341 can only scroll along the long edge of the panel, so the
+/// console uses hardware scrolling when the display is in a portrait
+/// orientation (40 columns by 26 rows with the default font), and otherwise
+/// redraws the whole screen when it scrolls (53 columns by 20 rows).
+pub struct Console {
+    display: LCD,
+    grid: TextGrid,
+    terminal: Vt100,
+    decoder: Utf8Decoder,
+    font: &'static BitmapFont,
+    scroller: Option<Scroller>,
+    // The offset, in pixels, of the top of the screen into the scrolling area.
+    offset: u16,
+}
+
+impl Console {
+    /// Create a console filling the display, as returned by
+    /// [`Display::init`](crate::Display::init), and clear the screen.
+    pub fn new(mut display: LCD, config: ConsoleConfig) -> Result<Self, DisplayError> {
+        let width = display.width() as u16;
+        let height = display.height() as u16;
+        let columns = width / config.font.width() as u16;
+        let rows = height / config.font.height() as u16;
+
+        // Only the rows of the scrolling area are scrolled; the remaining
+        // lines below the last whole row are fixed.
+        let scroller = if height > width {
+            let fixed = height - rows * config.font.height() as u16;
+            let scroller = display
+                .configure_vertical_scroll(0, fixed)
+                .map_err(|_| DisplayError::Interface)?;
+            Some(scroller)
+        } else {
+            None
+        };
+
+        let mut grid = TextGrid::new(columns, rows, config.foreground, config.background);
+        grid.set_tab_width(config.tab_width);
+
+        let terminal = Vt100::new(config.foreground, config.background);
+
+        let mut console = Self {
+            display,
+            grid,
+            terminal,
+            decoder: Utf8Decoder::new(),
+            font: config.font,
+            scroller,
+            offset: 0,
+        };
+        console.fill(0, 0, width, height, config.background)?;
+
+        Ok(console)
+    }
+
+    /// Return the grid of characters on screen.
+    pub fn grid(&self) -> &TextGrid {
+        &self.grid
+    }
+
+    /// Set the default colours, used for newly written characters unless
+    /// overridden by an escape sequence.
+    pub fn set_colors(&mut self, fg: Rgb565, bg: Rgb565) {
+        self.terminal.set_default_colors(&mut self.grid, fg, bg);
+    }
+
+    /// Move the cursor, clamping it to the screen.
+    pub fn set_cursor(&mut self, column: u16, row: u16) {
+        self.grid.set_cursor(column, row);
+    }
+
+    /// Clear the screen to the current background colour, and move the cursor
+    /// to the top left.
+    pub fn clear(&mut self) -> Result<(), DisplayError> {
+        self.grid.clear();
+
+        // Scroll back to the start of the scrolling area, so that rows are
+        // drawn where they were initially.
+        if let Some(scroller) = self.scroller.as_mut() {
+            let area = self.area();
+            let remaining = (area - self.offset) % area;
+            self.display
+                .scroll_vertically(scroller, remaining)
+                .map_err(|_| DisplayError::Interface)?;
+            self.offset = 0;
+        }
+
+        let (_, bg) = self.grid.colors();
+        let width = self.display.width() as u16;
+        let height = self.display.height() as u16;
+        self.fill(0, 0, width, height, bg)
+    }
+
+    /// Write a character at the cursor, or feed it to the escape sequence
+    /// being parsed.
+    pub fn write_char(&mut self, c: char) -> Result<(), DisplayError> {
+        let damage = self.terminal.feed(&mut self.grid, c);
+
+        if damage.scrolled > 0 {
+            self.scroll(damage.scrolled)?;
+        }
+        if let Some(span) = damage.rows {
+            for row in span.start..span.end {
+                self.draw_row(row)?;
+            }
+        }
+        if let Some(span) = damage.cells {
+            for column in span.start..span.end {
+                self.draw_cell(column, span.row)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decode the bytes as UTF-8 and write the characters at the cursor. A
+    /// character split across calls is written once its last byte arrives,
+    /// and invalid sequences are written as `U+FFFD`.
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), DisplayError> {
+        let mut decoder = self.decoder;
+        let result = decoder.decode(bytes).try_for_each(|c| self.write_char(c));
+        self.decoder = decoder;
+        result
+    }
+
+    /// Release the display.
+    pub fn release(self) -> LCD {
+        self.display
+    }
+
+    fn scroll(&mut self, rows: u16) -> Result<(), DisplayError> {
+        match self.scroller.as_mut() {
+            Some(scroller) => {
+                let area = self.area();
+                let lines = rows * self.font.height() as u16 % area;
+                self.display
+                    .scroll_vertically(scroller, lines)
+                    .map_err(|_| DisplayError::Interface)?;
+                self.offset = (self.offset + lines) % area;
+
+                // The rows which scrolled off the top are now at the bottom.
+                let first = self.grid.rows().saturating_sub(rows);
+                for row in first..self.grid.rows() {
+                    self.draw_row(row)?;
+                }
+            }
+            None => {
+                for row in 0..self.grid.rows() {
+                    self.draw_row(row)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn draw_row(&mut self, row: u16) -> Result<(), DisplayError> {
+        (0..self.grid.columns()).try_for_each(|column| self.draw_cell(column, row))
+    }
+
+    fn draw_cell(&mut self, column: u16, row: u16) -> Result<(), DisplayError> {
+        let cell = match self.grid.cell(column, row) {
+            Some(cell) => *cell,
+            None => return Ok(()),
+        };
+
+        let (x, y) = self.position(column, row);
+        let width = self.font.width() as u16;
+        let height = self.font.height() as u16;
+        let pixels = self
+            .font
+            .glyph_or_default(cell.ch)
+            .pixels(cell.fg, cell.bg)
+            .map(|color| RawU16::from(color).into_inner());
+
+        self.display
+            .draw_iter(x, y, x + width - 1, y + height - 1, pixels)
+            .map_err(|_| DisplayError::Interface)
+    }
+
+    // The pixel coordinates at which the cell is drawn, accounting for
+    // hardware scrolling.
+    fn position(&self, column: u16, row: u16) -> (u16, u16) {
+        let height = self.font.height() as u16;
+        let y = (row * height + self.offset) % self.area();
+        (column * self.font.width() as u16, y)
+    }
+
+    // The height, in pixels, of the rows of the grid.
+    fn area(&self) -> u16 {
+        self.grid.rows() * self.font.height() as u16
+    }
+
+    fn fill(
+        &mut self,
+        x: u16,
+        y: u16,
+        width: u16,
+        height: u16,
+        color: Rgb565,
+    ) -> Result<(), DisplayError> {
+        let style = PrimitiveStyleBuilder::new().fill_color(color).build();
+        let top_left = Point::new(x as i32, y as i32);
+        let bottom_right = top_left + Point::new(width as i32 - 1, height as i32 - 1);
+
+        Rectangle::new(top_left, bottom_right)
+            .into_styled(style)
+            .draw(&mut self.display)
+            .map_err(|_| DisplayError::Interface)
+    }
+}
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.chars()
+            .try_for_each(|c| self.write_char(c))
+            .map_err(|_| fmt::Error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(columns: u16, rows: u16) -> TextGrid {
+        TextGrid::new(columns, rows, Rgb565::WHITE, Rgb565::BLACK)
+    }
+
+    fn write(grid: &mut TextGrid, s: &str) -> Damage {
+        s.chars().fold(Damage::default(), |damage, c| {
+            let next = grid.write_char(c);
+            Damage {
+                scrolled: damage.scrolled + next.scrolled,
+                rows: next.rows.or(damage.rows),
+                cells: next.cells,
+            }
+        })
+    }
+
+    fn ch(grid: &TextGrid, column: u16, row: u16) -> char {
+        grid.cell(column, row).unwrap().ch
+    }
+
+    fn cells(row: u16, start: u16, end: u16) -> Option<CellSpan> {
+        Some(CellSpan { row, start, end })
+    }
+
+    fn rows(start: u16, end: u16) -> Option<RowSpan> {
+        Some(RowSpan { start, end })
+    }
+
+    #[test]
+    fn size_is_limited() {
+        let grid = grid(1000, 0);
+        assert_eq!((grid.columns(), grid.rows()), (MAX_COLUMNS as u16, 1));
+        assert!(grid.cell(MAX_COLUMNS as u16, 0).is_none());
+        assert!(grid.cell(0, 1).is_none());
+    }
+
+    #[test]
+    fn wraps_after_last_column() {
+        let mut grid = grid(MAX_COLUMNS as u16, 3);
+        for _ in 0..MAX_COLUMNS {
+            grid.write_char('a');
+        }
+
+        // The cursor waits past the last column until another character.
+        assert_eq!(grid.cursor(), (53, 0));
+        assert_eq!(ch(&grid, 52, 0), 'a');
+
+        let damage = grid.write_char('b');
+        assert_eq!(damage.cells, cells(1, 0, 1));
+        assert_eq!(damage.scrolled, 0);
+        assert_eq!(grid.cursor(), (1, 1));
+        assert_eq!(ch(&grid, 0, 1), 'b');
+    }
+
+    #[test]
+    fn control_characters() {
+        let mut grid = grid(10, 3);
+        write(&mut grid, "abc\x08d\re\x07");
+        assert_eq!(grid.cursor(), (1, 0));
+        assert_eq!(
+            (ch(&grid, 0, 0), ch(&grid, 1, 0), ch(&grid, 2, 0)),
+            ('e', 'b', 'd')
+        );
+
+        grid.write_char('\x08');
+        grid.write_char('\x08');
+        assert_eq!(grid.cursor(), (0, 0));
+    }
+
+    #[test]
+    fn tab_stops() {
+        let mut grid = grid(10, 3);
+        grid.write_char('a');
+        assert_eq!(grid.write_char('\t').cells, cells(0, 1, 4));
+        assert_eq!(grid.write_char('\t').cells, cells(0, 4, 8));
+
+        // The last tab stop is the end of the line.
+        assert_eq!(grid.write_char('\t').cells, cells(0, 8, 10));
+        assert_eq!(grid.cursor(), (10, 0));
+
+        grid.set_tab_width(3);
+        assert_eq!(grid.write_char('\t').cells, cells(1, 0, 3));
+        assert_eq!(grid.cursor(), (3, 1));
+
+        grid.set_tab_width(0);
+        assert_eq!(grid.write_char('\t').cells, cells(1, 3, 4));
+    }
+
+    #[test]
+    fn whole_grid_scrolls_as_a_ring() {
+        let mut grid = grid(5, 3);
+        assert_eq!(write(&mut grid, "a\nb\nc").scrolled, 0);
+
+        let damage = grid.write_char('\n');
+        assert_eq!(damage.scrolled, 1);
+        assert_eq!(damage.rows, None);
+        assert_eq!(grid.cursor(), (0, 2));
+        assert_eq!(
+            (ch(&grid, 0, 0), ch(&grid, 0, 1), ch(&grid, 0, 2)),
+            ('b', 'c', ' ')
+        );
+
+        // Scroll the top of the ring past the end of the cells.
+        assert_eq!(write(&mut grid, "d\ne\nf\ng").scrolled, 3);
+        assert_eq!(grid.top, 1);
+        assert_eq!(
+            (ch(&grid, 0, 0), ch(&grid, 0, 1), ch(&grid, 0, 2)),
+            ('e', 'f', 'g')
+        );
+    }
+
+    #[test]
+    fn scroll_region() {
+        let mut grid = grid(5, 4);
+        grid.set_cursor(0, 3);
+        grid.write_char('z');
+
+        grid.set_scroll_region(1, 2);
+        assert_eq!(grid.scroll_region(), (1, 2));
+        assert_eq!(grid.cursor(), (0, 0));
+
+        write(&mut grid, "a");
+        grid.set_cursor(0, 1);
+        assert_eq!(write(&mut grid, "b\nc").rows, None);
+
+        let damage = grid.write_char('\n');
+        assert_eq!(damage.scrolled, 0);
+        assert_eq!(damage.rows, rows(1, 3));
+        assert_eq!(grid.cursor(), (0, 2));
+        let column: [char; 4] = [
+            ch(&grid, 0, 0),
+            ch(&grid, 0, 1),
+            ch(&grid, 0, 2),
+            ch(&grid, 0, 3),
+        ];
+        assert_eq!(column, ['a', 'c', ' ', 'z']);
+
+        // Below the region, the last row neither moves nor scrolls.
+        grid.set_cursor(0, 3);
+        assert_eq!(grid.write_char('\n'), Damage::default());
+        assert_eq!(grid.cursor(), (0, 3));
+    }
+
+    #[test]
+    fn small_scroll_regions_are_ignored() {
+        let mut grid = grid(5, 4);
+        grid.set_cursor(2, 2);
+        grid.set_scroll_region(2, 2);
+        assert_eq!(grid.scroll_region(), (0, 3));
+        assert_eq!(grid.cursor(), (0, 0));
+
+        grid.set_scroll_region(1, 100);
+        assert_eq!(grid.scroll_region(), (1, 3));
+        grid.clear();
+        assert_eq!(grid.scroll_region(), (0, 3));
+    }
+
+    #[test]
+    fn erase_line() {
+        let mut grid = grid(5, 3);
+        for row in 0..3 {
+            grid.set_cursor(0, row);
+            write(&mut grid, "abcde");
+        }
+        grid.set_cursor(2, 1);
+
+        assert_eq!(grid.erase_line(Erase::ToEnd).cells, cells(1, 2, 5));
+        assert_eq!((ch(&grid, 1, 1), ch(&grid, 2, 1)), ('b', ' '));
+
+        grid.set_cursor(1, 1);
+        assert_eq!(grid.erase_line(Erase::ToStart).cells, cells(1, 0, 2));
+        assert_eq!(ch(&grid, 0, 1), ' ');
+
+        let damage = grid.erase_line(Erase::All);
+        assert_eq!(damage.cells, cells(1, 0, 5));
+        assert_eq!(damage.rows, None);
+        assert_eq!((ch(&grid, 0, 0), ch(&grid, 0, 2)), ('a', 'a'));
+        assert_eq!(grid.cursor(), (1, 1));
+    }
+
+    #[test]
+    fn erase_display() {
+        let mut grid = grid(5, 3);
+        let fill = |grid: &mut TextGrid| {
+            for row in 0..3 {
+                grid.set_cursor(0, row);
+                write(grid, "abcde");
+            }
+            grid.set_cursor(2, 1);
+        };
+
+        fill(&mut grid);
+        assert_eq!(grid.erase_display(Erase::ToEnd).rows, rows(1, 3));
+        assert_eq!((ch(&grid, 4, 0), ch(&grid, 1, 1)), ('e', 'b'));
+        assert_eq!((ch(&grid, 2, 1), ch(&grid, 0, 2)), (' ', ' '));
+
+        fill(&mut grid);
+        assert_eq!(grid.erase_display(Erase::ToStart).rows, rows(0, 2));
+        assert_eq!((ch(&grid, 4, 0), ch(&grid, 2, 1)), (' ', ' '));
+        assert_eq!((ch(&grid, 3, 1), ch(&grid, 0, 2)), ('d', 'a'));
+
+        fill(&mut grid);
+        assert_eq!(grid.erase_display(Erase::All).rows, rows(0, 3));
+        assert_eq!((ch(&grid, 0, 0), ch(&grid, 4, 2)), (' ', ' '));
+        assert_eq!(grid.cursor(), (2, 1));
+    }
+
+    #[test]
+    fn erase_uses_current_colors() {
+        let mut grid = grid(5, 3);
+        grid.set_colors(Rgb565::BLACK, Rgb565::RED);
+        grid.erase_line(Erase::All);
+        assert_eq!(
+            grid.cell(4, 0),
+            Some(&Cell::blank(Rgb565::BLACK, Rgb565::RED))
+        );
+        assert_eq!(
+            grid.cell(4, 1),
+            Some(&Cell::blank(Rgb565::WHITE, Rgb565::BLACK))
+        );
+    }
+}
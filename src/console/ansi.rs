@@ -0,0 +1,802 @@
+use embedded_graphics::pixelcolor::Rgb565;
+use heapless::consts::U16;
+use heapless::Vec;
+
+use super::{Damage, Erase, RowSpan, TextGrid};
+
+/// The numeric parameters of a control sequence. Missing parameters are
+/// zero, and values too large for a `u16` saturate.
+pub type Params = Vec<u16, U16>;
+
+/// An action decoded from the character stream by a [`Parser`].
+///
+/// Cursor positions are counted from zero, unlike on the wire.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Draw a character.
+    Print(char),
+    /// Perform a C0 control function, such as `'\n'`.
+    Execute(char),
+    /// Move the cursor up by the given number of rows (CUU).
+    CursorUp(u16),
+    /// Move the cursor down by the given number of rows (CUD).
+    CursorDown(u16),
+    /// Move the cursor right by the given number of columns (CUF).
+    CursorForward(u16),
+    /// Move the cursor left by the given number of columns (CUB).
+    CursorBack(u16),
+    /// Move the cursor to the start of a following row (CNL).
+    CursorNextLine(u16),
+    /// Move the cursor to the start of a preceding row (CPL).
+    CursorPreviousLine(u16),
+    /// Move the cursor to the given column (CHA).
+    CursorColumn(u16),
+    /// Move the cursor to the given row (VPA).
+    CursorRow(u16),
+    /// Move the cursor to the given row and column (CUP).
+    CursorPosition { row: u16, column: u16 },
+    /// Erase part of the screen (ED).
+    EraseDisplay(Erase),
+    /// Erase part of the cursor's line (EL).
+    EraseLine(Erase),
+    /// Set the colours and attributes of following characters (SGR).
+    SetGraphics(Params),
+    /// Set the scrolling region (DECSTBM). A `bottom` of `None` means the
+    /// bottom row of the screen.
+    SetScrollRegion { top: u16, bottom: Option<u16> },
+    /// Save the cursor position (DECSC).
+    SaveCursor,
+    /// Restore the saved cursor position (DECRC).
+    RestoreCursor,
+    /// Reset the terminal to its initial state (RIS).
+    Reset,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    Csi,
+    CsiIgnore,
+    Osc,
+}
+
+const ESC: char = '\x1b';
+const BEL: char = '\x07';
+const CAN: char = '\x18';
+const SUB: char = '\x1a';
+const DEL: char = '\x7f';
+
+/// A parser for the subset of VT100 and ANSI escape sequences understood by
+/// the [`Console`](super::Console).
+///
+/// The parser is a state machine which consumes a character at a time, and
+/// never fails: unsupported or malformed sequences are silently discarded.
+/// Operating system commands (`ESC ]`) are skipped, and private sequences
+/// such as `ESC [ ? 25 l` are ignored.
+#[derive(Clone, Debug)]
+pub struct Parser {
+    state: State,
+    params: Params,
+    param: u16,
+    private: bool,
+}
+
+impl Parser {
+    /// Create a parser in its initial state.
+    pub fn new() -> Self {
+        Self {
+            state: State::Ground,
+            params: Vec::new(),
+            param: 0,
+            private: false,
+        }
+    }
+
+    /// Consume a character, returning the action it completes, if any.
+    pub fn advance(&mut self, c: char) -> Option<Action> {
+        // These are recognised in every state.
+        match c {
+            CAN | SUB => {
+                self.state = State::Ground;
+                return None;
+            }
+            ESC => {
+                self.state = State::Escape;
+                return None;
+            }
+            _ => {}
+        }
+
+        match self.state {
+            State::Ground => match c {
+                DEL => None,
+                c if (c as u32) < 0x20 => Some(Action::Execute(c)),
+                c => Some(Action::Print(c)),
+            },
+            State::Escape => self.escape(c),
+            State::EscapeIntermediate => {
+                if !('\x20'..='\x2f').contains(&c) {
+                    self.state = State::Ground;
+                }
+                None
+            }
+            State::Csi => self.csi(c),
+            State::CsiIgnore => {
+                if ('\x40'..='\x7e').contains(&c) {
+                    self.state = State::Ground;
+                }
+                None
+            }
+            State::Osc => {
+                if c == BEL {
+                    self.state = State::Ground;
+                }
+                None
+            }
+        }
+    }
+
+    fn escape(&mut self, c: char) -> Option<Action> {
+        self.state = State::Ground;
+
+        match c {
+            '[' => {
+                self.state = State::Csi;
+                self.params = Vec::new();
+                self.param = 0;
+                self.private = false;
+                None
+            }
+            ']' => {
+                self.state = State::Osc;
+                None
+            }
+            '\x20'..='\x2f' => {
+                self.state = State::EscapeIntermediate;
+                None
+            }
+            '7' => Some(Action::SaveCursor),
+            '8' => Some(Action::RestoreCursor),
+            'c' => Some(Action::Reset),
+            _ => None,
+        }
+    }
+
+    fn csi(&mut self, c: char) -> Option<Action> {
+        match c {
+            '0'..='9' => {
+                let digit = c as u16 - '0' as u16;
+                self.param = self.param.saturating_mul(10).saturating_add(digit);
+                None
+            }
+            ';' => {
+                // Parameters beyond the limit are dropped.
+                self.params.push(self.param).ok();
+                self.param = 0;
+                None
+            }
+            '<'..='?' if self.params.is_empty() && self.param == 0 => {
+                self.private = true;
+                None
+            }
+            '\x40'..='\x7e' => {
+                self.state = State::Ground;
+                self.params.push(self.param).ok();
+                if self.private {
+                    None
+                } else {
+                    self.dispatch(c)
+                }
+            }
+            c if (c as u32) < 0x20 => Some(Action::Execute(c)),
+            _ => {
+                self.state = State::CsiIgnore;
+                None
+            }
+        }
+    }
+
+    fn dispatch(&mut self, c: char) -> Option<Action> {
+        let params = &self.params;
+        let param = |i: usize| params.get(i).copied().unwrap_or(0);
+        // Most parameters default to one when missing or zero.
+        let count = |i: usize| param(i).max(1);
+        let erase = |i: usize| match param(i) {
+            0 => Some(Erase::ToEnd),
+            1 => Some(Erase::ToStart),
+            2 => Some(Erase::All),
+            _ => None,
+        };
+
+        match c {
+            'A' => Some(Action::CursorUp(count(0))),
+            'B' => Some(Action::CursorDown(count(0))),
+            'C' => Some(Action::CursorForward(count(0))),
+            'D' => Some(Action::CursorBack(count(0))),
+            'E' => Some(Action::CursorNextLine(count(0))),
+            'F' => Some(Action::CursorPreviousLine(count(0))),
+            'G' => Some(Action::CursorColumn(count(0) - 1)),
+            'd' => Some(Action::CursorRow(count(0) - 1)),
+            'H' | 'f' => Some(Action::CursorPosition {
+                row: count(0) - 1,
+                column: count(1) - 1,
+            }),
+            'J' => erase(0).map(Action::EraseDisplay),
+            'K' => erase(0).map(Action::EraseLine),
+            'm' => Some(Action::SetGraphics(self.params.clone())),
+            'r' => Some(Action::SetScrollRegion {
+                top: count(0) - 1,
+                bottom: match param(1) {
+                    0 => None,
+                    bottom => Some(bottom - 1),
+                },
+            }),
+            's' => Some(Action::SaveCursor),
+            'u' => Some(Action::RestoreCursor),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The standard 16 terminal colours, as 8-bit RGB.
+#[rustfmt::skip]
+const PALETTE: [(u8, u8, u8); 16] = [
+    (0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0),
+    (0, 0, 238), (205, 0, 205), (0, 205, 205), (229, 229, 229),
+    (127, 127, 127), (255, 0, 0), (0, 255, 0), (255, 255, 0),
+    (92, 92, 255), (255, 0, 255), (0, 255, 255), (255, 255, 255),
+];
+
+/// Convert an 8-bit per channel RGB colour to `Rgb565`.
+pub fn rgb(r: u8, g: u8, b: u8) -> Rgb565 {
+    Rgb565::new(r >> 3, g >> 2, b >> 3)
+}
+
+/// Return the colour at the given index of the 256-colour terminal palette:
+/// the 16 standard colours, a 6x6x6 colour cube, then 24 shades of grey.
+pub fn indexed_color(index: u8) -> Rgb565 {
+    match index {
+        0..=15 => {
+            let (r, g, b) = PALETTE[index as usize];
+            rgb(r, g, b)
+        }
+        16..=231 => {
+            let level = |n: u8| if n == 0 { 0 } else { 55 + n * 40 };
+            let n = index - 16;
+            rgb(level(n / 36), level(n / 6 % 6), level(n % 6))
+        }
+        _ => {
+            let grey = 8 + (index - 232) * 10;
+            rgb(grey, grey, grey)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Ink {
+    Default,
+    Indexed(u8),
+    Rgb(Rgb565),
+}
+
+// The graphic rendition set by SGR sequences.
+#[derive(Clone, Copy, Debug)]
+struct Attributes {
+    fg: Ink,
+    bg: Ink,
+    bold: bool,
+    inverse: bool,
+}
+
+impl Attributes {
+    const fn new() -> Self {
+        Self {
+            fg: Ink::Default,
+            bg: Ink::Default,
+            bold: false,
+            inverse: false,
+        }
+    }
+
+    fn apply(&mut self, params: &[u16]) {
+        let mut params = params.iter().copied();
+
+        while let Some(param) = params.next() {
+            match param {
+                0 => *self = Self::new(),
+                1 => self.bold = true,
+                22 => self.bold = false,
+                7 => self.inverse = true,
+                27 => self.inverse = false,
+                30..=37 => self.fg = Ink::Indexed((param - 30) as u8),
+                38 => self.fg = extended(&mut params).unwrap_or(self.fg),
+                39 => self.fg = Ink::Default,
+                40..=47 => self.bg = Ink::Indexed((param - 40) as u8),
+                48 => self.bg = extended(&mut params).unwrap_or(self.bg),
+                49 => self.bg = Ink::Default,
+                90..=97 => self.fg = Ink::Indexed((param - 90 + 8) as u8),
+                100..=107 => self.bg = Ink::Indexed((param - 100 + 8) as u8),
+                _ => {}
+            }
+        }
+    }
+
+    fn colors(&self, default_fg: Rgb565, default_bg: Rgb565) -> (Rgb565, Rgb565) {
+        let fg = match self.fg {
+            Ink::Default => default_fg,
+            // Bold brightens the eight standard colours.
+            Ink::Indexed(index) if self.bold && index < 8 => indexed_color(index + 8),
+            Ink::Indexed(index) => indexed_color(index),
+            Ink::Rgb(color) => color,
+        };
+        let bg = match self.bg {
+            Ink::Default => default_bg,
+            Ink::Indexed(index) => indexed_color(index),
+            Ink::Rgb(color) => color,
+        };
+
+        if self.inverse {
+            (bg, fg)
+        } else {
+            (fg, bg)
+        }
+    }
+}
+
+// Parse the rest of a `38` or `48` SGR parameter: `5;n` for an indexed
+// colour, or `2;r;g;b` for an RGB colour.
+fn extended(params: &mut impl Iterator<Item = u16>) -> Option<Ink> {
+    let mut channel = || params.next().map(|value| value.min(255) as u8);
+
+    match channel()? {
+        5 => channel().map(Ink::Indexed),
+        2 => {
+            let (r, g, b) = (channel()?, channel()?, channel()?);
+            Some(Ink::Rgb(rgb(r, g, b)))
+        }
+        _ => None,
+    }
+}
+
+/// Interprets the [`Action`]s decoded by a [`Parser`] against a
+/// [`TextGrid`], as a VT100-compatible terminal.
+#[derive(Clone, Debug)]
+pub struct Vt100 {
+    parser: Parser,
+    attributes: Attributes,
+    default_fg: Rgb565,
+    default_bg: Rgb565,
+    saved: (u16, u16),
+}
+
+impl Vt100 {
+    /// Create a terminal whose default colours are the given ones.
+    pub fn new(default_fg: Rgb565, default_bg: Rgb565) -> Self {
+        Self {
+            parser: Parser::new(),
+            attributes: Attributes::new(),
+            default_fg,
+            default_bg,
+            saved: (0, 0),
+        }
+    }
+
+    /// Set the default colours, which are used when no SGR colour is set, and
+    /// update the grid's colours.
+    pub fn set_default_colors(&mut self, grid: &mut TextGrid, fg: Rgb565, bg: Rgb565) {
+        self.default_fg = fg;
+        self.default_bg = bg;
+        self.update_colors(grid);
+    }
+
+    /// Consume a character, applying any completed action to the grid, and
+    /// return what changed.
+    pub fn feed(&mut self, grid: &mut TextGrid, c: char) -> Damage {
+        match self.parser.advance(c) {
+            Some(action) => self.perform(grid, action),
+            None => Damage::default(),
+        }
+    }
+
+    /// Apply an action to the grid, and return what changed.
+    pub fn perform(&mut self, grid: &mut TextGrid, action: Action) -> Damage {
+        let (column, row) = grid.cursor();
+
+        match action {
+            Action::Print(c) | Action::Execute(c) => return grid.write_char(c),
+            Action::CursorUp(n) => grid.set_cursor(column, row.saturating_sub(n)),
+            Action::CursorDown(n) => grid.set_cursor(column, row.saturating_add(n)),
+            Action::CursorForward(n) => grid.set_cursor(column.saturating_add(n), row),
+            Action::CursorBack(n) => grid.set_cursor(column.saturating_sub(n), row),
+            Action::CursorNextLine(n) => grid.set_cursor(0, row.saturating_add(n)),
+            Action::CursorPreviousLine(n) => grid.set_cursor(0, row.saturating_sub(n)),
+            Action::CursorColumn(column) => grid.set_cursor(column, row),
+            Action::CursorRow(row) => grid.set_cursor(column, row),
+            Action::CursorPosition { row, column } => grid.set_cursor(column, row),
+            Action::EraseDisplay(erase) => return grid.erase_display(erase),
+            Action::EraseLine(erase) => return grid.erase_line(erase),
+            Action::SetGraphics(params) => {
+                self.attributes.apply(&params);
+                self.update_colors(grid);
+            }
+            Action::SetScrollRegion { top, bottom } => {
+                let bottom = bottom.unwrap_or(grid.rows() - 1);
+                grid.set_scroll_region(top, bottom);
+            }
+            Action::SaveCursor => self.saved = (column, row),
+            Action::RestoreCursor => grid.set_cursor(self.saved.0, self.saved.1),
+            Action::Reset => {
+                self.attributes = Attributes::new();
+                self.saved = (0, 0);
+                self.update_colors(grid);
+                grid.clear();
+
+                return Damage {
+                    rows: Some(RowSpan {
+                        start: 0,
+                        end: grid.rows(),
+                    }),
+                    ..Damage::default()
+                };
+            }
+        }
+
+        Damage::default()
+    }
+
+    fn update_colors(&self, grid: &mut TextGrid) {
+        let (fg, bg) = self.attributes.colors(self.default_fg, self.default_bg);
+        grid.set_colors(fg, bg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use embedded_graphics::pixelcolor::RgbColor;
+    use heapless::consts::{U32, U64};
+
+    use crate::console::CellSpan;
+
+    fn parse(s: &str) -> Vec<Action, U32> {
+        let mut parser = Parser::new();
+        s.chars().filter_map(|c| parser.advance(c)).collect()
+    }
+
+    fn params(values: &[u16]) -> Params {
+        values.iter().copied().collect()
+    }
+
+    fn terminal(columns: u16, rows: u16) -> (Vt100, TextGrid) {
+        let grid = TextGrid::new(columns, rows, Rgb565::WHITE, Rgb565::BLACK);
+        (Vt100::new(Rgb565::WHITE, Rgb565::BLACK), grid)
+    }
+
+    fn feed(vt: &mut Vt100, grid: &mut TextGrid, s: &str) -> Damage {
+        s.chars()
+            .map(|c| vt.feed(grid, c))
+            .fold(Damage::default(), |damage, next| Damage {
+                scrolled: damage.scrolled + next.scrolled,
+                rows: next.rows.or(damage.rows),
+                cells: next.cells.or(damage.cells),
+            })
+    }
+
+    #[test]
+    fn printing_and_controls() {
+        let actions = parse("a\r\n\x7f\u{e9}");
+        let expected = [
+            Action::Print('a'),
+            Action::Execute('\r'),
+            Action::Execute('\n'),
+            Action::Print('\u{e9}'),
+        ];
+        assert_eq!(actions, expected);
+    }
+
+    #[test]
+    fn cursor_movement() {
+        let expected = [
+            Action::CursorPosition { row: 4, column: 9 },
+            Action::CursorPosition { row: 0, column: 0 },
+            Action::CursorPosition { row: 0, column: 2 },
+            Action::CursorPosition { row: 0, column: 0 },
+            Action::CursorUp(1),
+            Action::CursorDown(3),
+            Action::CursorForward(1),
+            Action::CursorBack(2),
+            Action::CursorNextLine(1),
+            Action::CursorPreviousLine(4),
+            Action::CursorColumn(6),
+            Action::CursorRow(0),
+        ];
+        let actions = parse("\x1b[5;10H\x1b[H\x1b[;3f\x1b[0;0H\x1b[A\x1b[3B\x1b[0C\x1b[2D\x1b[E\x1b[4F\x1b[7G\x1b[d");
+        assert_eq!(actions, expected);
+    }
+
+    #[test]
+    fn cursor_is_clamped_to_grid() {
+        let (mut vt, mut grid) = terminal(10, 5);
+
+        feed(&mut vt, &mut grid, "\x1b[3;4H");
+        assert_eq!(grid.cursor(), (3, 2));
+        feed(&mut vt, &mut grid, "\x1b[99;99H");
+        assert_eq!(grid.cursor(), (9, 4));
+        feed(&mut vt, &mut grid, "\x1b[20A\x1b[2C");
+        assert_eq!(grid.cursor(), (9, 0));
+        feed(&mut vt, &mut grid, "\x1b[2E");
+        assert_eq!(grid.cursor(), (0, 2));
+        feed(&mut vt, &mut grid, "\x1b[5G\x1b[4d\x1b[2D");
+        assert_eq!(grid.cursor(), (2, 3));
+    }
+
+    #[test]
+    fn save_and_restore_cursor() {
+        let (mut vt, mut grid) = terminal(10, 5);
+
+        feed(&mut vt, &mut grid, "\x1b[2;3H\x1b7\x1b[H\x1b8");
+        assert_eq!(grid.cursor(), (2, 1));
+        feed(&mut vt, &mut grid, "\x1b[4;5H\x1b[s\x1b[H\x1b[u");
+        assert_eq!(grid.cursor(), (4, 3));
+    }
+
+    #[test]
+    fn sgr_parameters() {
+        assert_eq!(parse("\x1b[m"), [Action::SetGraphics(params(&[0]))]);
+        assert_eq!(
+            parse("\x1b[1;31;48;5;20m"),
+            [Action::SetGraphics(params(&[1, 31, 48, 5, 20]))]
+        );
+    }
+
+    #[test]
+    fn sgr_standard_colors() {
+        let (mut vt, mut grid) = terminal(10, 5);
+
+        feed(&mut vt, &mut grid, "\x1b[31;42m");
+        assert_eq!(grid.colors(), (rgb(205, 0, 0), rgb(0, 205, 0)));
+        feed(&mut vt, &mut grid, "\x1b[94;107m");
+        assert_eq!(grid.colors(), (rgb(92, 92, 255), rgb(255, 255, 255)));
+
+        // Bold brightens only the eight standard foreground colours.
+        feed(&mut vt, &mut grid, "\x1b[1;31;41m");
+        assert_eq!(grid.colors(), (rgb(255, 0, 0), rgb(205, 0, 0)));
+        feed(&mut vt, &mut grid, "\x1b[22m");
+        assert_eq!(grid.colors().0, rgb(205, 0, 0));
+
+        feed(&mut vt, &mut grid, "\x1b[7m");
+        assert_eq!(grid.colors(), (rgb(205, 0, 0), rgb(205, 0, 0)));
+        feed(&mut vt, &mut grid, "\x1b[27;39;49m");
+        assert_eq!(grid.colors(), (Rgb565::WHITE, Rgb565::BLACK));
+    }
+
+    #[test]
+    fn sgr_256_colors() {
+        let (mut vt, mut grid) = terminal(10, 5);
+
+        feed(&mut vt, &mut grid, "\x1b[38;5;196;48;5;232m");
+        assert_eq!(grid.colors(), (rgb(255, 0, 0), rgb(8, 8, 8)));
+
+        assert_eq!(indexed_color(9), rgb(255, 0, 0));
+        assert_eq!(indexed_color(16), rgb(0, 0, 0));
+        assert_eq!(indexed_color(110), rgb(135, 175, 215));
+        assert_eq!(indexed_color(231), rgb(255, 255, 255));
+        assert_eq!(indexed_color(255), rgb(238, 238, 238));
+    }
+
+    #[test]
+    fn sgr_truecolor() {
+        let (mut vt, mut grid) = terminal(10, 5);
+
+        feed(&mut vt, &mut grid, "\x1b[38;2;10;20;30;48;2;255;128;0m");
+        assert_eq!(grid.colors(), (rgb(10, 20, 30), rgb(255, 128, 0)));
+
+        // Channels saturate, and an incomplete colour is ignored.
+        feed(&mut vt, &mut grid, "\x1b[38;2;300;0;0m\x1b[48;2;1m");
+        assert_eq!(grid.colors(), (rgb(255, 0, 0), rgb(255, 128, 0)));
+
+        feed(&mut vt, &mut grid, "\x1b[0m");
+        assert_eq!(grid.colors(), (Rgb565::WHITE, Rgb565::BLACK));
+    }
+
+    #[test]
+    fn default_colors() {
+        let (mut vt, mut grid) = terminal(10, 5);
+
+        vt.set_default_colors(&mut grid, Rgb565::GREEN, Rgb565::BLUE);
+        assert_eq!(grid.colors(), (Rgb565::GREEN, Rgb565::BLUE));
+        feed(&mut vt, &mut grid, "\x1b[31m");
+        assert_eq!(grid.colors(), (rgb(205, 0, 0), Rgb565::BLUE));
+    }
+
+    #[test]
+    fn erase() {
+        let expected = [
+            Action::EraseLine(Erase::ToEnd),
+            Action::EraseLine(Erase::ToStart),
+            Action::EraseLine(Erase::All),
+            Action::EraseDisplay(Erase::ToEnd),
+            Action::EraseDisplay(Erase::All),
+        ];
+        assert_eq!(parse("\x1b[K\x1b[1K\x1b[2K\x1b[J\x1b[3J\x1b[2J"), expected);
+
+        let (mut vt, mut grid) = terminal(10, 5);
+        feed(&mut vt, &mut grid, "abcdef\x1b[3G");
+        let damage = feed(&mut vt, &mut grid, "\x1b[K");
+        assert_eq!(
+            damage.cells,
+            Some(CellSpan {
+                row: 0,
+                start: 2,
+                end: 10
+            })
+        );
+        assert_eq!(grid.cell(1, 0).unwrap().ch, 'b');
+        assert_eq!(grid.cell(2, 0).unwrap().ch, ' ');
+
+        let damage = feed(&mut vt, &mut grid, "\x1b[2J");
+        assert_eq!(damage.rows, Some(RowSpan { start: 0, end: 5 }));
+        assert_eq!(grid.cell(0, 0).unwrap().ch, ' ');
+    }
+
+    #[test]
+    fn scroll_region() {
+        assert_eq!(
+            parse("\x1b[2;4r\x1b[r"),
+            [
+                Action::SetScrollRegion {
+                    top: 1,
+                    bottom: Some(3),
+                },
+                Action::SetScrollRegion {
+                    top: 0,
+                    bottom: None,
+                },
+            ]
+        );
+
+        let (mut vt, mut grid) = terminal(10, 6);
+        feed(&mut vt, &mut grid, "\x1b[3;3H\x1b[2;4r");
+        assert_eq!(grid.scroll_region(), (1, 3));
+        assert_eq!(grid.cursor(), (0, 0));
+
+        let damage = feed(&mut vt, &mut grid, "\x1b[4Ha\nb");
+        assert_eq!(damage.rows, Some(RowSpan { start: 1, end: 4 }));
+        assert_eq!(grid.cell(0, 2).unwrap().ch, 'a');
+        assert_eq!(grid.cell(0, 3).unwrap().ch, 'b');
+
+        feed(&mut vt, &mut grid, "\x1b[r");
+        assert_eq!(grid.scroll_region(), (0, 5));
+    }
+
+    #[test]
+    fn long_csi_sequences() {
+        // Parameters beyond the limit are dropped, and values saturate.
+        let mut sgr = heapless::String::<U64>::new();
+        sgr.push_str("\x1b[").unwrap();
+        for _ in 0..20 {
+            sgr.push_str("1;").unwrap();
+        }
+        sgr.push('m').unwrap();
+        assert_eq!(parse(&sgr), [Action::SetGraphics(params(&[1; 16]))]);
+
+        assert_eq!(parse("\x1b[999999A"), [Action::CursorUp(u16::MAX)]);
+    }
+
+    #[test]
+    fn ignored_csi_sequences() {
+        // Private sequences, unknown final bytes, intermediate bytes and
+        // invalid erase parameters are all ignored.
+        assert_eq!(parse("\x1b[?25la"), [Action::Print('a')]);
+        assert_eq!(parse("\x1b[>0cb"), [Action::Print('b')]);
+        assert_eq!(parse("\x1b[5zc"), [Action::Print('c')]);
+        assert_eq!(parse("\x1b[1 qd"), [Action::Print('d')]);
+        assert_eq!(parse("\x1b[4Ke"), [Action::Print('e')]);
+
+        // A control character inside a sequence is still executed.
+        assert_eq!(
+            parse("\x1b[1\n2H"),
+            [
+                Action::Execute('\n'),
+                Action::CursorPosition { row: 11, column: 0 },
+            ]
+        );
+    }
+
+    #[test]
+    fn cancelled_sequences() {
+        assert_eq!(parse("\x1b[12\x18x"), [Action::Print('x')]);
+        assert_eq!(parse("\x1b[12\x1ay"), [Action::Print('y')]);
+        assert_eq!(
+            parse("\x1b[12\x1b[Az"),
+            [Action::CursorUp(1), Action::Print('z')]
+        );
+    }
+
+    #[test]
+    fn escape_sequences() {
+        assert_eq!(
+            parse("\x1b7\x1b8\x1bc"),
+            [Action::SaveCursor, Action::RestoreCursor, Action::Reset]
+        );
+        // Character set designations and unknown escapes are ignored.
+        assert_eq!(
+            parse("\x1b(Bx\x1b#8y\x1bZz"),
+            [Action::Print('x'), Action::Print('y'), Action::Print('z'),]
+        );
+    }
+
+    #[test]
+    fn osc_is_skipped() {
+        assert_eq!(parse("\x1b]0;title\x07x"), [Action::Print('x')]);
+        // The string terminator is ESC followed by a backslash.
+        assert_eq!(parse("\x1b]2;title\x1b\\y"), [Action::Print('y')]);
+        // Control characters other than BEL do not end it.
+        assert_eq!(parse("\x1b]0;a\nb\x07z"), [Action::Print('z')]);
+    }
+
+    #[test]
+    fn reset() {
+        let (mut vt, mut grid) = terminal(10, 5);
+
+        feed(&mut vt, &mut grid, "\x1b[2;4r\x1b[31mab\x1b[2;2H\x1b7");
+        let damage = feed(&mut vt, &mut grid, "\x1bc");
+        assert_eq!(damage.rows, Some(RowSpan { start: 0, end: 5 }));
+        assert_eq!(grid.cell(0, 0).unwrap().ch, ' ');
+        assert_eq!(grid.cursor(), (0, 0));
+        assert_eq!(grid.scroll_region(), (0, 4));
+        assert_eq!(grid.colors(), (Rgb565::WHITE, Rgb565::BLACK));
+
+        feed(&mut vt, &mut grid, "\x1b[3;3H\x1b8");
+        assert_eq!(grid.cursor(), (0, 0));
+    }
+
+    #[test]
+    fn random_input_keeps_cursor_in_grid() {
+        // Bytes from the sequences above are far more likely than others, so
+        // that the random input reaches every state of the parser.
+        const INTERESTING: &[u8] =
+            b"\x1b\x1b[[]];;0123456789?\x07\x18\n\r\t\x08 HfJKmrABCDEFGdsu78c";
+
+        let (mut vt, mut grid) = terminal(12, 6);
+        let mut state = 0x2545_f491_u32;
+        let mut random = move || {
+            // Xorshift, which is plenty for this.
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+
+        for _ in 0..200_000 {
+            let r = random();
+            let byte = if r & 1 == 0 {
+                INTERESTING[(r >> 8) as usize % INTERESTING.len()]
+            } else {
+                (r >> 8) as u8
+            };
+            let damage = vt.feed(&mut grid, byte as char);
+
+            let (column, row) = grid.cursor();
+            assert!(column <= grid.columns() && row < grid.rows());
+            let (top, bottom) = grid.scroll_region();
+            assert!(top < bottom && bottom < grid.rows());
+            if let Some(cells) = damage.cells {
+                assert!(cells.row < grid.rows());
+                assert!(cells.start <= cells.end && cells.end <= grid.columns());
+            }
+            if let Some(rows) = damage.rows {
+                assert!(rows.start <= rows.end && rows.end <= grid.rows());
+            }
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use embedded_graphics::pixelcolor::Rgb565;
+
+// The fonts converted from `assets/fonts` by the build script.
+include!(concat!(env!("OUT_DIR"), "/fonts.rs"));
+
+/// A run of consecutive code points with glyphs, stored one after another
+/// starting at glyph `index`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GlyphRange {
+    pub start: u32,
+    pub len: u16,
+    pub index: u16,
+}
+
+/// A fixed-width bitmap font.
+///
+/// Each glyph fills the whole character cell, packed one bit per pixel in
+/// row-major order with the most significant bit first, so a 6x12 glyph takes
+/// nine bytes. Fonts are generated from BDF files in `assets/fonts` when the
+/// crate is built; [`WIO_6X12`] covers ASCII, Latin-1, arrows, box drawing and
+/// block elements.
+#[derive(Clone, Copy, Debug)]
+pub struct BitmapFont {
+    width: u8,
+    height: u8,
+    ranges: &'static [GlyphRange],
+    bitmaps: &'static [u8],
+    default_glyph: u16,
+}
+
+impl BitmapFont {
+    /// Create a font from its glyph ranges, sorted by code point, and packed
+    /// bitmaps. The default glyph is drawn for characters the font lacks.
+    pub const fn new(
+        width: u8,
+        height: u8,
+        ranges: &'static [GlyphRange],
+        bitmaps: &'static [u8],
+        default_glyph: u16,
+    ) -> Self {
+        Self {
+            width,
+            height,
+            ranges,
+            bitmaps,
+            default_glyph,
+        }
+    }
+
+    /// Return the width of a character cell, in pixels.
+    pub fn width(&self) -> u8 {
+        self.width
+    }
+
+    /// Return the height of a character cell, in pixels.
+    pub fn height(&self) -> u8 {
+        self.height
+    }
+
+    /// Return the glyph for `c`, if the font has one.
+    pub fn glyph(&self, c: char) -> Option<Glyph> {
+        let c = c as u32;
+        let range = match self.ranges.binary_search_by(|range| {
+            if c < range.start {
+                core::cmp::Ordering::Greater
+            } else if c - range.start >= range.len as u32 {
+                core::cmp::Ordering::Less
+            } else {
+                core::cmp::Ordering::Equal
+            }
+        }) {
+            Ok(i) => &self.ranges[i],
+            Err(_) => return None,
+        };
+
+        Some(self.glyph_at(range.index as usize + (c - range.start) as usize))
+    }
+
+    /// Return the glyph for `c`, or the default glyph if the font has none.
+    pub fn glyph_or_default(&self, c: char) -> Glyph {
+        self.glyph(c)
+            .unwrap_or_else(|| self.glyph_at(self.default_glyph as usize))
+    }
+
+    fn glyph_at(&self, index: usize) -> Glyph {
+        let size = (self.width as usize * self.height as usize + 7) / 8;
+        Glyph {
+            data: &self.bitmaps[index * size..(index + 1) * size],
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+/// The bitmap of a single character from a [`BitmapFont`].
+#[derive(Clone, Copy, Debug)]
+pub struct Glyph {
+    data: &'static [u8],
+    width: u8,
+    height: u8,
+}
+
+impl Glyph {
+    /// Return whether the pixel at (`x`, `y`) is set. Pixels outside the
+    /// glyph are never set.
+    pub fn pixel(&self, x: u8, y: u8) -> bool {
+        if x >= self.width || y >= self.height {
+            return false;
+        }
+
+        let bit = y as usize * self.width as usize + x as usize;
+        self.data[bit / 8] & (0x80 >> (bit % 8)) != 0
+    }
+
+    /// Return an iterator over the glyph's pixels in row-major order, drawn in
+    /// `fg` on `bg`, suitable for writing to a display window.
+    pub fn pixels(&self, fg: Rgb565, bg: Rgb565) -> impl Iterator<Item = Rgb565> {
+        let glyph = *self;
+        (0..glyph.height).flat_map(move |y| {
+            (0..glyph.width).map(move |x| if glyph.pixel(x, y) { fg } else { bg })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Return the rows of a glyph as they are written in a BDF file: one byte
+    // per row, with the leftmost pixel in the most significant bit.
+    fn rows(glyph: Glyph) -> [u8; 12] {
+        let mut rows = [0; 12];
+        for (y, row) in rows.iter_mut().enumerate() {
+            for x in 0..glyph.width {
+                if glyph.pixel(x, y as u8) {
+                    *row |= 0x80 >> x;
+                }
+            }
+        }
+        rows
+    }
+
+    #[test]
+    fn glyphs_fill_the_cell() {
+        assert_eq!((WIO_6X12.width(), WIO_6X12.height()), (6, 12));
+
+        let glyphs: usize = WIO_6X12.ranges.iter().map(|range| range.len as usize).sum();
+        assert_eq!(WIO_6X12.bitmaps.len(), glyphs * 9);
+        assert_eq!(WIO_6X12.glyph('A').unwrap().data.len(), 9);
+    }
+
+    #[test]
+    fn ranges_are_sorted_and_consecutive() {
+        let mut index = 0;
+        for pair in WIO_6X12.ranges.windows(2) {
+            assert!(pair[0].start + (pair[0].len as u32) < pair[1].start);
+        }
+        for range in WIO_6X12.ranges {
+            assert_eq!(range.index, index);
+            index += range.len;
+        }
+    }
+
+    #[test]
+    fn glyphs_are_found_at_the_ends_of_each_range() {
+        for range in WIO_6X12.ranges {
+            let first = core::char::from_u32(range.start).unwrap();
+            let last = core::char::from_u32(range.start + range.len as u32 - 1).unwrap();
+            let after = core::char::from_u32(range.start + range.len as u32).unwrap();
+
+            assert_eq!(
+                WIO_6X12.glyph(first).unwrap().data.as_ptr(),
+                WIO_6X12.bitmaps[range.index as usize * 9..].as_ptr()
+            );
+            assert!(WIO_6X12.glyph(last).is_some());
+            assert!(WIO_6X12.glyph(after).is_none());
+        }
+    }
+
+    #[test]
+    fn glyphs_match_the_bdf_source() {
+        assert_eq!(rows(WIO_6X12.glyph(' ').unwrap()), [0; 12]);
+        assert_eq!(
+            rows(WIO_6X12.glyph('A').unwrap()),
+            [0, 0, 0x70, 0x88, 0x88, 0x88, 0xf8, 0x88, 0x88, 0, 0, 0]
+        );
+        assert_eq!(rows(WIO_6X12.glyph('█').unwrap()), [0xfc; 12]);
+    }
+
+    #[test]
+    fn missing_glyphs_use_the_default() {
+        assert!(WIO_6X12.glyph('\u{4e00}').is_none());
+        assert!(WIO_6X12.glyph('\u{1f600}').is_none());
+
+        let default = WIO_6X12.glyph(core::char::REPLACEMENT_CHARACTER).unwrap();
+        let fallback = WIO_6X12.glyph_or_default('\u{4e00}');
+        assert_eq!(fallback.data, default.data);
+        assert_eq!(
+            WIO_6X12.glyph_or_default('A').data,
+            WIO_6X12.glyph('A').unwrap().data
+        );
+    }
+
+    #[test]
+    fn pixels_outside_the_glyph_are_clear() {
+        let block = WIO_6X12.glyph('█').unwrap();
+        assert!(block.pixel(5, 11));
+        assert!(!block.pixel(6, 0));
+        assert!(!block.pixel(0, 12));
+
+        let fg = Rgb565::new(31, 0, 0);
+        let bg = Rgb565::new(0, 0, 0);
+        let a = WIO_6X12.glyph('A').unwrap();
+        assert_eq!(a.pixels(fg, bg).count(), 72);
+        // The third row of `A` is `.###..`.
+        assert!(a
+            .pixels(fg, bg)
+            .skip(12)
+            .take(6)
+            .eq([bg, fg, fg, fg, bg, bg].iter().copied()));
+    }
+}
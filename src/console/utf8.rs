@@ -0,0 +1,283 @@
+use core::char::REPLACEMENT_CHARACTER;
+
+/// A streaming UTF-8 decoder, which keeps incomplete sequences between calls
+/// so that text split across several reads or packets decodes correctly.
+///
+/// Invalid input is replaced with `U+FFFD`, following the WHATWG decoder:
+/// each maximal invalid subsequence becomes a single replacement character.
+#[derive(Clone, Copy, Debug)]
+pub struct Utf8Decoder {
+    code: u32,
+    needed: u8,
+    seen: u8,
+    lower: u8,
+    upper: u8,
+}
+
+// The result of feeding a single byte to the decoder.
+enum Step {
+    Pending,
+    Char(char),
+    // An invalid sequence ended; `consumed` is false if the byte must be fed
+    // again, as the start of a new sequence.
+    Invalid { consumed: bool },
+}
+
+impl Utf8Decoder {
+    /// Create a decoder with no incomplete sequence.
+    pub const fn new() -> Self {
+        Self {
+            code: 0,
+            needed: 0,
+            seen: 0,
+            lower: 0x80,
+            upper: 0xbf,
+        }
+    }
+
+    /// Return whether the decoder is part way through a sequence.
+    pub fn is_pending(&self) -> bool {
+        self.needed != 0
+    }
+
+    /// Return an iterator over the characters decoded from `bytes`. A
+    /// sequence left incomplete at the end of `bytes` is continued by the
+    /// next call.
+    pub fn decode<'a>(&'a mut self, bytes: &'a [u8]) -> Chars<'a> {
+        Chars {
+            decoder: self,
+            bytes,
+            index: 0,
+        }
+    }
+
+    /// Discard any incomplete sequence, returning a replacement character if
+    /// there was one.
+    pub fn finish(&mut self) -> Option<char> {
+        let pending = self.is_pending();
+        *self = Self::new();
+
+        if pending {
+            Some(REPLACEMENT_CHARACTER)
+        } else {
+            None
+        }
+    }
+
+    fn step(&mut self, byte: u8) -> Step {
+        if self.needed == 0 {
+            match byte {
+                0x00..=0x7f => return Step::Char(byte as char),
+                0xc2..=0xdf => {
+                    self.needed = 1;
+                    self.code = (byte & 0x1f) as u32;
+                }
+                0xe0..=0xef => {
+                    match byte {
+                        0xe0 => self.lower = 0xa0,
+                        0xed => self.upper = 0x9f,
+                        _ => {}
+                    }
+                    self.needed = 2;
+                    self.code = (byte & 0x0f) as u32;
+                }
+                0xf0..=0xf4 => {
+                    match byte {
+                        0xf0 => self.lower = 0x90,
+                        0xf4 => self.upper = 0x8f,
+                        _ => {}
+                    }
+                    self.needed = 3;
+                    self.code = (byte & 0x07) as u32;
+                }
+                _ => return Step::Invalid { consumed: true },
+            }
+            return Step::Pending;
+        }
+
+        if byte < self.lower || byte > self.upper {
+            *self = Self::new();
+            return Step::Invalid { consumed: false };
+        }
+
+        self.lower = 0x80;
+        self.upper = 0xbf;
+        self.code = self.code << 6 | (byte & 0x3f) as u32;
+        self.seen += 1;
+
+        if self.seen < self.needed {
+            return Step::Pending;
+        }
+
+        let code = self.code;
+        *self = Self::new();
+        Step::Char(core::char::from_u32(code).unwrap_or(REPLACEMENT_CHARACTER))
+    }
+}
+
+impl Default for Utf8Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An iterator over the characters decoded by a [`Utf8Decoder`].
+pub struct Chars<'a> {
+    decoder: &'a mut Utf8Decoder,
+    bytes: &'a [u8],
+    index: usize,
+}
+
+impl<'a> Iterator for Chars<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        while let Some(&byte) = self.bytes.get(self.index) {
+            match self.decoder.step(byte) {
+                Step::Pending => self.index += 1,
+                Step::Char(c) => {
+                    self.index += 1;
+                    return Some(c);
+                }
+                Step::Invalid { consumed } => {
+                    if consumed {
+                        self.index += 1;
+                    }
+                    return Some(REPLACEMENT_CHARACTER);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use heapless::consts::U64;
+    use heapless::String;
+
+    // Decode each of `chunks` in turn with one decoder.
+    fn decode(chunks: &[&[u8]]) -> String<U64> {
+        let mut decoder = Utf8Decoder::new();
+        let mut text = String::new();
+        for chunk in chunks {
+            for c in decoder.decode(chunk) {
+                text.push(c).unwrap();
+            }
+        }
+        if let Some(c) = decoder.finish() {
+            text.push(c).unwrap();
+        }
+        text
+    }
+
+    #[test]
+    fn ascii_passes_through() {
+        assert_eq!(decode(&[b"Hello\r\n\x1b[0m"]).as_str(), "Hello\r\n\x1b[0m");
+        assert_eq!(decode(&[b"\x00\x7f"]).as_str(), "\u{0}\u{7f}");
+    }
+
+    #[test]
+    fn characters_split_across_calls() {
+        let mut decoder = Utf8Decoder::new();
+        assert_eq!(decoder.decode(&[0xc3]).next(), None);
+        assert!(decoder.is_pending());
+        assert_eq!(decoder.decode(&[0xa9]).next(), Some('é'));
+        assert!(!decoder.is_pending());
+
+        // A four-byte character fed one byte at a time.
+        assert_eq!(decode(&[&[0xf0], &[0x9f], &[0x98], &[0x80]]).as_str(), "😀");
+        assert_eq!(decode(&[b"a\xe2\x94", b"\x80b"]).as_str(), "a─b");
+    }
+
+    #[test]
+    fn every_split_decodes_the_same() {
+        let text = "Wio: é ─ € 😀 ÿ";
+        let bytes = text.as_bytes();
+        for split in 0..=bytes.len() {
+            assert_eq!(
+                decode(&[&bytes[..split], &bytes[split..]]).as_str(),
+                text,
+                "split at {}",
+                split
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_sequences() {
+        // The bytes read so far are replaced, and the byte which ended the
+        // sequence starts a new one.
+        assert_eq!(decode(&[b"\xe2\x82A"]).as_str(), "\u{fffd}A");
+        assert_eq!(decode(&[b"\xf0\x9f\x98", b"A"]).as_str(), "\u{fffd}A");
+        assert_eq!(decode(&[b"\xc3\xc3\xa9"]).as_str(), "\u{fffd}é");
+
+        // A sequence left incomplete is replaced once the input finishes.
+        let mut decoder = Utf8Decoder::new();
+        assert_eq!(decoder.decode(b"\xe2\x82").next(), None);
+        assert_eq!(decoder.finish(), Some(REPLACEMENT_CHARACTER));
+        assert_eq!(decoder.finish(), None);
+        assert_eq!(decoder.decode(b"A").next(), Some('A'));
+    }
+
+    #[test]
+    fn overlong_sequences() {
+        // Each byte of an overlong sequence is replaced on its own.
+        assert_eq!(decode(&[b"\xc0\x80"]).as_str(), "\u{fffd}\u{fffd}");
+        assert_eq!(decode(&[b"\xc1\xbf"]).as_str(), "\u{fffd}\u{fffd}");
+        assert_eq!(
+            decode(&[b"\xe0\x80\xaf"]).as_str(),
+            "\u{fffd}\u{fffd}\u{fffd}"
+        );
+        assert_eq!(
+            decode(&[b"\xf0\x8f\xbf\xbf"]).as_str(),
+            "\u{fffd}\u{fffd}\u{fffd}\u{fffd}"
+        );
+
+        // The shortest encodings just above the overlong ones are valid.
+        assert_eq!(decode(&[b"\xc2\x80"]).as_str(), "\u{80}");
+        assert_eq!(decode(&[b"\xe0\xa0\x80"]).as_str(), "\u{800}");
+        assert_eq!(decode(&[b"\xf0\x90\x80\x80"]).as_str(), "\u{10000}");
+    }
+
+    #[test]
+    fn surrogates() {
+        assert_eq!(
+            decode(&[b"\xed\xa0\x80"]).as_str(),
+            "\u{fffd}\u{fffd}\u{fffd}"
+        );
+        assert_eq!(
+            decode(&[b"\xed\xbf\xbf"]).as_str(),
+            "\u{fffd}\u{fffd}\u{fffd}"
+        );
+        assert_eq!(decode(&[b"\xed\x9f\xbf"]).as_str(), "\u{d7ff}");
+        assert_eq!(decode(&[b"\xee\x80\x80"]).as_str(), "\u{e000}");
+    }
+
+    #[test]
+    fn above_the_last_code_point() {
+        assert_eq!(decode(&[b"\xf4\x8f\xbf\xbf"]).as_str(), "\u{10ffff}");
+        assert_eq!(
+            decode(&[b"\xf4\x90\x80\x80"]).as_str(),
+            "\u{fffd}\u{fffd}\u{fffd}\u{fffd}"
+        );
+        assert_eq!(
+            decode(&[b"\xf5\x80\x80\x80"]).as_str(),
+            "\u{fffd}\u{fffd}\u{fffd}\u{fffd}"
+        );
+        assert_eq!(decode(&[b"\xfe\xff"]).as_str(), "\u{fffd}\u{fffd}");
+    }
+
+    #[test]
+    fn lone_continuation_bytes() {
+        assert_eq!(decode(&[b"\x80"]).as_str(), "\u{fffd}");
+        assert_eq!(decode(&[b"a\xbfb"]).as_str(), "a\u{fffd}b");
+        assert_eq!(
+            decode(&[b"\xc3\xa9\xa9", b"\x80"]).as_str(),
+            "é\u{fffd}\u{fffd}"
+        );
+    }
+}
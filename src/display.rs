@@ -4,10 +4,21 @@ use atsamd_hal::gpio::*;
 use atsamd_hal::hal::spi;
 use atsamd_hal::prelude::*;
 use atsamd_hal::sercom::{PadPin, SPIMaster7, Sercom7Pad1, Sercom7Pad2, Sercom7Pad3};
-use atsamd_hal::target_device::{MCLK, SERCOM7};
+use atsamd_hal::target_device::{DMAC, MCLK, SERCOM7};
+use atsamd_hal::time::Hertz;
 use display_interface_spi::SPIInterface;
 use ili9341::{Ili9341, Orientation};
 
+mod backlight;
+mod dma;
+mod framebuffer;
+mod rotate;
+
+pub use backlight::*;
+pub use dma::*;
+pub use framebuffer::*;
+pub use rotate::*;
+
 /// ILI9341 LCD display pins (uses `SERCOM7`)
 pub struct Display {
     /// LCD MISO pin
@@ -32,22 +43,100 @@ pub struct Display {
     pub backlight: Pc5<Input<Floating>>,
 }
 
+/// Type alias for the SPI bus connected to the LCD display.
+pub type LcdSpi =
+    SPIMaster7<Sercom7Pad2<Pb18<PfD>>, Sercom7Pad3<Pb19<PfD>>, Sercom7Pad1<Pb20<PfD>>>;
+
 /// Type alias for the ILI9341 LCD display.
 pub type LCD = Ili9341<
-    SPIInterface<
-        SPIMaster7<Sercom7Pad2<Pb18<PfD>>, Sercom7Pad3<Pb19<PfD>>, Sercom7Pad1<Pb20<PfD>>>,
-        Pc6<Output<PushPull>>,
-        Pb21<Output<PushPull>>,
-    >,
+    SPIInterface<LcdSpi, Pc6<Output<PushPull>>, Pb21<Output<PushPull>>>,
     Pc7<Output<PushPull>>,
 >;
 
 pub use ili9341::Scroller;
 
+/// Errors which can occur while initializing or drawing to the display.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayError {
+    /// The clock for `SERCOM7` could not be configured.
+    ClockConfiguration,
+    /// The ILI9341 could not be reset or initialized.
+    DriverInitialization,
+    /// A command could not be written to the ILI9341.
+    Interface,
+    /// The backlight pin could not be set.
+    BacklightPin,
+    /// A DMA transfer is still in progress.
+    Busy,
+    /// The pixel data is too large for a single DMA transfer.
+    TransferTooLarge,
+    /// The requested SPI frequency cannot be generated from the `SERCOM7`
+    /// clock.
+    InvalidSpiFrequency,
+    /// Colour inversion was requested from [`Display::init_with_config`],
+    /// but the `LCD` driver cannot send the command; use the `DmaLCD`
+    /// instead.
+    InversionUnsupported,
+}
+
+/// The default SPI frequency of the LCD display.
+pub const DEFAULT_SPI_FREQUENCY: u32 = 20_000_000;
+
+/// Configuration for [`Display::init_with_config`] and
+/// [`Display::init_dma_with_config`].
+pub struct DisplayConfig {
+    /// The frequency of the SPI bus. It must be no more than half of the
+    /// `SERCOM7` clock (60MHz when clocked from `GCLK0` at 120MHz), and no
+    /// less than 1/512th of it.
+    pub spi_frequency: Hertz,
+    /// The initial orientation of the display.
+    pub orientation: Orientation,
+    /// Whether the backlight is initially on.
+    pub backlight: bool,
+    /// Whether the display inverts its colours.
+    ///
+    /// Only the `DmaLCD` supports inversion: the `ili9341` driver behind the
+    /// blocking `LCD` has no way to send the command, so
+    /// [`Display::init_with_config`] fails with
+    /// [`DisplayError::InversionUnsupported`] if this is set. Use
+    /// [`Display::init_dma_with_config`] for an inverted display.
+    pub invert_colors: bool,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            spi_frequency: Hertz(DEFAULT_SPI_FREQUENCY),
+            orientation: Orientation::LandscapeFlipped,
+            backlight: true,
+            invert_colors: false,
+        }
+    }
+}
+
+/// Check that the SPI frequency `requested` can be generated from the
+/// `SERCOM7` clock frequency `sercom_clock`, returning it if so.
+///
+/// In SPI master mode the SERCOM divides its clock by `2 * (BAUD + 1)`, where
+/// `BAUD` is an 8-bit value.
+pub fn validate_spi_frequency(
+    requested: Hertz,
+    sercom_clock: Hertz,
+) -> Result<Hertz, DisplayError> {
+    let max = sercom_clock.0 / 2;
+    let min = sercom_clock.0 / 512;
+
+    if requested.0 == 0 || requested.0 > max || requested.0 < min {
+        Err(DisplayError::InvalidSpiFrequency)
+    } else {
+        Ok(requested)
+    }
+}
+
 impl Display {
-    /// Initialize the display and its corresponding SPI bus peripheral. Return
-    /// a tuple containing the configured display driver struct and backlight
-    /// pin.
+    /// Initialize the display and its corresponding SPI bus peripheral, using
+    /// the default [`DisplayConfig`]. Return a tuple containing the configured
+    /// display driver struct and backlight pin.
     pub fn init(
         self,
         clocks: &mut GenericClockController,
@@ -55,13 +144,130 @@ impl Display {
         mclk: &mut MCLK,
         port: &mut Port,
         delay: &mut Delay,
-    ) -> Result<(LCD, Pc5<Output<PushPull>>), ()> {
+    ) -> Result<(LCD, Pc5<Output<PushPull>>), DisplayError> {
+        self.init_with_config(DisplayConfig::default(), clocks, sercom7, mclk, port, delay)
+    }
+
+    /// Initialize the display and its corresponding SPI bus peripheral, using
+    /// the given configuration. Return a tuple containing the configured
+    /// display driver struct and backlight pin.
+    ///
+    /// Colour inversion is not supported by the `LCD`, so this fails with
+    /// [`DisplayError::InversionUnsupported`] if
+    /// [`DisplayConfig::invert_colors`] is set.
+    pub fn init_with_config(
+        self,
+        config: DisplayConfig,
+        clocks: &mut GenericClockController,
+        sercom7: SERCOM7,
+        mclk: &mut MCLK,
+        port: &mut Port,
+        delay: &mut Delay,
+    ) -> Result<(LCD, Pc5<Output<PushPull>>), DisplayError> {
+        // The `ili9341` driver has no way to send the inversion command.
+        if config.invert_colors {
+            return Err(DisplayError::InversionUnsupported);
+        }
+
+        let (spi, cs, dc, reset, backlight) =
+            self.into_bus(config.spi_frequency, clocks, sercom7, mclk, port)?;
+
+        // Create a SPIInterface over the peripheral, then create the ILI9341 driver
+        // using said interface and set its initial orientation.
+        let interface = SPIInterface::new(spi, dc, cs);
+        let mut ili9341 = Ili9341::new(interface, reset, delay)
+            .map_err(|_| DisplayError::DriverInitialization)?;
+        ili9341
+            .set_orientation(config.orientation)
+            .map_err(|_| DisplayError::Interface)?;
+
+        let backlight = init_backlight(backlight, config.backlight, port)?;
+
+        // Return a result consisting of a Tuple containing the display driver and
+        // backlight pin.
+        Ok((ili9341, backlight))
+    }
+
+    /// Initialize the display for DMA transfers, using `SERCOM7` for the SPI
+    /// bus and channel 0 of the DMAC, and the default [`DisplayConfig`].
+    /// Return a tuple containing the configured display driver struct and
+    /// backlight pin.
+    pub fn init_dma(
+        self,
+        clocks: &mut GenericClockController,
+        sercom7: SERCOM7,
+        dmac: DMAC,
+        mclk: &mut MCLK,
+        port: &mut Port,
+        delay: &mut Delay,
+    ) -> Result<(DmaLCD, Pc5<Output<PushPull>>), DisplayError> {
+        self.init_dma_with_config(
+            DisplayConfig::default(),
+            clocks,
+            sercom7,
+            dmac,
+            mclk,
+            port,
+            delay,
+        )
+    }
+
+    /// Initialize the display for DMA transfers, using `SERCOM7` for the SPI
+    /// bus and channel 0 of the DMAC, and the given configuration. Return a
+    /// tuple containing the configured display driver struct and backlight
+    /// pin.
+    #[allow(clippy::too_many_arguments)]
+    pub fn init_dma_with_config(
+        self,
+        config: DisplayConfig,
+        clocks: &mut GenericClockController,
+        sercom7: SERCOM7,
+        dmac: DMAC,
+        mclk: &mut MCLK,
+        port: &mut Port,
+        delay: &mut Delay,
+    ) -> Result<(DmaLCD, Pc5<Output<PushPull>>), DisplayError> {
+        let (spi, cs, dc, reset, backlight) =
+            self.into_bus(config.spi_frequency, clocks, sercom7, mclk, port)?;
+
+        let mut lcd = DmaLCD::new(spi, dc, cs, reset, dmac, mclk, delay)?;
+        lcd.set_orientation(config.orientation)?;
+        lcd.invert_colors(config.invert_colors)?;
+
+        let backlight = init_backlight(backlight, config.backlight, port)?;
+
+        Ok((lcd, backlight))
+    }
+
+    // Initialize the SPI peripheral and configure the control pins, leaving the
+    // backlight pin untouched.
+    fn into_bus(
+        self,
+        frequency: Hertz,
+        clocks: &mut GenericClockController,
+        sercom7: SERCOM7,
+        mclk: &mut MCLK,
+        port: &mut Port,
+    ) -> Result<
+        (
+            LcdSpi,
+            Pb21<Output<PushPull>>,
+            Pc6<Output<PushPull>>,
+            Pc7<Output<PushPull>>,
+            Pc5<Input<Floating>>,
+        ),
+        DisplayError,
+    > {
         // Initialize the SPI peripherial on the configured pins, using SERCOM7 and
-        // running at 20MHz.
+        // running at the requested frequency.
         let gclk0 = clocks.gclk0();
+        let clock = clocks
+            .sercom7_core(&gclk0)
+            .ok_or(DisplayError::ClockConfiguration)?;
+        let frequency = validate_spi_frequency(frequency, clock.freq())?;
         let spi = SPIMaster7::new(
-            &clocks.sercom7_core(&gclk0).ok_or(())?,
-            20.mhz(),
+            &clock,
+            frequency,
             spi::Mode {
                 phase: spi::Phase::CaptureOnFirstTransition,
                 polarity: spi::Polarity::IdleLow,
@@ -80,23 +286,64 @@ impl Display {
         let dc = self.dc.into_push_pull_output(port);
         let reset = self.reset.into_push_pull_output(port);
 
-        // Create a SPIInterface over the peripheral, then create the ILI9341 driver
-        // using said interface and set its default orientation.
-        let interface = SPIInterface::new(spi, dc, cs);
-        let mut ili9341 = Ili9341::new(interface, reset, delay).map_err(|_| ())?;
-        ili9341
-            .set_orientation(Orientation::LandscapeFlipped)
-            .map_err(|_| ())?;
+        Ok((spi, cs, dc, reset, self.backlight))
+    }
+}
 
-        // Configure the backlight pin as a push-pull output; unfortunately this pin
-        // does not appear to support PWM.
-        //   HIGH - backlight enabled
-        //   LOW  - backlight disabled
-        let mut backlight = self.backlight.into_push_pull_output(port);
-        backlight.set_high()?;
+fn init_backlight(
+    backlight: Pc5<Input<Floating>>,
+    enabled: bool,
+    port: &mut Port,
+) -> Result<Pc5<Output<PushPull>>, DisplayError> {
+    // Configure the backlight pin as a push-pull output; unfortunately this pin
+    // does not appear to support PWM, so see `Backlight` for brightness
+    // control.
+    //   HIGH - backlight enabled
+    //   LOW  - backlight disabled
+    let mut backlight = backlight.into_push_pull_output(port);
+    let result = if enabled {
+        backlight.set_high()
+    } else {
+        backlight.set_low()
+    };
+    result.map_err(|_| DisplayError::BacklightPin)?;
 
-        // Return a result consisting of a Tuple containing the display driver and
-        // backlight pin.
-        Ok((ili9341, backlight))
+    Ok(backlight)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `SERCOM7` clocked from `GCLK0` at 120MHz.
+    const SERCOM_CLOCK: Hertz = Hertz(120_000_000);
+    const MIN: u32 = 234_375;
+    const MAX: u32 = 60_000_000;
+
+    fn validate(requested: u32) -> Result<u32, DisplayError> {
+        validate_spi_frequency(Hertz(requested), SERCOM_CLOCK).map(|hertz| hertz.0)
+    }
+
+    #[test]
+    fn zero_is_rejected() {
+        assert_eq!(validate(0), Err(DisplayError::InvalidSpiFrequency));
+        assert_eq!(
+            validate_spi_frequency(Hertz(0), Hertz(100)).map(|hertz| hertz.0),
+            Err(DisplayError::InvalidSpiFrequency)
+        );
+    }
+
+    #[test]
+    fn limits_are_accepted() {
+        assert_eq!(validate(MIN), Ok(MIN));
+        assert_eq!(validate(DEFAULT_SPI_FREQUENCY), Ok(DEFAULT_SPI_FREQUENCY));
+        assert_eq!(validate(MAX), Ok(MAX));
+    }
+
+    #[test]
+    fn frequencies_outside_the_limits_are_rejected() {
+        assert_eq!(validate(MIN - 1), Err(DisplayError::InvalidSpiFrequency));
+        assert_eq!(validate(MAX + 1), Err(DisplayError::InvalidSpiFrequency));
+        assert_eq!(validate(u32::MAX), Err(DisplayError::InvalidSpiFrequency));
     }
 }
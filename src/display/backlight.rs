@@ -0,0 +1,361 @@
+use atsamd_hal::gpio::{Output, Pc5, PushPull};
+use atsamd_hal::hal::timer::CountDown;
+use atsamd_hal::prelude::*;
+use atsamd_hal::timer::{Count16, TimerCounter};
+
+/// The frequency of the backlight's software PWM, in Hz.
+pub const PWM_FREQUENCY: u32 = 200;
+
+/// The number of timer ticks in one PWM period; a duty cycle of `PWM_STEPS`
+/// keeps the backlight on for the whole period.
+pub const PWM_STEPS: u16 = 1000;
+
+// Perceived brightness is not linear in duty cycle, so map brightness to duty
+// cycle with a gamma of 2.2. Any non-zero brightness keeps the backlight on
+// for at least 4 ticks, to bound the timer interrupt rate.
+#[rustfmt::skip]
+const GAMMA: [u16; 101] = [
+    0, 4, 4, 4, 4, 4, 4, 4, 4, 5,
+    6, 8, 9, 11, 13, 15, 18, 20, 23, 26,
+    29, 32, 36, 39, 43, 47, 52, 56, 61, 66,
+    71, 76, 82, 87, 93, 99, 106, 112, 119, 126,
+    133, 141, 148, 156, 164, 173, 181, 190, 199, 208,
+    218, 227, 237, 247, 258, 268, 279, 290, 302, 313,
+    325, 337, 349, 362, 375, 388, 401, 414, 428, 442,
+    456, 471, 485, 500, 516, 531, 547, 563, 579, 595,
+    612, 629, 646, 664, 681, 699, 718, 736, 755, 774,
+    793, 813, 832, 852, 873, 893, 914, 935, 957, 978,
+    1000,
+];
+
+/// Return the duty cycle, out of [`PWM_STEPS`], for the given brightness
+/// percentage. Brightness above 100 is treated as 100.
+pub fn brightness_to_duty(brightness: u8) -> u16 {
+    GAMMA[brightness.min(100) as usize]
+}
+
+/// Software PWM, splitting each period into an on phase and an off phase.
+///
+/// Each call to [`SoftPwm::next_phase`] returns the level for the next phase, and
+/// how many ticks it lasts; a fully on or off duty cycle is a single phase
+/// lasting the whole period.
+#[derive(Clone, Copy, Debug)]
+pub struct SoftPwm {
+    duty: u16,
+    on: bool,
+}
+
+impl SoftPwm {
+    /// Create a new PWM with a duty cycle of zero.
+    pub const fn new() -> Self {
+        Self { duty: 0, on: false }
+    }
+
+    /// Set the duty cycle, out of [`PWM_STEPS`]. This takes effect from the
+    /// start of the next period.
+    pub fn set_duty(&mut self, duty: u16) {
+        self.duty = duty.min(PWM_STEPS);
+    }
+
+    /// Return the duty cycle, out of [`PWM_STEPS`].
+    pub fn duty(&self) -> u16 {
+        self.duty
+    }
+
+    /// Advance to the next phase, and return its level and length in ticks.
+    pub fn next_phase(&mut self) -> (bool, u16) {
+        match self.duty {
+            0 => {
+                self.on = false;
+                (false, PWM_STEPS)
+            }
+            PWM_STEPS => {
+                self.on = true;
+                (true, PWM_STEPS)
+            }
+            duty if !self.on => {
+                self.on = true;
+                (true, duty)
+            }
+            duty => {
+                self.on = false;
+                (false, PWM_STEPS - duty)
+            }
+        }
+    }
+}
+
+impl Default for SoftPwm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A linear change in brightness over time.
+#[derive(Clone, Copy, Debug)]
+pub struct Ramp {
+    from: u8,
+    to: u8,
+    start_ms: u32,
+    duration_ms: u32,
+}
+
+impl Ramp {
+    /// Create a ramp from one brightness to another, starting at `start_ms`.
+    pub const fn new(from: u8, to: u8, start_ms: u32, duration_ms: u32) -> Self {
+        Self {
+            from,
+            to,
+            start_ms,
+            duration_ms,
+        }
+    }
+
+    /// Return the brightness at `now_ms`. Time is allowed to wrap around.
+    pub fn level(&self, now_ms: u32) -> u8 {
+        let elapsed = now_ms.wrapping_sub(self.start_ms);
+        if elapsed >= self.duration_ms {
+            return self.to;
+        }
+
+        let from = self.from as i64;
+        let delta = self.to as i64 - from;
+        (from + delta * elapsed as i64 / self.duration_ms as i64) as u8
+    }
+
+    /// Return whether the ramp has finished at `now_ms`.
+    pub fn is_done(&self, now_ms: u32) -> bool {
+        now_ms.wrapping_sub(self.start_ms) >= self.duration_ms
+    }
+
+    /// Return the brightness at the end of the ramp.
+    pub fn target(&self) -> u8 {
+        self.to
+    }
+}
+
+// Dim the backlight once there has been no activity for `timeout_ms`.
+#[derive(Clone, Copy, Debug)]
+struct SleepDim {
+    timeout_ms: u32,
+    level: u8,
+    fade_ms: u32,
+    last_activity_ms: u32,
+    dimmed: bool,
+}
+
+/// The LCD backlight, with its brightness controlled by software PWM.
+///
+/// The backlight pin has no PWM output, so it is toggled from the interrupt
+/// of the given timer/counter instead: [`Backlight::interrupt`] must be called
+/// from that TC's interrupt handler, and the interrupt unmasked in the NVIC.
+/// Fades and the sleep timeout are driven by calling [`Backlight::update`]
+/// with the current time in milliseconds.
+pub struct Backlight<TC> {
+    pin: Pc5<Output<PushPull>>,
+    timer: TimerCounter<TC>,
+    pwm: SoftPwm,
+    brightness: u8,
+    awake_brightness: u8,
+    ramp: Option<Ramp>,
+    sleep: Option<SleepDim>,
+}
+
+impl<TC> Backlight<TC>
+where
+    TC: Count16,
+{
+    /// Take control of the backlight pin, as returned by
+    /// [`Display::init`](crate::Display::init), using the given
+    /// timer/counter. The backlight starts at full brightness.
+    pub fn new(pin: Pc5<Output<PushPull>>, mut timer: TimerCounter<TC>) -> Self {
+        let mut pwm = SoftPwm::new();
+        pwm.set_duty(PWM_STEPS);
+
+        timer.start(PWM_FREQUENCY.hz());
+        timer.enable_interrupt();
+
+        Self {
+            pin,
+            timer,
+            pwm,
+            brightness: 100,
+            awake_brightness: 100,
+            ramp: None,
+            sleep: None,
+        }
+    }
+
+    /// Handle the timer/counter interrupt, advancing the software PWM.
+    pub fn interrupt(&mut self) {
+        self.timer.wait().ok();
+
+        let (on, ticks) = self.pwm.next_phase();
+        if on {
+            self.pin.set_high().ok();
+        } else {
+            self.pin.set_low().ok();
+        }
+
+        let ticks_per_second = PWM_FREQUENCY * PWM_STEPS as u32;
+        self.timer.start((ticks_per_second / ticks as u32).hz());
+    }
+
+    /// Set the brightness as a percentage, immediately. Cancels any fade in
+    /// progress.
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.ramp = None;
+        self.awake_brightness = brightness.min(100);
+        self.apply(brightness);
+    }
+
+    /// Return the current brightness as a percentage.
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// Fade from the current brightness to `target` over `duration_ms`.
+    pub fn fade_to(&mut self, target: u8, duration_ms: u32, now_ms: u32) {
+        let target = target.min(100);
+        self.awake_brightness = target;
+        self.ramp = Some(Ramp::new(self.brightness, target, now_ms, duration_ms));
+    }
+
+    /// Fade from off to full brightness over `duration_ms`.
+    pub fn fade_in(&mut self, duration_ms: u32, now_ms: u32) {
+        self.apply(0);
+        self.fade_to(100, duration_ms, now_ms);
+    }
+
+    /// Fade from the current brightness to off over `duration_ms`.
+    pub fn fade_out(&mut self, duration_ms: u32, now_ms: u32) {
+        self.fade_to(0, duration_ms, now_ms);
+    }
+
+    /// Dim the backlight to `level`, fading over `fade_ms`, once
+    /// [`Backlight::wake`] has not been called for `timeout_ms`.
+    pub fn set_sleep_timeout(&mut self, timeout_ms: u32, level: u8, fade_ms: u32, now_ms: u32) {
+        self.sleep = Some(SleepDim {
+            timeout_ms,
+            level: level.min(100),
+            fade_ms,
+            last_activity_ms: now_ms,
+            dimmed: false,
+        });
+    }
+
+    /// Disable the sleep timeout. If the backlight was dimmed, it is restored.
+    pub fn clear_sleep_timeout(&mut self, now_ms: u32) {
+        self.wake(now_ms);
+        self.sleep = None;
+    }
+
+    /// Record user activity, restarting the sleep timeout and restoring the
+    /// brightness if the backlight was dimmed.
+    pub fn wake(&mut self, now_ms: u32) {
+        if let Some(sleep) = self.sleep.as_mut() {
+            sleep.last_activity_ms = now_ms;
+            if sleep.dimmed {
+                sleep.dimmed = false;
+                let target = self.awake_brightness;
+                let fade_ms = sleep.fade_ms;
+                self.fade_to(target, fade_ms, now_ms);
+            }
+        }
+    }
+
+    /// Advance any fade in progress, and dim the backlight if the sleep
+    /// timeout has elapsed. Should be called regularly from the main loop.
+    pub fn update(&mut self, now_ms: u32) {
+        if let Some(sleep) = self.sleep.as_mut() {
+            let idle = now_ms.wrapping_sub(sleep.last_activity_ms);
+            if !sleep.dimmed && idle >= sleep.timeout_ms {
+                sleep.dimmed = true;
+                let ramp = Ramp::new(self.brightness, sleep.level, now_ms, sleep.fade_ms);
+                self.ramp = Some(ramp);
+            }
+        }
+
+        if let Some(ramp) = self.ramp {
+            self.apply(ramp.level(now_ms));
+            if ramp.is_done(now_ms) {
+                self.ramp = None;
+            }
+        }
+    }
+
+    /// Release the backlight pin and timer/counter. The timer's interrupt is
+    /// disabled.
+    pub fn release(mut self) -> (Pc5<Output<PushPull>>, TimerCounter<TC>) {
+        self.timer.disable_interrupt();
+        (self.pin, self.timer)
+    }
+
+    fn apply(&mut self, brightness: u8) {
+        self.brightness = brightness.min(100);
+        self.pwm.set_duty(brightness_to_duty(self.brightness));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duty_endpoints() {
+        assert_eq!(brightness_to_duty(0), 0);
+        assert_eq!(brightness_to_duty(100), PWM_STEPS);
+        assert_eq!(brightness_to_duty(255), PWM_STEPS);
+    }
+
+    #[test]
+    fn duty_never_decreases() {
+        for brightness in 1..=100 {
+            assert!(brightness_to_duty(brightness) >= brightness_to_duty(brightness - 1));
+        }
+    }
+
+    #[test]
+    fn duty_follows_gamma() {
+        // round(1000 * (brightness / 100) ^ 2.2)
+        assert_eq!(brightness_to_duty(10), 6);
+        assert_eq!(brightness_to_duty(25), 47);
+        assert_eq!(brightness_to_duty(50), 218);
+        assert_eq!(brightness_to_duty(75), 531);
+        assert_eq!(brightness_to_duty(90), 793);
+
+        // Dim levels are raised to the minimum on time.
+        assert_eq!(brightness_to_duty(1), 4);
+        assert_eq!(brightness_to_duty(8), 4);
+    }
+
+    #[test]
+    fn pwm_phases() {
+        let mut pwm = SoftPwm::default();
+        assert_eq!(pwm.next_phase(), (false, PWM_STEPS));
+
+        pwm.set_duty(250);
+        assert_eq!(pwm.next_phase(), (true, 250));
+        assert_eq!(pwm.next_phase(), (false, 750));
+        assert_eq!(pwm.next_phase(), (true, 250));
+
+        pwm.set_duty(PWM_STEPS + 1);
+        assert_eq!(pwm.duty(), PWM_STEPS);
+        assert_eq!(pwm.next_phase(), (true, PWM_STEPS));
+        assert_eq!(pwm.next_phase(), (true, PWM_STEPS));
+    }
+
+    #[test]
+    fn ramp() {
+        let up = Ramp::new(20, 80, u32::MAX - 99, 200);
+        assert_eq!(up.level(u32::MAX - 99), 20);
+        assert_eq!(up.level(0), 50);
+        assert!(!up.is_done(99));
+        assert_eq!(up.level(100), 80);
+        assert!(up.is_done(100));
+
+        let down = Ramp::new(80, 20, 0, 600);
+        assert_eq!(down.level(300), 50);
+        assert_eq!(down.target(), 20);
+    }
+}
@@ -0,0 +1,505 @@
+use core::ptr::addr_of;
+
+use atsamd_hal::delay::Delay;
+use atsamd_hal::gpio::*;
+use atsamd_hal::hal::blocking::spi::Write;
+use atsamd_hal::prelude::*;
+use atsamd_hal::target_device::{DMAC, MCLK, SERCOM7};
+use ili9341::Orientation;
+
+use super::{DisplayError, LcdSpi};
+
+// ILI9341 commands used by `DmaLCD`.
+const SOFTWARE_RESET: u8 = 0x01;
+const SLEEP_OUT: u8 = 0x11;
+const DISPLAY_INVERSION_OFF: u8 = 0x20;
+const DISPLAY_INVERSION_ON: u8 = 0x21;
+const DISPLAY_ON: u8 = 0x29;
+const COLUMN_ADDRESS_SET: u8 = 0x2a;
+const PAGE_ADDRESS_SET: u8 = 0x2b;
+const MEMORY_WRITE: u8 = 0x2c;
+const MEMORY_ACCESS_CONTROL: u8 = 0x36;
+const PIXEL_FORMAT_SET: u8 = 0x3a;
+
+// The DMAC trigger source for SERCOM7's transmit register being empty.
+const TRIGSRC_SERCOM7_TX: u8 = 0x13;
+
+// Bits of a descriptor's BTCTRL register.
+const BTCTRL_VALID: u16 = 1 << 0;
+const BTCTRL_BLOCKACT_INT: u16 = 1 << 3;
+const BTCTRL_SRCINC: u16 = 1 << 10;
+
+// Each block transfer moves at most 65535 one-byte beats, so larger transfers
+// are split across a chain of descriptors.
+const MAX_BLOCK_BYTES: usize = 0xffff;
+const CHAIN_LEN: usize = 4;
+
+/// The maximum number of pixels in a single [`DmaLCD::start_draw`] transfer;
+/// enough for a full screen.
+pub const MAX_DMA_PIXELS: usize = CHAIN_LEN * MAX_BLOCK_BYTES / 2;
+
+/// A DMAC transfer descriptor, laid out as the DMAC expects in SRAM.
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+struct Descriptor {
+    btctrl: u16,
+    btcnt: u16,
+    srcaddr: u32,
+    dstaddr: u32,
+    descaddr: u32,
+}
+
+impl Descriptor {
+    const EMPTY: Descriptor = Descriptor {
+        btctrl: 0,
+        btcnt: 0,
+        srcaddr: 0,
+        dstaddr: 0,
+        descaddr: 0,
+    };
+}
+
+// Only channel 0 is used, so the DMAC needs a single base and write-back
+// descriptor; the remaining descriptors of a chain are linked from the base.
+// These are only accessed by the `DmaLCD`, which owns the DMAC.
+static mut BASE_DESCRIPTOR: Descriptor = Descriptor::EMPTY;
+static mut WRITEBACK_DESCRIPTOR: Descriptor = Descriptor::EMPTY;
+static mut CHAINED_DESCRIPTORS: [Descriptor; CHAIN_LEN - 1] = [Descriptor::EMPTY; CHAIN_LEN - 1];
+
+/// An ILI9341 LCD display driver which writes pixel data using the DMAC, so
+/// the CPU is free to do other work during a redraw.
+///
+/// A transfer is started with [`DmaLCD::start_draw`], and is complete once
+/// [`DmaLCD::poll`] returns `true`. Alternatively, unmask the `DMAC_0`
+/// interrupt and call [`DmaLCD::interrupt`] from its handler, which invokes
+/// the completion callback set with [`DmaLCD::set_callback`].
+pub struct DmaLCD {
+    spi: LcdSpi,
+    dc: Pc6<Output<PushPull>>,
+    cs: Pb21<Output<PushPull>>,
+    reset: Pc7<Output<PushPull>>,
+    dmac: DMAC,
+    orientation: Orientation,
+    busy: bool,
+    callback: Option<fn()>,
+}
+
+impl DmaLCD {
+    pub(crate) fn new(
+        spi: LcdSpi,
+        dc: Pc6<Output<PushPull>>,
+        cs: Pb21<Output<PushPull>>,
+        reset: Pc7<Output<PushPull>>,
+        dmac: DMAC,
+        mclk: &mut MCLK,
+        delay: &mut Delay,
+    ) -> Result<Self, DisplayError> {
+        let mut lcd = Self {
+            spi,
+            dc,
+            cs,
+            reset,
+            dmac,
+            orientation: Orientation::Portrait,
+            busy: false,
+            callback: None,
+        };
+        lcd.init_dmac(mclk);
+        lcd.init_display(delay)?;
+
+        Ok(lcd)
+    }
+
+    /// Return the width of the display in its current orientation.
+    pub fn width(&self) -> usize {
+        match self.orientation {
+            Orientation::Portrait | Orientation::PortraitFlipped => 240,
+            Orientation::Landscape | Orientation::LandscapeFlipped => 320,
+        }
+    }
+
+    /// Return the height of the display in its current orientation.
+    pub fn height(&self) -> usize {
+        match self.orientation {
+            Orientation::Portrait | Orientation::PortraitFlipped => 320,
+            Orientation::Landscape | Orientation::LandscapeFlipped => 240,
+        }
+    }
+
+    /// Change the orientation of the display.
+    pub fn set_orientation(&mut self, orientation: Orientation) -> Result<(), DisplayError> {
+        self.ensure_idle()?;
+
+        let madctl = match orientation {
+            Orientation::Portrait => 0x40 | 0x08,
+            Orientation::Landscape => 0x20 | 0x08,
+            Orientation::PortraitFlipped => 0x80 | 0x08,
+            Orientation::LandscapeFlipped => 0x40 | 0x80 | 0x20 | 0x08,
+        };
+        self.command(MEMORY_ACCESS_CONTROL, &[madctl])?;
+        self.orientation = orientation;
+
+        Ok(())
+    }
+
+    /// Enable or disable colour inversion.
+    pub fn invert_colors(&mut self, invert: bool) -> Result<(), DisplayError> {
+        self.ensure_idle()?;
+
+        if invert {
+            self.command(DISPLAY_INVERSION_ON, &[])
+        } else {
+            self.command(DISPLAY_INVERSION_OFF, &[])
+        }
+    }
+
+    /// Set the function called when a transfer completes, from
+    /// [`DmaLCD::interrupt`].
+    pub fn set_callback(&mut self, callback: Option<fn()>) {
+        self.callback = callback;
+    }
+
+    /// Draw pixel data to the rectangle `(x0, y0)..=(x1, y1)`, blocking until
+    /// it has been written.
+    ///
+    /// Each pixel is an `Rgb565` value in native byte order.
+    pub fn draw_raw(
+        &mut self,
+        x0: u16,
+        y0: u16,
+        x1: u16,
+        y1: u16,
+        data: &[u16],
+    ) -> Result<(), DisplayError> {
+        self.draw_iter(x0, y0, x1, y1, data.iter().copied())
+    }
+
+    /// Draw pixel data to the rectangle `(x0, y0)..=(x1, y1)`, blocking until
+    /// it has been written.
+    ///
+    /// Each pixel is an `Rgb565` value in native byte order.
+    pub fn draw_iter<I>(
+        &mut self,
+        x0: u16,
+        y0: u16,
+        x1: u16,
+        y1: u16,
+        data: I,
+    ) -> Result<(), DisplayError>
+    where
+        I: IntoIterator<Item = u16>,
+    {
+        self.ensure_idle()?;
+        self.set_window(x0, y0, x1, y1)?;
+
+        self.cs.set_low().ok();
+        self.dc.set_low().ok();
+        let result = self.spi.write(&[MEMORY_WRITE]).and_then(|_| {
+            self.dc.set_high().ok();
+            data.into_iter()
+                .try_for_each(|pixel| self.spi.write(&pixel.to_be_bytes()))
+        });
+        self.cs.set_high().ok();
+
+        result.map_err(|_| DisplayError::Interface)
+    }
+
+    /// Start a DMA transfer of pixel data to the rectangle
+    /// `(x0, y0)..=(x1, y1)`, returning as soon as it has started.
+    ///
+    /// The DMAC copies the pixels byte for byte, so each `Rgb565` value must
+    /// be stored big endian, eg. by `u16::to_be`. At most [`MAX_DMA_PIXELS`]
+    /// can be written in one transfer.
+    pub fn start_draw(
+        &mut self,
+        x0: u16,
+        y0: u16,
+        x1: u16,
+        y1: u16,
+        pixels: &'static [u16],
+    ) -> Result<(), DisplayError> {
+        if pixels.len() > MAX_DMA_PIXELS {
+            return Err(DisplayError::TransferTooLarge);
+        }
+        self.ensure_idle()?;
+        if pixels.is_empty() {
+            return Ok(());
+        }
+        self.set_window(x0, y0, x1, y1)?;
+
+        // Leave the display selected, in data mode, for the duration of the
+        // transfer.
+        self.cs.set_low().ok();
+        self.dc.set_low().ok();
+        self.spi
+            .write(&[MEMORY_WRITE])
+            .map_err(|_| DisplayError::Interface)?;
+        self.dc.set_high().ok();
+
+        self.start_transfer(pixels);
+        self.busy = true;
+
+        Ok(())
+    }
+
+    /// Return whether the display is idle, ie. any transfer started with
+    /// [`DmaLCD::start_draw`] has completed.
+    pub fn poll(&mut self) -> bool {
+        if self.busy && self.transfer_complete() {
+            self.finish_transfer();
+        }
+
+        !self.busy
+    }
+
+    /// Block until any transfer in progress has completed.
+    pub fn wait(&mut self) {
+        while !self.poll() {}
+    }
+
+    /// Handle the `DMAC_0` interrupt, completing the transfer in progress and
+    /// calling the completion callback.
+    pub fn interrupt(&mut self) {
+        if self.busy && self.transfer_complete() {
+            self.finish_transfer();
+            if let Some(callback) = self.callback {
+                callback();
+            }
+        }
+    }
+
+    /// Release the SPI bus, control pins and DMAC, once any transfer in
+    /// progress has completed.
+    pub fn release(
+        mut self,
+    ) -> (
+        LcdSpi,
+        Pc6<Output<PushPull>>,
+        Pb21<Output<PushPull>>,
+        Pc7<Output<PushPull>>,
+        DMAC,
+    ) {
+        self.wait();
+        (self.spi, self.dc, self.cs, self.reset, self.dmac)
+    }
+
+    fn ensure_idle(&mut self) -> Result<(), DisplayError> {
+        if self.poll() {
+            Ok(())
+        } else {
+            Err(DisplayError::Busy)
+        }
+    }
+
+    fn init_display(&mut self, delay: &mut Delay) -> Result<(), DisplayError> {
+        // Hardware reset, followed by the same minimal initialization sequence
+        // as the `ili9341` driver: 16-bit pixels, out of sleep, display on.
+        self.reset.set_high().ok();
+        delay.delay_ms(1u16);
+        self.reset.set_low().ok();
+        delay.delay_ms(10u16);
+        self.reset.set_high().ok();
+        delay.delay_ms(120u16);
+
+        self.command(SOFTWARE_RESET, &[])?;
+        delay.delay_ms(120u16);
+        self.command(PIXEL_FORMAT_SET, &[0x55])?;
+        self.command(SLEEP_OUT, &[])?;
+        delay.delay_ms(5u16);
+        self.command(DISPLAY_ON, &[])
+    }
+
+    fn init_dmac(&mut self, mclk: &mut MCLK) {
+        mclk.ahbmask.modify(|_, w| w.dmac_().set_bit());
+
+        let dmac = &self.dmac;
+        dmac.ctrl.modify(|_, w| w.dmaenable().clear_bit());
+        dmac.ctrl.modify(|_, w| w.swrst().set_bit());
+        while dmac.ctrl.read().swrst().bit_is_set() {}
+
+        unsafe {
+            dmac.baseaddr
+                .write(|w| w.baseaddr().bits(addr_of!(BASE_DESCRIPTOR) as u32));
+            dmac.wrbaddr
+                .write(|w| w.wrbaddr().bits(addr_of!(WRITEBACK_DESCRIPTOR) as u32));
+        }
+        dmac.ctrl
+            .modify(|_, w| w.lvlen0().set_bit().dmaenable().set_bit());
+
+        // Channel 0 moves one byte to SERCOM7 each time its transmit register
+        // is empty, and raises an interrupt at the end of the last block.
+        let channel = &dmac.channel[0];
+        channel.chctrla.write(|w| w.swrst().set_bit());
+        while channel.chctrla.read().swrst().bit_is_set() {}
+        channel.chctrla.write(|w| unsafe {
+            w.trigsrc()
+                .bits(TRIGSRC_SERCOM7_TX)
+                .trigact()
+                .burst()
+                .burstlen()
+                .single()
+        });
+        channel.chintenset.write(|w| w.tcmpl().set_bit());
+    }
+
+    fn start_transfer(&mut self, pixels: &'static [u16]) {
+        let data = unsafe { &(*SERCOM7::ptr()).spim().data as *const _ as u32 };
+        let (chain, blocks) = build_chain(
+            pixels.as_ptr() as u32,
+            pixels.len() * 2,
+            data,
+            |block| unsafe { addr_of!(CHAINED_DESCRIPTORS[block]) as u32 },
+        );
+
+        unsafe {
+            BASE_DESCRIPTOR = chain[0];
+            for (block, &descriptor) in chain.iter().enumerate().take(blocks).skip(1) {
+                CHAINED_DESCRIPTORS[block - 1] = descriptor;
+            }
+        }
+
+        let channel = &self.dmac.channel[0];
+        channel.chintflag.write(|w| w.tcmpl().set_bit());
+        channel.chctrla.modify(|_, w| w.enable().set_bit());
+    }
+
+    fn transfer_complete(&self) -> bool {
+        self.dmac.channel[0].chintflag.read().tcmpl().bit_is_set()
+    }
+
+    fn finish_transfer(&mut self) {
+        self.dmac.channel[0]
+            .chintflag
+            .write(|w| w.tcmpl().set_bit());
+
+        // The DMAC has written the last byte to SERCOM7, but it may not have
+        // been shifted out yet. The received bytes were never read, so clear
+        // the overflow they caused before the bus is used again.
+        let spim = unsafe { (*SERCOM7::ptr()).spim() };
+        while spim.intflag.read().txc().bit_is_clear() {}
+        while spim.intflag.read().rxc().bit_is_set() {
+            spim.data.read();
+        }
+        spim.status.write(|w| w.bufovf().set_bit());
+
+        self.cs.set_high().ok();
+        self.busy = false;
+    }
+
+    fn set_window(&mut self, x0: u16, y0: u16, x1: u16, y1: u16) -> Result<(), DisplayError> {
+        self.command(
+            COLUMN_ADDRESS_SET,
+            &[(x0 >> 8) as u8, x0 as u8, (x1 >> 8) as u8, x1 as u8],
+        )?;
+        self.command(
+            PAGE_ADDRESS_SET,
+            &[(y0 >> 8) as u8, y0 as u8, (y1 >> 8) as u8, y1 as u8],
+        )
+    }
+
+    fn command(&mut self, command: u8, args: &[u8]) -> Result<(), DisplayError> {
+        self.cs.set_low().ok();
+        self.dc.set_low().ok();
+        let result = self.spi.write(&[command]).and_then(|_| {
+            self.dc.set_high().ok();
+            self.spi.write(args)
+        });
+        self.cs.set_high().ok();
+
+        result.map_err(|_| DisplayError::Interface)
+    }
+}
+
+// Build the descriptors moving `len` bytes from `start` to the register at
+// `data`, returning them and the number used. Each descriptor but the last
+// links to the next, which the DMAC reads from `link(block)`, where `block`
+// is the index of the descriptor doing the linking.
+fn build_chain(
+    start: u32,
+    len: usize,
+    data: u32,
+    link: impl Fn(usize) -> u32,
+) -> ([Descriptor; CHAIN_LEN], usize) {
+    let mut chain = [Descriptor::EMPTY; CHAIN_LEN];
+    let blocks = (len + MAX_BLOCK_BYTES - 1) / MAX_BLOCK_BYTES;
+
+    for (block, descriptor) in chain.iter_mut().enumerate().take(blocks) {
+        let offset = block * MAX_BLOCK_BYTES;
+        let count = (len - offset).min(MAX_BLOCK_BYTES);
+        let last = block + 1 == blocks;
+
+        // With the source address incrementing, SRCADDR is the address just
+        // past the end of the block.
+        *descriptor = Descriptor {
+            btctrl: BTCTRL_VALID | BTCTRL_SRCINC | if last { BTCTRL_BLOCKACT_INT } else { 0 },
+            btcnt: count as u16,
+            srcaddr: start + (offset + count) as u32,
+            dstaddr: data,
+            descaddr: if last { 0 } else { link(block) },
+        };
+    }
+
+    (chain, blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: u32 = 0x2000_1000;
+    const DATA: u32 = 0x4300_0c28;
+
+    // Link each descriptor to a made-up address which records its index.
+    fn link(block: usize) -> u32 {
+        0x2000_0000 + block as u32 * 16
+    }
+
+    fn check_chain(len: usize, counts: &[u16]) {
+        let (chain, blocks) = build_chain(START, len, DATA, link);
+        assert_eq!(blocks, counts.len());
+
+        let mut end = START;
+        for (block, &count) in counts.iter().enumerate() {
+            let descriptor = &chain[block];
+            let last = block + 1 == blocks;
+            end += count as u32;
+
+            assert_eq!(descriptor.btcnt, count);
+            assert_eq!(descriptor.srcaddr, end);
+            assert_eq!(descriptor.dstaddr, DATA);
+            assert_eq!(descriptor.btctrl & BTCTRL_VALID, BTCTRL_VALID);
+            assert_eq!(descriptor.btctrl & BTCTRL_SRCINC, BTCTRL_SRCINC);
+            // Only the last block interrupts, and ends the chain.
+            assert_eq!(descriptor.btctrl & BTCTRL_BLOCKACT_INT != 0, last);
+            assert_eq!(descriptor.descaddr, if last { 0 } else { link(block) });
+        }
+        assert_eq!(end, START + len as u32);
+
+        // The descriptors which are not needed are left invalid.
+        for descriptor in &chain[blocks..] {
+            assert_eq!(descriptor.btctrl, 0);
+        }
+    }
+
+    #[test]
+    fn small_transfer_is_one_block() {
+        check_chain(2, &[2]);
+        check_chain(240 * 2, &[480]);
+    }
+
+    #[test]
+    fn full_block() {
+        check_chain(MAX_BLOCK_BYTES, &[0xffff]);
+        check_chain(MAX_BLOCK_BYTES + 1, &[0xffff, 1]);
+    }
+
+    #[test]
+    fn full_screen() {
+        check_chain(320 * 240 * 2, &[0xffff, 0xffff, 22530]);
+    }
+
+    #[test]
+    fn largest_transfer_fills_the_chain() {
+        check_chain(MAX_DMA_PIXELS * 2, &[0xffff; CHAIN_LEN]);
+    }
+}
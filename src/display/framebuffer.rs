@@ -0,0 +1,516 @@
+use core::convert::Infallible;
+
+use embedded_graphics::drawable::Pixel;
+use embedded_graphics::geometry::Size;
+use embedded_graphics::pixelcolor::raw::RawU16;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::DrawTarget;
+use heapless::consts::U8;
+use heapless::Vec;
+
+use super::{DisplayError, DmaLCD, LCD};
+
+/// The approximate cost of an address window write, in pixels. Two dirty
+/// rectangles are merged if doing so writes no more than this many pixels
+/// which did not need writing.
+pub const WINDOW_COST: u32 = 64;
+
+/// The maximum number of dirty rectangles tracked by [`DirtyRegions`]. Once
+/// it is full, further rectangles are merged with whichever existing one
+/// wastes the fewest pixels.
+pub const MAX_DIRTY_RECTS: usize = 8;
+
+/// A rectangle of pixels, from `(x0, y0)` to `(x1, y1)` inclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DirtyRect {
+    pub x0: u16,
+    pub y0: u16,
+    pub x1: u16,
+    pub y1: u16,
+}
+
+impl DirtyRect {
+    /// Create a rectangle from its corners, in any order.
+    pub fn new(x0: u16, y0: u16, x1: u16, y1: u16) -> Self {
+        Self {
+            x0: x0.min(x1),
+            y0: y0.min(y1),
+            x1: x0.max(x1),
+            y1: y0.max(y1),
+        }
+    }
+
+    /// Create a rectangle covering a single pixel.
+    pub const fn pixel(x: u16, y: u16) -> Self {
+        Self {
+            x0: x,
+            y0: y,
+            x1: x,
+            y1: y,
+        }
+    }
+
+    /// Return the width of the rectangle in pixels.
+    pub fn width(&self) -> u16 {
+        self.x1 - self.x0 + 1
+    }
+
+    /// Return the height of the rectangle in pixels.
+    pub fn height(&self) -> u16 {
+        self.y1 - self.y0 + 1
+    }
+
+    /// Return the number of pixels in the rectangle.
+    pub fn area(&self) -> u32 {
+        self.width() as u32 * self.height() as u32
+    }
+
+    /// Return whether the rectangle contains the given pixel.
+    pub fn contains(&self, x: u16, y: u16) -> bool {
+        x >= self.x0 && x <= self.x1 && y >= self.y0 && y <= self.y1
+    }
+
+    /// Return the smallest rectangle containing both rectangles.
+    pub fn union(&self, other: &DirtyRect) -> DirtyRect {
+        DirtyRect {
+            x0: self.x0.min(other.x0),
+            y0: self.y0.min(other.y0),
+            x1: self.x1.max(other.x1),
+            y1: self.y1.max(other.y1),
+        }
+    }
+
+    /// Return the number of pixels in both rectangles.
+    pub fn overlap(&self, other: &DirtyRect) -> u32 {
+        let x0 = self.x0.max(other.x0);
+        let y0 = self.y0.max(other.y0);
+        let x1 = self.x1.min(other.x1);
+        let y1 = self.y1.min(other.y1);
+
+        if x0 > x1 || y0 > y1 {
+            0
+        } else {
+            (x1 - x0 + 1) as u32 * (y1 - y0 + 1) as u32
+        }
+    }
+
+    // The number of pixels which merging the two rectangles would write
+    // without needing to, or zero if merging saves pixels.
+    fn merge_waste(&self, other: &DirtyRect) -> u32 {
+        let covered = self.area() + other.area() - self.overlap(other);
+        self.union(other).area() - covered
+    }
+}
+
+/// A set of dirty rectangles, merged so that they can be written in as few
+/// address windows as is worthwhile.
+#[derive(Clone, Debug, Default)]
+pub struct DirtyRegions {
+    rects: Vec<DirtyRect, U8>,
+}
+
+impl DirtyRegions {
+    /// Create an empty set of dirty regions.
+    pub fn new() -> Self {
+        Self { rects: Vec::new() }
+    }
+
+    /// Mark the given rectangle as dirty.
+    ///
+    /// The rectangle is merged with any existing rectangle when that costs no
+    /// more than [`WINDOW_COST`] extra pixels, repeatedly, since a merged
+    /// rectangle may then be worth merging with another.
+    pub fn add(&mut self, rect: DirtyRect) {
+        let mut rect = rect;
+
+        while let Some(i) = self
+            .rects
+            .iter()
+            .position(|other| rect.merge_waste(other) <= WINDOW_COST)
+        {
+            rect = rect.union(&self.rects.swap_remove(i));
+        }
+
+        if let Err(rect) = self.rects.push(rect) {
+            // Full, so merge with the rectangle which wastes the fewest pixels.
+            let (i, _) = self
+                .rects
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, other)| rect.merge_waste(other))
+                .unwrap();
+            let merged = rect.union(&self.rects.swap_remove(i));
+            self.add(merged);
+        }
+    }
+
+    /// Return the dirty rectangles. They do not overlap enough to be worth
+    /// merging, but may overlap.
+    pub fn rects(&self) -> &[DirtyRect] {
+        &self.rects
+    }
+
+    /// Return whether there are no dirty rectangles.
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    /// Remove all dirty rectangles.
+    pub fn clear(&mut self) {
+        self.rects.clear();
+    }
+}
+
+/// A display which a [`FrameBuffer`] can be flushed to.
+pub trait FlushTarget {
+    /// Write pixels to the rectangle `(x0, y0)..=(x1, y1)` in a single address
+    /// window, in row-major order. Each pixel is an `Rgb565` value in native
+    /// byte order.
+    fn write_window<I>(
+        &mut self,
+        x0: u16,
+        y0: u16,
+        x1: u16,
+        y1: u16,
+        pixels: I,
+    ) -> Result<(), DisplayError>
+    where
+        I: IntoIterator<Item = u16>;
+}
+
+impl FlushTarget for LCD {
+    fn write_window<I>(
+        &mut self,
+        x0: u16,
+        y0: u16,
+        x1: u16,
+        y1: u16,
+        pixels: I,
+    ) -> Result<(), DisplayError>
+    where
+        I: IntoIterator<Item = u16>,
+    {
+        self.draw_iter(x0, y0, x1, y1, pixels)
+            .map_err(|_| DisplayError::Interface)
+    }
+}
+
+impl FlushTarget for DmaLCD {
+    fn write_window<I>(
+        &mut self,
+        x0: u16,
+        y0: u16,
+        x1: u16,
+        y1: u16,
+        pixels: I,
+    ) -> Result<(), DisplayError>
+    where
+        I: IntoIterator<Item = u16>,
+    {
+        self.draw_iter(x0, y0, x1, y1, pixels)
+    }
+}
+
+/// An off-screen buffer for drawing with `embedded-graphics`, which writes
+/// only the regions which have changed to the display when flushed.
+///
+/// The buffer can cover the whole screen (`320 * 240` pixels, 150KiB), or a
+/// smaller area of it placed at an offset, eg. for a status bar.
+pub struct FrameBuffer<'a> {
+    pixels: &'a mut [u16],
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+    dirty: DirtyRegions,
+}
+
+impl<'a> FrameBuffer<'a> {
+    /// Create a buffer covering `width * height` pixels of the screen, with
+    /// its top-left corner at `(x, y)`. Return `None` if `pixels` is too
+    /// small, or either dimension is zero.
+    ///
+    /// The buffer's contents are assumed to already be on the screen.
+    pub fn new(pixels: &'a mut [u16], x: u16, y: u16, width: u16, height: u16) -> Option<Self> {
+        if width == 0 || height == 0 || pixels.len() < width as usize * height as usize {
+            return None;
+        }
+
+        Some(Self {
+            pixels,
+            x,
+            y,
+            width,
+            height,
+            dirty: DirtyRegions::new(),
+        })
+    }
+
+    /// Return the colour of the pixel at `(x, y)`, relative to the buffer, or
+    /// `None` if it lies outside of the buffer.
+    pub fn get_pixel(&self, x: u16, y: u16) -> Option<Rgb565> {
+        self.index(x, y)
+            .map(|i| Rgb565::from(RawU16::new(self.pixels[i])))
+    }
+
+    /// Mark the whole buffer as dirty, so that the next flush rewrites it.
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty.clear();
+        self.dirty
+            .add(DirtyRect::new(0, 0, self.width - 1, self.height - 1));
+    }
+
+    /// Return the regions which have changed since the last flush, relative to
+    /// the buffer.
+    pub fn dirty(&self) -> &DirtyRegions {
+        &self.dirty
+    }
+
+    /// Write each changed region to the display, one address window per
+    /// region. On error, the regions are left dirty.
+    pub fn flush<T>(&mut self, display: &mut T) -> Result<(), DisplayError>
+    where
+        T: FlushTarget,
+    {
+        for rect in self.dirty.rects() {
+            let stride = self.width as usize;
+            let width = rect.width() as usize;
+            let pixels = &self.pixels;
+            let rows = (rect.y0..=rect.y1).flat_map(move |row| {
+                let start = row as usize * stride + rect.x0 as usize;
+                pixels[start..start + width].iter().copied()
+            });
+
+            display.write_window(
+                self.x + rect.x0,
+                self.y + rect.y0,
+                self.x + rect.x1,
+                self.y + rect.y1,
+                rows,
+            )?;
+        }
+
+        self.dirty.clear();
+        Ok(())
+    }
+
+    fn index(&self, x: u16, y: u16) -> Option<usize> {
+        if x < self.width && y < self.height {
+            Some(y as usize * self.width as usize + x as usize)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> DrawTarget<Rgb565> for FrameBuffer<'a> {
+    type Error = Infallible;
+
+    fn draw_pixel(&mut self, pixel: Pixel<Rgb565>) -> Result<(), Self::Error> {
+        let Pixel(point, color) = pixel;
+        if point.x < 0 || point.y < 0 || point.x > u16::MAX as i32 || point.y > u16::MAX as i32 {
+            return Ok(());
+        }
+
+        let (x, y) = (point.x as u16, point.y as u16);
+        if let Some(i) = self.index(x, y) {
+            let raw = RawU16::from(color).into_inner();
+            if self.pixels[i] != raw {
+                self.pixels[i] = raw;
+                self.dirty.add(DirtyRect::pixel(x, y));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn size(&self) -> Size {
+        Size::new(self.width as u32, self.height as u32)
+    }
+
+    fn clear(&mut self, color: Rgb565) -> Result<(), Self::Error> {
+        let raw = RawU16::from(color).into_inner();
+        let len = self.width as usize * self.height as usize;
+        self.pixels[..len].iter_mut().for_each(|pixel| *pixel = raw);
+        self.mark_all_dirty();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use embedded_graphics::prelude::*;
+    use heapless::consts::U16;
+
+    // Records each window written by a flush, and the pixels written to it.
+    #[derive(Default)]
+    struct Recorder {
+        windows: Vec<(DirtyRect, Vec<u16, U16>), U8>,
+    }
+
+    impl FlushTarget for Recorder {
+        fn write_window<I>(
+            &mut self,
+            x0: u16,
+            y0: u16,
+            x1: u16,
+            y1: u16,
+            pixels: I,
+        ) -> Result<(), DisplayError>
+        where
+            I: IntoIterator<Item = u16>,
+        {
+            let pixels = pixels.into_iter().collect();
+            self.windows
+                .push((DirtyRect::new(x0, y0, x1, y1), pixels))
+                .ok();
+            Ok(())
+        }
+    }
+
+    fn regions(rects: &[DirtyRect]) -> DirtyRegions {
+        let mut regions = DirtyRegions::new();
+        rects.iter().for_each(|&rect| regions.add(rect));
+        regions
+    }
+
+    #[test]
+    fn overlapping_rects_merge() {
+        // The union is 225 pixels, of which 50 are not dirty.
+        let regions = regions(&[DirtyRect::new(0, 0, 9, 9), DirtyRect::new(5, 5, 14, 14)]);
+        assert_eq!(regions.rects(), [DirtyRect::new(0, 0, 14, 14)]);
+    }
+
+    #[test]
+    fn adjacent_rects_merge() {
+        let regions = regions(&[
+            DirtyRect::new(0, 0, 9, 9),
+            DirtyRect::new(10, 0, 19, 9),
+            DirtyRect::new(0, 10, 19, 10),
+        ]);
+        assert_eq!(regions.rects(), [DirtyRect::new(0, 0, 19, 10)]);
+    }
+
+    #[test]
+    fn merge_waste_threshold() {
+        // Two pixels on a row waste the pixels between them when merged.
+        let near = regions(&[DirtyRect::pixel(0, 0), DirtyRect::pixel(65, 0)]);
+        assert_eq!(near.rects(), [DirtyRect::new(0, 0, 65, 0)]);
+
+        let far = regions(&[DirtyRect::pixel(0, 0), DirtyRect::pixel(66, 0)]);
+        assert_eq!(
+            far.rects(),
+            [DirtyRect::pixel(0, 0), DirtyRect::pixel(66, 0)]
+        );
+    }
+
+    #[test]
+    fn merge_chains() {
+        // The third rect bridges the first two, which are then worth merging.
+        let regions = regions(&[
+            DirtyRect::new(0, 0, 9, 9),
+            DirtyRect::new(30, 0, 39, 9),
+            DirtyRect::new(10, 0, 29, 9),
+        ]);
+        assert_eq!(regions.rects(), [DirtyRect::new(0, 0, 39, 9)]);
+    }
+
+    #[test]
+    fn full_regions_are_forced_to_merge() {
+        // No two of these pixels are worth merging.
+        let pixels: Vec<DirtyRect, U16> = (0..=MAX_DIRTY_RECTS as u16)
+            .map(|i| DirtyRect::pixel(i * 10, i * 10))
+            .collect();
+        let regions = regions(&pixels);
+
+        assert_eq!(regions.rects().len(), MAX_DIRTY_RECTS);
+        assert!(regions.rects().contains(&DirtyRect::new(70, 70, 80, 80)));
+        for pixel in &pixels {
+            let covered = regions.rects().iter();
+            assert_eq!(
+                covered.filter(|r| r.contains(pixel.x0, pixel.y0)).count(),
+                1
+            );
+        }
+    }
+
+    #[test]
+    fn clear_empties_regions() {
+        let mut regions = regions(&[DirtyRect::pixel(1, 1)]);
+        assert!(!regions.is_empty());
+        regions.clear();
+        assert!(regions.is_empty());
+    }
+
+    #[test]
+    fn new_checks_size() {
+        let mut pixels = [0; 12];
+        assert!(FrameBuffer::new(&mut pixels, 0, 0, 4, 4).is_none());
+        assert!(FrameBuffer::new(&mut pixels, 0, 0, 0, 3).is_none());
+        assert!(FrameBuffer::new(&mut pixels, 0, 0, 4, 3).is_some());
+    }
+
+    #[test]
+    fn drawing_is_clipped_to_the_buffer() {
+        let mut pixels = [0; 12];
+        let mut fb = FrameBuffer::new(&mut pixels, 10, 20, 4, 3).unwrap();
+
+        for &(x, y) in &[
+            (-1, 0),
+            (0, -1),
+            (4, 0),
+            (0, 3),
+            (i32::MAX, 0),
+            (0, i32::MIN),
+        ] {
+            fb.draw_pixel(Pixel(Point::new(x, y), Rgb565::WHITE))
+                .unwrap();
+        }
+        assert!(fb.dirty().is_empty());
+
+        fb.draw_pixel(Pixel(Point::new(3, 2), Rgb565::WHITE))
+            .unwrap();
+        assert_eq!(fb.dirty().rects(), [DirtyRect::pixel(3, 2)]);
+        assert_eq!(fb.get_pixel(3, 2), Some(Rgb565::WHITE));
+        assert_eq!(fb.get_pixel(4, 2), None);
+    }
+
+    #[test]
+    fn unchanged_pixels_are_not_dirty() {
+        let mut pixels = [0; 12];
+        let mut fb = FrameBuffer::new(&mut pixels, 0, 0, 4, 3).unwrap();
+
+        fb.draw_pixel(Pixel(Point::new(1, 1), Rgb565::BLACK))
+            .unwrap();
+        assert!(fb.dirty().is_empty());
+    }
+
+    #[test]
+    fn flush_writes_dirty_windows_at_offset() {
+        let mut pixels = [0; 12];
+        let mut fb = FrameBuffer::new(&mut pixels, 10, 20, 4, 3).unwrap();
+        let white = RawU16::from(Rgb565::WHITE).into_inner();
+
+        fb.draw_pixel(Pixel(Point::new(1, 1), Rgb565::WHITE))
+            .unwrap();
+        fb.draw_pixel(Pixel(Point::new(2, 2), Rgb565::WHITE))
+            .unwrap();
+        let mut display = Recorder::default();
+        fb.flush(&mut display).unwrap();
+
+        assert!(fb.dirty().is_empty());
+        assert_eq!(display.windows.len(), 1);
+        let (window, written) = &display.windows[0];
+        assert_eq!(*window, DirtyRect::new(11, 21, 12, 22));
+        assert_eq!(written[..], [white, 0, 0, white]);
+
+        fb.mark_all_dirty();
+        let mut display = Recorder::default();
+        fb.flush(&mut display).unwrap();
+        assert_eq!(display.windows[0].0, DirtyRect::new(10, 20, 13, 22));
+        assert_eq!(display.windows[0].1.len(), 12);
+    }
+}
@@ -0,0 +1,351 @@
+use embedded_graphics::geometry::Point;
+use ili9341::Orientation;
+use lis3dh::accelerometer::{self, RawAccelerometer, Tracker};
+
+use super::{DisplayError, LCD};
+use crate::LIS3DH;
+
+/// The default time, in milliseconds, a new orientation must be held before
+/// [`AutoRotate`] rotates the display.
+pub const DEFAULT_SETTLE_MS: u32 = 250;
+
+/// Errors which can occur while following the accelerometer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AutoRotateError {
+    /// The acceleration could not be read from the LIS3DH.
+    Accelerometer,
+    /// The display could not be rotated.
+    Display(DisplayError),
+}
+
+/// Given an accelerometer orientation, return the equivalent display
+/// orientation, if one exists. Orientations where the device is lying flat,
+/// or which are unknown, have no equivalent.
+pub fn display_orientation(orientation: accelerometer::Orientation) -> Option<Orientation> {
+    use accelerometer::Orientation as Accel;
+
+    match orientation {
+        Accel::LandscapeUp => Some(Orientation::LandscapeFlipped),
+        Accel::LandscapeDown => Some(Orientation::Landscape),
+        Accel::PortraitUp => Some(Orientation::Portrait),
+        Accel::PortraitDown => Some(Orientation::PortraitFlipped),
+        _ => None,
+    }
+}
+
+/// Given the width and height of the display and of an item, return the
+/// coordinate at which to draw the item to center it on the display.
+pub fn center(display_width: usize, display_height: usize, width: u32, height: u32) -> Point {
+    let x = (display_width as i32 / 2) - (width as i32 / 2);
+    let y = (display_height as i32 / 2) - (height as i32 / 2);
+    Point::new(x, y)
+}
+
+// The accelerometer orientation equivalent to the given display orientation.
+fn accelerometer_orientation(orientation: &Orientation) -> accelerometer::Orientation {
+    use accelerometer::Orientation as Accel;
+
+    match orientation {
+        Orientation::LandscapeFlipped => Accel::LandscapeUp,
+        Orientation::Landscape => Accel::LandscapeDown,
+        Orientation::Portrait => Accel::PortraitUp,
+        Orientation::PortraitFlipped => Accel::PortraitDown,
+    }
+}
+
+/// Filters accelerometer orientations, so that the display is only rotated
+/// once the device has been held in a new orientation for a while.
+///
+/// Flat and unknown orientations are ignored, leaving the display as it was,
+/// and a new orientation must be seen continuously for the settle time before
+/// it is accepted.
+#[derive(Clone, Copy, Debug)]
+pub struct RotationFilter {
+    settle_ms: u32,
+    current: accelerometer::Orientation,
+    candidate: Option<(accelerometer::Orientation, u32)>,
+}
+
+impl RotationFilter {
+    /// Create a filter starting in the given display orientation.
+    pub fn new(current: Orientation, settle_ms: u32) -> Self {
+        Self {
+            settle_ms,
+            current: accelerometer_orientation(&current),
+            candidate: None,
+        }
+    }
+
+    /// Return the accepted display orientation.
+    pub fn orientation(&self) -> Orientation {
+        // `current` is only ever set from orientations with an equivalent.
+        display_orientation(self.current).unwrap()
+    }
+
+    /// Set the time a new orientation must be held before it is accepted.
+    pub fn set_settle_time(&mut self, settle_ms: u32) {
+        self.settle_ms = settle_ms;
+    }
+
+    /// Update the filter with the orientation read at `now_ms`. Return the
+    /// new display orientation if it has changed.
+    pub fn update(
+        &mut self,
+        orientation: accelerometer::Orientation,
+        now_ms: u32,
+    ) -> Option<Orientation> {
+        // Flat or ambiguous, so neither accept nor abandon a candidate.
+        let display = display_orientation(orientation)?;
+
+        if orientation == self.current {
+            self.candidate = None;
+            return None;
+        }
+
+        let since = match self.candidate {
+            Some((candidate, since)) if candidate == orientation => since,
+            _ => {
+                self.candidate = Some((orientation, now_ms));
+                now_ms
+            }
+        };
+
+        if now_ms.wrapping_sub(since) >= self.settle_ms {
+            self.current = orientation;
+            self.candidate = None;
+            Some(display)
+        } else {
+            None
+        }
+    }
+}
+
+/// Rotates the LCD display to follow the orientation of the device, as read
+/// from the LIS3DH accelerometer.
+///
+/// Call [`AutoRotate::update`] regularly from the main loop. After the display
+/// is rotated, the redraw callback is called so the screen can be redrawn for
+/// its new dimensions.
+pub struct AutoRotate {
+    lis3dh: LIS3DH,
+    tracker: Tracker,
+    filter: RotationFilter,
+    display: LCD,
+    redraw: Option<fn(&mut LCD)>,
+}
+
+impl AutoRotate {
+    /// Take control of the accelerometer and display, as returned by
+    /// [`Accelerometer::init`](crate::Accelerometer::init) and
+    /// [`Display::init`](crate::Display::init). The display is assumed to be
+    /// in the `orientation` it was initialized with.
+    ///
+    /// The `threshold` is passed to the accelerometer's `Tracker`; see its
+    /// documentation for how to obtain it.
+    pub fn new(lis3dh: LIS3DH, display: LCD, orientation: Orientation, threshold: f32) -> Self {
+        Self {
+            lis3dh,
+            tracker: Tracker::new(threshold),
+            filter: RotationFilter::new(orientation, DEFAULT_SETTLE_MS),
+            display,
+            redraw: None,
+        }
+    }
+
+    /// Set the time a new orientation must be held before the display is
+    /// rotated.
+    pub fn set_settle_time(&mut self, settle_ms: u32) {
+        self.filter.set_settle_time(settle_ms);
+    }
+
+    /// Set a function to be called with the display after it is rotated.
+    pub fn set_callback(&mut self, redraw: Option<fn(&mut LCD)>) {
+        self.redraw = redraw;
+    }
+
+    /// Return the current orientation of the display.
+    pub fn orientation(&self) -> Orientation {
+        self.filter.orientation()
+    }
+
+    /// Return the display, for drawing to.
+    pub fn display(&mut self) -> &mut LCD {
+        &mut self.display
+    }
+
+    /// Read the accelerometer, and rotate the display if the device has been
+    /// held in a new orientation for long enough. Return whether the display
+    /// was rotated.
+    pub fn update(&mut self, now_ms: u32) -> Result<bool, AutoRotateError> {
+        let acceleration = self
+            .lis3dh
+            .accel_raw()
+            .map_err(|_| AutoRotateError::Accelerometer)?;
+        let orientation = self.tracker.update(acceleration);
+
+        match self.filter.update(orientation, now_ms) {
+            Some(orientation) => {
+                self.display
+                    .set_orientation(orientation)
+                    .map_err(|_| AutoRotateError::Display(DisplayError::Interface))?;
+                if let Some(redraw) = self.redraw {
+                    redraw(&mut self.display);
+                }
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Release the accelerometer and display.
+    pub fn release(self) -> (LIS3DH, LCD) {
+        (self.lis3dh, self.display)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use accelerometer::Orientation as Accel;
+
+    const SETTLE_MS: u32 = 250;
+
+    // Return the accelerometer orientation equivalent to a result of
+    // `RotationFilter::update`, which is easier to compare.
+    fn update(filter: &mut RotationFilter, orientation: Accel, now_ms: u32) -> Option<Accel> {
+        filter
+            .update(orientation, now_ms)
+            .map(|display| accelerometer_orientation(&display))
+    }
+
+    fn current(filter: &RotationFilter) -> Accel {
+        accelerometer_orientation(&filter.orientation())
+    }
+
+    #[test]
+    fn starts_in_the_given_orientation() {
+        for &accel in &[
+            Accel::LandscapeUp,
+            Accel::LandscapeDown,
+            Accel::PortraitUp,
+            Accel::PortraitDown,
+        ] {
+            let display = display_orientation(accel).unwrap();
+            assert_eq!(current(&RotationFilter::new(display, SETTLE_MS)), accel);
+        }
+    }
+
+    #[test]
+    fn current_orientation_is_held() {
+        let mut filter = RotationFilter::new(Orientation::LandscapeFlipped, SETTLE_MS);
+        for now in (0..1000).step_by(10) {
+            assert_eq!(update(&mut filter, Accel::LandscapeUp, now), None);
+        }
+        assert_eq!(current(&filter), Accel::LandscapeUp);
+    }
+
+    #[test]
+    fn rotates_after_the_settle_time() {
+        let mut filter = RotationFilter::new(Orientation::LandscapeFlipped, SETTLE_MS);
+        assert_eq!(update(&mut filter, Accel::PortraitUp, 1000), None);
+        assert_eq!(update(&mut filter, Accel::PortraitUp, 1100), None);
+        assert_eq!(update(&mut filter, Accel::PortraitUp, 1249), None);
+        assert_eq!(current(&filter), Accel::LandscapeUp);
+
+        assert_eq!(
+            update(&mut filter, Accel::PortraitUp, 1250),
+            Some(Accel::PortraitUp)
+        );
+        assert_eq!(current(&filter), Accel::PortraitUp);
+        assert_eq!(update(&mut filter, Accel::PortraitUp, 1300), None);
+    }
+
+    #[test]
+    fn brief_tilts_are_ignored() {
+        // Tilting away and back restarts the settle time, however long the
+        // device spends tilted in total.
+        let mut filter = RotationFilter::new(Orientation::LandscapeFlipped, SETTLE_MS);
+        for start in (0..2000).step_by(200) {
+            assert_eq!(update(&mut filter, Accel::PortraitUp, start), None);
+            assert_eq!(update(&mut filter, Accel::PortraitUp, start + 150), None);
+            assert_eq!(update(&mut filter, Accel::LandscapeUp, start + 160), None);
+        }
+        assert_eq!(current(&filter), Accel::LandscapeUp);
+    }
+
+    #[test]
+    fn a_new_candidate_restarts_the_settle_time() {
+        let mut filter = RotationFilter::new(Orientation::LandscapeFlipped, SETTLE_MS);
+        assert_eq!(update(&mut filter, Accel::PortraitUp, 0), None);
+        assert_eq!(update(&mut filter, Accel::PortraitDown, 200), None);
+        assert_eq!(update(&mut filter, Accel::PortraitDown, 300), None);
+        assert_eq!(
+            update(&mut filter, Accel::PortraitDown, 450),
+            Some(Accel::PortraitDown)
+        );
+    }
+
+    #[test]
+    fn flat_device_is_ignored() {
+        // The tracker reports a device lying flat, with little acceleration
+        // along x and y, as face up or down; those never rotate the display,
+        // nor abandon a candidate.
+        let mut filter = RotationFilter::new(Orientation::Portrait, SETTLE_MS);
+        for now in (0..1000).step_by(50) {
+            assert_eq!(update(&mut filter, Accel::FaceUp, now), None);
+            assert_eq!(update(&mut filter, Accel::FaceDown, now + 10), None);
+            assert_eq!(update(&mut filter, Accel::Unknown, now + 20), None);
+        }
+        assert_eq!(current(&filter), Accel::PortraitUp);
+
+        assert_eq!(update(&mut filter, Accel::LandscapeDown, 2000), None);
+        assert_eq!(update(&mut filter, Accel::FaceUp, 2100), None);
+        assert_eq!(
+            update(&mut filter, Accel::LandscapeDown, 2250),
+            Some(Accel::LandscapeDown)
+        );
+    }
+
+    #[test]
+    fn turning_upside_down() {
+        let mut filter = RotationFilter::new(Orientation::LandscapeFlipped, SETTLE_MS);
+        assert_eq!(update(&mut filter, Accel::LandscapeDown, 0), None);
+        assert_eq!(
+            update(&mut filter, Accel::LandscapeDown, 250),
+            Some(Accel::LandscapeDown)
+        );
+        assert_eq!(current(&filter), Accel::LandscapeDown);
+
+        assert_eq!(update(&mut filter, Accel::LandscapeUp, 500), None);
+        assert_eq!(
+            update(&mut filter, Accel::LandscapeUp, 750),
+            Some(Accel::LandscapeUp)
+        );
+        assert_eq!(current(&filter), Accel::LandscapeUp);
+    }
+
+    #[test]
+    fn settle_time_survives_the_clock_wrapping() {
+        let mut filter = RotationFilter::new(Orientation::Portrait, SETTLE_MS);
+        assert_eq!(
+            update(&mut filter, Accel::PortraitDown, u32::MAX - 100),
+            None
+        );
+        assert_eq!(update(&mut filter, Accel::PortraitDown, 100), None);
+        assert_eq!(
+            update(&mut filter, Accel::PortraitDown, 149),
+            Some(Accel::PortraitDown)
+        );
+    }
+
+    #[test]
+    fn settle_time_can_be_changed() {
+        let mut filter = RotationFilter::new(Orientation::Portrait, SETTLE_MS);
+        filter.set_settle_time(0);
+        assert_eq!(
+            update(&mut filter, Accel::LandscapeUp, 10),
+            Some(Accel::LandscapeUp)
+        );
+    }
+}
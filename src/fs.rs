@@ -0,0 +1,1280 @@
+//! A FAT16 and FAT32 filesystem on a [`BlockDevice`], such as the `SD` card.
+//!
+//! The [`FileSystem`] lists directories, and opens, creates, reads, appends to
+//! and deletes files, reading and writing long file names as well as short
+//! (8.3) ones. It needs no allocator: it holds a single block in memory, which
+//! caches the FAT, directory and file data in turn, and names are held in
+//! fixed-size strings.
+//!
+//! Paths are separated by `/` and are always relative to the root directory;
+//! names are matched against both the long and short name of each entry,
+//! ignoring ASCII case.
+//!
+//! Files are only ever written at their end: [`Mode::Append`] continues an
+//! existing file, and [`Mode::Create`] starts a new, empty one. The size of a
+//! file is only recorded in its directory entry by [`FileSystem::flush`] and
+//! [`FileSystem::close`], so a file written to but never flushed is truncated
+//! at its previous size.
+//!
+//! Only the first FAT16 or FAT32 partition of a card is used, or the whole
+//! card if it has no partition table. FAT12, which is only used on volumes of
+//! a few megabytes, is not supported.
+//!
+//! The filesystem depends only on the [`BlockDevice`] trait, so it may be used
+//! on a host with a device reading and writing an image file, to test code
+//! against images made by a PC.
+
+use crate::{BlockDevice, BLOCK_SIZE};
+
+mod dir;
+mod name;
+mod volume;
+
+pub use dir::*;
+pub use name::*;
+pub use volume::*;
+
+/// The size of a directory entry, in bytes.
+const ENTRY_SIZE: usize = 32;
+const ENTRIES_PER_BLOCK: u32 = (BLOCK_SIZE / ENTRY_SIZE) as u32;
+
+/// The most entries a directory may hold.
+const MAX_DIR_ENTRIES: u32 = 65536;
+
+// The largest numeric tail tried when generating a unique short name.
+const MAX_SHORT_NAME_TAIL: u32 = 999_999;
+
+// The FAT entries marking the end of a chain.
+const FAT16_END_OF_CHAIN: u32 = 0xffff;
+const FAT32_END_OF_CHAIN: u32 = 0x0fff_ffff;
+
+/// Errors which can occur while using the filesystem.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsError<E> {
+    /// The block device could not be read or written.
+    Device(E),
+    /// No FAT volume was found on the device.
+    NoFilesystem,
+    /// The volume is formatted as FAT12, which is not supported.
+    Unsupported,
+    /// The volume's FAT or directories are inconsistent.
+    Corrupt,
+    /// No file or directory has the given path.
+    NotFound,
+    /// A component of the path is a file, not a directory.
+    NotADirectory,
+    /// The path names a directory, but a file was expected.
+    IsADirectory,
+    /// The directory to be deleted is not empty.
+    DirectoryNotEmpty,
+    /// The name is empty, ends with a dot or space, or contains a character
+    /// which is not allowed in file names.
+    InvalidName,
+    /// The name is longer than [`MAX_NAME_LEN`] UTF-16 code units.
+    NameTooLong,
+    /// The directory has no room for another entry.
+    DirectoryFull,
+    /// The volume has no free clusters.
+    DiskFull,
+    /// The file was opened for reading, or is read-only.
+    NotWritable,
+    /// The file would be larger than 4GiB, the largest size FAT can record.
+    FileTooLarge,
+}
+
+/// How a file is opened by [`FileSystem::open`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Read the file from its start; it must already exist.
+    Read,
+    /// Write to the end of the file, creating it if it does not exist.
+    Append,
+    /// Create an empty file to write to, replacing any existing file with the
+    /// same name.
+    Create,
+}
+
+/// An open file. Files opened for writing must be passed to
+/// [`FileSystem::close`] once written, or their new size is lost.
+#[derive(Debug)]
+pub struct File {
+    mode: Mode,
+    first_cluster: u32,
+    size: u32,
+    position: u32,
+    // The cluster holding `position`, and its offset in the file.
+    cluster: u32,
+    cluster_start: u32,
+    // The location of the file's short directory entry.
+    block: u32,
+    offset: usize,
+    dirty: bool,
+}
+
+impl File {
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Return the size of the file, in bytes.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Return the offset of the next byte to be read or written.
+    pub fn position(&self) -> u32 {
+        self.position
+    }
+
+    /// Return whether every byte of the file has been read.
+    pub fn is_eof(&self) -> bool {
+        self.position >= self.size
+    }
+}
+
+/// A mounted FAT volume.
+pub struct FileSystem<D> {
+    device: D,
+    volume: Volume,
+    buffer: [u8; BLOCK_SIZE],
+    cached: Option<u32>,
+    dirty: bool,
+    next_free: u32,
+    info_invalidated: bool,
+    time: Timestamp,
+}
+
+impl<D> FileSystem<D>
+where
+    D: BlockDevice,
+{
+    /// Mount the FAT volume on `device`.
+    pub fn mount(mut device: D) -> Result<Self, FsError<D::Error>> {
+        let mut buffer = [0; BLOCK_SIZE];
+        device.read_block(0, &mut buffer).map_err(FsError::Device)?;
+        let start = volume_start(&buffer).ok_or(FsError::NoFilesystem)?;
+        if start != 0 {
+            device
+                .read_block(start, &mut buffer)
+                .map_err(FsError::Device)?;
+        }
+
+        let mut fs = Self {
+            device,
+            volume: Volume::parse(&buffer, start)?,
+            buffer,
+            cached: Some(start),
+            dirty: false,
+            next_free: 2,
+            info_invalidated: false,
+            time: DEFAULT_TIMESTAMP,
+        };
+
+        if let Some(info) = fs.volume.info_block {
+            if let Some(next) = info_next_free(fs.block(info)?) {
+                if fs.volume.is_cluster(next) {
+                    fs.next_free = next;
+                }
+            }
+        }
+
+        Ok(fs)
+    }
+
+    /// Write any changes still held in memory to the device, and return it.
+    /// Files open for writing must be closed first.
+    pub fn unmount(mut self) -> Result<D, FsError<D::Error>> {
+        self.flush_cache()?;
+        Ok(self.device)
+    }
+
+    pub fn fat_type(&self) -> FatType {
+        self.volume.kind
+    }
+
+    /// Set the time recorded when files are created or modified; there is no
+    /// clock, so this should be called whenever the time is known.
+    pub fn set_time(&mut self, time: Timestamp) {
+        self.time = time;
+    }
+
+    /// Open the directory at `path` to list its entries.
+    pub fn open_dir(&mut self, path: &str) -> Result<Dir, FsError<D::Error>> {
+        let (parent, name) = self.parent(path)?;
+        let start = if name.is_empty() {
+            parent
+        } else {
+            let entry = self.find(parent, name)?.ok_or(FsError::NotFound)?;
+            if !entry.is_dir() {
+                return Err(FsError::NotADirectory);
+            }
+            self.dir_start(entry.cluster)
+        };
+
+        Ok(Dir {
+            start,
+            cursor: Cursor::new(start),
+            done: false,
+        })
+    }
+
+    /// Return the next entry of a directory, excluding the `.` and `..`
+    /// entries and volume labels, or `None` once every entry has been listed.
+    pub fn next_entry(&mut self, dir: &mut Dir) -> Result<Option<DirEntry>, FsError<D::Error>> {
+        let mut long_name = LongName::new();
+
+        while !dir.done {
+            let index = dir.cursor.index;
+            let location = match self.step(&mut dir.cursor)? {
+                Some(location) => location,
+                None => break,
+            };
+            let entry = self.entry(location)?;
+
+            match entry[0] {
+                END_OF_DIRECTORY => break,
+                DELETED => long_name.reset(),
+                _ if entry[11] & ATTR_LONG_NAME_MASK == ATTR_LONG_NAME => {
+                    long_name.push(index, &entry)
+                }
+                b'.' => long_name.reset(),
+                _ if entry[11] & ATTR_VOLUME_ID != 0 => long_name.reset(),
+                _ => {
+                    return Ok(Some(DirEntry::new(
+                        dir.start, index, location, &entry, &long_name,
+                    )))
+                }
+            }
+        }
+
+        dir.done = true;
+        Ok(None)
+    }
+
+    /// Open the file at `path`.
+    pub fn open(&mut self, path: &str, mode: Mode) -> Result<File, FsError<D::Error>> {
+        let (parent, name) = self.parent(path)?;
+        if name.is_empty() {
+            return Err(FsError::IsADirectory);
+        }
+
+        let entry = match self.find(parent, name)? {
+            Some(entry) => entry,
+            None if mode == Mode::Read => return Err(FsError::NotFound),
+            None => {
+                let (block, offset) = self.create_entry(parent, name, ATTR_ARCHIVE)?;
+                return Ok(File {
+                    mode,
+                    first_cluster: 0,
+                    size: 0,
+                    position: 0,
+                    cluster: 0,
+                    cluster_start: 0,
+                    block,
+                    offset,
+                    dirty: false,
+                });
+            }
+        };
+
+        if entry.is_dir() {
+            return Err(FsError::IsADirectory);
+        }
+        if mode != Mode::Read && entry.is_read_only() {
+            return Err(FsError::NotWritable);
+        }
+        if entry.cluster != 0 && !self.volume.is_cluster(entry.cluster) {
+            return Err(FsError::Corrupt);
+        }
+
+        let mut file = File {
+            mode,
+            first_cluster: entry.cluster,
+            size: entry.size,
+            position: 0,
+            cluster: entry.cluster,
+            cluster_start: 0,
+            block: entry.block,
+            offset: entry.offset,
+            dirty: false,
+        };
+
+        match mode {
+            Mode::Read => {}
+            Mode::Append => {
+                // Find the cluster holding the last byte of the file.
+                if file.size > 0 {
+                    let cluster_size = self.volume.cluster_size();
+                    for _ in 0..(file.size - 1) / cluster_size {
+                        file.cluster = self.next_cluster(file.cluster)?.ok_or(FsError::Corrupt)?;
+                        file.cluster_start += cluster_size;
+                    }
+                }
+                file.position = file.size;
+            }
+            Mode::Create => {
+                // Detach the old clusters from the entry before freeing them.
+                file.first_cluster = 0;
+                file.cluster = 0;
+                file.size = 0;
+                file.dirty = true;
+                self.flush(&mut file)?;
+                if entry.cluster != 0 {
+                    self.free_chain(entry.cluster)?;
+                }
+            }
+        }
+
+        Ok(file)
+    }
+
+    /// Read from `file` into `buf`, returning the number of bytes read, which
+    /// is only less than the length of `buf` at the end of the file.
+    pub fn read(&mut self, file: &mut File, buf: &mut [u8]) -> Result<usize, FsError<D::Error>> {
+        let mut read = 0;
+
+        while read < buf.len() && file.position < file.size {
+            let (block, offset) = self.file_block(file, false)?;
+            let len = (BLOCK_SIZE - offset)
+                .min((file.size - file.position) as usize)
+                .min(buf.len() - read);
+
+            buf[read..read + len].copy_from_slice(&self.block(block)?[offset..offset + len]);
+            read += len;
+            file.position += len as u32;
+        }
+
+        Ok(read)
+    }
+
+    /// Write `data` to the end of `file`, allocating clusters as needed.
+    pub fn write(&mut self, file: &mut File, data: &[u8]) -> Result<(), FsError<D::Error>> {
+        if file.mode == Mode::Read {
+            return Err(FsError::NotWritable);
+        }
+        if file.size as u64 + data.len() as u64 > u32::MAX as u64 {
+            return Err(FsError::FileTooLarge);
+        }
+
+        let mut written = 0;
+        while written < data.len() {
+            let (block, offset) = self.file_block(file, true)?;
+            let len = (BLOCK_SIZE - offset).min(data.len() - written);
+
+            // A whole block need not be read before it is overwritten.
+            let buffer = if len == BLOCK_SIZE {
+                self.overwrite_block(block)?
+            } else {
+                self.block_mut(block)?
+            };
+            buffer[offset..offset + len].copy_from_slice(&data[written..written + len]);
+
+            written += len;
+            file.position += len as u32;
+            file.size = file.size.max(file.position);
+            file.dirty = true;
+        }
+
+        Ok(())
+    }
+
+    /// Record the size and modification time of `file` in its directory
+    /// entry, and write everything held in memory to the device.
+    pub fn flush(&mut self, file: &mut File) -> Result<(), FsError<D::Error>> {
+        if file.dirty {
+            let time = self.time;
+            let offset = file.offset;
+            let entry = &mut self.block_mut(file.block)?[offset..offset + ENTRY_SIZE];
+            set_extent(entry, file.first_cluster, file.size);
+            set_modified(entry, &time);
+            entry[11] |= ATTR_ARCHIVE;
+            file.dirty = false;
+        }
+
+        self.flush_cache()
+    }
+
+    /// Flush and close `file`.
+    pub fn close(&mut self, mut file: File) -> Result<(), FsError<D::Error>> {
+        self.flush(&mut file)
+    }
+
+    /// Delete the file or empty directory at `path`.
+    pub fn delete(&mut self, path: &str) -> Result<(), FsError<D::Error>> {
+        let (parent, name) = self.parent(path)?;
+        if name.is_empty() {
+            return Err(FsError::IsADirectory);
+        }
+        let entry = self.find(parent, name)?.ok_or(FsError::NotFound)?;
+
+        if entry.is_dir() {
+            if entry.cluster == 0 {
+                return Err(FsError::Corrupt);
+            }
+            let mut dir = Dir {
+                start: entry.cluster,
+                cursor: Cursor::new(entry.cluster),
+                done: false,
+            };
+            if self.next_entry(&mut dir)?.is_some() {
+                return Err(FsError::DirectoryNotEmpty);
+            }
+        }
+
+        // Mark the long name entries and the short entry as deleted.
+        let mut cursor = Cursor::new(entry.dir);
+        while cursor.index <= entry.index {
+            let index = cursor.index;
+            let (block, offset) = self.step(&mut cursor)?.ok_or(FsError::Corrupt)?;
+            if index >= entry.first {
+                self.block_mut(block)?[offset] = DELETED;
+            }
+        }
+
+        if entry.cluster != 0 {
+            self.free_chain(entry.cluster)?;
+        }
+
+        self.flush_cache()
+    }
+
+    // Resolve every component of `path` but the last, returning the first
+    // cluster of the directory holding it, and its name. The name is empty if
+    // the path is the root directory.
+    fn parent<'p>(&mut self, path: &'p str) -> Result<(u32, &'p str), FsError<D::Error>> {
+        let path = path.trim_matches('/');
+        let (dirs, name) = match path.rfind('/') {
+            Some(i) => (&path[..i], &path[i + 1..]),
+            None => ("", path),
+        };
+
+        let mut dir = self.volume.root();
+        for component in dirs.split('/').filter(|c| !c.is_empty()) {
+            let entry = self.find(dir, component)?.ok_or(FsError::NotFound)?;
+            if !entry.is_dir() {
+                return Err(FsError::NotADirectory);
+            }
+            dir = self.dir_start(entry.cluster);
+        }
+
+        Ok((dir, name))
+    }
+
+    // Return the first cluster of a directory, given the cluster recorded in
+    // its entry, which is 0 in `..` entries referring to the root.
+    fn dir_start(&self, cluster: u32) -> u32 {
+        if cluster == 0 {
+            self.volume.root()
+        } else {
+            cluster
+        }
+    }
+
+    // Find the entry called `name` in a directory.
+    fn find(&mut self, dir: u32, name: &str) -> Result<Option<DirEntry>, FsError<D::Error>> {
+        let mut dir = Dir {
+            start: dir,
+            cursor: Cursor::new(dir),
+            done: false,
+        };
+
+        while let Some(entry) = self.next_entry(&mut dir)? {
+            if entry.matches(name) {
+                return Ok(Some(entry));
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Add an entry called `name` to a directory, with long name entries if it
+    // is not a valid short name, returning the location of its short entry.
+    fn create_entry(
+        &mut self,
+        dir: u32,
+        name: &str,
+        attributes: u8,
+    ) -> Result<(u32, usize), FsError<D::Error>> {
+        if !is_valid_name(name) {
+            return Err(FsError::InvalidName);
+        }
+        if name.encode_utf16().count() > MAX_NAME_LEN {
+            return Err(FsError::NameTooLong);
+        }
+
+        let (short, case, long_entries) = match exact_short_name(name) {
+            Some((short, case)) => (short, case, 0),
+            None => (
+                self.unique_short_name(dir, name)?,
+                0,
+                long_entry_count(name),
+            ),
+        };
+
+        // Find enough consecutive free entries, growing the directory if
+        // there are not.
+        let needed = long_entries + 1;
+        let mut cursor = Cursor::new(dir);
+        let mut start = cursor;
+        let mut free = 0;
+        while free < needed {
+            let before = cursor;
+            match self.step(&mut cursor)? {
+                Some(location) => match self.entry(location)?[0] {
+                    END_OF_DIRECTORY | DELETED => {
+                        if free == 0 {
+                            start = before;
+                        }
+                        free += 1;
+                    }
+                    _ => free = 0,
+                },
+                None if cursor.cluster == 0 || cursor.index >= MAX_DIR_ENTRIES => {
+                    return Err(FsError::DirectoryFull)
+                }
+                None => {
+                    let cluster = self.allocate(Some(cursor.cluster))?;
+                    self.zero_cluster(cluster)?;
+                }
+            }
+        }
+
+        let checksum = checksum(&short);
+        let mut cursor = start;
+        for seq in (1..=long_entries).rev() {
+            let (block, offset) = self.step(&mut cursor)?.ok_or(FsError::Corrupt)?;
+            self.block_mut(block)?[offset..offset + ENTRY_SIZE].copy_from_slice(&long_entry(
+                name,
+                seq,
+                long_entries,
+                checksum,
+            ));
+        }
+
+        let (block, offset) = self.step(&mut cursor)?.ok_or(FsError::Corrupt)?;
+        let entry = short_entry(&short, case, attributes, &self.time);
+        self.block_mut(block)?[offset..offset + ENTRY_SIZE].copy_from_slice(&entry);
+        self.flush_cache()?;
+
+        Ok((block, offset))
+    }
+
+    // Generate a short name for `name` which is not used in a directory.
+    fn unique_short_name(&mut self, dir: u32, name: &str) -> Result<ShortName, FsError<D::Error>> {
+        let basis = short_name_basis(name);
+
+        for n in 1..=MAX_SHORT_NAME_TAIL {
+            let short = with_tail(&basis, n);
+            if !self.short_name_exists(dir, &short)? {
+                return Ok(short);
+            }
+        }
+
+        Err(FsError::DirectoryFull)
+    }
+
+    fn short_name_exists(
+        &mut self,
+        dir: u32,
+        short: &ShortName,
+    ) -> Result<bool, FsError<D::Error>> {
+        let mut cursor = Cursor::new(dir);
+
+        while let Some(location) = self.step(&mut cursor)? {
+            let entry = self.entry(location)?;
+            if entry[0] == END_OF_DIRECTORY {
+                break;
+            }
+            if entry[11] & ATTR_LONG_NAME_MASK != ATTR_LONG_NAME && entry[..11] == short[..] {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    // Return the location of the directory entry at `cursor` and advance it,
+    // or `None` at the end of the directory.
+    fn step(&mut self, cursor: &mut Cursor) -> Result<Option<(u32, usize)>, FsError<D::Error>> {
+        let block = if cursor.cluster == 0 {
+            if cursor.index >= self.volume.root_entries {
+                return Ok(None);
+            }
+            self.volume.root_start + cursor.index / ENTRIES_PER_BLOCK
+        } else {
+            if cursor.index >= MAX_DIR_ENTRIES {
+                return Ok(None);
+            }
+
+            let per_cluster = ENTRIES_PER_BLOCK * self.volume.blocks_per_cluster;
+            if cursor.index / per_cluster > cursor.cluster_index {
+                match self.next_cluster(cursor.cluster)? {
+                    Some(cluster) => {
+                        cursor.cluster = cluster;
+                        cursor.cluster_index += 1;
+                    }
+                    None => return Ok(None),
+                }
+            }
+            self.volume.cluster_block(cursor.cluster)
+                + cursor.index % per_cluster / ENTRIES_PER_BLOCK
+        };
+
+        let offset = (cursor.index % ENTRIES_PER_BLOCK) as usize * ENTRY_SIZE;
+        cursor.index += 1;
+        Ok(Some((block, offset)))
+    }
+
+    fn entry(
+        &mut self,
+        (block, offset): (u32, usize),
+    ) -> Result<[u8; ENTRY_SIZE], FsError<D::Error>> {
+        let mut entry = [0; ENTRY_SIZE];
+        entry.copy_from_slice(&self.block(block)?[offset..offset + ENTRY_SIZE]);
+        Ok(entry)
+    }
+
+    // Return the block and offset of the byte at a file's position, moving to
+    // the next cluster if the position has reached it. When writing, clusters
+    // are allocated at the end of the file.
+    fn file_block(
+        &mut self,
+        file: &mut File,
+        write: bool,
+    ) -> Result<(u32, usize), FsError<D::Error>> {
+        let cluster_size = self.volume.cluster_size();
+
+        if file.first_cluster == 0 {
+            if !write {
+                return Err(FsError::Corrupt);
+            }
+            file.first_cluster = self.allocate(None)?;
+            file.cluster = file.first_cluster;
+            file.cluster_start = 0;
+        } else if file.position >= file.cluster_start + cluster_size {
+            file.cluster = match self.next_cluster(file.cluster)? {
+                Some(cluster) => cluster,
+                None if write => self.allocate(Some(file.cluster))?,
+                None => return Err(FsError::Corrupt),
+            };
+            file.cluster_start += cluster_size;
+        }
+
+        let offset = file.position - file.cluster_start;
+        Ok((
+            self.volume.cluster_block(file.cluster) + offset / BLOCK_SIZE as u32,
+            offset as usize % BLOCK_SIZE,
+        ))
+    }
+
+    fn fat_entry(&mut self, cluster: u32) -> Result<u32, FsError<D::Error>> {
+        let kind = self.volume.kind;
+        let (block, offset) = self.volume.fat_position(cluster);
+        let fat = self.block(block)?;
+
+        Ok(match kind {
+            FatType::Fat16 => u16_at(fat, offset) as u32,
+            FatType::Fat32 => u32_at(fat, offset) & 0x0fff_ffff,
+        })
+    }
+
+    // Set a cluster's entry in every copy of the FAT.
+    fn set_fat_entry(&mut self, cluster: u32, value: u32) -> Result<(), FsError<D::Error>> {
+        self.invalidate_info()?;
+
+        let Volume {
+            kind,
+            fat_count,
+            fat_size,
+            ..
+        } = self.volume;
+        let (block, offset) = self.volume.fat_position(cluster);
+        for i in 0..fat_count {
+            let fat = self.block_mut(block + i * fat_size)?;
+            match kind {
+                FatType::Fat16 => {
+                    fat[offset..offset + 2].copy_from_slice(&(value as u16).to_le_bytes())
+                }
+                FatType::Fat32 => {
+                    // The top four bits are reserved, and must be preserved.
+                    let value = u32_at(fat, offset) & 0xf000_0000 | value;
+                    fat[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Return the cluster following `cluster` in its chain, or `None` at the
+    // end of the chain.
+    fn next_cluster(&mut self, cluster: u32) -> Result<Option<u32>, FsError<D::Error>> {
+        let next = self.fat_entry(cluster)?;
+
+        if next >= self.volume.end_of_chain() {
+            Ok(None)
+        } else if self.volume.is_cluster(next) {
+            Ok(Some(next))
+        } else {
+            Err(FsError::Corrupt)
+        }
+    }
+
+    // Allocate a free cluster, appending it to the chain ending at `last`.
+    fn allocate(&mut self, last: Option<u32>) -> Result<u32, FsError<D::Error>> {
+        let end = self.volume.cluster_count + 2;
+        let mut cluster = self.next_free;
+
+        for _ in 0..self.volume.cluster_count {
+            if self.fat_entry(cluster)? == 0 {
+                // Mark the new cluster as the end of its chain before linking
+                // it, so an interrupted write can only leak it.
+                let end_of_chain = match self.volume.kind {
+                    FatType::Fat16 => FAT16_END_OF_CHAIN,
+                    FatType::Fat32 => FAT32_END_OF_CHAIN,
+                };
+                self.set_fat_entry(cluster, end_of_chain)?;
+                if let Some(last) = last {
+                    self.set_fat_entry(last, cluster)?;
+                }
+
+                self.next_free = if cluster + 1 < end { cluster + 1 } else { 2 };
+                return Ok(cluster);
+            }
+
+            cluster = if cluster + 1 < end { cluster + 1 } else { 2 };
+        }
+
+        Err(FsError::DiskFull)
+    }
+
+    fn free_chain(&mut self, first: u32) -> Result<(), FsError<D::Error>> {
+        let mut cluster = first;
+
+        for _ in 0..self.volume.cluster_count {
+            let next = self.next_cluster(cluster)?;
+            self.set_fat_entry(cluster, 0)?;
+            self.next_free = self.next_free.min(cluster);
+
+            match next {
+                Some(next) => cluster = next,
+                None => return Ok(()),
+            }
+        }
+
+        Err(FsError::Corrupt)
+    }
+
+    fn zero_cluster(&mut self, cluster: u32) -> Result<(), FsError<D::Error>> {
+        let first = self.volume.cluster_block(cluster);
+        for block in first..first + self.volume.blocks_per_cluster {
+            *self.overwrite_block(block)? = [0; BLOCK_SIZE];
+        }
+
+        Ok(())
+    }
+
+    // Mark the FAT32 free cluster count as unknown the first time the FAT is
+    // changed, rather than keeping it up to date.
+    fn invalidate_info(&mut self) -> Result<(), FsError<D::Error>> {
+        if self.info_invalidated {
+            return Ok(());
+        }
+
+        if let Some(info) = self.volume.info_block {
+            if is_info_block(self.block(info)?) {
+                self.block_mut(info)?[FSINFO_FREE_COUNT..FSINFO_FREE_COUNT + 4]
+                    .copy_from_slice(&u32::MAX.to_le_bytes());
+            }
+        }
+        self.info_invalidated = true;
+
+        Ok(())
+    }
+
+    // Return a block, reading it into the buffer if it is not already there.
+    fn block(&mut self, block: u32) -> Result<&[u8; BLOCK_SIZE], FsError<D::Error>> {
+        self.load(block, true)?;
+        Ok(&self.buffer)
+    }
+
+    // Return a block to be modified; it is written back once another block is
+    // needed, or the cache is flushed.
+    fn block_mut(&mut self, block: u32) -> Result<&mut [u8; BLOCK_SIZE], FsError<D::Error>> {
+        self.load(block, true)?;
+        self.dirty = true;
+        Ok(&mut self.buffer)
+    }
+
+    // Return a block whose contents will be entirely replaced, without reading
+    // it.
+    fn overwrite_block(&mut self, block: u32) -> Result<&mut [u8; BLOCK_SIZE], FsError<D::Error>> {
+        self.load(block, false)?;
+        self.dirty = true;
+        Ok(&mut self.buffer)
+    }
+
+    fn load(&mut self, block: u32, read: bool) -> Result<(), FsError<D::Error>> {
+        if self.cached == Some(block) {
+            return Ok(());
+        }
+
+        self.flush_cache()?;
+        self.cached = None;
+        if read {
+            self.device
+                .read_block(block, &mut self.buffer)
+                .map_err(FsError::Device)?;
+        }
+        self.cached = Some(block);
+
+        Ok(())
+    }
+
+    fn flush_cache(&mut self) -> Result<(), FsError<D::Error>> {
+        if let (Some(block), true) = (self.cached, self.dirty) {
+            self.device
+                .write_block(block, &self.buffer)
+                .map_err(FsError::Device)?;
+        }
+        self.dirty = false;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use heapless::consts::{U16, U64};
+    use heapless::{FnvIndexMap, Vec};
+
+    // The sizes of the volumes formatted by `format`, in blocks.
+    const FAT12_BLOCKS: u32 = 2048;
+    const FAT16_BLOCKS: u32 = 8192;
+    const FAT32_BLOCKS: u32 = 70_000;
+
+    const LONG_NAME: &str = "A long file name.txt";
+
+    #[derive(Debug, PartialEq)]
+    enum DiskError {
+        OutOfRange,
+        Full,
+    }
+
+    // A block device in memory, which holds only the blocks written to it;
+    // every other block reads as zeros.
+    struct RamDisk {
+        blocks: FnvIndexMap<u32, [u8; BLOCK_SIZE], U64>,
+        count: u32,
+    }
+
+    impl RamDisk {
+        fn new(count: u32) -> Self {
+            Self {
+                blocks: FnvIndexMap::new(),
+                count,
+            }
+        }
+    }
+
+    impl BlockDevice for RamDisk {
+        type Error = DiskError;
+
+        fn read_block(&mut self, block: u32, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), DiskError> {
+            if block >= self.count {
+                return Err(DiskError::OutOfRange);
+            }
+            *buf = self.blocks.get(&block).cloned().unwrap_or([0; BLOCK_SIZE]);
+            Ok(())
+        }
+
+        fn write_block(&mut self, block: u32, data: &[u8; BLOCK_SIZE]) -> Result<(), DiskError> {
+            if block >= self.count {
+                return Err(DiskError::OutOfRange);
+            }
+            self.blocks
+                .insert(block, *data)
+                .map(|_| ())
+                .map_err(|_| DiskError::Full)
+        }
+
+        fn block_count(&mut self) -> Result<u32, DiskError> {
+            Ok(self.count)
+        }
+    }
+
+    // Format a volume of `blocks` blocks starting at block `start`, as a PC
+    // would: one block per cluster, two FATs, and either a 512-entry fixed
+    // root directory or, with `fat32`, a root directory in cluster 2 and an
+    // FSInfo block.
+    fn format(disk: &mut RamDisk, start: u32, blocks: u32, fat32: bool) {
+        let (reserved, root_entries, entry_size) = if fat32 { (32, 0, 4) } else { (1, 512, 2) };
+        let fat_size = blocks * entry_size / BLOCK_SIZE as u32 + 1;
+
+        let mut boot = [0; BLOCK_SIZE];
+        boot[..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+        boot[11..13].copy_from_slice(&(BLOCK_SIZE as u16).to_le_bytes());
+        boot[13] = 1;
+        boot[14..16].copy_from_slice(&(reserved as u16).to_le_bytes());
+        boot[16] = 2;
+        boot[17..19].copy_from_slice(&(root_entries as u16).to_le_bytes());
+        boot[21] = 0xf8;
+        if blocks < 0x10000 {
+            boot[19..21].copy_from_slice(&(blocks as u16).to_le_bytes());
+        } else {
+            boot[32..36].copy_from_slice(&blocks.to_le_bytes());
+        }
+        if fat32 {
+            boot[36..40].copy_from_slice(&fat_size.to_le_bytes());
+            boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+            boot[48..50].copy_from_slice(&1u16.to_le_bytes());
+        } else {
+            boot[22..24].copy_from_slice(&(fat_size as u16).to_le_bytes());
+        }
+        boot[510..].copy_from_slice(&[0x55, 0xaa]);
+        disk.write_block(start, &boot).unwrap();
+
+        // The first two FAT entries are reserved, and a FAT32 root directory
+        // takes the first cluster.
+        let mut fat = [0; BLOCK_SIZE];
+        if fat32 {
+            fat[..12].copy_from_slice(&[
+                0xf8, 0xff, 0xff, 0x0f, 0xff, 0xff, 0xff, 0x0f, 0xff, 0xff, 0xff, 0x0f,
+            ]);
+        } else {
+            fat[..4].copy_from_slice(&[0xf8, 0xff, 0xff, 0xff]);
+        }
+        for i in 0..2 {
+            disk.write_block(start + reserved + i * fat_size, &fat)
+                .unwrap();
+        }
+
+        if fat32 {
+            let mut info = [0; BLOCK_SIZE];
+            info[..4].copy_from_slice(&0x4161_5252u32.to_le_bytes());
+            info[484..488].copy_from_slice(&0x6141_7272u32.to_le_bytes());
+            info[488..492].copy_from_slice(&(blocks - reserved - 2 * fat_size - 1).to_le_bytes());
+            info[492..496].copy_from_slice(&3u32.to_le_bytes());
+            info[508..].copy_from_slice(&[0, 0, 0x55, 0xaa]);
+            disk.write_block(start + 1, &info).unwrap();
+        }
+    }
+
+    fn mount(fat32: bool) -> FileSystem<RamDisk> {
+        let blocks = if fat32 { FAT32_BLOCKS } else { FAT16_BLOCKS };
+        let mut disk = RamDisk::new(blocks);
+        format(&mut disk, 0, blocks, fat32);
+        FileSystem::mount(disk).unwrap()
+    }
+
+    fn remount(fs: FileSystem<RamDisk>) -> FileSystem<RamDisk> {
+        FileSystem::mount(fs.unmount().unwrap()).unwrap()
+    }
+
+    fn pattern(i: usize) -> u8 {
+        (i * 7 % 251) as u8
+    }
+
+    fn write_file(fs: &mut FileSystem<RamDisk>, path: &str, mode: Mode, data: &[u8]) {
+        let mut file = fs.open(path, mode).unwrap();
+        fs.write(&mut file, data).unwrap();
+        fs.close(file).unwrap();
+    }
+
+    fn read_file(fs: &mut FileSystem<RamDisk>, path: &str, buf: &mut [u8]) -> usize {
+        let mut file = fs.open(path, Mode::Read).unwrap();
+        let read = fs.read(&mut file, buf).unwrap();
+        assert!(file.is_eof());
+        read
+    }
+
+    fn list(fs: &mut FileSystem<RamDisk>, path: &str) -> Vec<DirEntry, U16> {
+        let mut dir = fs.open_dir(path).unwrap();
+        let mut entries = Vec::new();
+        while let Some(entry) = fs.next_entry(&mut dir).unwrap() {
+            entries.push(entry).unwrap();
+        }
+        entries
+    }
+
+    // Return the clusters of a file's chain.
+    fn chain(fs: &mut FileSystem<RamDisk>, path: &str) -> Vec<u32, U16> {
+        let file = fs.open(path, Mode::Read).unwrap();
+        let mut clusters = Vec::new();
+        let mut cluster = Some(file.first_cluster).filter(|&c| c != 0);
+        while let Some(c) = cluster {
+            clusters.push(c).unwrap();
+            cluster = fs.next_cluster(c).unwrap();
+        }
+        clusters
+    }
+
+    #[test]
+    fn mount_finds_the_fat_type() {
+        assert_eq!(mount(false).fat_type(), FatType::Fat16);
+        assert_eq!(mount(true).fat_type(), FatType::Fat32);
+    }
+
+    #[test]
+    fn mount_uses_the_first_fat_partition() {
+        let mut disk = RamDisk::new(FAT16_BLOCKS + 64);
+        let mut mbr = [0; BLOCK_SIZE];
+        // A Linux partition, then a FAT16 one.
+        mbr[446 + 4] = 0x83;
+        mbr[462 + 4] = 0x06;
+        mbr[462 + 8..462 + 12].copy_from_slice(&64u32.to_le_bytes());
+        mbr[510..].copy_from_slice(&[0x55, 0xaa]);
+        disk.write_block(0, &mbr).unwrap();
+        format(&mut disk, 64, FAT16_BLOCKS, false);
+
+        let mut fs = FileSystem::mount(disk).unwrap();
+        write_file(&mut fs, "a.txt", Mode::Create, b"partitioned");
+
+        let mut fs = remount(fs);
+        let mut buf = [0; 16];
+        assert_eq!(read_file(&mut fs, "a.txt", &mut buf), 11);
+        assert_eq!(&buf[..11], b"partitioned");
+        assert_eq!(fs.unmount().unwrap().blocks.get(&0), Some(&mbr));
+    }
+
+    #[test]
+    fn mount_rejects_devices_without_a_volume() {
+        let disk = RamDisk::new(FAT16_BLOCKS);
+        assert_eq!(FileSystem::mount(disk).err(), Some(FsError::NoFilesystem));
+
+        // A partition table with no FAT partition.
+        let mut disk = RamDisk::new(FAT16_BLOCKS);
+        let mut mbr = [0; BLOCK_SIZE];
+        mbr[446 + 4] = 0x83;
+        mbr[510..].copy_from_slice(&[0x55, 0xaa]);
+        disk.write_block(0, &mbr).unwrap();
+        assert_eq!(FileSystem::mount(disk).err(), Some(FsError::NoFilesystem));
+    }
+
+    #[test]
+    fn mount_rejects_fat12() {
+        let mut disk = RamDisk::new(FAT12_BLOCKS);
+        format(&mut disk, 0, FAT12_BLOCKS, false);
+        assert_eq!(FileSystem::mount(disk).err(), Some(FsError::Unsupported));
+    }
+
+    #[test]
+    fn long_names_are_created_listed_and_deleted() {
+        for &fat32 in &[false, true] {
+            let mut fs = mount(fat32);
+            write_file(&mut fs, LONG_NAME, Mode::Create, b"first");
+            write_file(&mut fs, "A long file name 2.txt", Mode::Create, b"second");
+            write_file(&mut fs, "Grüße.txt", Mode::Create, b"third");
+            write_file(&mut fs, "short.txt", Mode::Create, b"fourth");
+
+            let mut fs = remount(fs);
+            let entries = list(&mut fs, "/");
+            let names: Vec<(&str, &str, u32), U16> = entries
+                .iter()
+                .map(|e| (e.name(), e.short_name(), e.size()))
+                .collect();
+            assert_eq!(
+                names,
+                [
+                    (LONG_NAME, "ALONGF~1.TXT", 5),
+                    ("A long file name 2.txt", "ALONGF~2.TXT", 6),
+                    ("Grüße.txt", "GR__E~1.TXT", 5),
+                    ("short.txt", "short.txt", 6),
+                ]
+            );
+
+            // Files are found by either name, in any case.
+            let mut buf = [0; 8];
+            assert_eq!(read_file(&mut fs, "alongf~2.txt", &mut buf), 6);
+            assert_eq!(&buf[..6], b"second");
+            assert_eq!(read_file(&mut fs, "a LONG file NAME.TXT", &mut buf), 5);
+            assert_eq!(&buf[..5], b"first");
+
+            fs.delete(LONG_NAME).unwrap();
+            assert_eq!(
+                fs.open(LONG_NAME, Mode::Read).err(),
+                Some(FsError::NotFound)
+            );
+            assert_eq!(
+                fs.open("ALONGF~1.TXT", Mode::Read).err(),
+                Some(FsError::NotFound)
+            );
+
+            // The deleted name's entries are reused, and its short name may
+            // be given out again.
+            write_file(&mut fs, "Another long name.txt", Mode::Create, b"fifth");
+            let mut fs = remount(fs);
+            let entries = list(&mut fs, "");
+            let names: Vec<(&str, &str), U16> =
+                entries.iter().map(|e| (e.name(), e.short_name())).collect();
+            assert_eq!(
+                names,
+                [
+                    ("Another long name.txt", "ANOTHE~1.TXT"),
+                    ("A long file name 2.txt", "ALONGF~2.TXT"),
+                    ("Grüße.txt", "GR__E~1.TXT"),
+                    ("short.txt", "short.txt"),
+                ]
+            );
+            write_file(&mut fs, "A long file name 3.txt", Mode::Create, b"");
+            assert_eq!(list(&mut fs, "")[4].short_name(), "ALONGF~1.TXT");
+        }
+    }
+
+    #[test]
+    fn names_are_checked() {
+        let mut fs = mount(false);
+        assert_eq!(
+            fs.open("a?.txt", Mode::Create).err(),
+            Some(FsError::InvalidName)
+        );
+        assert_eq!(
+            fs.open("a.", Mode::Create).err(),
+            Some(FsError::InvalidName)
+        );
+
+        let mut name: heapless::String<heapless::consts::U512> = heapless::String::new();
+        for _ in 0..MAX_NAME_LEN {
+            name.push('x').unwrap();
+        }
+        write_file(&mut fs, &name, Mode::Create, b"");
+        assert_eq!(list(&mut fs, "")[0].name(), name.as_str());
+        name.push('x').unwrap();
+        assert_eq!(
+            fs.open(&name, Mode::Create).err(),
+            Some(FsError::NameTooLong)
+        );
+    }
+
+    #[test]
+    fn appends_across_clusters() {
+        for &fat32 in &[false, true] {
+            let mut fs = mount(fat32);
+            let mut data = [0; 2600];
+            for (i, b) in data.iter_mut().enumerate() {
+                *b = pattern(i);
+            }
+
+            // Appends start exactly at, just after and just before the end
+            // of a cluster.
+            write_file(&mut fs, "log.bin", Mode::Append, &data[..512]);
+            write_file(&mut fs, "log.bin", Mode::Append, &data[512..513]);
+            write_file(&mut fs, "log.bin", Mode::Append, &data[513..1535]);
+            let mut fs = remount(fs);
+            write_file(&mut fs, "log.bin", Mode::Append, &data[1535..]);
+
+            let mut fs = remount(fs);
+            let mut buf = [0; 3000];
+            assert_eq!(read_file(&mut fs, "log.bin", &mut buf), data.len());
+            assert_eq!(&buf[..data.len()], &data[..]);
+
+            let clusters = chain(&mut fs, "log.bin");
+            assert_eq!(clusters.len(), 6);
+            let end = if fat32 { 0x0fff_ffff } else { 0xffff };
+            assert_eq!(fs.fat_entry(clusters[5]).unwrap(), end);
+        }
+    }
+
+    #[test]
+    fn reads_are_split_at_the_end_of_the_file() {
+        let mut fs = mount(false);
+        write_file(&mut fs, "a.bin", Mode::Create, &[1; 700]);
+
+        let mut file = fs.open("a.bin", Mode::Read).unwrap();
+        let mut buf = [0; 300];
+        assert_eq!(fs.read(&mut file, &mut buf).unwrap(), 300);
+        assert_eq!(fs.read(&mut file, &mut buf).unwrap(), 300);
+        assert_eq!(fs.read(&mut file, &mut buf).unwrap(), 100);
+        assert_eq!(fs.read(&mut file, &mut buf).unwrap(), 0);
+        assert_eq!(fs.write(&mut file, b"x").err(), Some(FsError::NotWritable));
+    }
+
+    #[test]
+    fn create_replaces_a_file() {
+        for &fat32 in &[false, true] {
+            let mut fs = mount(fat32);
+            write_file(&mut fs, LONG_NAME, Mode::Create, &[0xaa; 2000]);
+            let old = chain(&mut fs, LONG_NAME);
+            assert_eq!(old.len(), 4);
+
+            let mut file = fs.open(LONG_NAME, Mode::Create).unwrap();
+            assert_eq!(file.size(), 0);
+            // The old clusters are freed as soon as the file is opened.
+            for &cluster in &old {
+                assert_eq!(fs.fat_entry(cluster).unwrap(), 0);
+            }
+            fs.write(&mut file, b"new").unwrap();
+            fs.close(file).unwrap();
+
+            let mut fs = remount(fs);
+            let entries = list(&mut fs, "");
+            assert_eq!(entries.len(), 1);
+            assert_eq!((entries[0].name(), entries[0].size()), (LONG_NAME, 3));
+            let mut buf = [0; 8];
+            assert_eq!(read_file(&mut fs, LONG_NAME, &mut buf), 3);
+            assert_eq!(&buf[..3], b"new");
+            assert_eq!(chain(&mut fs, LONG_NAME).len(), 1);
+        }
+    }
+
+    #[test]
+    fn unflushed_writes_keep_the_old_size() {
+        let mut fs = mount(true);
+        write_file(&mut fs, "a.txt", Mode::Create, b"kept");
+        let mut file = fs.open("a.txt", Mode::Append).unwrap();
+        fs.write(&mut file, b" lost").unwrap();
+
+        let mut fs = remount(fs);
+        let mut buf = [0; 16];
+        assert_eq!(read_file(&mut fs, "a.txt", &mut buf), 4);
+        assert_eq!(&buf[..4], b"kept");
+    }
+
+    #[test]
+    fn fat32_free_count_is_invalidated() {
+        let mut fs = mount(true);
+        write_file(&mut fs, "a.txt", Mode::Create, b"data");
+
+        let mut disk = fs.unmount().unwrap();
+        let mut info = [0; BLOCK_SIZE];
+        disk.read_block(1, &mut info).unwrap();
+        assert!(is_info_block(&info));
+        assert_eq!(
+            &info[FSINFO_FREE_COUNT..FSINFO_FREE_COUNT + 4],
+            &u32::MAX.to_le_bytes()
+        );
+
+        // Both copies of the FAT are kept the same.
+        let fs = FileSystem::mount(disk).unwrap();
+        let Volume {
+            fat_start,
+            fat_size,
+            ..
+        } = fs.volume;
+        let mut disk = fs.unmount().unwrap();
+        let (mut first, mut second) = ([0; BLOCK_SIZE], [0; BLOCK_SIZE]);
+        disk.read_block(fat_start, &mut first).unwrap();
+        disk.read_block(fat_start + fat_size, &mut second).unwrap();
+        assert_eq!(&first[..], &second[..]);
+        assert_eq!(u32_at(&first, 12), FAT32_END_OF_CHAIN);
+    }
+
+    #[test]
+    fn deleted_files_are_gone_after_remount() {
+        let mut fs = mount(false);
+        write_file(&mut fs, "a.txt", Mode::Create, &[1; 600]);
+        let clusters = chain(&mut fs, "a.txt");
+        fs.delete("a.txt").unwrap();
+        assert_eq!(fs.delete("a.txt").err(), Some(FsError::NotFound));
+
+        let mut fs = remount(fs);
+        assert!(list(&mut fs, "").is_empty());
+        for &cluster in &clusters {
+            assert_eq!(fs.fat_entry(cluster).unwrap(), 0);
+        }
+        assert_eq!(fs.delete("").err(), Some(FsError::IsADirectory));
+        assert_eq!(fs.open_dir("a.txt/b").err(), Some(FsError::NotFound));
+    }
+}
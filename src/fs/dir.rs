@@ -0,0 +1,235 @@
+use core::char;
+
+use heapless::consts::{U12, U255};
+use heapless::String;
+
+use super::name::{display_short_name, LongName, ShortName};
+use super::volume::{u16_at, u32_at};
+use super::ENTRY_SIZE;
+
+// Directory entry attributes.
+pub(crate) const ATTR_READ_ONLY: u8 = 0x01;
+pub(crate) const ATTR_VOLUME_ID: u8 = 0x08;
+pub(crate) const ATTR_DIRECTORY: u8 = 0x10;
+pub(crate) const ATTR_ARCHIVE: u8 = 0x20;
+pub(crate) const ATTR_LONG_NAME: u8 = 0x0f;
+pub(crate) const ATTR_LONG_NAME_MASK: u8 = 0x3f;
+
+// The first byte of a name marking a deleted entry, and the end of the
+// directory.
+pub(crate) const DELETED: u8 = 0xe5;
+pub(crate) const END_OF_DIRECTORY: u8 = 0x00;
+
+/// A date and time, as stored in directory entries: from 1980 to 2107, with a
+/// resolution of two seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timestamp {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+}
+
+/// The timestamp given to files until [`FileSystem::set_time`] is called.
+///
+/// [`FileSystem::set_time`]: super::FileSystem::set_time
+pub const DEFAULT_TIMESTAMP: Timestamp = Timestamp {
+    year: 2020,
+    month: 1,
+    day: 1,
+    hours: 0,
+    minutes: 0,
+    seconds: 0,
+};
+
+impl Timestamp {
+    pub(crate) fn from_fat(date: u16, time: u16) -> Self {
+        Self {
+            year: 1980 + (date >> 9),
+            month: (date >> 5 & 0x0f) as u8,
+            day: (date & 0x1f) as u8,
+            hours: (time >> 11) as u8,
+            minutes: (time >> 5 & 0x3f) as u8,
+            seconds: (time & 0x1f) as u8 * 2,
+        }
+    }
+
+    pub(crate) fn fat_date(&self) -> u16 {
+        self.year.saturating_sub(1980).min(127) << 9
+            | (self.month as u16 & 0x0f) << 5
+            | self.day as u16 & 0x1f
+    }
+
+    pub(crate) fn fat_time(&self) -> u16 {
+        (self.hours as u16 & 0x1f) << 11
+            | (self.minutes as u16 & 0x3f) << 5
+            | (self.seconds as u16 / 2) & 0x1f
+    }
+}
+
+/// An open directory, read with [`FileSystem::next_entry`].
+///
+/// [`FileSystem::next_entry`]: super::FileSystem::next_entry
+#[derive(Clone, Debug)]
+pub struct Dir {
+    pub(crate) start: u32,
+    pub(crate) cursor: Cursor,
+    pub(crate) done: bool,
+}
+
+/// The position of an entry in a directory, and the cluster holding it.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Cursor {
+    pub index: u32,
+    /// The cluster holding the entry, or 0 in a fixed FAT16 root directory.
+    pub cluster: u32,
+    /// The position of `cluster` in the directory's chain.
+    pub cluster_index: u32,
+}
+
+impl Cursor {
+    pub fn new(start: u32) -> Self {
+        Self {
+            index: 0,
+            cluster: start,
+            cluster_index: 0,
+        }
+    }
+}
+
+/// A file or directory, as listed by [`FileSystem::next_entry`].
+///
+/// [`FileSystem::next_entry`]: super::FileSystem::next_entry
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    name: String<U255>,
+    short_name: String<U12>,
+    pub(crate) attributes: u8,
+    pub(crate) cluster: u32,
+    pub(crate) size: u32,
+    modified: Timestamp,
+    // The directory holding the entry, the index of its first long name
+    // entry, if any, and of its short entry, and the short entry's location.
+    pub(crate) dir: u32,
+    pub(crate) first: u32,
+    pub(crate) index: u32,
+    pub(crate) block: u32,
+    pub(crate) offset: usize,
+}
+
+impl DirEntry {
+    pub(crate) fn new(
+        dir: u32,
+        index: u32,
+        location: (u32, usize),
+        entry: &[u8; ENTRY_SIZE],
+        long_name: &LongName,
+    ) -> Self {
+        let mut short = ShortName::default();
+        short.copy_from_slice(&entry[..11]);
+        let short_name = display_short_name(&short, entry[12]);
+
+        // Fall back to the short name if the long one is missing, or too long
+        // to hold as UTF-8.
+        let mut name = String::new();
+        let first = match long_name.get(&short) {
+            Some((units, first)) => {
+                let fits = char::decode_utf16(units.iter().cloned())
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .all(|c| name.push(c).is_ok());
+                if !fits {
+                    name = String::new();
+                    name.push_str(&short_name).ok();
+                }
+                first
+            }
+            None => {
+                name.push_str(&short_name).ok();
+                index
+            }
+        };
+
+        Self {
+            name,
+            short_name,
+            attributes: entry[11],
+            cluster: (u16_at(entry, 20) as u32) << 16 | u16_at(entry, 26) as u32,
+            size: u32_at(entry, 28),
+            modified: Timestamp::from_fat(u16_at(entry, 24), u16_at(entry, 22)),
+            dir,
+            first,
+            index,
+            block: location.0,
+            offset: location.1,
+        }
+    }
+
+    /// Return the entry's name: its long name if it has one, otherwise its
+    /// short name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return the entry's short (8.3) name.
+    pub fn short_name(&self) -> &str {
+        &self.short_name
+    }
+
+    /// Return whether the entry's name, long or short, is `name`, ignoring
+    /// ASCII case.
+    pub fn matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name) || self.short_name.eq_ignore_ascii_case(name)
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.attributes & ATTR_READ_ONLY != 0
+    }
+
+    /// Return the size of the file, in bytes; directories have no size.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Return the time the entry was last modified.
+    pub fn modified(&self) -> Timestamp {
+        self.modified
+    }
+}
+
+/// Build a short directory entry.
+pub(crate) fn short_entry(
+    short: &ShortName,
+    case: u8,
+    attributes: u8,
+    time: &Timestamp,
+) -> [u8; ENTRY_SIZE] {
+    let mut entry = [0u8; ENTRY_SIZE];
+    entry[..11].copy_from_slice(short);
+    entry[11] = attributes;
+    entry[12] = case;
+    entry[14..16].copy_from_slice(&time.fat_time().to_le_bytes());
+    entry[16..18].copy_from_slice(&time.fat_date().to_le_bytes());
+    set_modified(&mut entry, time);
+
+    entry
+}
+
+/// Set the first cluster and size of a short directory entry.
+pub(crate) fn set_extent(entry: &mut [u8], cluster: u32, size: u32) {
+    entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    entry[28..32].copy_from_slice(&size.to_le_bytes());
+}
+
+/// Set the access and modification time of a short directory entry.
+pub(crate) fn set_modified(entry: &mut [u8], time: &Timestamp) {
+    entry[18..20].copy_from_slice(&time.fat_date().to_le_bytes());
+    entry[22..24].copy_from_slice(&time.fat_time().to_le_bytes());
+    entry[24..26].copy_from_slice(&time.fat_date().to_le_bytes());
+}
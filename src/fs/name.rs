@@ -0,0 +1,275 @@
+use heapless::consts::U12;
+use heapless::String;
+
+use super::ENTRY_SIZE;
+
+/// The most UTF-16 code units in a long file name.
+pub const MAX_NAME_LEN: usize = 255;
+
+// Each long name entry holds 13 UTF-16 code units, at these byte offsets.
+const UNITS_PER_ENTRY: usize = 13;
+const UNIT_OFFSETS: [usize; UNITS_PER_ENTRY] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const MAX_LONG_ENTRIES: usize = (MAX_NAME_LEN + UNITS_PER_ENTRY - 1) / UNITS_PER_ENTRY;
+const LAST_LONG_ENTRY: u8 = 0x40;
+
+// The case of the base and extension of a short name, stored in the reserved
+// byte of its directory entry.
+pub(crate) const LOWER_CASE_BASE: u8 = 0x08;
+pub(crate) const LOWER_CASE_EXTENSION: u8 = 0x10;
+
+// Characters other than ASCII letters and digits which are valid in short
+// names.
+const SHORT_SYMBOLS: &[u8] = b"!#$%&'()-@^_`{}~";
+const INVALID_CHARS: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+
+/// The 11-byte short (8.3) name of a directory entry, padded with spaces.
+pub(crate) type ShortName = [u8; 11];
+
+/// Return whether `name` may be given to a new file: it must not be empty,
+/// `.` or `..`, end with a dot or space, or contain control characters or any
+/// of `"*/:<>?\|`.
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.ends_with('.')
+        && !name.ends_with(' ')
+        && !name
+            .chars()
+            .any(|c| c < ' ' || c == '\x7f' || INVALID_CHARS.contains(&c))
+}
+
+/// Return the short name and case flags for `name` if it is a valid 8.3 name
+/// in its own right, so no long name is needed.
+pub(crate) fn exact_short_name(name: &str) -> Option<(ShortName, u8)> {
+    let (base, extension) = match name.find('.') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (name, ""),
+    };
+    if base.is_empty()
+        || base.len() > 8
+        || extension.len() > 3
+        || !name.bytes().filter(|&b| b != b'.').all(is_short_char)
+        || extension.contains('.')
+    {
+        return None;
+    }
+
+    let mut short = [b' '; 11];
+    let mut flags = 0;
+    if fill_exact(&mut short[..8], base)? {
+        flags |= LOWER_CASE_BASE;
+    }
+    if fill_exact(&mut short[8..], extension)? {
+        flags |= LOWER_CASE_EXTENSION;
+    }
+
+    Some((short, flags))
+}
+
+// Copy one part of an exact short name, returning whether it is lower case,
+// or `None` if its case is mixed.
+fn fill_exact(dest: &mut [u8], part: &str) -> Option<bool> {
+    let lower = part.bytes().any(|b| b.is_ascii_lowercase());
+    if lower && part.bytes().any(|b| b.is_ascii_uppercase()) {
+        return None;
+    }
+    for (d, b) in dest.iter_mut().zip(part.bytes()) {
+        *d = b.to_ascii_uppercase();
+    }
+
+    Some(lower)
+}
+
+/// Derive the short name for a file with a long name, before a numeric tail
+/// is added: the name is upper-cased, characters which are invalid in short
+/// names are replaced with underscores, spaces and leading dots are dropped,
+/// and the base and extension are truncated.
+pub(crate) fn short_name_basis(name: &str) -> ShortName {
+    let name = name.trim_start_matches('.');
+    let (base, extension) = match name.rfind('.') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (name, ""),
+    };
+
+    let mut short = [b' '; 11];
+    fill_basis(&mut short[..8], base);
+    fill_basis(&mut short[8..], extension);
+    if short[0] == b' ' {
+        short[0] = b'_';
+    }
+
+    short
+}
+
+fn fill_basis(dest: &mut [u8], part: &str) {
+    let chars = part.chars().filter(|&c| c != ' ' && c != '.').map(|c| {
+        if c.is_ascii() && is_short_char(c as u8) {
+            (c as u8).to_ascii_uppercase()
+        } else {
+            b'_'
+        }
+    });
+    for (d, b) in dest.iter_mut().zip(chars) {
+        *d = b;
+    }
+}
+
+/// Add the numeric tail `~n` to a short name basis, truncating its base so
+/// the tail fits. `n` must have no more than six digits.
+pub(crate) fn with_tail(basis: &ShortName, n: u32) -> ShortName {
+    let mut digits = [0u8; 10];
+    let mut len = 0;
+    let mut rest = n;
+    loop {
+        digits[len] = b'0' + (rest % 10) as u8;
+        len += 1;
+        rest /= 10;
+        if rest == 0 {
+            break;
+        }
+    }
+
+    let mut short = *basis;
+    let base_len = short[..8].iter().position(|&b| b == b' ').unwrap_or(8);
+    let start = base_len.min(8 - len - 1);
+    short[start] = b'~';
+    for i in 0..len {
+        short[start + 1 + i] = digits[len - 1 - i];
+    }
+    for b in &mut short[start + 1 + len..8] {
+        *b = b' ';
+    }
+
+    short
+}
+
+/// Return a short name as it is shown to the user, such as `README.txt`.
+pub(crate) fn display_short_name(short: &ShortName, flags: u8) -> String<U12> {
+    let mut name = String::new();
+    let part = |bytes: &[u8], lower: bool, name: &mut String<U12>| {
+        for (i, &b) in bytes.iter().enumerate() {
+            if b == b' ' {
+                break;
+            }
+            // A leading 0x05 stands for 0xe5, which marks deleted entries.
+            let b = if i == 0 && b == 0x05 { 0xe5 } else { b };
+            let c = if !b.is_ascii() {
+                '_'
+            } else if lower {
+                b.to_ascii_lowercase() as char
+            } else {
+                b as char
+            };
+            name.push(c).ok();
+        }
+    };
+
+    part(&short[..8], flags & LOWER_CASE_BASE != 0, &mut name);
+    if short[8] != b' ' {
+        name.push('.').ok();
+        part(&short[8..], flags & LOWER_CASE_EXTENSION != 0, &mut name);
+    }
+
+    name
+}
+
+/// Return the checksum of a short name, stored in each of its long name
+/// entries.
+pub(crate) fn checksum(short: &ShortName) -> u8 {
+    short
+        .iter()
+        .fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+/// Return the number of long name entries needed for `name`.
+pub(crate) fn long_entry_count(name: &str) -> usize {
+    (name.encode_utf16().count() + UNITS_PER_ENTRY - 1) / UNITS_PER_ENTRY
+}
+
+/// Build the long name entry with sequence number `seq`, counting from 1, of
+/// the `count` entries holding `name`.
+pub(crate) fn long_entry(name: &str, seq: usize, count: usize, checksum: u8) -> [u8; ENTRY_SIZE] {
+    let mut entry = [0u8; ENTRY_SIZE];
+    entry[0] = seq as u8 | if seq == count { LAST_LONG_ENTRY } else { 0 };
+    entry[11] = super::ATTR_LONG_NAME;
+    entry[13] = checksum;
+
+    // The name is terminated by a null if there is room, then padded.
+    let units = name
+        .encode_utf16()
+        .chain(Some(0))
+        .chain(core::iter::repeat(0xffff))
+        .skip((seq - 1) * UNITS_PER_ENTRY);
+    for (&offset, unit) in UNIT_OFFSETS.iter().zip(units) {
+        entry[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+    }
+
+    entry
+}
+
+/// Collects the long name entries which precede a short entry.
+pub(crate) struct LongName {
+    units: [u16; MAX_LONG_ENTRIES * UNITS_PER_ENTRY],
+    // The sequence number of the last entry collected, or 0 if none are.
+    seq: u8,
+    checksum: u8,
+    first: u32,
+}
+
+impl LongName {
+    pub fn new() -> Self {
+        Self {
+            units: [0xffff; MAX_LONG_ENTRIES * UNITS_PER_ENTRY],
+            seq: 0,
+            checksum: 0,
+            first: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.seq = 0;
+    }
+
+    /// Collect the long name entry at `index` in its directory.
+    pub fn push(&mut self, index: u32, entry: &[u8; ENTRY_SIZE]) {
+        let seq = entry[0] & !LAST_LONG_ENTRY;
+        if entry[0] & LAST_LONG_ENTRY != 0 {
+            // The entries are stored last first, so this starts a new name.
+            if seq == 0 || seq as usize > MAX_LONG_ENTRIES {
+                self.reset();
+                return;
+            }
+            self.units = [0xffff; MAX_LONG_ENTRIES * UNITS_PER_ENTRY];
+            self.checksum = entry[13];
+            self.first = index;
+        } else if seq == 0 || seq + 1 != self.seq || entry[13] != self.checksum {
+            self.reset();
+            return;
+        }
+
+        self.seq = seq;
+        let start = (seq as usize - 1) * UNITS_PER_ENTRY;
+        for (i, &offset) in UNIT_OFFSETS.iter().enumerate() {
+            self.units[start + i] = u16::from_le_bytes([entry[offset], entry[offset + 1]]);
+        }
+    }
+
+    /// Return the name collected for the short entry `short`, and the index
+    /// of its first entry, if every entry was found and their checksums
+    /// match.
+    pub fn get(&self, short: &ShortName) -> Option<(&[u16], u32)> {
+        if self.seq != 1 || self.checksum != checksum(short) {
+            return None;
+        }
+
+        let len = self
+            .units
+            .iter()
+            .position(|&u| u == 0 || u == 0xffff)
+            .unwrap_or(self.units.len());
+        Some((&self.units[..len.min(MAX_NAME_LEN)], self.first))
+    }
+}
+
+fn is_short_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || SHORT_SYMBOLS.contains(&b)
+}
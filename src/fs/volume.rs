@@ -0,0 +1,205 @@
+use crate::BLOCK_SIZE;
+
+use super::FsError;
+
+/// The variant of FAT used by a mounted volume, decided by its cluster count.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FatType {
+    Fat16,
+    Fat32,
+}
+
+// Volumes with fewer clusters than these are FAT12 and FAT16 respectively.
+const MIN_FAT16_CLUSTERS: u32 = 4085;
+const MIN_FAT32_CLUSTERS: u32 = 65525;
+
+// MBR partition types of FAT16 and FAT32 volumes.
+const PARTITION_TYPES: [u8; 5] = [0x04, 0x06, 0x0b, 0x0c, 0x0e];
+
+// The FSInfo block's signatures, and the offset of its free cluster count.
+const FSINFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+pub(crate) const FSINFO_FREE_COUNT: usize = 488;
+const FSINFO_NEXT_FREE: usize = 492;
+
+/// The layout of a FAT volume, with every position an absolute block index.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Volume {
+    pub kind: FatType,
+    pub fat_start: u32,
+    pub fat_size: u32,
+    pub fat_count: u32,
+    /// The fixed root directory of a FAT16 volume.
+    pub root_start: u32,
+    pub root_entries: u32,
+    /// The first cluster of a FAT32 volume's root directory.
+    pub root_cluster: u32,
+    /// The block holding cluster 2, the first data cluster.
+    pub data_start: u32,
+    pub blocks_per_cluster: u32,
+    pub cluster_count: u32,
+    pub info_block: Option<u32>,
+}
+
+impl Volume {
+    /// Parse the boot block of a volume beginning at block `start`.
+    pub fn parse<E>(boot: &[u8; BLOCK_SIZE], start: u32) -> Result<Self, FsError<E>> {
+        if !is_boot_block(boot) {
+            return Err(FsError::NoFilesystem);
+        }
+
+        let blocks_per_cluster = boot[13] as u32;
+        let reserved = u16_at(boot, 14) as u32;
+        let fat_count = boot[16] as u32;
+        let root_entries = u16_at(boot, 17) as u32;
+        let total = match u16_at(boot, 19) {
+            0 => u32_at(boot, 32),
+            n => n as u32,
+        };
+        let fat_size = match u16_at(boot, 22) {
+            0 => u32_at(boot, 36),
+            n => n as u32,
+        };
+
+        let root_blocks = (root_entries * 32 + BLOCK_SIZE as u32 - 1) / BLOCK_SIZE as u32;
+        let data_offset = reserved + fat_count * fat_size + root_blocks;
+        if fat_size == 0 || data_offset >= total {
+            return Err(FsError::NoFilesystem);
+        }
+
+        let cluster_count = (total - data_offset) / blocks_per_cluster;
+        let kind = if cluster_count < MIN_FAT16_CLUSTERS {
+            return Err(FsError::Unsupported);
+        } else if cluster_count < MIN_FAT32_CLUSTERS {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+
+        let info = u16_at(boot, 48) as u32;
+        let info_block = if kind == FatType::Fat32 && info != 0 && info < reserved {
+            Some(start + info)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            kind,
+            fat_start: start + reserved,
+            fat_size,
+            fat_count,
+            root_start: start + reserved + fat_count * fat_size,
+            root_entries: if kind == FatType::Fat16 {
+                root_entries
+            } else {
+                0
+            },
+            root_cluster: if kind == FatType::Fat32 {
+                u32_at(boot, 44)
+            } else {
+                0
+            },
+            data_start: start + data_offset,
+            blocks_per_cluster,
+            cluster_count,
+            info_block,
+        })
+    }
+
+    /// Return the first cluster of the root directory, or 0 for the fixed
+    /// root directory of a FAT16 volume.
+    pub fn root(&self) -> u32 {
+        self.root_cluster
+    }
+
+    /// Return the number of bytes in a cluster.
+    pub fn cluster_size(&self) -> u32 {
+        self.blocks_per_cluster * BLOCK_SIZE as u32
+    }
+
+    /// Return the first block of a data cluster.
+    pub fn cluster_block(&self, cluster: u32) -> u32 {
+        self.data_start + (cluster - 2) * self.blocks_per_cluster
+    }
+
+    /// Return whether `cluster` is a data cluster on the volume.
+    pub fn is_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.cluster_count + 2
+    }
+
+    /// Return the block and byte offset of a cluster's entry in the first FAT.
+    pub fn fat_position(&self, cluster: u32) -> (u32, usize) {
+        let bytes = match self.kind {
+            FatType::Fat16 => cluster * 2,
+            FatType::Fat32 => cluster * 4,
+        };
+        (
+            self.fat_start + bytes / BLOCK_SIZE as u32,
+            bytes as usize % BLOCK_SIZE,
+        )
+    }
+
+    /// Return the smallest FAT entry value marking the end of a chain.
+    pub fn end_of_chain(&self) -> u32 {
+        match self.kind {
+            FatType::Fat16 => 0xfff8,
+            FatType::Fat32 => 0x0fff_fff8,
+        }
+    }
+}
+
+/// Find the first block of the FAT volume on a device, given its first
+/// block: either the volume itself, or a master boot record whose first FAT16
+/// or FAT32 partition is used.
+pub(crate) fn volume_start(block0: &[u8; BLOCK_SIZE]) -> Option<u32> {
+    if is_boot_block(block0) {
+        return Some(0);
+    }
+    if block0[510..] != [0x55, 0xaa] {
+        return None;
+    }
+
+    (0..4)
+        .map(|i| 446 + i * 16)
+        .find(|&entry| PARTITION_TYPES.contains(&block0[entry + 4]))
+        .map(|entry| u32_at(block0, entry + 8))
+}
+
+/// Return the next free cluster hint of an FSInfo block, if it is valid.
+pub(crate) fn info_next_free(info: &[u8; BLOCK_SIZE]) -> Option<u32> {
+    if is_info_block(info) {
+        Some(u32_at(info, FSINFO_NEXT_FREE))
+    } else {
+        None
+    }
+}
+
+pub(crate) fn is_info_block(info: &[u8; BLOCK_SIZE]) -> bool {
+    u32_at(info, 0) == FSINFO_LEAD_SIGNATURE && u32_at(info, 484) == FSINFO_STRUCT_SIGNATURE
+}
+
+// A FAT boot block starts with a jump instruction, has 512-byte sectors and a
+// power of two sectors per cluster.
+fn is_boot_block(block: &[u8; BLOCK_SIZE]) -> bool {
+    let blocks_per_cluster = block[13];
+
+    (block[0] == 0xeb || block[0] == 0xe9)
+        && block[510..] == [0x55, 0xaa]
+        && u16_at(block, 11) as usize == BLOCK_SIZE
+        && blocks_per_cluster.is_power_of_two()
+        && u16_at(block, 14) != 0
+        && block[16] != 0
+}
+
+pub(crate) fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+pub(crate) fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
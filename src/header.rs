@@ -0,0 +1,301 @@
+#[rustfmt::skip]
+use atsamd_hal::gpio::{
+    Floating, Input, PfB, PfC, PfD, PfE, PfF, Port,
+    Pa4, Pa6, Pa7, Pa14, Pb4, Pb5, Pb6, Pb7, Pb8, Pb9, Pb17, Pb28, Pb29,
+    Pc1, Pc2, Pc3, Pc4, Pc31, Pd0, Pd1,
+};
+use atsamd_hal::sercom::{
+    PadPin, Sercom0Pad0, Sercom0Pad2, Sercom0Pad3, Sercom2Pad2, Sercom4Pad0, Sercom4Pad1,
+    Sercom5Pad1, Sercom6Pad0,
+};
+
+/// 40-pin header GPIO pins
+///
+/// Each pin can be switched to the functions it supports with the
+/// [`IntoAnalog`], [`IntoPwm`], [`IntoI2c`] and [`IntoUart`] traits, which are
+/// only implemented for pins with that function; see [`HEADER_PINS`] for what
+/// each pin supports.
+pub struct Header {
+    /// `A0`/`D0` pin
+    pub a0_d0: Pb8<Input<Floating>>,
+
+    /// `A1`/`D1` pin
+    pub a1_d1: Pb9<Input<Floating>>,
+
+    /// `A2`/`D2` pin
+    pub a2_d2: Pa7<Input<Floating>>,
+
+    /// `A3`/`D3` pin
+    pub a3_d3: Pb4<Input<Floating>>,
+
+    /// `A4`/`D4` pin
+    pub a4_d4: Pb5<Input<Floating>>,
+
+    /// `A5`/`D5` pin
+    pub a5_d5: Pb6<Input<Floating>>,
+
+    /// `A6`/`D6` pin
+    pub a6_d6: Pa4<Input<Floating>>,
+
+    /// `A7`/`D7` pin
+    pub a7_d7: Pb7<Input<Floating>>,
+
+    /// `A8`/`D8` pin
+    pub a8_d8: Pa6<Input<Floating>>,
+}
+
+/// FPC connector pins
+///
+/// Like the [`Header`] pins, these can be switched to the functions they
+/// support with the `Into*` traits. `D13`/`A13` is also connected to the
+/// light sensor, so it is found in the `LightSensor` set instead.
+pub struct FPC {
+    /// `D3`/`PWM3` pin
+    pub d3_pwm3: Pb28<Input<Floating>>,
+
+    /// `D4`/`PWM4` pin
+    pub d4_pwm4: Pb17<Input<Floating>>,
+
+    /// `D5`/`PWM5` pin
+    pub d5_pwm5: Pb29<Input<Floating>>,
+
+    /// `D6`/`PWM6` pin
+    pub d6_pwm6: Pa14<Input<Floating>>,
+
+    /// `D7`/`A7` pin
+    pub d7_a7: Pc1<Input<Floating>>,
+
+    /// `D8`/`A8` pin
+    pub d8_a8: Pc2<Input<Floating>>,
+
+    /// `D9`/`A9` pin
+    pub d9_a9: Pc3<Input<Floating>>,
+
+    /// `D10`/`PWM10` pin
+    pub d10_pwm10: Pc4<Input<Floating>>,
+
+    /// `D11`/`A11` pin
+    pub d11_a11: Pc31<Input<Floating>>,
+
+    /// `D12`/`A12` pin
+    pub d12_a12: Pd0<Input<Floating>>,
+}
+
+/// A function which a header or FPC pin may support.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capability {
+    /// Digital input and output, which every pin supports.
+    Digital,
+    /// Analog input, through an ADC.
+    Analog,
+    /// PWM output, through a TC or TCC.
+    Pwm,
+    /// I2C, through a SERCOM pad 0 (SDA) or 1 (SCL).
+    I2c,
+    /// UART, through a SERCOM pad.
+    Uart,
+}
+
+/// The ADC input a pin is connected to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AnalogInput {
+    pub adc: u8,
+    pub channel: u8,
+}
+
+/// The timer output a pin is connected to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PwmOutput {
+    /// The timer, such as `"TC4"` or `"TCC1"`.
+    pub timer: &'static str,
+    /// The waveform output of the timer.
+    pub output: u8,
+}
+
+/// The SERCOM pad a pin is connected to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SercomPad {
+    pub sercom: u8,
+    pub pad: u8,
+}
+
+/// The functions supported by a header or FPC pin, as switched to by the
+/// `Into*` traits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PinCapabilities {
+    /// The name of the pin in `Pins`, such as `"a0_d0"`.
+    pub name: &'static str,
+    /// The port pin, such as `"PB08"`.
+    pub pin: &'static str,
+    pub analog: Option<AnalogInput>,
+    pub pwm: Option<PwmOutput>,
+    pub sercom: Option<SercomPad>,
+}
+
+impl PinCapabilities {
+    /// Return whether the pin supports the given function.
+    pub fn supports(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Digital => true,
+            Capability::Analog => self.analog.is_some(),
+            Capability::Pwm => self.pwm.is_some(),
+            Capability::I2c => match self.sercom {
+                Some(sercom) => sercom.pad < 2,
+                None => false,
+            },
+            Capability::Uart => self.sercom.is_some(),
+        }
+    }
+}
+
+const fn adc(adc: u8, channel: u8) -> Option<AnalogInput> {
+    Some(AnalogInput { adc, channel })
+}
+
+const fn tc(timer: &'static str, output: u8) -> Option<PwmOutput> {
+    Some(PwmOutput { timer, output })
+}
+
+const fn pad(sercom: u8, pad: u8) -> Option<SercomPad> {
+    Some(SercomPad { sercom, pad })
+}
+
+/// The functions supported by each pin of the 40-pin header and the FPC
+/// connector, for diagnostics.
+#[rustfmt::skip]
+pub static HEADER_PINS: [PinCapabilities; 20] = [
+    PinCapabilities { name: "a0_d0",         pin: "PB08", analog: adc(0, 2),  pwm: tc("TC4", 0),  sercom: pad(4, 0)  },
+    PinCapabilities { name: "a1_d1",         pin: "PB09", analog: adc(0, 3),  pwm: tc("TC4", 1),  sercom: pad(4, 1)  },
+    PinCapabilities { name: "a2_d2",         pin: "PA07", analog: adc(0, 7),  pwm: tc("TC1", 1),  sercom: pad(0, 3)  },
+    PinCapabilities { name: "a3_d3",         pin: "PB04", analog: adc(1, 6),  pwm: None,          sercom: None       },
+    PinCapabilities { name: "a4_d4",         pin: "PB05", analog: adc(1, 7),  pwm: None,          sercom: None       },
+    PinCapabilities { name: "a5_d5",         pin: "PB06", analog: adc(1, 8),  pwm: None,          sercom: None       },
+    PinCapabilities { name: "a6_d6",         pin: "PA04", analog: adc(0, 4),  pwm: tc("TC0", 0),  sercom: pad(0, 0)  },
+    PinCapabilities { name: "a7_d7",         pin: "PB07", analog: adc(1, 9),  pwm: None,          sercom: None       },
+    PinCapabilities { name: "a8_d8",         pin: "PA06", analog: adc(0, 6),  pwm: tc("TC1", 0),  sercom: pad(0, 2)  },
+    PinCapabilities { name: "fpc_d3_pwm3",   pin: "PB28", analog: None,       pwm: tc("TCC1", 4), sercom: None       },
+    PinCapabilities { name: "fpc_d4_pwm4",   pin: "PB17", analog: None,       pwm: tc("TCC3", 1), sercom: pad(5, 1)  },
+    PinCapabilities { name: "fpc_d5_pwm5",   pin: "PB29", analog: None,       pwm: tc("TCC1", 5), sercom: None       },
+    PinCapabilities { name: "fpc_d6_pwm6",   pin: "PA14", analog: None,       pwm: tc("TCC2", 0), sercom: pad(2, 2)  },
+    PinCapabilities { name: "fpc_d7_a7",     pin: "PC01", analog: adc(1, 11), pwm: None,          sercom: None       },
+    PinCapabilities { name: "fpc_d8_a8",     pin: "PC02", analog: adc(1, 4),  pwm: None,          sercom: None       },
+    PinCapabilities { name: "fpc_d9_a9",     pin: "PC03", analog: adc(1, 5),  pwm: None,          sercom: None       },
+    PinCapabilities { name: "fpc_d10_pwm10", pin: "PC04", analog: None,       pwm: tc("TCC0", 0), sercom: pad(6, 0)  },
+    PinCapabilities { name: "fpc_d11_a11",   pin: "PC31", analog: adc(1, 13), pwm: None,          sercom: None       },
+    PinCapabilities { name: "fpc_d12_a12",   pin: "PD00", analog: adc(1, 14), pwm: None,          sercom: None       },
+    PinCapabilities { name: "fpc_d13_a13",   pin: "PD01", analog: adc(1, 15), pwm: None,          sercom: None       },
+];
+
+/// Return the functions supported by the header or FPC pin with the given
+/// name in `Pins`.
+pub fn pin_capabilities(name: &str) -> Option<&'static PinCapabilities> {
+    HEADER_PINS.iter().find(|pin| pin.name == name)
+}
+
+/// A pin which can be used as an analog input.
+pub trait IntoAnalog {
+    type Output;
+
+    /// Configure the pin for the ADC, to be read with `Adc::read`.
+    fn into_analog(self, port: &mut Port) -> Self::Output;
+}
+
+/// A pin which can be used as a PWM output.
+pub trait IntoPwm {
+    type Output;
+
+    /// Configure the pin as an output of its timer.
+    fn into_pwm(self, port: &mut Port) -> Self::Output;
+}
+
+/// A pin which can be used for I2C.
+pub trait IntoI2c {
+    type Output;
+
+    /// Configure the pin as a SERCOM pad, to be passed to the I2C master.
+    fn into_i2c(self, port: &mut Port) -> Self::Output;
+}
+
+/// A pin which can be used for a UART.
+pub trait IntoUart {
+    type Output;
+
+    /// Configure the pin as a SERCOM pad, to be passed to the UART.
+    fn into_uart(self, port: &mut Port) -> Self::Output;
+}
+
+macro_rules! analog {
+    ($($Pin:ident),+) => {
+        $(
+            impl IntoAnalog for $Pin<Input<Floating>> {
+                type Output = $Pin<PfB>;
+
+                fn into_analog(self, port: &mut Port) -> Self::Output {
+                    self.into_function_b(port)
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! pwm {
+    ($($Pin:ident: $Pf:ident => $into:ident),+) => {
+        $(
+            impl IntoPwm for $Pin<Input<Floating>> {
+                type Output = $Pin<$Pf>;
+
+                fn into_pwm(self, port: &mut Port) -> Self::Output {
+                    self.$into(port)
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! sercom {
+    ($Trait:ident, $method:ident: $($Pin:ident: $Pad:ident<$Pf:ident>),+) => {
+        $(
+            impl $Trait for $Pin<Input<Floating>> {
+                type Output = $Pad<$Pin<$Pf>>;
+
+                fn $method(self, port: &mut Port) -> Self::Output {
+                    self.into_pad(port)
+                }
+            }
+        )+
+    };
+}
+
+analog!(Pa4, Pa6, Pa7, Pb4, Pb5, Pb6, Pb7, Pb8, Pb9, Pc1, Pc2, Pc3, Pc31, Pd0, Pd1);
+
+pwm!(
+    Pa4: PfE => into_function_e,
+    Pa6: PfE => into_function_e,
+    Pa7: PfE => into_function_e,
+    Pb8: PfE => into_function_e,
+    Pb9: PfE => into_function_e,
+    Pb17: PfF => into_function_f,
+    Pb28: PfF => into_function_f,
+    Pb29: PfF => into_function_f,
+    Pa14: PfF => into_function_f,
+    Pc4: PfF => into_function_f
+);
+
+sercom!(IntoI2c, into_i2c:
+    Pa4: Sercom0Pad0<PfD>,
+    Pb8: Sercom4Pad0<PfD>,
+    Pb9: Sercom4Pad1<PfD>,
+    Pb17: Sercom5Pad1<PfC>,
+    Pc4: Sercom6Pad0<PfC>
+);
+
+sercom!(IntoUart, into_uart:
+    Pa4: Sercom0Pad0<PfD>,
+    Pa6: Sercom0Pad2<PfD>,
+    Pa7: Sercom0Pad3<PfD>,
+    Pb8: Sercom4Pad0<PfD>,
+    Pb9: Sercom4Pad1<PfD>,
+    Pb17: Sercom5Pad1<PfC>,
+    Pa14: Sercom2Pad2<PfC>,
+    Pc4: Sercom6Pad0<PfC>
+);
@@ -8,6 +8,9 @@
 //! [atsamd-hal]: https://github.com/atsamd-rs/atsamd
 
 #![no_std]
+// `div_ceil` and `is_multiple_of` are newer than the compilers this crate
+// supports, so their manual forms are kept.
+#![allow(unknown_lints, clippy::manual_div_ceil, clippy::manual_is_multiple_of)]
 
 // Re-export the HAL and the PAC to give the user lower-level access to the
 // device should they need it.
@@ -20,22 +23,33 @@ pub use cortex_m_rt::entry;
 // useful to the user.
 pub use lis3dh::accelerometer;
 
-// `prelude` is the only module from this crate which is public, as the
-// remaining have their members exposed via the Sets struct.
+// `prelude`, `config`, `console`, `fs` and `nvm` are the only modules from this
+// crate which are public, as the remaining have their members exposed via the
+// Sets struct.
+pub mod config;
+pub mod console;
+pub mod fs;
+pub mod nvm;
 pub mod prelude;
 
 mod buttons;
 mod display;
+mod header;
+mod misc;
 mod pins;
 mod sensors;
 mod serial;
 mod sound;
 mod storage;
+mod touch;
 
 pub use buttons::*;
 pub use display::*;
+pub use header::*;
+pub use misc::*;
 pub use pins::*;
 pub use sensors::*;
 pub use serial::*;
 pub use sound::*;
 pub use storage::*;
+pub use touch::*;
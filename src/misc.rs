@@ -0,0 +1,17 @@
+use atsamd_hal::gpio::{Floating, Input, Pb31, Pc14, Pc15};
+
+/// Infrared emitter pins
+pub struct IR {
+    /// Infrared LED control pin
+    pub ctl: Pb31<Input<Floating>>,
+}
+
+/// Power output control pins, which switch the 5V and 3.3V supplies to the
+/// 40-pin header and Grove ports
+pub struct PowerOutputs {
+    /// 5V output control pin
+    pub ctr_5v: Pc14<Input<Floating>>,
+
+    /// 3.3V output control pin
+    pub ctr_3v3: Pc15<Input<Floating>>,
+}
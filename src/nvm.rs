@@ -0,0 +1,257 @@
+//! Settings kept in the SAMD51's internal flash, for boards without the QSPI
+//! flash populated.
+//!
+//! The NVMCTRL's SmartEEPROM emulates a small byte-writable EEPROM in blocks
+//! at the end of the main flash, levelling the wear on them itself. It is
+//! enabled by the SEESBLK and SEEPSZ fuses in the user page, which
+//! [`SmartEeprom::configure`] sets, and which take effect after a reset.
+//!
+//! The application must not be linked into the blocks the SmartEEPROM uses.
+//! Set the `WIO_TERMINAL_SMART_EEPROM_BLOCKS` environment variable to the
+//! number of blocks (1 to 10) when building, and the build script removes 16K
+//! per block from the end of the flash in `memory.x`. The driver refuses to
+//! use more blocks than were reserved.
+//!
+//! A [`SettingsStore`] keeps a typed, versioned [`Settings`] struct in the
+//! SmartEEPROM, or in any other [`Eeprom`].
+
+use core::ptr;
+
+use atsamd_hal::target_device::NVMCTRL;
+
+mod settings;
+
+pub use settings::*;
+
+include!(concat!(env!("OUT_DIR"), "/nvm.rs"));
+
+/// The most blocks the SmartEEPROM may use.
+pub const MAX_SMART_EEPROM_BLOCKS: u32 = 10;
+
+/// A byte-addressable memory which may be rewritten in place.
+pub trait Eeprom {
+    type Error;
+
+    /// The size of the memory, in bytes.
+    fn capacity(&self) -> usize;
+
+    /// Read `buf.len()` bytes starting at `offset`.
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Write `data` starting at `offset`.
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// Errors which can occur while using the SmartEEPROM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmartEepromError {
+    /// The SEESBLK fuse is 0, so the SmartEEPROM is disabled.
+    NotConfigured,
+    /// The SmartEEPROM uses more blocks than [`SMART_EEPROM_BLOCKS`] reserves
+    /// in `memory.x`, so it may overlap the application.
+    Collision,
+    /// The block count or page size is not one the SmartEEPROM supports.
+    InvalidConfiguration,
+    /// The SmartEEPROM is locked against writes.
+    Locked,
+    /// The range extends past the end of the SmartEEPROM.
+    OutOfRange,
+    /// The NVM controller reported an error while writing the user page.
+    Programming,
+}
+
+// The start of the SmartEEPROM's virtual address space, and of the user page.
+const SEEPROM_ADDR: usize = 0x4400_0000;
+const USER_PAGE_ADDR: usize = 0x0080_4000;
+const USER_PAGE_WORDS: usize = 128;
+
+// The SEESBLK and SEEPSZ fuses occupy the low bits of the user page's second
+// word.
+const USER_WORD_SEE: usize = 1;
+const USER_SEESBLK_MASK: u32 = 0xf;
+const USER_SEEPSZ_SHIFT: u32 = 4;
+const USER_SEEPSZ_MASK: u32 = 0x7 << USER_SEEPSZ_SHIFT;
+
+// Bits of the NVMCTRL registers.
+const CTRLA_WMODE_MASK: u16 = 0x3 << 4;
+const CTRLB_CMDEX: u16 = 0xa5 << 8;
+const CTRLB_CMD_EP: u16 = 0x00;
+const CTRLB_CMD_WQW: u16 = 0x04;
+const CTRLB_CMD_PBC: u16 = 0x15;
+const STATUS_READY: u16 = 1 << 0;
+const INTFLAG_ERRORS: u16 = 0x4e;
+const SEESTAT_BUSY: u32 = 1 << 2;
+const SEESTAT_LOCK: u32 = 1 << 3;
+const SEESTAT_SBLK_SHIFT: u32 = 8;
+const SEESTAT_PSZ_SHIFT: u32 = 16;
+
+/// The SmartEEPROM of the NVM controller.
+pub struct SmartEeprom {
+    nvmctrl: NVMCTRL,
+    capacity: usize,
+}
+
+impl SmartEeprom {
+    /// Use the SmartEEPROM as configured by the fuses at reset.
+    pub fn new(nvmctrl: NVMCTRL) -> Result<Self, SmartEepromError> {
+        let status = nvmctrl.seestat.read().bits();
+        let blocks = (status >> SEESTAT_SBLK_SHIFT) & 0xf;
+        let psz = (status >> SEESTAT_PSZ_SHIFT) & 0x7;
+
+        if blocks == 0 {
+            return Err(SmartEepromError::NotConfigured);
+        }
+        if blocks > SMART_EEPROM_BLOCKS {
+            return Err(SmartEepromError::Collision);
+        }
+
+        // The virtual size is 512 bytes with 4-byte pages, doubling with each
+        // page size up to 32 bytes. Larger pages give more only with more
+        // blocks, so only the 4K every block count allows is used.
+        Ok(Self {
+            nvmctrl,
+            capacity: 512 << psz.min(3),
+        })
+    }
+
+    /// Set the fuses to give the SmartEEPROM `blocks` blocks, each taking 16K
+    /// of flash, and pages of `page_size` bytes, a power of two from 4 to 512.
+    /// Return whether the fuses changed, in which case the device must be
+    /// reset before the SmartEEPROM uses the new configuration.
+    ///
+    /// The whole user page is erased and rewritten, so a power cut while
+    /// doing so loses every fuse, including those protecting the bootloader.
+    pub fn configure(
+        nvmctrl: &mut NVMCTRL,
+        blocks: u32,
+        page_size: u32,
+    ) -> Result<bool, SmartEepromError> {
+        if blocks == 0
+            || blocks > MAX_SMART_EEPROM_BLOCKS
+            || !page_size.is_power_of_two()
+            || page_size < 4
+            || page_size > 512
+        {
+            return Err(SmartEepromError::InvalidConfiguration);
+        }
+        if blocks > SMART_EEPROM_BLOCKS {
+            return Err(SmartEepromError::Collision);
+        }
+
+        let mut page = [0; USER_PAGE_WORDS];
+        for (i, word) in page.iter_mut().enumerate() {
+            *word = unsafe { ptr::read_volatile((USER_PAGE_ADDR as *const u32).add(i)) };
+        }
+
+        let psz = page_size.trailing_zeros() - 2;
+        let see = (page[USER_WORD_SEE] & !(USER_SEESBLK_MASK | USER_SEEPSZ_MASK))
+            | blocks
+            | psz << USER_SEEPSZ_SHIFT;
+        if see == page[USER_WORD_SEE] {
+            return Ok(false);
+        }
+        page[USER_WORD_SEE] = see;
+
+        // Fill the page buffer by writes, and program it only by command.
+        let ctrla = nvmctrl.ctrla.read().bits();
+        unsafe {
+            nvmctrl.ctrla.write(|w| w.bits(ctrla & !CTRLA_WMODE_MASK));
+            nvmctrl.intflag.write(|w| w.bits(INTFLAG_ERRORS));
+        }
+        let result = write_user_page(nvmctrl, &page);
+        unsafe { nvmctrl.ctrla.write(|w| w.bits(ctrla)) };
+
+        result.map(|_| true)
+    }
+
+    /// Release the NVM controller.
+    pub fn release(self) -> NVMCTRL {
+        self.nvmctrl
+    }
+
+    fn check_range(&self, offset: usize, len: usize) -> Result<(), SmartEepromError> {
+        if offset + len > self.capacity {
+            Err(SmartEepromError::OutOfRange)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn wait(&self) {
+        while self.nvmctrl.seestat.read().bits() & SEESTAT_BUSY != 0 {}
+    }
+}
+
+impl Eeprom for SmartEeprom {
+    type Error = SmartEepromError;
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Self::Error> {
+        self.check_range(offset, buf.len())?;
+
+        self.wait();
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = unsafe { ptr::read_volatile((SEEPROM_ADDR + offset + i) as *const u8) };
+        }
+
+        Ok(())
+    }
+
+    /// Write `data` starting at `offset`. Bytes which already hold their new
+    /// value are not written, to spare the flash.
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Self::Error> {
+        self.check_range(offset, data.len())?;
+        if self.nvmctrl.seestat.read().bits() & SEESTAT_LOCK != 0 {
+            return Err(SmartEepromError::Locked);
+        }
+
+        for (i, &byte) in data.iter().enumerate() {
+            let address = (SEEPROM_ADDR + offset + i) as *mut u8;
+            self.wait();
+            if unsafe { ptr::read_volatile(address) } != byte {
+                unsafe { ptr::write_volatile(address, byte) };
+            }
+        }
+        self.wait();
+
+        Ok(())
+    }
+}
+
+fn write_user_page(
+    nvmctrl: &mut NVMCTRL,
+    page: &[u32; USER_PAGE_WORDS],
+) -> Result<(), SmartEepromError> {
+    command(nvmctrl, CTRLB_CMD_EP, USER_PAGE_ADDR)?;
+
+    for (i, quad) in page.chunks(4).enumerate() {
+        let address = USER_PAGE_ADDR + i * 16;
+        command(nvmctrl, CTRLB_CMD_PBC, address)?;
+        for (j, &word) in quad.iter().enumerate() {
+            unsafe { ptr::write_volatile((address as *mut u32).add(j), word) };
+        }
+        command(nvmctrl, CTRLB_CMD_WQW, address)?;
+    }
+
+    Ok(())
+}
+
+// Run an NVMCTRL command on `address`, and wait for it to complete.
+fn command(nvmctrl: &mut NVMCTRL, cmd: u16, address: usize) -> Result<(), SmartEepromError> {
+    while nvmctrl.status.read().bits() & STATUS_READY == 0 {}
+
+    unsafe {
+        nvmctrl.addr.write(|w| w.bits(address as u32));
+        nvmctrl.ctrlb.write(|w| w.bits(CTRLB_CMDEX | cmd));
+    }
+    while nvmctrl.status.read().bits() & STATUS_READY == 0 {}
+
+    if nvmctrl.intflag.read().bits() & INTFLAG_ERRORS != 0 {
+        Err(SmartEepromError::Programming)
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,737 @@
+use core::str;
+
+use crate::config::{crc32, Value, MAX_VALUE_LEN};
+
+use super::Eeprom;
+
+// The settings are kept in two slots, and each save overwrites the older, so
+// a save torn by a power cut leaves the previous settings in place. Each slot
+// starts with a header: a magic number, the version of the settings, the
+// sequence number of the save, the length of the encoded settings, and a
+// CRC-32 of those and the settings which follow.
+const SETTINGS_MAGIC: u16 = 0x5753;
+const SLOT_SIZE: usize = 256;
+const HEADER_SIZE: usize = 12;
+
+/// The number of bytes used by a [`SettingsStore`].
+pub const SETTINGS_SIZE: usize = 2 * SLOT_SIZE;
+
+/// The most bytes [`Settings::encode`] may write.
+pub const MAX_SETTINGS_LEN: usize = SLOT_SIZE - HEADER_SIZE;
+
+/// A struct of settings, which is encoded as a sequence of fields.
+///
+/// A field may be added to the end of the struct without a new version, by
+/// decoding it only if the [`Reader`] is not yet empty. Any other change to
+/// the encoding needs a new version, and a [`migrate`](Settings::migrate)
+/// from the older ones.
+pub trait Settings: Default {
+    /// The version of the encoding written by [`encode`](Settings::encode).
+    const VERSION: u16;
+
+    /// Encode each field of the settings.
+    fn encode(&self, writer: &mut Writer);
+
+    /// Decode the settings from the fields written by `encode`, or return
+    /// `None` if they are not valid.
+    fn decode(reader: &mut Reader) -> Option<Self>;
+
+    /// Decode settings written by an older version of the encoding, or
+    /// return `None` to use the defaults, as is done unless overridden.
+    fn migrate(_version: u16, _reader: &mut Reader) -> Option<Self> {
+        None
+    }
+}
+
+/// Encodes the fields of [`Settings`], each as its length in a byte followed
+/// by its bytes.
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    overflow: bool,
+}
+
+impl<'a> Writer<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self {
+            buf,
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    /// Write a field holding a value.
+    pub fn write<V: Value>(&mut self, value: &V) {
+        let mut bytes = [0; MAX_VALUE_LEN];
+        let len = value.encode(&mut bytes);
+        self.write_bytes(&bytes[..len]);
+    }
+
+    /// Write a field holding up to 255 bytes.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        let end = self.len + 1 + bytes.len();
+        if bytes.len() > u8::MAX as usize || end > self.buf.len() {
+            self.overflow = true;
+            return;
+        }
+
+        self.buf[self.len] = bytes.len() as u8;
+        self.buf[self.len + 1..end].copy_from_slice(bytes);
+        self.len = end;
+    }
+
+    /// Write a field holding a string of up to 255 bytes.
+    pub fn write_str(&mut self, s: &str) {
+        self.write_bytes(s.as_bytes());
+    }
+}
+
+/// Decodes the fields of [`Settings`] written by a [`Writer`].
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Read a field holding a value, or return `None` if there are no more
+    /// fields or the field does not hold a `V`.
+    pub fn read<V: Value>(&mut self) -> Option<V> {
+        V::decode(self.read_bytes()?)
+    }
+
+    /// Read a field's bytes, or return `None` if there are no more fields.
+    pub fn read_bytes(&mut self) -> Option<&'a [u8]> {
+        let (&len, rest) = self.data.split_first()?;
+        if rest.len() < len as usize {
+            return None;
+        }
+
+        let (bytes, rest) = rest.split_at(len as usize);
+        self.data = rest;
+        Some(bytes)
+    }
+
+    /// Read a field holding a string, or return `None` if there are no more
+    /// fields or the field is not UTF-8.
+    pub fn read_str(&mut self) -> Option<&'a str> {
+        str::from_utf8(self.read_bytes()?).ok()
+    }
+
+    /// Return whether every field has been read.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+/// Errors which can occur while using the store.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SettingsError<E> {
+    /// The EEPROM could not be read or written.
+    Eeprom(E),
+    /// The store extends past the end of the EEPROM.
+    OutOfRange,
+    /// The settings encode to more than [`MAX_SETTINGS_LEN`] bytes, or a
+    /// field to more than 255.
+    TooLarge,
+}
+
+// The header of a slot holding valid settings.
+#[derive(Clone, Copy)]
+struct Header {
+    version: u16,
+    sequence: u16,
+    len: usize,
+}
+
+/// A store for one [`Settings`] struct, in the [`SETTINGS_SIZE`] bytes of an
+/// [`Eeprom`] from an offset.
+pub struct SettingsStore<E> {
+    eeprom: E,
+    offset: usize,
+    // The slot holding the newest valid settings, and their header.
+    current: Option<(usize, Header)>,
+}
+
+impl<E> SettingsStore<E>
+where
+    E: Eeprom,
+{
+    /// Open the store at `offset` in `eeprom`.
+    pub fn new(eeprom: E, offset: usize) -> Result<Self, SettingsError<E::Error>> {
+        if offset + SETTINGS_SIZE > eeprom.capacity() {
+            return Err(SettingsError::OutOfRange);
+        }
+
+        let mut store = Self {
+            eeprom,
+            offset,
+            current: None,
+        };
+
+        let mut buf = [0; SLOT_SIZE];
+        for slot in 0..2 {
+            if let Some(header) = store.slot(slot, &mut buf)? {
+                let newer = match store.current {
+                    Some((_, current)) => header.sequence.wrapping_sub(current.sequence) as i16 > 0,
+                    None => true,
+                };
+                if newer {
+                    store.current = Some((slot, header));
+                }
+            }
+        }
+
+        Ok(store)
+    }
+
+    /// Load the settings, migrating them from an older version if need be.
+    /// Return the defaults if none were saved, or they cannot be decoded.
+    pub fn load<S: Settings>(&mut self) -> Result<S, SettingsError<E::Error>> {
+        let (slot, header) = match self.current {
+            Some(current) => current,
+            None => return Ok(S::default()),
+        };
+
+        let mut buf = [0; SLOT_SIZE];
+        self.read(slot, &mut buf)?;
+        let mut reader = Reader {
+            data: &buf[HEADER_SIZE..HEADER_SIZE + header.len],
+        };
+
+        let settings = if header.version == S::VERSION {
+            S::decode(&mut reader)
+        } else if header.version < S::VERSION {
+            S::migrate(header.version, &mut reader)
+        } else {
+            None
+        };
+
+        Ok(settings.unwrap_or_default())
+    }
+
+    /// Save the settings. Nothing is written if they are already saved.
+    pub fn save<S: Settings>(&mut self, settings: &S) -> Result<(), SettingsError<E::Error>> {
+        let mut buf = [0; SLOT_SIZE];
+        let mut writer = Writer::new(&mut buf[HEADER_SIZE..]);
+        settings.encode(&mut writer);
+        if writer.overflow {
+            return Err(SettingsError::TooLarge);
+        }
+        let len = writer.len;
+
+        let (slot, sequence) = match self.current {
+            Some((slot, header)) => {
+                let mut saved = [0; SLOT_SIZE];
+                self.read(slot, &mut saved)?;
+                if header.version == S::VERSION
+                    && saved[HEADER_SIZE..HEADER_SIZE + header.len]
+                        == buf[HEADER_SIZE..HEADER_SIZE + len]
+                {
+                    return Ok(());
+                }
+                (1 - slot, header.sequence.wrapping_add(1))
+            }
+            None => (0, 0),
+        };
+
+        buf[0..2].copy_from_slice(&SETTINGS_MAGIC.to_le_bytes());
+        buf[2..4].copy_from_slice(&S::VERSION.to_le_bytes());
+        buf[4..6].copy_from_slice(&sequence.to_le_bytes());
+        buf[6..8].copy_from_slice(&(len as u16).to_le_bytes());
+        let crc = crc32(crc32(0, &buf[..8]), &buf[HEADER_SIZE..HEADER_SIZE + len]);
+        buf[8..12].copy_from_slice(&crc.to_le_bytes());
+
+        self.eeprom
+            .write(self.offset + slot * SLOT_SIZE, &buf[..HEADER_SIZE + len])
+            .map_err(SettingsError::Eeprom)?;
+        self.current = Some((
+            slot,
+            Header {
+                version: S::VERSION,
+                sequence,
+                len,
+            },
+        ));
+
+        Ok(())
+    }
+
+    /// Release the EEPROM.
+    pub fn release(self) -> E {
+        self.eeprom
+    }
+
+    // Read a slot into `buf`, returning its header if it holds valid
+    // settings.
+    fn slot(
+        &mut self,
+        slot: usize,
+        buf: &mut [u8; SLOT_SIZE],
+    ) -> Result<Option<Header>, SettingsError<E::Error>> {
+        self.read(slot, buf)?;
+
+        let field = |i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]);
+        let header = Header {
+            version: field(2),
+            sequence: field(4),
+            len: field(6) as usize,
+        };
+        if field(0) != SETTINGS_MAGIC || header.len > MAX_SETTINGS_LEN {
+            return Ok(None);
+        }
+
+        let crc = crc32(
+            crc32(0, &buf[..8]),
+            &buf[HEADER_SIZE..HEADER_SIZE + header.len],
+        );
+        if crc.to_le_bytes() != buf[8..12] {
+            return Ok(None);
+        }
+
+        Ok(Some(header))
+    }
+
+    fn read(
+        &mut self,
+        slot: usize,
+        buf: &mut [u8; SLOT_SIZE],
+    ) -> Result<(), SettingsError<E::Error>> {
+        self.eeprom
+            .read(self.offset + slot * SLOT_SIZE, buf)
+            .map_err(SettingsError::Eeprom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The store is kept after some other data, to check it stays in its
+    // bytes.
+    const OFFSET: usize = 16;
+    const CAPACITY: usize = OFFSET + SETTINGS_SIZE + 16;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    struct PowerCut;
+
+    // An EEPROM in RAM, which counts its writes and can lose power after
+    // writing a given number of bytes.
+    struct FakeEeprom {
+        memory: [u8; CAPACITY],
+        writes: usize,
+        bytes_left: Option<usize>,
+    }
+
+    impl FakeEeprom {
+        fn new() -> Self {
+            Self {
+                memory: [0xff; CAPACITY],
+                writes: 0,
+                bytes_left: None,
+            }
+        }
+    }
+
+    impl Eeprom for &mut FakeEeprom {
+        type Error = PowerCut;
+
+        fn capacity(&self) -> usize {
+            CAPACITY
+        }
+
+        fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), PowerCut> {
+            buf.copy_from_slice(&self.memory[offset..offset + buf.len()]);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), PowerCut> {
+            self.writes += 1;
+            for (i, &byte) in data.iter().enumerate() {
+                if self.bytes_left == Some(0) {
+                    self.bytes_left = None;
+                    return Err(PowerCut);
+                }
+                self.memory[offset + i] = byte;
+                self.bytes_left = self.bytes_left.map(|n| n - 1);
+            }
+            Ok(())
+        }
+    }
+
+    // The current settings. The volume went from a percentage in version 1
+    // to a byte in version 2, and `muted` was appended without a new
+    // version.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    struct Audio {
+        volume: u8,
+        muted: bool,
+    }
+
+    impl Default for Audio {
+        fn default() -> Self {
+            Self {
+                volume: 128,
+                muted: false,
+            }
+        }
+    }
+
+    impl Settings for Audio {
+        const VERSION: u16 = 2;
+
+        fn encode(&self, writer: &mut Writer) {
+            writer.write(&self.volume);
+            writer.write(&self.muted);
+        }
+
+        fn decode(reader: &mut Reader) -> Option<Self> {
+            let volume = reader.read()?;
+            let muted = if reader.is_empty() {
+                false
+            } else {
+                reader.read()?
+            };
+            Some(Self { volume, muted })
+        }
+
+        fn migrate(version: u16, reader: &mut Reader) -> Option<Self> {
+            match version {
+                1 => {
+                    let percent: u8 = reader.read()?;
+                    Some(Self {
+                        volume: (percent.min(100) as u32 * 255 / 100) as u8,
+                        muted: false,
+                    })
+                }
+                _ => None,
+            }
+        }
+    }
+
+    // The settings as saved by version 1.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    struct AudioV1 {
+        percent: u8,
+    }
+
+    impl Settings for AudioV1 {
+        const VERSION: u16 = 1;
+
+        fn encode(&self, writer: &mut Writer) {
+            writer.write(&self.percent);
+        }
+
+        fn decode(reader: &mut Reader) -> Option<Self> {
+            Some(Self {
+                percent: reader.read()?,
+            })
+        }
+    }
+
+    // The settings as saved by version 2 before `muted` was added.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    struct AudioV2 {
+        volume: u8,
+    }
+
+    impl Settings for AudioV2 {
+        const VERSION: u16 = 2;
+
+        fn encode(&self, writer: &mut Writer) {
+            writer.write(&self.volume);
+        }
+
+        fn decode(reader: &mut Reader) -> Option<Self> {
+            Some(Self {
+                volume: reader.read()?,
+            })
+        }
+    }
+
+    // The settings as saved by some future version.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    struct AudioV3 {
+        volume: u16,
+    }
+
+    impl Settings for AudioV3 {
+        const VERSION: u16 = 3;
+
+        fn encode(&self, writer: &mut Writer) {
+            writer.write(&self.volume);
+        }
+
+        fn decode(reader: &mut Reader) -> Option<Self> {
+            Some(Self {
+                volume: reader.read()?,
+            })
+        }
+    }
+
+    // Settings which encode to `len` bytes in `fields` fields.
+    #[derive(Default)]
+    struct Padding {
+        len: usize,
+        fields: usize,
+    }
+
+    impl Settings for Padding {
+        const VERSION: u16 = 1;
+
+        fn encode(&self, writer: &mut Writer) {
+            let bytes = [0x55; 256];
+            for _ in 0..self.fields {
+                writer.write_bytes(&bytes[..self.len / self.fields - 1]);
+            }
+        }
+
+        fn decode(_reader: &mut Reader) -> Option<Self> {
+            None
+        }
+    }
+
+    fn open(eeprom: &mut FakeEeprom) -> SettingsStore<&mut FakeEeprom> {
+        SettingsStore::new(eeprom, OFFSET).unwrap()
+    }
+
+    fn audio(volume: u8) -> Audio {
+        Audio {
+            volume,
+            muted: volume % 2 == 1,
+        }
+    }
+
+    // Return the sequence number in the header of a slot.
+    fn sequence(eeprom: &FakeEeprom, slot: usize) -> u16 {
+        let header = OFFSET + slot * SLOT_SIZE;
+        u16::from_le_bytes([eeprom.memory[header + 4], eeprom.memory[header + 5]])
+    }
+
+    #[test]
+    fn store_must_fit_in_the_eeprom() {
+        let mut eeprom = FakeEeprom::new();
+        assert!(SettingsStore::new(&mut eeprom, CAPACITY - SETTINGS_SIZE).is_ok());
+        assert_eq!(
+            SettingsStore::new(&mut eeprom, CAPACITY - SETTINGS_SIZE + 1).err(),
+            Some(SettingsError::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn blank_store_loads_the_defaults() {
+        let mut eeprom = FakeEeprom::new();
+        assert_eq!(open(&mut eeprom).load::<Audio>(), Ok(Audio::default()));
+    }
+
+    #[test]
+    fn saves_alternate_between_slots() {
+        let mut eeprom = FakeEeprom::new();
+        for volume in 0..5 {
+            open(&mut eeprom).save(&audio(volume)).unwrap();
+            let slot = volume as usize % 2;
+            assert_eq!(sequence(&eeprom, slot), volume as u16);
+            assert_eq!(open(&mut eeprom).load::<Audio>(), Ok(audio(volume)));
+        }
+
+        assert_eq!(eeprom.memory[..OFFSET], [0xff; OFFSET]);
+        assert_eq!(eeprom.memory[OFFSET + SETTINGS_SIZE..], [0xff; 16]);
+    }
+
+    #[test]
+    fn sequence_number_wraps() {
+        let mut eeprom = FakeEeprom::new();
+        let mut store = open(&mut eeprom);
+        for i in 0..0xfffe {
+            store.save(&audio(i as u8)).unwrap();
+        }
+
+        // The saves either side of the wrap must each be found to be newer.
+        for i in 0xfffe..0x10004 {
+            open(&mut eeprom).save(&audio(i as u8)).unwrap();
+            assert_eq!(sequence(&eeprom, i % 2), i as u16);
+            assert_eq!(open(&mut eeprom).load::<Audio>(), Ok(audio(i as u8)));
+        }
+    }
+
+    #[test]
+    fn torn_save_keeps_the_previous_settings() {
+        let mut eeprom = FakeEeprom::new();
+        open(&mut eeprom).save(&audio(1)).unwrap();
+        open(&mut eeprom).save(&audio(2)).unwrap();
+
+        let len = HEADER_SIZE + 4;
+        for cut in 0..len {
+            let mut torn = FakeEeprom::new();
+            torn.memory = eeprom.memory;
+            torn.bytes_left = Some(cut);
+            assert_eq!(
+                open(&mut torn).save(&audio(4)),
+                Err(SettingsError::Eeprom(PowerCut))
+            );
+            assert_eq!(open(&mut torn).load::<Audio>(), Ok(audio(2)));
+
+            // The next save goes to the torn slot again.
+            open(&mut torn).save(&audio(5)).unwrap();
+            assert_eq!(sequence(&torn, 0), 2);
+            assert_eq!(open(&mut torn).load::<Audio>(), Ok(audio(5)));
+        }
+    }
+
+    #[test]
+    fn corrupt_slot_is_ignored() {
+        let mut eeprom = FakeEeprom::new();
+        open(&mut eeprom).save(&audio(1)).unwrap();
+        open(&mut eeprom).save(&audio(2)).unwrap();
+
+        eeprom.memory[OFFSET + SLOT_SIZE + HEADER_SIZE + 1] ^= 1;
+        assert_eq!(open(&mut eeprom).load::<Audio>(), Ok(audio(1)));
+
+        eeprom.memory[OFFSET] ^= 1;
+        assert_eq!(open(&mut eeprom).load::<Audio>(), Ok(Audio::default()));
+    }
+
+    #[test]
+    fn older_version_is_migrated() {
+        let mut eeprom = FakeEeprom::new();
+        open(&mut eeprom).save(&AudioV1 { percent: 40 }).unwrap();
+        assert_eq!(
+            open(&mut eeprom).load::<Audio>(),
+            Ok(Audio {
+                volume: 102,
+                muted: false
+            })
+        );
+
+        // Saving the migrated settings replaces the old version, even though
+        // they encode to the same bytes.
+        let mut store = open(&mut eeprom);
+        store.save(&AudioV1 { percent: 1 }).unwrap();
+        store.save(&AudioV2 { volume: 1 }).unwrap();
+        assert_eq!(
+            open(&mut eeprom).load::<Audio>(),
+            Ok(Audio {
+                volume: 1,
+                muted: false
+            })
+        );
+    }
+
+    #[test]
+    fn newer_version_loads_the_defaults() {
+        let mut eeprom = FakeEeprom::new();
+        open(&mut eeprom).save(&AudioV3 { volume: 1000 }).unwrap();
+        assert_eq!(open(&mut eeprom).load::<Audio>(), Ok(Audio::default()));
+    }
+
+    #[test]
+    fn undecodable_settings_load_the_defaults() {
+        let mut eeprom = FakeEeprom::new();
+        open(&mut eeprom).save(&AudioV3 { volume: 1000 }).unwrap();
+        assert_eq!(open(&mut eeprom).load::<AudioV2>(), Ok(AudioV2::default()));
+        open(&mut eeprom)
+            .save(&Padding { len: 4, fields: 1 })
+            .unwrap();
+        assert_eq!(open(&mut eeprom).load::<Audio>(), Ok(Audio::default()));
+    }
+
+    #[test]
+    fn field_can_be_appended_without_a_new_version() {
+        let mut eeprom = FakeEeprom::new();
+        open(&mut eeprom).save(&AudioV2 { volume: 7 }).unwrap();
+        assert_eq!(
+            open(&mut eeprom).load::<Audio>(),
+            Ok(Audio {
+                volume: 7,
+                muted: false
+            })
+        );
+
+        // Older code ignores the new field.
+        open(&mut eeprom).save(&audio(9)).unwrap();
+        assert_eq!(
+            open(&mut eeprom).load::<AudioV2>(),
+            Ok(AudioV2 { volume: 9 })
+        );
+    }
+
+    #[test]
+    fn unchanged_settings_are_not_written() {
+        let mut eeprom = FakeEeprom::new();
+        let mut store = open(&mut eeprom);
+        store.save(&audio(1)).unwrap();
+        store.save(&audio(1)).unwrap();
+        store.save(&audio(2)).unwrap();
+        store.save(&audio(2)).unwrap();
+        assert_eq!(store.release().writes, 2);
+
+        open(&mut eeprom).save(&audio(2)).unwrap();
+        assert_eq!(eeprom.writes, 2);
+        open(&mut eeprom).save(&audio(1)).unwrap();
+        assert_eq!(eeprom.writes, 3);
+    }
+
+    #[test]
+    fn settings_must_fit_in_a_slot() {
+        let mut eeprom = FakeEeprom::new();
+        let mut store = open(&mut eeprom);
+        store
+            .save(&Padding {
+                len: MAX_SETTINGS_LEN,
+                fields: 4,
+            })
+            .unwrap();
+        assert_eq!(
+            store.save(&Padding {
+                len: MAX_SETTINGS_LEN + 4,
+                fields: 4,
+            }),
+            Err(SettingsError::TooLarge)
+        );
+        assert_eq!(store.release().writes, 1);
+    }
+
+    #[test]
+    fn writer_overflow() {
+        let mut buf = [0; 8];
+        let mut writer = Writer::new(&mut buf);
+        writer.write(&0x0201u16);
+        writer.write_str("abcd");
+        assert!(!writer.overflow);
+        assert_eq!(writer.len, 8);
+        assert_eq!(buf, [2, 1, 2, 4, b'a', b'b', b'c', b'd']);
+
+        let mut writer = Writer::new(&mut buf);
+        writer.write_str("abcdefgh");
+        assert!(writer.overflow);
+
+        let mut buf = [0; 300];
+        let mut writer = Writer::new(&mut buf);
+        writer.write_bytes(&[0; 255]);
+        assert!(!writer.overflow);
+        let mut writer = Writer::new(&mut buf);
+        writer.write_bytes(&[0; 256]);
+        assert!(writer.overflow);
+    }
+
+    #[test]
+    fn reader_fields() {
+        let data = [1, 7, 0, 2, b'h', b'i', 2, 1, 0, 1, 0xff, 3, 1, 2];
+        let mut reader = Reader { data: &data };
+        assert_eq!(reader.read::<u8>(), Some(7));
+        assert_eq!(reader.read_bytes(), Some(&[][..]));
+        assert_eq!(reader.read_str(), Some("hi"));
+        assert_eq!(reader.read::<u16>(), Some(1));
+        assert!(!reader.is_empty());
+        // A field which is not UTF-8 is still consumed.
+        assert_eq!(reader.read_str(), None);
+        assert!(!reader.is_empty());
+        // A field running past the end is not.
+        assert_eq!(reader.read_bytes(), None);
+        assert_eq!(reader.read_bytes(), None);
+        assert!(!reader.is_empty());
+
+        let mut reader = Reader { data: &[2, 1, 0] };
+        assert_eq!(reader.read::<u8>(), None);
+        assert!(reader.is_empty());
+        assert_eq!(reader.read_bytes(), None);
+    }
+}
@@ -3,10 +3,13 @@ use atsamd_hal::{define_pins, target_device};
 
 use super::buttons::ButtonPins;
 use super::display::Display;
-use super::sensors::{Accelerometer, LightSensor};
-use super::serial::{UART, USB};
-use super::sound::{Buzzer, Microphone};
+use super::header::{Header, FPC};
+use super::misc::{PowerOutputs, IR};
+use super::sensors::{Accelerometer, Gyroscope, LightSensor};
+use super::serial::{I2C, SPI, UART, USB};
+use super::sound::{Buzzer, Microphone, DAC, I2S};
 use super::storage::{QSPIFlash, SDCard};
+use super::touch::TouchScreen;
 
 define_pins!(
     /// Map the desired pin names to their physical pins
@@ -164,12 +167,33 @@ pub struct Sets {
     /// Buzzer pins
     pub buzzer: Buzzer,
 
+    /// DAC output pins
+    pub dac: DAC,
+
     /// LCD display pins
     pub display: Display,
 
     /// QSPI Flash pins
     pub flash: QSPIFlash,
 
+    /// FPC connector pins
+    pub fpc: FPC,
+
+    /// Gyroscope interrupt pins
+    pub gyroscope: Gyroscope,
+
+    /// 40-pin header GPIO pins
+    pub header: Header,
+
+    /// External I2C pins
+    pub i2c: I2C,
+
+    /// I2S pins
+    pub i2s: I2S,
+
+    /// Infrared emitter pins
+    pub ir: IR,
+
     /// Analog Light Sensor pins
     pub light_sensor: LightSensor,
 
@@ -179,9 +203,18 @@ pub struct Sets {
     /// GPIO port
     pub port: Port,
 
+    /// 5V and 3.3V output control pins
+    pub power: PowerOutputs,
+
     /// SD Card pins
     pub sd_card: SDCard,
 
+    /// External SPI pins
+    pub spi: SPI,
+
+    /// Resistive touch panel pins
+    pub touch_screen: TouchScreen,
+
     /// UART (external pinout) pins
     pub uart: UART,
 
@@ -206,6 +239,11 @@ impl Pins {
             ctr: self.buzzer_ctr,
         };
 
+        let dac = DAC {
+            dac0: self.dac0,
+            dac1: self.dac1,
+        };
+
         let display = Display {
             miso: self.lcd_miso,
             mosi: self.lcd_mosi,
@@ -225,6 +263,49 @@ impl Pins {
             d3: self.mcu_flash_qspi_io3,
         };
 
+        let fpc = FPC {
+            d3_pwm3: self.fpc_d3_pwm3,
+            d4_pwm4: self.fpc_d4_pwm4,
+            d5_pwm5: self.fpc_d5_pwm5,
+            d6_pwm6: self.fpc_d6_pwm6,
+            d7_a7: self.fpc_d7_a7,
+            d8_a8: self.fpc_d8_a8,
+            d9_a9: self.fpc_d9_a9,
+            d10_pwm10: self.fpc_d10_pwm10,
+            d11_a11: self.fpc_d11_a11,
+            d12_a12: self.fpc_d12_a12,
+        };
+
+        let gyroscope = Gyroscope {
+            int1: self.gyroscope_int1,
+        };
+
+        let header = Header {
+            a0_d0: self.a0_d0,
+            a1_d1: self.a1_d1,
+            a2_d2: self.a2_d2,
+            a3_d3: self.a3_d3,
+            a4_d4: self.a4_d4,
+            a5_d5: self.a5_d5,
+            a6_d6: self.a6_d6,
+            a7_d7: self.a7_d7,
+            a8_d8: self.a8_d8,
+        };
+
+        let i2c = I2C {
+            scl: self.i2c1_scl,
+            sda: self.i2c1_sda,
+        };
+
+        let i2s = I2S {
+            lrclk: self.i2s_lrclk,
+            sdin: self.i2s_sdin,
+            sdout: self.i2s_sdout,
+            blck: self.i2s_blck,
+        };
+
+        let ir = IR { ctl: self.ir_ctl };
+
         let light_sensor = LightSensor {
             pd1: self.fpc_d13_a13,
         };
@@ -235,6 +316,11 @@ impl Pins {
 
         let port = self.port;
 
+        let power = PowerOutputs {
+            ctr_5v: self.output_ctr_5v,
+            ctr_3v3: self.output_ctr_3v3,
+        };
+
         let sd_card = SDCard {
             cs: self.sd_cs,
             mosi: self.sd_mosi,
@@ -243,6 +329,20 @@ impl Pins {
             det: self.sd_det,
         };
 
+        let spi = SPI {
+            miso: self.spi_miso,
+            cs: self.spi_cs,
+            mosi: self.spi_mosi,
+            sck: self.spi_sck,
+        };
+
+        let touch_screen = TouchScreen {
+            xl: self.lcd_xl,
+            yu: self.lcd_yu,
+            xr: self.lcd_xr,
+            yd: self.lcd_yd,
+        };
+
         let uart = UART {
             rx: self.rxd,
             tx: self.txd,
@@ -269,12 +369,22 @@ impl Pins {
         Sets {
             accelerometer,
             buzzer,
+            dac,
             display,
             flash,
+            fpc,
+            gyroscope,
+            header,
+            i2c,
+            i2s,
+            ir,
             light_sensor,
             microphone,
             port,
+            power,
             sd_card,
+            spi,
+            touch_screen,
             uart,
             usb,
             user_led,
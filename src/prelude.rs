@@ -8,3 +8,5 @@
 
 pub use atsamd_hal::prelude::*;
 pub use lis3dh::accelerometer::{Accelerometer, RawAccelerometer};
+
+pub use crate::{IntoAnalog, IntoI2c, IntoPwm, IntoUart};
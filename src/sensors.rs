@@ -1,6 +1,6 @@
 use atsamd_hal::adc::Adc;
 use atsamd_hal::clock::GenericClockController;
-use atsamd_hal::gpio::{Floating, Input, Pa12, Pa13, Pd1, PfB, PfD, Port};
+use atsamd_hal::gpio::{Floating, Input, Pa12, Pa13, Pc21, Pd1, PfB, PfD, Port};
 use atsamd_hal::prelude::*;
 use atsamd_hal::sercom::{I2CMaster4, PadPin, Sercom4Pad0, Sercom4Pad1};
 use atsamd_hal::target_device::gclk::pchctrl::GEN_A::GCLK11;
@@ -8,6 +8,19 @@ use atsamd_hal::target_device::{ADC1, MCLK, SERCOM4};
 
 use lis3dh::{Lis3dh, SlaveAddr};
 
+/// Errors which can occur while initializing the accelerometer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccelerometerError {
+    /// The clock for `SERCOM4` could not be configured.
+    ClockConfiguration,
+    /// The LIS3DH could not be initialized; it may be missing, or not
+    /// responding on the bus.
+    DriverInitialization,
+}
+
+/// Type alias for the LIS3DH accelerometer.
+pub type LIS3DH = Lis3dh<I2CMaster4<Sercom4Pad0<Pa13<PfD>>, Sercom4Pad1<Pa12<PfD>>>>;
+
 /// I2C Accelerometer pins (uses `SERCOM4`)
 pub struct Accelerometer {
     /// `I2C0` bus clock pin
@@ -19,20 +32,22 @@ pub struct Accelerometer {
 
 impl Accelerometer {
     /// Initialize the LIS3DH accelerometer using the correct pins and
-    // peripherals. Use the driver's default settings.
+    /// peripherals. Use the driver's default settings.
     pub fn init(
         self,
         clocks: &mut GenericClockController,
         sercom4: SERCOM4,
         mclk: &mut MCLK,
         port: &mut Port,
-    ) -> Lis3dh<I2CMaster4<Sercom4Pad0<Pa13<PfD>>, Sercom4Pad1<Pa12<PfD>>>> {
+    ) -> Result<LIS3DH, AccelerometerError> {
         // The accelerometer is connected to the Wio Terminal's `I2C0` bus, so
         // based on the possible padouts listed in the datasheet it must use
         // `SERCOM4` and in turn `I2CMaster4`.
         let gclk0 = clocks.gclk0();
         let i2c = I2CMaster4::new(
-            &clocks.sercom4_core(&gclk0).unwrap(),
+            &clocks
+                .sercom4_core(&gclk0)
+                .ok_or(AccelerometerError::ClockConfiguration)?,
             400.khz(),
             sercom4,
             mclk,
@@ -42,10 +57,16 @@ impl Accelerometer {
 
         // The schematic states that the alternate I2C address `0x19` is used,
         // but that doesn't appear to work!
-        Lis3dh::new(i2c, SlaveAddr::Default).unwrap()
+        Lis3dh::new(i2c, SlaveAddr::Default).map_err(|_| AccelerometerError::DriverInitialization)
     }
 }
 
+/// Gyroscope pins
+pub struct Gyroscope {
+    /// Gyroscope interrupt 1 pin
+    pub int1: Pc21<Input<Floating>>,
+}
+
 /// Analog Light Sensor
 pub struct LightSensor {
     /// Analog Light Sensor input pin
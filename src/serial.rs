@@ -1,5 +1,8 @@
 use atsamd_hal::clock::GenericClockController;
-use atsamd_hal::gpio::{Floating, Input, IntoFunction, Pa24, Pa25, Pb26, Pb27, PfC, Port};
+use atsamd_hal::gpio::{
+    Floating, Input, IntoFunction, Pa16, Pa17, Pa24, Pa25, Pb0, Pb1, Pb2, Pb26, Pb27, Pb3, PfC,
+    Port,
+};
 use atsamd_hal::sercom::{PadPin, Sercom2Pad0, Sercom2Pad1, UART2};
 use atsamd_hal::target_device::{self, MCLK, SERCOM2};
 use atsamd_hal::time::Hertz;
@@ -9,6 +12,30 @@ use atsamd_hal::usb::{usb_device::bus::UsbBusAllocator, UsbBus};
 #[cfg(feature = "usb")]
 use target_device::gclk::{genctrl::SRC_A, pchctrl::GEN_A};
 
+/// External I2C pins, on the Grove port and 40-pin header (uses `SERCOM3`)
+pub struct I2C {
+    /// `I2C1` bus clock pin
+    pub scl: Pa16<Input<Floating>>,
+
+    /// `I2C1` bus data pin
+    pub sda: Pa17<Input<Floating>>,
+}
+
+/// External SPI pins, on the 40-pin header (uses `SERCOM5`)
+pub struct SPI {
+    /// SPI MISO pin
+    pub miso: Pb0<Input<Floating>>,
+
+    /// SPI chip select pin
+    pub cs: Pb1<Input<Floating>>,
+
+    /// SPI MOSI pin
+    pub mosi: Pb2<Input<Floating>>,
+
+    /// SPI SCK pin
+    pub sck: Pb3<Input<Floating>>,
+}
+
 /// UART pins (uses `SERCOM2`)
 pub struct UART {
     /// UART transmit pin
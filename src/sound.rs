@@ -1,6 +1,6 @@
 use atsamd_hal::adc::Adc;
 use atsamd_hal::clock::GenericClockController;
-use atsamd_hal::gpio::{Floating, Input, Pc30, Pd11, PfB, Port};
+use atsamd_hal::gpio::{Floating, Input, Pa2, Pa20, Pa21, Pa22, Pa5, Pb16, Pc30, Pd11, PfB, Port};
 use atsamd_hal::prelude::*;
 use atsamd_hal::pwm::{TCC0Pinout, Tcc0Pwm};
 use atsamd_hal::target_device::gclk::pchctrl::GEN_A::GCLK11;
@@ -37,6 +37,30 @@ impl Buzzer {
     }
 }
 
+/// I2S pins
+pub struct I2S {
+    /// I2S left/right (word select) clock pin
+    pub lrclk: Pa20<Input<Floating>>,
+
+    /// I2S serial data input pin
+    pub sdin: Pa21<Input<Floating>>,
+
+    /// I2S serial data output pin
+    pub sdout: Pa22<Input<Floating>>,
+
+    /// I2S bit clock pin
+    pub blck: Pb16<Input<Floating>>,
+}
+
+/// DAC output pins
+pub struct DAC {
+    /// DAC channel 0 output pin
+    pub dac0: Pa2<Input<Floating>>,
+
+    /// DAC channel 1 output pin
+    pub dac1: Pa5<Input<Floating>>,
+}
+
 /// Microphone pins
 pub struct Microphone {
     /// Microphone output (analog input) pin
@@ -1,10 +1,28 @@
+use core::convert::Infallible;
+
+use atsamd_hal::clock::GenericClockController;
 #[rustfmt::skip]
 use atsamd_hal::gpio::{
-    Floating, Input,
+    Floating, Input, Output, PfC, Port, PushPull,
     Pa8, Pa9, Pa10, Pa11, Pb10, Pb11, Pc16, Pc17, Pc18, Pc19, Pd21,
 };
+use atsamd_hal::hal::spi;
+use atsamd_hal::prelude::*;
+use atsamd_hal::sercom::{PadPin, SPIMaster6, Sercom6Pad0, Sercom6Pad1, Sercom6Pad2};
+use atsamd_hal::target_device::{MCLK, QSPI, SERCOM6};
+use atsamd_hal::time::Hertz;
+
+mod block;
+mod flash;
+mod qspi;
+mod sdcard;
+
+pub use block::*;
+pub use flash::*;
+pub use qspi::*;
+pub use sdcard::*;
 
-/// QSPI Flash pins (uses `SERCOM4`)
+/// QSPI Flash pins (uses the `QSPI` peripheral)
 pub struct QSPIFlash {
     /// QSPI Flash `sck` pin
     pub sck: Pb10<Input<Floating>>,
@@ -25,6 +43,40 @@ pub struct QSPIFlash {
     pub d3: Pa11<Input<Floating>>,
 }
 
+impl QSPIFlash {
+    /// Initialize the QSPI peripheral on the flash pins, with SCK at no more
+    /// than `frequency`, and check that the W25Q32 is present. The driver
+    /// starts with single line transfers; see [`W25Q32::enable_quad`].
+    pub fn init<F: Into<Hertz>>(
+        self,
+        clocks: &mut GenericClockController,
+        frequency: F,
+        qspi: QSPI,
+        mclk: &mut MCLK,
+        port: &mut Port,
+    ) -> Result<W25Q32<Qspi>, FlashError<Infallible>> {
+        let pins = (
+            self.sck.into_function_h(port),
+            self.cs.into_function_h(port),
+            self.d0.into_function_h(port),
+            self.d1.into_function_h(port),
+            self.d2.into_function_h(port),
+            self.d3.into_function_h(port),
+        );
+
+        // The QSPI peripheral is clocked by the CPU clock, from `GCLK0`.
+        let source: Hertz = clocks.gclk0().into();
+        let qspi = Qspi::new(qspi, mclk, source, frequency.into(), pins);
+
+        let mut flash = W25Q32::new(qspi);
+        if flash.jedec_id()? != W25Q32_JEDEC_ID {
+            return Err(FlashError::UnknownDevice);
+        }
+
+        Ok(flash)
+    }
+}
+
 /// SD Card pins (uses `SERCOM6`)
 pub struct SDCard {
     /// SD Card chip select pin
@@ -42,3 +94,60 @@ pub struct SDCard {
     /// SD Card detect pin
     pub det: Pd21<Input<Floating>>,
 }
+
+/// Type alias for the SPI bus connected to the SD card.
+pub type SdSpi = SPIMaster6<Sercom6Pad2<Pc18<PfC>>, Sercom6Pad0<Pc16<PfC>>, Sercom6Pad1<Pc17<PfC>>>;
+
+/// Type alias for the SD card driver.
+pub type SD = SdCard<SdSpi, Pc19<Output<PushPull>>>;
+
+// Cards must be initialized with the SPI clock at no more than 400kHz.
+const SD_INIT_FREQUENCY: u32 = 400_000;
+
+impl SDCard {
+    /// Initialize the SPI bus and the card inserted in the slot, then run the
+    /// bus at `frequency` (at most 25MHz). Return a tuple containing the card
+    /// driver and its card detect switch.
+    pub fn init<F: Into<Hertz>>(
+        self,
+        clocks: &mut GenericClockController,
+        frequency: F,
+        sercom6: SERCOM6,
+        mclk: &mut MCLK,
+        port: &mut Port,
+    ) -> Result<(SD, CardDetect), SdError> {
+        let gclk0 = clocks.gclk0();
+        let clock = clocks
+            .sercom6_core(&gclk0)
+            .ok_or(SdError::ClockConfiguration)?;
+        let mode = spi::Mode {
+            phase: spi::Phase::CaptureOnFirstTransition,
+            polarity: spi::Polarity::IdleLow,
+        };
+
+        let spi = SPIMaster6::new(
+            &clock,
+            Hertz(SD_INIT_FREQUENCY),
+            mode,
+            sercom6,
+            mclk,
+            (
+                self.miso.into_pad(port),
+                self.mosi.into_pad(port),
+                self.sck.into_pad(port),
+            ),
+        );
+        let cs = self.cs.into_push_pull_output(port);
+        let detect = CardDetect::new(self.det.into_pull_up_input(port));
+
+        let mut card = SdCard::new(spi, cs);
+        let kind = card.init()?;
+
+        // Now the card is initialized, switch the bus to full speed.
+        let (spi, cs) = card.release();
+        let (pads, sercom6) = spi.free();
+        let spi = SPIMaster6::new(&clock, frequency.into(), mode, sercom6, mclk, pads);
+
+        Ok((SdCard::initialized(spi, cs, kind), detect))
+    }
+}
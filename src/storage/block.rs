@@ -0,0 +1,16 @@
+/// The size of a block, in bytes.
+pub const BLOCK_SIZE: usize = 512;
+
+/// A device storing data in fixed-size blocks, such as an SD card.
+pub trait BlockDevice {
+    type Error;
+
+    /// Read the block with the given index into `buf`.
+    fn read_block(&mut self, block: u32, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), Self::Error>;
+
+    /// Write `data` to the block with the given index.
+    fn write_block(&mut self, block: u32, data: &[u8; BLOCK_SIZE]) -> Result<(), Self::Error>;
+
+    /// Return the number of blocks on the device.
+    fn block_count(&mut self) -> Result<u32, Self::Error>;
+}
@@ -0,0 +1,19 @@
+/// A NOR flash memory: erased bits read as 1, programming may only clear
+/// bits to 0, and only whole sectors may be erased.
+pub trait Flash {
+    type Error;
+
+    /// The size of an erasable sector, in bytes.
+    const SECTOR_SIZE: u32;
+
+    /// Read `buf.len()` bytes starting at `address`.
+    fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Program `data` starting at `address`, clearing bits which are 0 in
+    /// `data`.
+    fn program(&mut self, address: u32, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Erase the sector starting at `address`, which must be aligned to
+    /// `SECTOR_SIZE`.
+    fn erase_sector(&mut self, address: u32) -> Result<(), Self::Error>;
+}
@@ -0,0 +1,865 @@
+use core::convert::Infallible;
+use core::ptr;
+use core::slice;
+
+use atsamd_hal::gpio::{Pa10, Pa11, Pa8, Pa9, Pb10, Pb11, PfH};
+use atsamd_hal::target_device::{MCLK, QSPI};
+use atsamd_hal::time::Hertz;
+
+use super::Flash;
+
+/// The capacity of the W25Q32 flash, in bytes.
+pub const FLASH_CAPACITY: u32 = 4 * 1024 * 1024;
+
+/// The largest number of bytes programmed by a single page program command;
+/// programs may not cross a page boundary.
+pub const FLASH_PAGE_SIZE: u32 = 256;
+
+/// The number of bytes erased by [`W25Q32::erase_sector`].
+pub const FLASH_SECTOR_SIZE: u32 = 4 * 1024;
+
+/// The number of bytes erased by [`W25Q32::erase_block`].
+pub const FLASH_BLOCK_SIZE: u32 = 64 * 1024;
+
+/// The JEDEC ID of the W25Q32: manufacturer, memory type and capacity.
+pub const W25Q32_JEDEC_ID: [u8; 3] = [0xef, 0x40, 0x16];
+
+// W25Q32 instructions used by the driver.
+const WRITE_ENABLE: u8 = 0x06;
+const READ_STATUS_1: u8 = 0x05;
+const READ_STATUS_2: u8 = 0x35;
+const WRITE_STATUS_2: u8 = 0x31;
+const FAST_READ: u8 = 0x0b;
+const FAST_READ_QUAD_OUTPUT: u8 = 0x6b;
+const PAGE_PROGRAM: u8 = 0x02;
+const PAGE_PROGRAM_QUAD: u8 = 0x32;
+const SECTOR_ERASE: u8 = 0x20;
+const BLOCK_ERASE: u8 = 0xd8;
+const JEDEC_ID: u8 = 0x9f;
+
+// Status register bits.
+const STATUS_1_BUSY: u8 = 1 << 0;
+const STATUS_2_QUAD_ENABLE: u8 = 1 << 1;
+
+// The fast read instructions are followed by eight dummy clocks.
+const FAST_READ_DUMMY_CYCLES: u8 = 8;
+
+/// Errors which can occur while using the QSPI flash.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlashError<E> {
+    /// The transfer on the QSPI bus failed.
+    Transport(E),
+    /// The address range extends past the end of the flash.
+    OutOfRange,
+    /// The erase address is not aligned to the sector or block size.
+    Unaligned,
+    /// The JEDEC ID read from the flash is not the W25Q32's.
+    UnknownDevice,
+    /// The quad enable bit could not be set.
+    QuadEnable,
+}
+
+/// The data lines used for the address and data of an [`Instruction`]. The
+/// opcode itself is always sent on a single line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Width {
+    /// Address and data on a single line each way.
+    Single,
+    /// Address on a single line, and data on all four.
+    QuadOutput,
+}
+
+/// An instruction sent to the flash, optionally followed by an address and
+/// dummy clocks before the data phase.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub opcode: u8,
+    /// The 24-bit address, if the instruction takes one.
+    pub address: Option<u32>,
+    pub width: Width,
+    pub dummy_cycles: u8,
+}
+
+impl Instruction {
+    /// Create a single line instruction, without an address or dummy clocks.
+    pub const fn new(opcode: u8) -> Self {
+        Self {
+            opcode,
+            address: None,
+            width: Width::Single,
+            dummy_cycles: 0,
+        }
+    }
+
+    /// Create a single line instruction followed by an address.
+    pub const fn with_address(opcode: u8, address: u32) -> Self {
+        Self {
+            opcode,
+            address: Some(address),
+            width: Width::Single,
+            dummy_cycles: 0,
+        }
+    }
+}
+
+/// Sequences instructions on the QSPI bus. This is implemented by [`Qspi`] for
+/// the SAMD51's QSPI peripheral, and can be implemented by a model of the
+/// flash for testing the [`W25Q32`] driver.
+pub trait QspiTransport {
+    type Error;
+
+    /// Send the instruction and read `buf.len()` bytes of data.
+    fn read(&mut self, instruction: Instruction, buf: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Send the instruction and write `data`, which may be empty.
+    fn write(&mut self, instruction: Instruction, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Map the whole flash into memory, with each read of the mapping sending
+    /// the instruction (without its address, which is taken from the read).
+    fn memory_map(&mut self, instruction: Instruction) -> Result<&[u8], Self::Error>;
+}
+
+/// A driver for the W25Q32 32Mbit QSPI flash.
+pub struct W25Q32<T> {
+    transport: T,
+    quad: bool,
+}
+
+impl<T: QspiTransport> W25Q32<T> {
+    /// Create a driver for the flash on the given bus, using single line
+    /// transfers until [`W25Q32::enable_quad`] is called.
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            quad: false,
+        }
+    }
+
+    /// Read the JEDEC ID of the flash.
+    pub fn jedec_id(&mut self) -> Result<[u8; 3], FlashError<T::Error>> {
+        let mut id = [0; 3];
+        self.transport
+            .read(Instruction::new(JEDEC_ID), &mut id)
+            .map_err(FlashError::Transport)?;
+        Ok(id)
+    }
+
+    /// Return whether reads and programs use all four data lines.
+    pub fn is_quad(&self) -> bool {
+        self.quad
+    }
+
+    /// Set the flash's quad enable bit, which is non-volatile, and use all
+    /// four data lines for reads and programs from now on.
+    pub fn enable_quad(&mut self) -> Result<(), FlashError<T::Error>> {
+        let status = self.status(READ_STATUS_2)?;
+        if status & STATUS_2_QUAD_ENABLE == 0 {
+            self.write_enable()?;
+            self.transport
+                .write(
+                    Instruction::new(WRITE_STATUS_2),
+                    &[status | STATUS_2_QUAD_ENABLE],
+                )
+                .map_err(FlashError::Transport)?;
+            self.wait_idle()?;
+
+            if self.status(READ_STATUS_2)? & STATUS_2_QUAD_ENABLE == 0 {
+                return Err(FlashError::QuadEnable);
+            }
+        }
+
+        self.quad = true;
+        Ok(())
+    }
+
+    /// Read `buf.len()` bytes starting at `address`.
+    pub fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), FlashError<T::Error>> {
+        check_range(address, buf.len())?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        let mut instruction = self.read_instruction();
+        instruction.address = Some(address);
+        self.transport
+            .read(instruction, buf)
+            .map_err(FlashError::Transport)
+    }
+
+    /// Program `data` starting at `address`, split into as many page programs
+    /// as needed. Programming can only clear bits, so the range should have
+    /// been erased first.
+    pub fn program(&mut self, address: u32, data: &[u8]) -> Result<(), FlashError<T::Error>> {
+        check_range(address, data.len())?;
+
+        let (opcode, width) = if self.quad {
+            (PAGE_PROGRAM_QUAD, Width::QuadOutput)
+        } else {
+            (PAGE_PROGRAM, Width::Single)
+        };
+
+        let mut address = address;
+        let mut data = data;
+        while !data.is_empty() {
+            let room = (FLASH_PAGE_SIZE - address % FLASH_PAGE_SIZE) as usize;
+            let (page, rest) = data.split_at(room.min(data.len()));
+
+            self.write_enable()?;
+            let instruction = Instruction {
+                width,
+                ..Instruction::with_address(opcode, address)
+            };
+            self.transport
+                .write(instruction, page)
+                .map_err(FlashError::Transport)?;
+            self.wait_idle()?;
+
+            address += page.len() as u32;
+            data = rest;
+        }
+
+        Ok(())
+    }
+
+    /// Erase the 4KB sector starting at `address`, setting every byte to
+    /// `0xff`.
+    pub fn erase_sector(&mut self, address: u32) -> Result<(), FlashError<T::Error>> {
+        self.erase(SECTOR_ERASE, address, FLASH_SECTOR_SIZE)
+    }
+
+    /// Erase the 64KB block starting at `address`, setting every byte to
+    /// `0xff`.
+    pub fn erase_block(&mut self, address: u32) -> Result<(), FlashError<T::Error>> {
+        self.erase(BLOCK_ERASE, address, FLASH_BLOCK_SIZE)
+    }
+
+    /// Map the flash into memory for execute-in-place (XIP) style reads, using
+    /// quad reads if enabled. The mapping is only valid while it is borrowed,
+    /// as any other command reconfigures the bus.
+    pub fn memory_mapped(&mut self) -> Result<&[u8], FlashError<T::Error>> {
+        let instruction = self.read_instruction();
+        self.transport
+            .memory_map(instruction)
+            .map_err(FlashError::Transport)
+    }
+
+    /// Release the bus.
+    pub fn release(self) -> T {
+        self.transport
+    }
+
+    fn read_instruction(&self) -> Instruction {
+        let (opcode, width) = if self.quad {
+            (FAST_READ_QUAD_OUTPUT, Width::QuadOutput)
+        } else {
+            (FAST_READ, Width::Single)
+        };
+
+        Instruction {
+            opcode,
+            address: None,
+            width,
+            dummy_cycles: FAST_READ_DUMMY_CYCLES,
+        }
+    }
+
+    fn erase(&mut self, opcode: u8, address: u32, size: u32) -> Result<(), FlashError<T::Error>> {
+        check_range(address, size as usize)?;
+        if address % size != 0 {
+            return Err(FlashError::Unaligned);
+        }
+
+        self.write_enable()?;
+        self.transport
+            .write(Instruction::with_address(opcode, address), &[])
+            .map_err(FlashError::Transport)?;
+        self.wait_idle()
+    }
+
+    fn status(&mut self, opcode: u8) -> Result<u8, FlashError<T::Error>> {
+        let mut status = [0];
+        self.transport
+            .read(Instruction::new(opcode), &mut status)
+            .map_err(FlashError::Transport)?;
+        Ok(status[0])
+    }
+
+    fn write_enable(&mut self) -> Result<(), FlashError<T::Error>> {
+        self.transport
+            .write(Instruction::new(WRITE_ENABLE), &[])
+            .map_err(FlashError::Transport)
+    }
+
+    // Wait for a program, erase or status write to finish.
+    fn wait_idle(&mut self) -> Result<(), FlashError<T::Error>> {
+        while self.status(READ_STATUS_1)? & STATUS_1_BUSY != 0 {}
+        Ok(())
+    }
+}
+
+impl<T: QspiTransport> Flash for W25Q32<T> {
+    type Error = FlashError<T::Error>;
+
+    const SECTOR_SIZE: u32 = FLASH_SECTOR_SIZE;
+
+    fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Self::Error> {
+        W25Q32::read(self, address, buf)
+    }
+
+    fn program(&mut self, address: u32, data: &[u8]) -> Result<(), Self::Error> {
+        W25Q32::program(self, address, data)
+    }
+
+    fn erase_sector(&mut self, address: u32) -> Result<(), Self::Error> {
+        W25Q32::erase_sector(self, address)
+    }
+}
+
+fn check_range<E>(address: u32, len: usize) -> Result<(), FlashError<E>> {
+    match (address as usize).checked_add(len) {
+        Some(end) if end <= FLASH_CAPACITY as usize => Ok(()),
+        _ => Err(FlashError::OutOfRange),
+    }
+}
+
+// The start of the region the QSPI peripheral maps the flash into.
+const QSPI_AHB: usize = 0x0400_0000;
+
+// Bits of the QSPI registers.
+const CTRLA_SWRST: u32 = 1 << 0;
+const CTRLA_ENABLE: u32 = 1 << 1;
+const CTRLA_LASTXFER: u32 = 1 << 24;
+const CTRLB_MODE_MEMORY: u32 = 1 << 0;
+const CTRLB_CSMODE_LASTXFER: u32 = 1 << 4;
+const INTFLAG_INSTREND: u32 = 1 << 10;
+const INSTRFRAME_WIDTH_QUAD_OUTPUT: u32 = 2;
+const INSTRFRAME_INSTREN: u32 = 1 << 4;
+const INSTRFRAME_ADDREN: u32 = 1 << 5;
+const INSTRFRAME_DATAEN: u32 = 1 << 7;
+const INSTRFRAME_TFRTYPE_READ: u32 = 0 << 12;
+const INSTRFRAME_TFRTYPE_READMEMORY: u32 = 1 << 12;
+const INSTRFRAME_TFRTYPE_WRITE: u32 = 2 << 12;
+const INSTRFRAME_TFRTYPE_WRITEMEMORY: u32 = 3 << 12;
+const INSTRFRAME_DUMMYLEN_SHIFT: u32 = 16;
+
+/// The SAMD51's QSPI peripheral in serial memory mode, driving the flash on
+/// the `QSPIFlash` pins.
+pub struct Qspi {
+    qspi: QSPI,
+    // Whether a memory-mapped read instruction has been left configured.
+    mapped: bool,
+    _pins: (
+        Pb10<PfH>,
+        Pb11<PfH>,
+        Pa8<PfH>,
+        Pa9<PfH>,
+        Pa10<PfH>,
+        Pa11<PfH>,
+    ),
+}
+
+impl Qspi {
+    pub(crate) fn new(
+        qspi: QSPI,
+        mclk: &mut MCLK,
+        source: Hertz,
+        frequency: Hertz,
+        pins: (
+            Pb10<PfH>,
+            Pb11<PfH>,
+            Pa8<PfH>,
+            Pa9<PfH>,
+            Pa10<PfH>,
+            Pa11<PfH>,
+        ),
+    ) -> Self {
+        mclk.apbcmask.modify(|_, w| w.qspi_().set_bit());
+        mclk.ahbmask
+            .modify(|_, w| w.qspi_().set_bit().qspi_2x_().clear_bit());
+
+        // SCK is the CPU clock divided by BAUD + 1, in SPI mode 0.
+        let frequency = frequency.0.max(1);
+        let divider = (source.0 + frequency - 1) / frequency;
+        let baud = divider.saturating_sub(1).min(0xff);
+
+        unsafe {
+            qspi.ctrla.write(|w| w.bits(CTRLA_SWRST));
+            qspi.ctrlb
+                .write(|w| w.bits(CTRLB_MODE_MEMORY | CTRLB_CSMODE_LASTXFER));
+            qspi.baud.write(|w| w.bits(baud << 8));
+            qspi.ctrla.write(|w| w.bits(CTRLA_ENABLE));
+        }
+
+        Self {
+            qspi,
+            mapped: false,
+            _pins: pins,
+        }
+    }
+
+    // Configure an instruction; its data phase, if any, is performed by
+    // accessing the AHB region.
+    fn start(&mut self, instruction: &Instruction, tfrtype: u32, data: bool) {
+        if self.mapped {
+            self.finish();
+            self.mapped = false;
+        }
+
+        let mut frame = INSTRFRAME_INSTREN | tfrtype;
+        if instruction.width == Width::QuadOutput {
+            frame |= INSTRFRAME_WIDTH_QUAD_OUTPUT;
+        }
+        if instruction.address.is_some() {
+            frame |= INSTRFRAME_ADDREN;
+        }
+        if data {
+            frame |= INSTRFRAME_DATAEN;
+        }
+        frame |= (instruction.dummy_cycles as u32) << INSTRFRAME_DUMMYLEN_SHIFT;
+
+        unsafe {
+            self.qspi
+                .instraddr
+                .write(|w| w.bits(instruction.address.unwrap_or(0)));
+            self.qspi
+                .instrctrl
+                .write(|w| w.bits(instruction.opcode as u32));
+            self.qspi.instrframe.write(|w| w.bits(frame));
+        }
+        // Reading back the frame synchronizes it before the AHB access.
+        let _ = self.qspi.instrframe.read().bits();
+    }
+
+    // End the instruction, raising chip select, and wait for it to finish.
+    fn finish(&mut self) {
+        unsafe {
+            self.qspi
+                .ctrla
+                .write(|w| w.bits(CTRLA_ENABLE | CTRLA_LASTXFER));
+        }
+        while self.qspi.intflag.read().bits() & INTFLAG_INSTREND == 0 {}
+        unsafe {
+            self.qspi.intflag.write(|w| w.bits(INTFLAG_INSTREND));
+        }
+    }
+
+    // The AHB address of the data phase; memory transfers take their address
+    // from it, rather than from INSTRADDR.
+    fn ahb(instruction: &Instruction) -> usize {
+        QSPI_AHB + instruction.address.unwrap_or(0) as usize
+    }
+}
+
+impl QspiTransport for Qspi {
+    type Error = Infallible;
+
+    fn read(&mut self, instruction: Instruction, buf: &mut [u8]) -> Result<(), Infallible> {
+        let tfrtype = if instruction.address.is_some() {
+            INSTRFRAME_TFRTYPE_READMEMORY
+        } else {
+            INSTRFRAME_TFRTYPE_READ
+        };
+        self.start(&instruction, tfrtype, !buf.is_empty());
+        unsafe {
+            ptr::copy_nonoverlapping(
+                Self::ahb(&instruction) as *const u8,
+                buf.as_mut_ptr(),
+                buf.len(),
+            );
+        }
+        self.finish();
+        Ok(())
+    }
+
+    fn write(&mut self, instruction: Instruction, data: &[u8]) -> Result<(), Infallible> {
+        let tfrtype = if instruction.address.is_some() && !data.is_empty() {
+            INSTRFRAME_TFRTYPE_WRITEMEMORY
+        } else {
+            INSTRFRAME_TFRTYPE_WRITE
+        };
+        self.start(&instruction, tfrtype, !data.is_empty());
+        unsafe {
+            ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                Self::ahb(&instruction) as *mut u8,
+                data.len(),
+            );
+        }
+        self.finish();
+        Ok(())
+    }
+
+    fn memory_map(&mut self, instruction: Instruction) -> Result<&[u8], Infallible> {
+        // Leave the instruction configured without ending it, so that each
+        // read of the region fetches from the flash.
+        let instruction = Instruction {
+            address: Some(0),
+            ..instruction
+        };
+        self.start(&instruction, INSTRFRAME_TFRTYPE_READMEMORY, true);
+        self.mapped = true;
+
+        Ok(unsafe { slice::from_raw_parts(QSPI_AHB as *const u8, FLASH_CAPACITY as usize) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use heapless::consts::{U512, U64};
+    use heapless::Vec;
+
+    const STATUS_1_WRITE_ENABLE: u8 = 1 << 1;
+
+    // The fake models the first two blocks of the flash, which are aliased
+    // across the rest of its address space.
+    const MODEL_SIZE: usize = 2 * FLASH_BLOCK_SIZE as usize;
+
+    // A model of the W25Q32, which records each instruction sent to it along
+    // with the length of its data, and panics if it is used incorrectly.
+    struct FakeFlash {
+        memory: [u8; MODEL_SIZE],
+        log: Vec<(Instruction, usize), U64>,
+        status_2: u8,
+        write_enable: bool,
+        // The number of status reads for which the flash stays busy after a
+        // program, erase or status write.
+        busy_reads: u8,
+        busy: u8,
+        // Whether the quad enable bit ignores writes.
+        quad_locked: bool,
+    }
+
+    impl FakeFlash {
+        fn new() -> Self {
+            Self {
+                memory: [0xff; MODEL_SIZE],
+                log: Vec::new(),
+                status_2: 0,
+                write_enable: false,
+                busy_reads: 2,
+                busy: 0,
+                quad_locked: false,
+            }
+        }
+
+        fn opcodes(&self) -> Vec<u8, U64> {
+            self.log.iter().map(|(i, _)| i.opcode).collect()
+        }
+
+        // Start an operation which needs the write enable latch, and return
+        // whether it was set.
+        fn start_write(&mut self) -> bool {
+            let enabled = self.write_enable;
+            self.write_enable = false;
+            self.busy = self.busy_reads;
+            enabled
+        }
+
+        fn index(address: Option<u32>) -> usize {
+            address.expect("missing address") as usize % MODEL_SIZE
+        }
+    }
+
+    impl QspiTransport for FakeFlash {
+        type Error = ();
+
+        fn read(&mut self, instruction: Instruction, buf: &mut [u8]) -> Result<(), ()> {
+            self.log.push((instruction, buf.len())).unwrap();
+            if self.busy > 0 && instruction.opcode != READ_STATUS_1 {
+                panic!("{:?} sent while busy", instruction);
+            }
+
+            match (instruction.opcode, instruction.width) {
+                (JEDEC_ID, Width::Single) => buf.copy_from_slice(&W25Q32_JEDEC_ID),
+                (READ_STATUS_1, Width::Single) => {
+                    let busy = self.busy > 0;
+                    self.busy = self.busy.saturating_sub(1);
+                    let wel = if self.write_enable {
+                        STATUS_1_WRITE_ENABLE
+                    } else {
+                        0
+                    };
+                    buf[0] = wel | if busy { STATUS_1_BUSY } else { 0 };
+                }
+                (READ_STATUS_2, Width::Single) => buf[0] = self.status_2,
+                (FAST_READ, Width::Single) | (FAST_READ_QUAD_OUTPUT, Width::QuadOutput) => {
+                    assert_eq!(instruction.dummy_cycles, FAST_READ_DUMMY_CYCLES);
+                    if instruction.width == Width::QuadOutput {
+                        assert!(self.status_2 & STATUS_2_QUAD_ENABLE != 0);
+                    }
+                    let start = Self::index(instruction.address);
+                    for (i, byte) in buf.iter_mut().enumerate() {
+                        *byte = self.memory[(start + i) % MODEL_SIZE];
+                    }
+                }
+                _ => panic!("unexpected read {:?}", instruction),
+            }
+            Ok(())
+        }
+
+        fn write(&mut self, instruction: Instruction, data: &[u8]) -> Result<(), ()> {
+            self.log.push((instruction, data.len())).unwrap();
+            if self.busy > 0 {
+                panic!("{:?} sent while busy", instruction);
+            }
+
+            match (instruction.opcode, instruction.width) {
+                (WRITE_ENABLE, Width::Single) => self.write_enable = true,
+                (WRITE_STATUS_2, Width::Single) => {
+                    if self.start_write() && !self.quad_locked {
+                        self.status_2 = data[0];
+                    }
+                }
+                (PAGE_PROGRAM, Width::Single) | (PAGE_PROGRAM_QUAD, Width::QuadOutput) => {
+                    if self.start_write() {
+                        // Programs wrap around within the page.
+                        let start = Self::index(instruction.address);
+                        let page = start - start % FLASH_PAGE_SIZE as usize;
+                        for (i, byte) in data.iter().enumerate() {
+                            let offset = (start + i) % FLASH_PAGE_SIZE as usize;
+                            self.memory[page + offset] &= byte;
+                        }
+                    }
+                }
+                (SECTOR_ERASE, Width::Single) | (BLOCK_ERASE, Width::Single) => {
+                    let size = if instruction.opcode == SECTOR_ERASE {
+                        FLASH_SECTOR_SIZE as usize
+                    } else {
+                        FLASH_BLOCK_SIZE as usize
+                    };
+                    assert!(data.is_empty());
+                    if self.start_write() {
+                        // The low bits of the address are ignored.
+                        let start = Self::index(instruction.address) / size * size;
+                        self.memory[start..start + size]
+                            .iter_mut()
+                            .for_each(|byte| *byte = 0xff);
+                    }
+                }
+                _ => panic!("unexpected write {:?}", instruction),
+            }
+            Ok(())
+        }
+
+        fn memory_map(&mut self, instruction: Instruction) -> Result<&[u8], ()> {
+            self.log.push((instruction, 0)).unwrap();
+            Ok(&self.memory)
+        }
+    }
+
+    fn flash() -> W25Q32<FakeFlash> {
+        W25Q32::new(FakeFlash::new())
+    }
+
+    fn pattern(len: usize) -> Vec<u8, U512> {
+        (0..len).map(|i| (i * 7 + 3) as u8).collect()
+    }
+
+    #[test]
+    fn jedec_id() {
+        let mut flash = flash();
+        assert_eq!(flash.jedec_id(), Ok(W25Q32_JEDEC_ID));
+    }
+
+    #[test]
+    fn program_splits_at_page_boundaries() {
+        let mut flash = flash();
+        let data = pattern(300);
+
+        flash.program(200, &data).unwrap();
+        let writes: Vec<(Instruction, usize), U64> = flash
+            .transport
+            .log
+            .iter()
+            .filter(|(i, _)| i.opcode == PAGE_PROGRAM)
+            .copied()
+            .collect();
+        assert_eq!(
+            writes,
+            [
+                (Instruction::with_address(PAGE_PROGRAM, 200), 56),
+                (Instruction::with_address(PAGE_PROGRAM, 256), 244),
+            ]
+        );
+
+        let mut buf = [0; 302];
+        flash.read(199, &mut buf).unwrap();
+        assert_eq!(buf[0], 0xff);
+        assert_eq!(buf[1..301], data[..]);
+        assert_eq!(buf[301], 0xff);
+    }
+
+    #[test]
+    fn program_waits_for_each_page() {
+        let mut flash = flash();
+        flash.program(0x1000, &pattern(512)).unwrap();
+
+        // Each page needs write enable, then is polled until idle.
+        let page = [
+            WRITE_ENABLE,
+            PAGE_PROGRAM,
+            READ_STATUS_1,
+            READ_STATUS_1,
+            READ_STATUS_1,
+        ];
+        let opcodes = flash.transport.opcodes();
+        assert_eq!(opcodes[..5], page);
+        assert_eq!(opcodes[5..], page);
+    }
+
+    #[test]
+    fn program_clears_bits() {
+        let mut flash = flash();
+        flash.program(10, &[0xf0, 0x0f]).unwrap();
+        flash.program(10, &[0x3c, 0x3c]).unwrap();
+
+        let mut buf = [0; 2];
+        flash.read(10, &mut buf).unwrap();
+        assert_eq!(buf, [0x30, 0x0c]);
+    }
+
+    #[test]
+    fn erase() {
+        let mut flash = flash();
+        flash.program(0x0ffe, &[0; 4]).unwrap();
+        flash.program(0x1_0000, &[0; 4]).unwrap();
+
+        flash.erase_sector(0x1000).unwrap();
+        let mut buf = [0; 4];
+        flash.read(0x0ffe, &mut buf).unwrap();
+        assert_eq!(buf, [0, 0, 0xff, 0xff]);
+
+        flash.erase_block(0).unwrap();
+        flash.read(0x0ffe, &mut buf).unwrap();
+        assert_eq!(buf, [0xff; 4]);
+        flash.read(0x1_0000, &mut buf).unwrap();
+        assert_eq!(buf, [0; 4]);
+
+        let opcodes = flash.transport.opcodes();
+        assert!(opcodes.ends_with(&[
+            WRITE_ENABLE,
+            BLOCK_ERASE,
+            READ_STATUS_1,
+            READ_STATUS_1,
+            READ_STATUS_1,
+            FAST_READ,
+            FAST_READ
+        ]));
+    }
+
+    #[test]
+    fn erase_must_be_aligned() {
+        let mut flash = flash();
+
+        assert_eq!(flash.erase_sector(0x1001), Err(FlashError::Unaligned));
+        assert_eq!(flash.erase_sector(0x0800), Err(FlashError::Unaligned));
+        assert_eq!(flash.erase_block(0x1000), Err(FlashError::Unaligned));
+        assert_eq!(flash.erase_block(0x8000), Err(FlashError::Unaligned));
+        assert!(flash.transport.log.is_empty());
+
+        assert_eq!(flash.erase_sector(0x3f_f000), Ok(()));
+        assert_eq!(flash.erase_block(0x3f_0000), Ok(()));
+    }
+
+    #[test]
+    fn range_is_checked() {
+        let mut flash = flash();
+        let mut buf = [0; 4];
+
+        assert_eq!(flash.read(FLASH_CAPACITY - 4, &mut buf), Ok(()));
+        assert_eq!(
+            flash.read(FLASH_CAPACITY - 3, &mut buf),
+            Err(FlashError::OutOfRange)
+        );
+        assert_eq!(flash.read(FLASH_CAPACITY, &mut []), Ok(()));
+        assert_eq!(flash.program(u32::MAX, &[0]), Err(FlashError::OutOfRange));
+        assert_eq!(
+            flash.erase_sector(FLASH_CAPACITY),
+            Err(FlashError::OutOfRange)
+        );
+        assert_eq!(
+            flash.erase_block(FLASH_CAPACITY),
+            Err(FlashError::OutOfRange)
+        );
+
+        // Only the read of the last four bytes reached the flash.
+        assert_eq!(flash.transport.log.len(), 1);
+
+        assert_eq!(check_range::<()>(0, FLASH_CAPACITY as usize), Ok(()));
+        assert_eq!(
+            check_range::<()>(1, FLASH_CAPACITY as usize),
+            Err(FlashError::OutOfRange)
+        );
+        assert_eq!(
+            check_range::<()>(u32::MAX, usize::MAX),
+            Err(FlashError::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn quad_mode() {
+        let mut flash = flash();
+        assert!(!flash.is_quad());
+
+        flash.enable_quad().unwrap();
+        assert!(flash.is_quad());
+        assert_eq!(flash.transport.status_2, STATUS_2_QUAD_ENABLE);
+
+        flash.program(0, &[0x5a]).unwrap();
+        let mut buf = [0; 1];
+        flash.read(0, &mut buf).unwrap();
+        assert_eq!(buf, [0x5a]);
+
+        let log = &flash.transport.log;
+        let program = log.iter().find(|(i, _)| i.opcode == PAGE_PROGRAM_QUAD);
+        assert_eq!(program.unwrap().0.width, Width::QuadOutput);
+        let read = log.last().unwrap().0;
+        assert_eq!(
+            (read.opcode, read.width),
+            (FAST_READ_QUAD_OUTPUT, Width::QuadOutput)
+        );
+
+        // The bit is non-volatile, so is not written again.
+        let mut flash = W25Q32::new(flash.release());
+        flash.transport.log.clear();
+        flash.enable_quad().unwrap();
+        assert_eq!(flash.transport.opcodes(), [READ_STATUS_2]);
+    }
+
+    #[test]
+    fn quad_enable_failure() {
+        let mut fake = FakeFlash::new();
+        fake.quad_locked = true;
+        let mut flash = W25Q32::new(fake);
+
+        assert_eq!(flash.enable_quad(), Err(FlashError::QuadEnable));
+        assert!(!flash.is_quad());
+    }
+
+    #[test]
+    fn memory_mapped() {
+        let mut flash = flash();
+        flash.program(0x20, b"xip").unwrap();
+
+        assert_eq!(&flash.memory_mapped().unwrap()[0x20..0x23], b"xip");
+        let (instruction, _) = *flash.transport.log.last().unwrap();
+        assert_eq!(instruction.opcode, FAST_READ);
+        assert_eq!(instruction.dummy_cycles, FAST_READ_DUMMY_CYCLES);
+    }
+
+    #[test]
+    fn flash_trait() {
+        fn erase_and_program<F: Flash>(flash: &mut F) -> Result<(), F::Error> {
+            flash.erase_sector(F::SECTOR_SIZE)?;
+            flash.program(F::SECTOR_SIZE, &[1, 2, 3])
+        }
+
+        let mut flash = flash();
+        erase_and_program(&mut flash).unwrap();
+        let mut buf = [0; 3];
+        Flash::read(&mut flash, FLASH_SECTOR_SIZE, &mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3]);
+    }
+}
@@ -0,0 +1,1063 @@
+use atsamd_hal::common::eic;
+use atsamd_hal::common::eic::pin::*;
+use atsamd_hal::gpio::{Input, Pd21, PfA, Port, PullUp};
+use atsamd_hal::hal::blocking::spi::Transfer;
+use atsamd_hal::hal::digital::v2::{InputPin, OutputPin};
+
+use super::{BlockDevice, BLOCK_SIZE};
+
+// SD commands used by the driver, in SPI mode.
+const GO_IDLE_STATE: u8 = 0;
+const SEND_IF_COND: u8 = 8;
+const SEND_CSD: u8 = 9;
+const SET_BLOCKLEN: u8 = 16;
+const READ_SINGLE_BLOCK: u8 = 17;
+const WRITE_BLOCK: u8 = 24;
+const APP_CMD: u8 = 55;
+const READ_OCR: u8 = 58;
+const CRC_ON_OFF: u8 = 59;
+const SD_SEND_OP_COND: u8 = 41;
+
+// R1 response bits.
+const R1_IDLE: u8 = 1 << 0;
+const R1_ILLEGAL_COMMAND: u8 = 1 << 2;
+const R1_CRC_ERROR: u8 = 1 << 3;
+
+// The argument to SEND_IF_COND: 2.7-3.6V, with a check pattern to be echoed.
+const IF_COND_ARGUMENT: u32 = 0x1aa;
+// Tells the card that the host supports high capacity cards.
+const HCS: u32 = 1 << 30;
+// The card capacity status bit of the OCR, set for SDHC and SDXC cards.
+const OCR_CCS: u32 = 1 << 30;
+
+const START_BLOCK_TOKEN: u8 = 0xfe;
+const DATA_ACCEPTED: u8 = 0x05;
+const DATA_CRC_ERROR: u8 = 0x0b;
+
+// How many bytes to wait for a command response, as the card responds within
+// eight.
+const RESPONSE_RETRIES: u32 = 8;
+// How many times to send SD_SEND_OP_COND while the card initializes, which
+// may take up to a second.
+const INIT_RETRIES: u32 = 10_000;
+// How many bytes to wait for a data token or for the card to stop being busy.
+const DATA_RETRIES: u32 = 1_000_000;
+
+/// The default time, in milliseconds, the card detect pin must be stable for
+/// before [`CardDetect::poll`] reports a change.
+pub const CARD_DETECT_DEBOUNCE_MS: u32 = 50;
+
+/// Errors which can occur while using an SD card.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SdError {
+    /// The clock for `SERCOM6` could not be configured.
+    ClockConfiguration,
+    /// A byte could not be transferred on the SPI bus.
+    Spi,
+    /// The chip select pin could not be set.
+    ChipSelect,
+    /// The card did not respond in time; it may be missing.
+    Timeout,
+    /// The card rejected a command, with the given R1 response.
+    Command(u8),
+    /// The card does not support the host's voltage, or is not an SD card.
+    UnsupportedCard,
+    /// A data block was corrupted between the card and the host.
+    Crc,
+    /// The card failed to read or write a block.
+    Data,
+    /// The card has not been initialized with [`SdCard::init`].
+    NotInitialized,
+    /// The block is past the end of the card.
+    OutOfRange,
+}
+
+/// The kind of SD card, which determines how blocks are addressed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CardKind {
+    /// A version 1 standard capacity card, addressed in bytes.
+    Sd1,
+    /// A version 2 standard capacity card, addressed in bytes.
+    Sd2,
+    /// A high or extended capacity (SDHC or SDXC) card, addressed in blocks.
+    Sdhc,
+}
+
+/// Return the CRC7 of a command, as sent in its final byte.
+pub fn crc7(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        for bit in (0..8).rev() {
+            let feedback = ((byte >> bit) ^ (crc >> 6)) & 1;
+            crc = (crc << 1) & 0x7f;
+            if feedback != 0 {
+                crc ^= 0x09;
+            }
+        }
+    }
+    crc
+}
+
+/// Return the CRC16 (CCITT) of a data block.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// A driver for an SD card in SPI mode, with CRC checking enabled.
+///
+/// Call [`SdCard::init`] once the card is inserted, with the bus at no more
+/// than 400kHz; the bus can then be run at full speed. Blocks are accessed
+/// through the [`BlockDevice`] trait.
+pub struct SdCard<SPI, CS> {
+    spi: SPI,
+    cs: CS,
+    kind: Option<CardKind>,
+}
+
+impl<SPI, CS> SdCard<SPI, CS>
+where
+    SPI: Transfer<u8>,
+    CS: OutputPin,
+{
+    /// Create a driver for the card on the given bus and chip select pin.
+    pub fn new(spi: SPI, cs: CS) -> Self {
+        Self {
+            spi,
+            cs,
+            kind: None,
+        }
+    }
+
+    // Create a driver for a card which has already been initialized.
+    pub(crate) fn initialized(spi: SPI, cs: CS, kind: CardKind) -> Self {
+        Self {
+            spi,
+            cs,
+            kind: Some(kind),
+        }
+    }
+
+    /// Return the kind of card, once initialized.
+    pub fn kind(&self) -> Option<CardKind> {
+        self.kind
+    }
+
+    /// Reset the card into SPI mode and initialize it, returning its kind.
+    pub fn init(&mut self) -> Result<CardKind, SdError> {
+        self.kind = None;
+
+        // At least 74 clocks with chip select high put the card in native
+        // mode, ready for GO_IDLE_STATE to switch it to SPI mode.
+        self.cs.set_high().map_err(|_| SdError::ChipSelect)?;
+        for _ in 0..10 {
+            self.transfer_byte(0xff)?;
+        }
+
+        let kind = self.selected(|card| {
+            let mut r1 = 0;
+            for _ in 0..RESPONSE_RETRIES {
+                r1 = card.command(GO_IDLE_STATE, 0)?;
+                if r1 == R1_IDLE {
+                    break;
+                }
+            }
+            if r1 != R1_IDLE {
+                return Err(SdError::Timeout);
+            }
+
+            let version2 = match card.command(SEND_IF_COND, IF_COND_ARGUMENT)? {
+                r1 if r1 & R1_ILLEGAL_COMMAND != 0 => false,
+                R1_IDLE => {
+                    let r7 = card.read_u32()?;
+                    if r7 & 0xfff != IF_COND_ARGUMENT {
+                        return Err(SdError::UnsupportedCard);
+                    }
+                    true
+                }
+                r1 => return Err(SdError::Command(r1)),
+            };
+
+            match card.command(CRC_ON_OFF, 1)? {
+                R1_IDLE => {}
+                r1 => return Err(SdError::Command(r1)),
+            }
+
+            let argument = if version2 { HCS } else { 0 };
+            let mut ready = false;
+            for _ in 0..INIT_RETRIES {
+                match card.app_command(SD_SEND_OP_COND, argument)? {
+                    0 => {
+                        ready = true;
+                        break;
+                    }
+                    R1_IDLE => {}
+                    r1 => return Err(SdError::Command(r1)),
+                }
+            }
+            if !ready {
+                return Err(SdError::Timeout);
+            }
+
+            if !version2 {
+                card.check(SET_BLOCKLEN, BLOCK_SIZE as u32)?;
+                return Ok(CardKind::Sd1);
+            }
+
+            card.check(READ_OCR, 0)?;
+            if card.read_u32()? & OCR_CCS != 0 {
+                Ok(CardKind::Sdhc)
+            } else {
+                card.check(SET_BLOCKLEN, BLOCK_SIZE as u32)?;
+                Ok(CardKind::Sd2)
+            }
+        })?;
+
+        self.kind = Some(kind);
+        Ok(kind)
+    }
+
+    /// Release the bus and chip select pin.
+    pub fn release(self) -> (SPI, CS) {
+        (self.spi, self.cs)
+    }
+
+    // Run `f` with the card selected, deselecting it afterwards even if `f`
+    // fails.
+    fn selected<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, SdError>,
+    ) -> Result<T, SdError> {
+        self.cs.set_low().map_err(|_| SdError::ChipSelect)?;
+        let result = f(self);
+        let deselected = self.cs.set_high().map_err(|_| SdError::ChipSelect);
+        // An extra byte lets the card release the data line.
+        let released = self.transfer_byte(0xff);
+
+        let value = result?;
+        deselected?;
+        released?;
+        Ok(value)
+    }
+
+    // The address of a block in the card's addressing scheme.
+    fn address(&self, block: u32) -> Result<u32, SdError> {
+        match self.kind {
+            Some(CardKind::Sdhc) => Ok(block),
+            Some(_) => block
+                .checked_mul(BLOCK_SIZE as u32)
+                .ok_or(SdError::OutOfRange),
+            None => Err(SdError::NotInitialized),
+        }
+    }
+
+    // Send a command and return its R1 response.
+    fn command(&mut self, index: u8, argument: u32) -> Result<u8, SdError> {
+        self.wait_ready()?;
+
+        let mut frame = [0; 6];
+        frame[0] = 0x40 | index;
+        frame[1..5].copy_from_slice(&argument.to_be_bytes());
+        frame[5] = crc7(&frame[..5]) << 1 | 1;
+        self.spi.transfer(&mut frame).map_err(|_| SdError::Spi)?;
+
+        for _ in 0..RESPONSE_RETRIES {
+            let r1 = self.transfer_byte(0xff)?;
+            if r1 & 0x80 == 0 {
+                return Ok(r1);
+            }
+        }
+
+        Err(SdError::Timeout)
+    }
+
+    fn app_command(&mut self, index: u8, argument: u32) -> Result<u8, SdError> {
+        match self.command(APP_CMD, 0)? {
+            r1 if r1 & !R1_IDLE == 0 => self.command(index, argument),
+            r1 => Err(SdError::Command(r1)),
+        }
+    }
+
+    // Send a command, which must succeed outside the idle state.
+    fn check(&mut self, index: u8, argument: u32) -> Result<(), SdError> {
+        match self.command(index, argument)? {
+            0 => Ok(()),
+            r1 if r1 & R1_CRC_ERROR != 0 => Err(SdError::Crc),
+            r1 => Err(SdError::Command(r1)),
+        }
+    }
+
+    // Read the four bytes following an R3 or R7 response.
+    fn read_u32(&mut self) -> Result<u32, SdError> {
+        let mut bytes = [0xff; 4];
+        self.spi.transfer(&mut bytes).map_err(|_| SdError::Spi)?;
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    // Wait for the card to stop holding the data line low while busy.
+    fn wait_ready(&mut self) -> Result<(), SdError> {
+        for _ in 0..DATA_RETRIES {
+            if self.transfer_byte(0xff)? == 0xff {
+                return Ok(());
+            }
+        }
+        Err(SdError::Timeout)
+    }
+
+    // Read a data block of `buf.len()` bytes, and check its CRC.
+    fn read_data(&mut self, buf: &mut [u8]) -> Result<(), SdError> {
+        let mut token = 0xff;
+        for _ in 0..DATA_RETRIES {
+            token = self.transfer_byte(0xff)?;
+            if token != 0xff {
+                break;
+            }
+        }
+        match token {
+            START_BLOCK_TOKEN => {}
+            0xff => return Err(SdError::Timeout),
+            _ => return Err(SdError::Data),
+        }
+
+        buf.iter_mut().for_each(|byte| *byte = 0xff);
+        self.spi.transfer(buf).map_err(|_| SdError::Spi)?;
+        let crc = self.read_crc()?;
+
+        if crc == crc16(buf) {
+            Ok(())
+        } else {
+            Err(SdError::Crc)
+        }
+    }
+
+    fn read_crc(&mut self) -> Result<u16, SdError> {
+        let mut crc = [0xff; 2];
+        self.spi.transfer(&mut crc).map_err(|_| SdError::Spi)?;
+        Ok(u16::from_be_bytes(crc))
+    }
+
+    fn transfer_byte(&mut self, byte: u8) -> Result<u8, SdError> {
+        let mut buf = [byte];
+        self.spi.transfer(&mut buf).map_err(|_| SdError::Spi)?;
+        Ok(buf[0])
+    }
+}
+
+impl<SPI, CS> BlockDevice for SdCard<SPI, CS>
+where
+    SPI: Transfer<u8>,
+    CS: OutputPin,
+{
+    type Error = SdError;
+
+    fn read_block(&mut self, block: u32, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), SdError> {
+        let address = self.address(block)?;
+        self.selected(|card| {
+            card.check(READ_SINGLE_BLOCK, address)?;
+            card.read_data(buf)
+        })
+    }
+
+    fn write_block(&mut self, block: u32, data: &[u8; BLOCK_SIZE]) -> Result<(), SdError> {
+        let address = self.address(block)?;
+        self.selected(|card| {
+            card.check(WRITE_BLOCK, address)?;
+
+            let mut block = [0; BLOCK_SIZE + 4];
+            block[0] = 0xff;
+            block[1] = START_BLOCK_TOKEN;
+            block[2..BLOCK_SIZE + 2].copy_from_slice(data);
+            block[BLOCK_SIZE + 2..].copy_from_slice(&crc16(data).to_be_bytes());
+            card.spi.transfer(&mut block).map_err(|_| SdError::Spi)?;
+
+            match card.transfer_byte(0xff)? & 0x1f {
+                DATA_ACCEPTED => card.wait_ready(),
+                DATA_CRC_ERROR => Err(SdError::Crc),
+                _ => Err(SdError::Data),
+            }
+        })
+    }
+
+    fn block_count(&mut self) -> Result<u32, SdError> {
+        if self.kind.is_none() {
+            return Err(SdError::NotInitialized);
+        }
+
+        let mut csd = [0; 16];
+        self.selected(|card| {
+            card.check(SEND_CSD, 0)?;
+            card.read_data(&mut csd)
+        })?;
+
+        match csd[0] >> 6 {
+            // CSD version 2: (C_SIZE + 1) * 512KB.
+            1 => {
+                let c_size = (csd[7] as u32 & 0x3f) << 16 | (csd[8] as u32) << 8 | csd[9] as u32;
+                Ok((c_size + 1) * 1024)
+            }
+            // CSD version 1: (C_SIZE + 1) * 2^(C_SIZE_MULT + 2) * 2^READ_BL_LEN.
+            0 => {
+                let read_bl_len = csd[5] as u32 & 0x0f;
+                let c_size =
+                    (csd[6] as u32 & 0x03) << 10 | (csd[7] as u32) << 2 | (csd[8] as u32) >> 6;
+                let c_size_mult = (csd[9] as u32 & 0x03) << 1 | (csd[10] as u32) >> 7;
+                // Count in blocks, as a 4GB card has too many bytes for a u32.
+                let shift = (c_size_mult + 2 + read_bl_len)
+                    .checked_sub(9)
+                    .ok_or(SdError::UnsupportedCard)?;
+                Ok((c_size + 1) << shift)
+            }
+            _ => Err(SdError::UnsupportedCard),
+        }
+    }
+}
+
+/// Whether a card was inserted or removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CardEvent {
+    Inserted,
+    Removed,
+}
+
+/// The SD card detect switch, which pulls `det` low while a card is
+/// inserted.
+pub struct CardDetect {
+    pin: Pd21<Input<PullUp>>,
+    debounce: Debounce,
+}
+
+impl CardDetect {
+    pub(crate) fn new(pin: Pd21<Input<PullUp>>) -> Self {
+        let inserted = pin.is_low().unwrap_or(false);
+        Self {
+            pin,
+            debounce: Debounce::new(inserted),
+        }
+    }
+
+    /// Return whether a card is inserted, without debouncing.
+    pub fn is_inserted(&self) -> bool {
+        self.read()
+    }
+
+    /// Set the time the switch must be stable for before a change is
+    /// reported.
+    pub fn set_debounce_time(&mut self, debounce_ms: u32) {
+        self.debounce.debounce_ms = debounce_ms;
+    }
+
+    /// Read the switch at `now_ms`, and return a change once it has been
+    /// stable for the debounce time.
+    pub fn poll(&mut self, now_ms: u32) -> Option<CardEvent> {
+        let inserted = self.read();
+        self.debounce.update(inserted, now_ms)
+    }
+
+    /// Switch the pin to raise `EXTINT11` when a card is inserted or removed.
+    ///
+    /// Note that `EXTINT11` is shared with the top middle button, and that
+    /// the `ButtonController` takes ownership of the EIC, so the two cannot be
+    /// used together.
+    pub fn into_interrupt(
+        self,
+        eic: &mut eic::ConfigurableEIC,
+        port: &mut Port,
+    ) -> CardDetectInterrupt {
+        let mut pin = self.pin.into_ei(port);
+        pin.sense(eic, Sense::BOTH);
+        pin.enable_interrupt(eic);
+
+        CardDetectInterrupt { pin }
+    }
+
+    fn read(&self) -> bool {
+        self.pin.is_low().unwrap_or(false)
+    }
+}
+
+// Debounces the card detect switch, whose level is read by the caller.
+struct Debounce {
+    inserted: bool,
+    candidate: Option<(bool, u32)>,
+    debounce_ms: u32,
+}
+
+impl Debounce {
+    fn new(inserted: bool) -> Self {
+        Self {
+            inserted,
+            candidate: None,
+            debounce_ms: CARD_DETECT_DEBOUNCE_MS,
+        }
+    }
+
+    fn update(&mut self, inserted: bool, now_ms: u32) -> Option<CardEvent> {
+        if inserted == self.inserted {
+            self.candidate = None;
+            return None;
+        }
+
+        let since = match self.candidate {
+            Some((candidate, since)) if candidate == inserted => since,
+            _ => {
+                self.candidate = Some((inserted, now_ms));
+                now_ms
+            }
+        };
+
+        if now_ms.wrapping_sub(since) < self.debounce_ms {
+            return None;
+        }
+
+        self.inserted = inserted;
+        self.candidate = None;
+        Some(event(inserted))
+    }
+}
+
+/// The SD card detect switch, raising `EXTINT11` when it changes.
+pub struct CardDetectInterrupt {
+    pin: ExtInt11<Pd21<PfA>>,
+}
+
+impl CardDetectInterrupt {
+    /// Return whether a card is inserted.
+    pub fn is_inserted(&self) -> bool {
+        !self.pin.state()
+    }
+
+    /// Handle the `EIC_EXTINT_11` interrupt, returning the change if it was
+    /// raised by the card detect switch.
+    pub fn interrupt(&mut self) -> Option<CardEvent> {
+        if !self.pin.is_interrupt() {
+            return None;
+        }
+        self.pin.clear_interrupt();
+        Some(event(self.is_inserted()))
+    }
+}
+
+fn event(inserted: bool) -> CardEvent {
+    if inserted {
+        CardEvent::Inserted
+    } else {
+        CardEvent::Removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::cell::Cell;
+    use heapless::consts::{U1024, U32};
+    use heapless::spsc::Queue;
+    use heapless::Vec;
+
+    const BLOCKS: usize = 4;
+
+    // A chip select pin, which tells the card whether it is selected.
+    struct FakeCs<'a>(&'a Cell<bool>);
+
+    impl OutputPin for FakeCs<'_> {
+        type Error = ();
+
+        fn set_low(&mut self) -> Result<(), ()> {
+            self.0.set(true);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), ()> {
+            self.0.set(false);
+            Ok(())
+        }
+    }
+
+    // A model of an SD card in SPI mode, which records each command sent to
+    // it, and panics if it is used incorrectly.
+    struct FakeCard<'a> {
+        selected: &'a Cell<bool>,
+        kind: CardKind,
+        present: bool,
+        csd: [u8; 16],
+        blocks: [[u8; BLOCK_SIZE]; BLOCKS],
+        log: Vec<(u8, u32), U32>,
+        // The bytes the card will send, and the bytes of the command or data
+        // block being received.
+        output: Queue<u8, U1024>,
+        input: Vec<u8, U1024>,
+        // The block being written, while its data is received.
+        writing: Option<usize>,
+        // The number of bytes clocked before the card was first selected.
+        clocks: u32,
+        idle: bool,
+        app: bool,
+        // How many times SD_SEND_OP_COND reports the card still idle.
+        init_polls: u8,
+        crc_enabled: bool,
+        corrupt_reads: bool,
+        reject_writes: bool,
+    }
+
+    impl<'a> FakeCard<'a> {
+        fn new(kind: CardKind, selected: &'a Cell<bool>) -> Self {
+            Self {
+                selected,
+                kind,
+                present: true,
+                csd: csd_v2(1023),
+                blocks: [[0; BLOCK_SIZE]; BLOCKS],
+                log: Vec::new(),
+                output: Queue::new(),
+                input: Vec::new(),
+                writing: None,
+                clocks: 0,
+                idle: false,
+                app: false,
+                init_polls: 2,
+                crc_enabled: false,
+                corrupt_reads: false,
+                reject_writes: false,
+            }
+        }
+
+        fn commands(&self) -> Vec<u8, U32> {
+            self.log.iter().map(|&(index, _)| index).collect()
+        }
+
+        fn exchange(&mut self, byte: u8) -> u8 {
+            if !self.present {
+                return 0xff;
+            }
+            if !self.selected.get() {
+                // Deselecting the card abandons any command in progress.
+                if self.log.is_empty() {
+                    self.clocks += 1;
+                }
+                self.input.clear();
+                while self.output.dequeue().is_some() {}
+                return 0xff;
+            }
+
+            let out = self.output.dequeue().unwrap_or(0xff);
+            if let Some(block) = self.writing {
+                self.receive_data(block, byte);
+            } else if !self.input.is_empty() || byte & 0xc0 == 0x40 {
+                self.input.push(byte).unwrap();
+                if self.input.len() == 6 {
+                    self.command();
+                }
+            }
+            out
+        }
+
+        // Queue a response, after a byte's delay.
+        fn respond(&mut self, bytes: &[u8]) {
+            self.output.enqueue(0xff).unwrap();
+            bytes.iter().for_each(|&b| self.output.enqueue(b).unwrap());
+        }
+
+        fn respond_data(&mut self, r1: u8, data: &[u8]) {
+            let mut crc = crc16(data);
+            if self.corrupt_reads {
+                crc ^= 1;
+            }
+            self.respond(&[r1, 0xff, START_BLOCK_TOKEN]);
+            data.iter().for_each(|&b| self.output.enqueue(b).unwrap());
+            crc.to_be_bytes()
+                .iter()
+                .for_each(|&b| self.output.enqueue(b).unwrap());
+        }
+
+        fn command(&mut self) {
+            let frame = [
+                self.input[0],
+                self.input[1],
+                self.input[2],
+                self.input[3],
+                self.input[4],
+                self.input[5],
+            ];
+            self.input.clear();
+
+            let index = frame[0] & 0x3f;
+            let argument = u32::from_be_bytes([frame[1], frame[2], frame[3], frame[4]]);
+            self.log.push((index, argument)).unwrap();
+            assert_eq!(frame[5] & 1, 1, "missing end bit");
+            if frame[5] >> 1 != crc7(&frame[..5]) {
+                self.respond(&[R1_CRC_ERROR | self.idle as u8]);
+                return;
+            }
+
+            let app = self.app;
+            self.app = false;
+            let r1 = self.idle as u8;
+
+            match (app, index) {
+                (_, APP_CMD) => {
+                    self.app = true;
+                    self.respond(&[r1]);
+                }
+                (true, SD_SEND_OP_COND) => {
+                    let hcs = if self.kind == CardKind::Sd1 { 0 } else { HCS };
+                    assert_eq!(argument, hcs);
+                    if self.init_polls > 0 {
+                        self.init_polls -= 1;
+                    } else {
+                        self.idle = false;
+                    }
+                    self.respond(&[self.idle as u8]);
+                }
+                (_, GO_IDLE_STATE) => {
+                    assert!(self.clocks >= 10, "too few clocks before reset");
+                    self.idle = true;
+                    self.respond(&[R1_IDLE]);
+                }
+                (_, SEND_IF_COND) if self.kind == CardKind::Sd1 => {
+                    self.respond(&[r1 | R1_ILLEGAL_COMMAND]);
+                }
+                (_, SEND_IF_COND) => {
+                    let echo = (argument & 0xfff).to_be_bytes();
+                    self.respond(&[r1, echo[0], echo[1], echo[2], echo[3]]);
+                }
+                (_, CRC_ON_OFF) => {
+                    self.crc_enabled = argument & 1 != 0;
+                    self.respond(&[r1]);
+                }
+                _ if self.idle => self.respond(&[r1 | R1_ILLEGAL_COMMAND]),
+                (_, READ_OCR) => {
+                    let ccs = if self.kind == CardKind::Sdhc {
+                        OCR_CCS
+                    } else {
+                        0
+                    };
+                    let ocr = (0x80ff_8000 | ccs).to_be_bytes();
+                    self.respond(&[r1, ocr[0], ocr[1], ocr[2], ocr[3]]);
+                }
+                (_, SET_BLOCKLEN) => {
+                    assert_ne!(self.kind, CardKind::Sdhc);
+                    assert_eq!(argument, BLOCK_SIZE as u32);
+                    self.respond(&[r1]);
+                }
+                (_, SEND_CSD) => {
+                    let csd = self.csd;
+                    self.respond_data(r1, &csd);
+                }
+                (_, READ_SINGLE_BLOCK) => {
+                    let data = self.blocks[self.block(argument)];
+                    self.respond_data(r1, &data);
+                }
+                (_, WRITE_BLOCK) => {
+                    self.writing = Some(self.block(argument));
+                    self.respond(&[r1]);
+                }
+                _ => self.respond(&[r1 | R1_ILLEGAL_COMMAND]),
+            }
+        }
+
+        fn block(&self, address: u32) -> usize {
+            let block = if self.kind == CardKind::Sdhc {
+                address
+            } else {
+                assert_eq!(address % BLOCK_SIZE as u32, 0);
+                address / BLOCK_SIZE as u32
+            };
+            block as usize
+        }
+
+        // Receive the start token, data and CRC of a block being written.
+        fn receive_data(&mut self, block: usize, byte: u8) {
+            if self.input.is_empty() && byte != START_BLOCK_TOKEN {
+                return;
+            }
+            self.input.push(byte).unwrap();
+            if self.input.len() < BLOCK_SIZE + 3 {
+                return;
+            }
+
+            let data = &self.input[1..BLOCK_SIZE + 1];
+            let crc = u16::from_be_bytes([self.input[BLOCK_SIZE + 1], self.input[BLOCK_SIZE + 2]]);
+            // The top bits of the data response are undefined.
+            let response = if self.reject_writes || (self.crc_enabled && crc != crc16(data)) {
+                0xe0 | DATA_CRC_ERROR
+            } else {
+                self.blocks[block].copy_from_slice(data);
+                0xe0 | DATA_ACCEPTED
+            };
+
+            self.input.clear();
+            self.writing = None;
+            // The card holds the data line low while it is busy writing.
+            [response, 0, 0, 0]
+                .iter()
+                .for_each(|&b| self.output.enqueue(b).unwrap());
+        }
+    }
+
+    impl Transfer<u8> for FakeCard<'_> {
+        type Error = ();
+
+        fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], ()> {
+            for word in words.iter_mut() {
+                *word = self.exchange(*word);
+            }
+            Ok(words)
+        }
+    }
+
+    fn card(kind: CardKind, selected: &Cell<bool>) -> SdCard<FakeCard<'_>, FakeCs<'_>> {
+        SdCard::new(FakeCard::new(kind, selected), FakeCs(selected))
+    }
+
+    fn csd_v1(read_bl_len: u8, c_size: u16, c_size_mult: u8) -> [u8; 16] {
+        let mut csd = [0; 16];
+        csd[5] = read_bl_len;
+        csd[6] = (c_size >> 10) as u8;
+        csd[7] = (c_size >> 2) as u8;
+        csd[8] = (c_size << 6) as u8;
+        csd[9] = c_size_mult >> 1;
+        csd[10] = c_size_mult << 7;
+        csd
+    }
+
+    fn csd_v2(c_size: u32) -> [u8; 16] {
+        let mut csd = [0; 16];
+        csd[0] = 0x40;
+        csd[7] = (c_size >> 16) as u8 & 0x3f;
+        csd[8] = (c_size >> 8) as u8;
+        csd[9] = c_size as u8;
+        csd
+    }
+
+    #[test]
+    fn crc7_vectors() {
+        // The examples from the SD physical layer specification.
+        assert_eq!(crc7(&[0x40, 0, 0, 0, 0]), 0x4a);
+        assert_eq!(crc7(&[0x51, 0, 0, 0, 0]), 0x2a);
+        assert_eq!(crc7(&[0x11, 0, 0, 0x09, 0]), 0x33);
+        // CMD8 with the usual argument, sent as 0x87.
+        assert_eq!(crc7(&[0x48, 0, 0, 0x01, 0xaa]) << 1 | 1, 0x87);
+    }
+
+    #[test]
+    fn crc16_vectors() {
+        assert_eq!(crc16(&[0xff; 512]), 0x7fa1);
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(crc16(&[]), 0);
+    }
+
+    #[test]
+    fn init_version_1() {
+        let selected = Cell::new(false);
+        let mut sd = card(CardKind::Sd1, &selected);
+
+        assert_eq!(sd.init(), Ok(CardKind::Sd1));
+        assert_eq!(sd.kind(), Some(CardKind::Sd1));
+        assert!(!selected.get());
+        assert_eq!(
+            sd.spi.log,
+            [
+                (GO_IDLE_STATE, 0),
+                (SEND_IF_COND, IF_COND_ARGUMENT),
+                (CRC_ON_OFF, 1),
+                (APP_CMD, 0),
+                (SD_SEND_OP_COND, 0),
+                (APP_CMD, 0),
+                (SD_SEND_OP_COND, 0),
+                (APP_CMD, 0),
+                (SD_SEND_OP_COND, 0),
+                (SET_BLOCKLEN, 512),
+            ]
+        );
+    }
+
+    #[test]
+    fn init_version_2() {
+        let selected = Cell::new(false);
+        let mut sd = card(CardKind::Sd2, &selected);
+
+        assert_eq!(sd.init(), Ok(CardKind::Sd2));
+        assert_eq!(
+            sd.spi.commands(),
+            [0, 8, 59, 55, 41, 55, 41, 55, 41, 58, 16]
+        );
+        assert_eq!(sd.spi.log[4], (SD_SEND_OP_COND, HCS));
+        assert!(sd.spi.crc_enabled);
+    }
+
+    #[test]
+    fn init_sdhc() {
+        let selected = Cell::new(false);
+        let mut sd = card(CardKind::Sdhc, &selected);
+        sd.spi.init_polls = 0;
+
+        assert_eq!(sd.init(), Ok(CardKind::Sdhc));
+        assert_eq!(sd.spi.commands(), [0, 8, 59, 55, 41, 58]);
+    }
+
+    #[test]
+    fn init_without_card() {
+        let selected = Cell::new(false);
+        let mut sd = card(CardKind::Sdhc, &selected);
+        sd.spi.present = false;
+
+        assert_eq!(sd.init(), Err(SdError::Timeout));
+        assert_eq!(sd.kind(), None);
+        assert!(!selected.get());
+    }
+
+    #[test]
+    fn not_initialized() {
+        let selected = Cell::new(false);
+        let mut sd = card(CardKind::Sdhc, &selected);
+        let mut buf = [0; BLOCK_SIZE];
+
+        assert_eq!(sd.read_block(0, &mut buf), Err(SdError::NotInitialized));
+        assert_eq!(sd.write_block(0, &buf), Err(SdError::NotInitialized));
+        assert_eq!(sd.block_count(), Err(SdError::NotInitialized));
+        assert!(sd.spi.log.is_empty());
+    }
+
+    #[test]
+    fn blocks_are_addressed_in_bytes() {
+        let selected = Cell::new(false);
+        let mut sd = card(CardKind::Sd2, &selected);
+        sd.init().unwrap();
+
+        let mut data = [0; BLOCK_SIZE];
+        data.iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+        sd.write_block(2, &data).unwrap();
+        let mut buf = [0; BLOCK_SIZE];
+        sd.read_block(2, &mut buf).unwrap();
+
+        assert_eq!(buf[..], data[..]);
+        assert_eq!(sd.spi.blocks[2][..], data[..]);
+        let log = &sd.spi.log[sd.spi.log.len() - 2..];
+        assert_eq!(log, [(WRITE_BLOCK, 1024), (READ_SINGLE_BLOCK, 1024)]);
+        assert_eq!(sd.read_block(0x80_0000, &mut buf), Err(SdError::OutOfRange));
+    }
+
+    #[test]
+    fn blocks_are_addressed_in_blocks() {
+        let selected = Cell::new(false);
+        let mut sd = card(CardKind::Sdhc, &selected);
+        sd.init().unwrap();
+
+        sd.write_block(3, &[0xa5; BLOCK_SIZE]).unwrap();
+        let mut buf = [0; BLOCK_SIZE];
+        sd.read_block(3, &mut buf).unwrap();
+
+        assert_eq!(buf[..], [0xa5; BLOCK_SIZE][..]);
+        let log = &sd.spi.log[sd.spi.log.len() - 2..];
+        assert_eq!(log, [(WRITE_BLOCK, 3), (READ_SINGLE_BLOCK, 3)]);
+    }
+
+    #[test]
+    fn read_crc_error() {
+        let selected = Cell::new(false);
+        let mut sd = card(CardKind::Sdhc, &selected);
+        sd.init().unwrap();
+        sd.spi.corrupt_reads = true;
+
+        let mut buf = [0; BLOCK_SIZE];
+        assert_eq!(sd.read_block(0, &mut buf), Err(SdError::Crc));
+        assert!(!selected.get());
+    }
+
+    #[test]
+    fn write_crc_error() {
+        let selected = Cell::new(false);
+        let mut sd = card(CardKind::Sdhc, &selected);
+        sd.init().unwrap();
+        sd.spi.reject_writes = true;
+
+        assert_eq!(sd.write_block(1, &[1; BLOCK_SIZE]), Err(SdError::Crc));
+        assert_eq!(sd.spi.blocks[1], [0; BLOCK_SIZE]);
+
+        // The card is still usable afterwards.
+        sd.spi.reject_writes = false;
+        assert_eq!(sd.write_block(1, &[1; BLOCK_SIZE]), Ok(()));
+    }
+
+    #[test]
+    fn csd_version_1() {
+        let selected = Cell::new(false);
+        let mut sd = card(CardKind::Sd1, &selected);
+        sd.init().unwrap();
+
+        // 1001 * 2^7 blocks of 512 bytes.
+        sd.spi.csd = csd_v1(9, 1000, 5);
+        assert_eq!(sd.block_count(), Ok(128_128));
+
+        // A 2GB card, with 1024 byte blocks.
+        sd.spi.csd = csd_v1(10, 4095, 7);
+        assert_eq!(sd.block_count(), Ok(4 * 1024 * 1024));
+
+        // A 4GB card, with 2048 byte blocks, which has 2^32 bytes.
+        sd.spi.csd = csd_v1(11, 4095, 7);
+        assert_eq!(sd.block_count(), Ok(8 * 1024 * 1024));
+    }
+
+    #[test]
+    fn csd_version_2() {
+        let selected = Cell::new(false);
+        let mut sd = card(CardKind::Sdhc, &selected);
+        sd.init().unwrap();
+
+        // An 8GB card.
+        sd.spi.csd = csd_v2(15_159);
+        assert_eq!(sd.block_count(), Ok(15_160 * 1024));
+
+        // The largest SDXC card.
+        sd.spi.csd = csd_v2(0x3f_feff);
+        assert_eq!(sd.block_count(), Ok(0x3f_ff00 * 1024));
+
+        sd.spi.csd[0] = 0x80;
+        assert_eq!(sd.block_count(), Err(SdError::UnsupportedCard));
+    }
+
+    #[test]
+    fn card_detect_debounce() {
+        let mut debounce = Debounce::new(false);
+
+        assert_eq!(debounce.update(true, 1000), None);
+        assert_eq!(debounce.update(true, 1049), None);
+        assert_eq!(debounce.update(true, 1050), Some(CardEvent::Inserted));
+        assert_eq!(debounce.update(true, 1100), None);
+
+        assert_eq!(debounce.update(false, 2000), None);
+        assert_eq!(debounce.update(false, 2050), Some(CardEvent::Removed));
+    }
+
+    #[test]
+    fn card_detect_bounce_is_rejected() {
+        let mut debounce = Debounce::new(true);
+
+        // Returning to the stable level restarts the debounce time.
+        assert_eq!(debounce.update(false, 0), None);
+        assert_eq!(debounce.update(true, 40), None);
+        assert_eq!(debounce.update(false, 45), None);
+        assert_eq!(debounce.update(false, 90), None);
+        assert_eq!(debounce.update(false, 95), Some(CardEvent::Removed));
+    }
+
+    #[test]
+    fn card_detect_time_wraps_around() {
+        let mut debounce = Debounce::new(false);
+        debounce.debounce_ms = 10;
+
+        assert_eq!(debounce.update(true, u32::MAX - 4), None);
+        assert_eq!(debounce.update(true, 4), None);
+        assert_eq!(debounce.update(true, 5), Some(CardEvent::Inserted));
+    }
+}
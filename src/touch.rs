@@ -0,0 +1,615 @@
+use atsamd_hal::gpio::{Floating, Input, Pc10, Pc11, Pc12, Pc13};
+use ili9341::Orientation;
+
+use crate::config::Value;
+
+/// The width of the panel, in pixels, in its native portrait orientation.
+pub const PANEL_WIDTH: u16 = 240;
+
+/// The height of the panel, in pixels, in its native portrait orientation.
+pub const PANEL_HEIGHT: u16 = 320;
+
+/// The default pressure below which the panel is considered untouched.
+pub const DEFAULT_PRESSURE_THRESHOLD: u16 = 200;
+
+// The largest difference between two consecutive raw readings of the same
+// axis for the touch to be considered stable.
+const MAX_JITTER: u16 = 40;
+
+// The largest value returned by the 12-bit ADC.
+const ADC_MAX: u16 = 4095;
+
+/// 4-wire resistive touch panel pins
+///
+/// These are only the pins, left as floating inputs. On the ATSAMD51 none of
+/// them is connected to an ADC channel, so the plates cannot be measured
+/// through `ADC1`, and this crate provides no [`TouchSampler`] for them.
+/// Instead, supply one for hardware which can measure the plates, such as an
+/// external 12-bit ADC, or ADC-capable pins wired to the plates. For each
+/// reading, it must:
+///
+/// - drive two plates as described for each method of [`TouchSampler`],
+///   leaving the others floating;
+/// - wait for the plates to settle, typically a few tens of microseconds;
+/// - measure the voltage on the sensing plate, scaled to 12 bits.
+pub struct TouchScreen {
+    /// Left X plate pin
+    pub xl: Pc10<Input<Floating>>,
+
+    /// Upper Y plate pin
+    pub yu: Pc11<Input<Floating>>,
+
+    /// Right X plate pin
+    pub xr: Pc12<Input<Floating>>,
+
+    /// Lower Y plate pin
+    pub yd: Pc13<Input<Floating>>,
+}
+
+/// Measures the plates of a 4-wire resistive touch panel. Each reading is a
+/// 12-bit value, from 0 to 4095.
+pub trait TouchSampler {
+    type Error;
+
+    /// Drive XL low and XR high, and return the voltage on YU.
+    fn sample_x(&mut self) -> Result<u16, Self::Error>;
+
+    /// Drive YD low and YU high, and return the voltage on XL.
+    fn sample_y(&mut self) -> Result<u16, Self::Error>;
+
+    /// Drive XL low and YU high, and return the voltages on XR and YD, as
+    /// `(z1, z2)`.
+    fn sample_z(&mut self) -> Result<(u16, u16), Self::Error>;
+}
+
+/// A point at which the panel is being touched, in display coordinates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TouchPoint {
+    pub x: u16,
+    pub y: u16,
+    /// How firmly the panel is pressed; larger values are firmer.
+    pub pressure: u16,
+}
+
+/// An affine transformation from raw readings to panel coordinates, in the
+/// panel's native portrait orientation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Calibration {
+    // x = a * raw_x + b * raw_y + c, y = d * raw_x + e * raw_y + f
+    a: f32,
+    b: f32,
+    c: f32,
+    d: f32,
+    e: f32,
+    f: f32,
+}
+
+impl Calibration {
+    /// Calculate the calibration which maps each of the three raw readings to
+    /// the corresponding panel coordinate. Return `None` if the readings lie
+    /// on a line, as the transformation is then undefined.
+    pub fn from_points(raw: [(u16, u16); 3], panel: [(u16, u16); 3]) -> Option<Self> {
+        let [(x0, y0), (x1, y1), (x2, y2)] = raw;
+        let (x0, y0, x1, y1, x2, y2) = (
+            x0 as i32, y0 as i32, x1 as i32, y1 as i32, x2 as i32, y2 as i32,
+        );
+
+        let det = x0 * (y1 - y2) - x1 * (y0 - y2) + x2 * (y0 - y1);
+        if det == 0 {
+            return None;
+        }
+        let det = det as f32;
+
+        // Solve `p = k0 * raw_x + k1 * raw_y + k2` for each coordinate by
+        // Cramer's rule.
+        let solve = |p0: u16, p1: u16, p2: u16| {
+            let (p0, p1, p2) = (p0 as f32, p1 as f32, p2 as f32);
+            let (x0, y0, x1, y1, x2, y2) = (
+                x0 as f32, y0 as f32, x1 as f32, y1 as f32, x2 as f32, y2 as f32,
+            );
+            let k0 = (p0 * (y1 - y2) - p1 * (y0 - y2) + p2 * (y0 - y1)) / det;
+            let k1 = (x0 * (p1 - p2) - x1 * (p0 - p2) + x2 * (p0 - p1)) / det;
+            let k2 = (x0 * (y1 * p2 - y2 * p1) - x1 * (y0 * p2 - y2 * p0)
+                + x2 * (y0 * p1 - y1 * p0))
+                / det;
+            (k0, k1, k2)
+        };
+
+        let (a, b, c) = solve(panel[0].0, panel[1].0, panel[2].0);
+        let (d, e, f) = solve(panel[0].1, panel[1].1, panel[2].1);
+
+        Some(Self { a, b, c, d, e, f })
+    }
+
+    /// Return the panel coordinate of a raw reading. The coordinate may lie
+    /// outside the panel.
+    pub fn apply(&self, raw_x: u16, raw_y: u16) -> (f32, f32) {
+        let (x, y) = (raw_x as f32, raw_y as f32);
+        (
+            self.a * x + self.b * y + self.c,
+            self.d * x + self.e * y + self.f,
+        )
+    }
+}
+
+impl Default for Calibration {
+    /// Scale the full range of the ADC to the panel, which is only roughly
+    /// right for any given panel.
+    fn default() -> Self {
+        let scale = |size: u16| size as f32 / (ADC_MAX + 1) as f32;
+        Self {
+            a: scale(PANEL_WIDTH),
+            b: 0.0,
+            c: 0.0,
+            d: 0.0,
+            e: scale(PANEL_HEIGHT),
+            f: 0.0,
+        }
+    }
+}
+
+// A calibration is stored as its six coefficients, so that it only need be
+// found once.
+impl Value for Calibration {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        let coefficients = [self.a, self.b, self.c, self.d, self.e, self.f];
+        for (i, k) in coefficients.iter().enumerate() {
+            k.encode(&mut buf[i * 4..]);
+        }
+        coefficients.len() * 4
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != 24 {
+            return None;
+        }
+        let k = |i: usize| f32::decode(&bytes[i * 4..i * 4 + 4]);
+        Some(Self {
+            a: k(0)?,
+            b: k(1)?,
+            c: k(2)?,
+            d: k(3)?,
+            e: k(4)?,
+            f: k(5)?,
+        })
+    }
+}
+
+// How the display coordinates relate to the panel's, for each orientation of
+// the ILI9341.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Rotation {
+    Portrait,
+    Landscape,
+    PortraitFlipped,
+    LandscapeFlipped,
+}
+
+impl Rotation {
+    fn new(orientation: &Orientation) -> Self {
+        match orientation {
+            Orientation::Portrait => Rotation::Portrait,
+            Orientation::Landscape => Rotation::Landscape,
+            Orientation::PortraitFlipped => Rotation::PortraitFlipped,
+            Orientation::LandscapeFlipped => Rotation::LandscapeFlipped,
+        }
+    }
+
+    // The size of the display, as `(width, height)`.
+    fn size(self) -> (u16, u16) {
+        match self {
+            Rotation::Portrait | Rotation::PortraitFlipped => (PANEL_WIDTH, PANEL_HEIGHT),
+            Rotation::Landscape | Rotation::LandscapeFlipped => (PANEL_HEIGHT, PANEL_WIDTH),
+        }
+    }
+
+    // Convert panel coordinates to display coordinates, following the
+    // ILI9341's memory access control for each orientation.
+    fn to_display(self, x: f32, y: f32) -> (f32, f32) {
+        let (w, h) = ((PANEL_WIDTH - 1) as f32, (PANEL_HEIGHT - 1) as f32);
+        match self {
+            Rotation::Portrait => (w - x, y),
+            Rotation::Landscape => (y, x),
+            Rotation::PortraitFlipped => (x, h - y),
+            Rotation::LandscapeFlipped => (h - y, w - x),
+        }
+    }
+
+    // Convert display coordinates to panel coordinates.
+    fn to_panel(self, x: u16, y: u16) -> (u16, u16) {
+        let (w, h) = (PANEL_WIDTH - 1, PANEL_HEIGHT - 1);
+        match self {
+            Rotation::Portrait => (w - x, y),
+            Rotation::Landscape => (y, x),
+            Rotation::PortraitFlipped => (x, h - y),
+            Rotation::LandscapeFlipped => (w - y, h - x),
+        }
+    }
+}
+
+/// Steps through calibrating a touch panel against three targets.
+///
+/// While [`CalibrationRoutine::target`] returns a point, draw a marker there,
+/// wait for it to be touched, and record the raw reading with
+/// [`CalibrationRoutine::record`]. Then pass the resulting calibration to
+/// [`TouchPanel::set_calibration`].
+pub struct CalibrationRoutine {
+    rotation: Rotation,
+    raw: [(u16, u16); 3],
+    recorded: usize,
+}
+
+impl CalibrationRoutine {
+    /// Start calibrating, with the display in the given orientation.
+    pub fn new(orientation: &Orientation) -> Self {
+        Self {
+            rotation: Rotation::new(orientation),
+            raw: [(0, 0); 3],
+            recorded: 0,
+        }
+    }
+
+    /// Return the display coordinate which should be touched next, or `None`
+    /// once every target has been recorded.
+    pub fn target(&self) -> Option<(u16, u16)> {
+        self.targets().get(self.recorded).copied()
+    }
+
+    /// Record the raw reading, as returned by [`TouchPanel::read_raw`], for
+    /// the current target.
+    pub fn record(&mut self, raw_x: u16, raw_y: u16) {
+        if self.recorded < self.raw.len() {
+            self.raw[self.recorded] = (raw_x, raw_y);
+            self.recorded += 1;
+        }
+    }
+
+    /// Start again from the first target.
+    pub fn restart(&mut self) {
+        self.recorded = 0;
+    }
+
+    /// Return the calibration once every target has been recorded, or `None`
+    /// if not, or if the readings were unusable.
+    pub fn calibration(&self) -> Option<Calibration> {
+        if self.recorded < self.raw.len() {
+            return None;
+        }
+
+        let targets = self.targets();
+        let mut panel = [(0, 0); 3];
+        for (panel, &(x, y)) in panel.iter_mut().zip(targets.iter()) {
+            *panel = self.rotation.to_panel(x, y);
+        }
+
+        Calibration::from_points(self.raw, panel)
+    }
+
+    // Three well separated, non-collinear points, inset from the edges so that
+    // they can be touched accurately.
+    fn targets(&self) -> [(u16, u16); 3] {
+        let (width, height) = self.rotation.size();
+        let (left, right) = (width / 10, width - 1 - width / 10);
+        let (top, bottom) = (height / 10, height - 1 - height / 10);
+
+        [(left, top), (right, height / 2), (width / 2, bottom)]
+    }
+}
+
+/// Turns the readings of a [`TouchSampler`] into points in the coordinates
+/// of the display, applying a [`Calibration`] and the display's orientation,
+/// and rejecting touches which are too light or unstable.
+pub struct TouchPanel<S> {
+    sampler: S,
+    calibration: Calibration,
+    rotation: Rotation,
+    threshold: u16,
+}
+
+impl<S: TouchSampler> TouchPanel<S> {
+    /// Create a panel measured by `sampler`, with the display in the given
+    /// orientation and the default calibration.
+    pub fn new(sampler: S, orientation: &Orientation) -> Self {
+        Self {
+            sampler,
+            calibration: Calibration::default(),
+            rotation: Rotation::new(orientation),
+            threshold: DEFAULT_PRESSURE_THRESHOLD,
+        }
+    }
+
+    /// Set the calibration, as found by a [`CalibrationRoutine`].
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+    }
+
+    /// Set the orientation of the display, which touches are reported for.
+    pub fn set_orientation(&mut self, orientation: &Orientation) {
+        self.rotation = Rotation::new(orientation);
+    }
+
+    /// Set the pressure below which the panel is considered untouched.
+    pub fn set_pressure_threshold(&mut self, threshold: u16) {
+        self.threshold = threshold;
+    }
+
+    /// Return the uncalibrated readings as `(x, y, pressure)`, or `None` if
+    /// the panel is not being touched, or the readings are unstable.
+    pub fn read_raw(&mut self) -> Result<Option<(u16, u16, u16)>, S::Error> {
+        let (z1, z2) = self.sampler.sample_z()?;
+        let pressure = ADC_MAX - z2.saturating_sub(z1).min(ADC_MAX);
+        if pressure < self.threshold {
+            return Ok(None);
+        }
+
+        let (x1, x2) = (self.sampler.sample_x()?, self.sampler.sample_x()?);
+        let (y1, y2) = (self.sampler.sample_y()?, self.sampler.sample_y()?);
+        if jitter(x1, x2) > MAX_JITTER || jitter(y1, y2) > MAX_JITTER {
+            return Ok(None);
+        }
+
+        let average = |a: u16, b: u16| ((a as u32 + b as u32) / 2) as u16;
+        Ok(Some((average(x1, x2), average(y1, y2), pressure)))
+    }
+
+    /// Return where the panel is being touched, in display coordinates, or
+    /// `None` if it is not being touched.
+    pub fn read(&mut self) -> Result<Option<TouchPoint>, S::Error> {
+        let (raw_x, raw_y, pressure) = match self.read_raw()? {
+            Some(raw) => raw,
+            None => return Ok(None),
+        };
+
+        let (x, y) = self.calibration.apply(raw_x, raw_y);
+        let (x, y) = self.rotation.to_display(x, y);
+        let (width, height) = self.rotation.size();
+
+        Ok(Some(TouchPoint {
+            x: clamp(x, width),
+            y: clamp(y, height),
+            pressure,
+        }))
+    }
+
+    /// Release the sampler.
+    pub fn release(self) -> S {
+        self.sampler
+    }
+}
+
+fn jitter(a: u16, b: u16) -> u16 {
+    a.max(b) - a.min(b)
+}
+
+// Round a coordinate to the nearest pixel within `0..size`.
+fn clamp(value: f32, size: u16) -> u16 {
+    let max = (size - 1) as f32;
+    if value <= 0.0 {
+        0
+    } else if value >= max {
+        size - 1
+    } else {
+        (value + 0.5) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIENTATIONS: [Orientation; 4] = [
+        Orientation::Portrait,
+        Orientation::Landscape,
+        Orientation::PortraitFlipped,
+        Orientation::LandscapeFlipped,
+    ];
+
+    // Reads back fixed plate voltages, taking each axis from a pair of
+    // readings in turn.
+    struct FakeSampler {
+        x: [u16; 2],
+        y: [u16; 2],
+        z: (u16, u16),
+        reads: usize,
+    }
+
+    impl FakeSampler {
+        fn new(x: u16, y: u16) -> Self {
+            Self {
+                x: [x; 2],
+                y: [y; 2],
+                z: (1000, 1500),
+                reads: 0,
+            }
+        }
+    }
+
+    impl TouchSampler for FakeSampler {
+        type Error = ();
+
+        fn sample_x(&mut self) -> Result<u16, ()> {
+            self.reads += 1;
+            Ok(self.x[self.reads % 2])
+        }
+
+        fn sample_y(&mut self) -> Result<u16, ()> {
+            self.reads += 1;
+            Ok(self.y[self.reads % 2])
+        }
+
+        fn sample_z(&mut self) -> Result<(u16, u16), ()> {
+            Ok(self.z)
+        }
+    }
+
+    fn assert_near(actual: (f32, f32), expected: (f32, f32)) {
+        let error = (actual.0 - expected.0)
+            .abs()
+            .max((actual.1 - expected.1).abs());
+        assert!(error < 0.01, "{:?} is not {:?}", actual, expected);
+    }
+
+    #[test]
+    fn calibration_maps_its_points() {
+        // The panel is mounted skewed, with raw x running down the panel.
+        let raw = [(300, 3700), (3800, 3500), (2002, 201)];
+        let panel = [(20, 30), (40, 290), (222, 160)];
+        let calibration = Calibration::from_points(raw, panel).unwrap();
+
+        for (&(raw_x, raw_y), &(x, y)) in raw.iter().zip(panel.iter()) {
+            assert_near(calibration.apply(raw_x, raw_y), (x as f32, y as f32));
+        }
+
+        // An affine transformation keeps the centroid of the points.
+        assert_near(calibration.apply(2034, 2467), (94.0, 160.0));
+    }
+
+    #[test]
+    fn calibration_of_collinear_points() {
+        let panel = [(20, 30), (120, 160), (220, 290)];
+        let collinear = [(100, 100), (2000, 2000), (3900, 3900)];
+        assert_eq!(Calibration::from_points(collinear, panel), None);
+
+        let repeated = [(100, 100), (100, 100), (3900, 200)];
+        assert_eq!(Calibration::from_points(repeated, panel), None);
+    }
+
+    #[test]
+    fn default_calibration() {
+        let calibration = Calibration::default();
+        assert_near(calibration.apply(0, 0), (0.0, 0.0));
+        assert_near(calibration.apply(2048, 2048), (120.0, 160.0));
+    }
+
+    #[test]
+    fn calibration_is_stored() {
+        let raw = [(300, 3700), (3800, 3500), (2000, 200)];
+        let panel = [(20, 30), (40, 290), (220, 160)];
+        let calibration = Calibration::from_points(raw, panel).unwrap();
+
+        let mut buf = [0; 32];
+        let len = calibration.encode(&mut buf);
+        assert_eq!(len, 24);
+        assert_eq!(Calibration::decode(&buf[..len]), Some(calibration));
+        assert_eq!(Calibration::decode(&buf[..len - 1]), None);
+    }
+
+    #[test]
+    fn rotations() {
+        // Where each corner of the panel appears on the display.
+        let corners = [(0.0, 0.0), (239.0, 0.0), (0.0, 319.0)];
+        let expected = [
+            (
+                Rotation::Portrait,
+                [(239.0, 0.0), (0.0, 0.0), (239.0, 319.0)],
+            ),
+            (
+                Rotation::Landscape,
+                [(0.0, 0.0), (0.0, 239.0), (319.0, 0.0)],
+            ),
+            (
+                Rotation::PortraitFlipped,
+                [(0.0, 319.0), (239.0, 319.0), (0.0, 0.0)],
+            ),
+            (
+                Rotation::LandscapeFlipped,
+                [(319.0, 239.0), (319.0, 0.0), (0.0, 239.0)],
+            ),
+        ];
+
+        for (orientation, &(rotation, display)) in ORIENTATIONS.iter().zip(expected.iter()) {
+            assert_eq!(Rotation::new(orientation), rotation);
+            for (&(x, y), &point) in corners.iter().zip(display.iter()) {
+                assert_near(rotation.to_display(x, y), point);
+            }
+        }
+    }
+
+    #[test]
+    fn rotations_round_trip() {
+        for orientation in &ORIENTATIONS {
+            let rotation = Rotation::new(orientation);
+            let (width, height) = rotation.size();
+            for &(x, y) in &[(0, 0), (width - 1, 0), (10, height - 1), (100, 50)] {
+                let (panel_x, panel_y) = rotation.to_panel(x, y);
+                assert!(panel_x < PANEL_WIDTH && panel_y < PANEL_HEIGHT);
+                assert_near(
+                    rotation.to_display(panel_x as f32, panel_y as f32),
+                    (x as f32, y as f32),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn calibration_routine() {
+        // A panel whose readings are the panel coordinates scaled and offset.
+        let raw = |(x, y): (u16, u16)| (100 + x * 15, 4000 - y * 12);
+
+        for orientation in &ORIENTATIONS {
+            let rotation = Rotation::new(orientation);
+            let mut routine = CalibrationRoutine::new(orientation);
+            assert_eq!(routine.calibration(), None);
+
+            let mut targets = 0;
+            while let Some((x, y)) = routine.target() {
+                let (raw_x, raw_y) = raw(rotation.to_panel(x, y));
+                routine.record(raw_x, raw_y);
+                targets += 1;
+            }
+            assert_eq!(targets, 3);
+
+            let calibration = routine.calibration().unwrap();
+            let mut panel = TouchPanel::new(FakeSampler::new(0, 0), orientation);
+            panel.set_calibration(calibration);
+            for &(x, y) in &[(0, 0), (100, 200), (239, 319)] {
+                let (raw_x, raw_y) = raw((x, y));
+                panel.sampler = FakeSampler::new(raw_x, raw_y);
+                let (display_x, display_y) = rotation.to_display(x as f32, y as f32);
+                let point = panel.read().unwrap().unwrap();
+                assert_eq!((point.x, point.y), (display_x as u16, display_y as u16));
+            }
+
+            routine.restart();
+            assert!(routine.target().is_some());
+        }
+    }
+
+    #[test]
+    fn read_in_display_coordinates() {
+        let mut panel = TouchPanel::new(FakeSampler::new(2048, 1024), &Orientation::Landscape);
+        let point = panel.read().unwrap().unwrap();
+        assert_eq!(
+            point,
+            TouchPoint {
+                x: 80,
+                y: 120,
+                pressure: 3595
+            }
+        );
+
+        panel.set_orientation(&Orientation::Portrait);
+        assert_eq!(panel.read().unwrap().unwrap().x, 119);
+
+        // Readings beyond the calibrated range are clamped to the display.
+        panel.sampler = FakeSampler::new(0, 4095);
+        let point = panel.read().unwrap().unwrap();
+        assert_eq!((point.x, point.y), (239, 319));
+    }
+
+    #[test]
+    fn light_or_unstable_touches_are_rejected() {
+        let mut panel = TouchPanel::new(FakeSampler::new(2048, 2048), &Orientation::Portrait);
+        panel.sampler.z = (0, 4000);
+        assert_eq!(panel.read_raw(), Ok(None));
+        panel.set_pressure_threshold(50);
+        assert_eq!(panel.read_raw(), Ok(Some((2048, 2048, 95))));
+
+        panel.sampler.x = [2000, 2041];
+        assert_eq!(panel.read_raw(), Ok(None));
+        panel.sampler.x = [2000, 2040];
+        assert_eq!(panel.read_raw(), Ok(Some((2020, 2048, 95))));
+        panel.sampler.y = [100, 0];
+        assert_eq!(panel.read(), Ok(None));
+    }
+}